@@ -1,6 +1,8 @@
 use core::profile;
-use core::profile::buffer::Decoder;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use core::profile::buffer::{decode_message, Buffer, Decoder, WireTypes};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use flate2::read::GzDecoder;
+use std::hint::black_box;
 use std::io::Read;
 use std::time::Duration;
 
@@ -60,16 +62,53 @@ pub fn profile_bench_big_1min_13025_lines(c: &mut Criterion) {
     }
 }
 
+// The post_decode benches measure post_decode alone, run them with and
+// without the parallel feature to compare:
+//   cargo bench --bench profile_decode -- post_decode --save-baseline serial
+//   cargo bench --features parallel --bench profile_decode -- post_decode --baseline serial
+// The small profile stays below the parallel threshold and should not move.
+fn bench_post_decode(c: &mut Criterion, name: &str, path: &str) {
+    let file = std::fs::File::open(path).unwrap();
+    let mut data = vec![];
+    GzDecoder::new(file).read_to_end(&mut data).unwrap();
+    let mut decoded = profile::Profile::default();
+    let mut b = Buffer {
+        field: 0,
+        r#type: WireTypes::WireBytes,
+        u64: 0,
+    };
+    decode_message(&mut b, &mut data, &mut decoded).unwrap();
+
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || decoded.clone(),
+            |mut p| {
+                p.post_decode().unwrap();
+                p
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+pub fn profile_bench_post_decode_small(c: &mut Criterion) {
+    bench_post_decode(c, "profile_bench_post_decode_small", "tests/CPU.pb.gz");
+}
+
+pub fn profile_bench_post_decode_big(c: &mut Criterion) {
+    bench_post_decode(c, "profile_bench_post_decode_big", "tests/RR_CPU.pb.gz");
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(100).nresamples(5000).measurement_time(Duration::from_secs(60)).warm_up_time(Duration::from_secs(1));
-    targets = profile_bench_cpu, profile_bench_heap, profile_bench_encoded
+    targets = profile_bench_cpu, profile_bench_heap, profile_bench_encoded, profile_bench_post_decode_small
 }
 
 criterion_group! {
     name = slow_bench;
     config = Criterion::default().sample_size(10).nresamples(5000).measurement_time(Duration::from_secs(60)).warm_up_time(Duration::from_secs(1));
-    targets = profile_bench_big_1min_13025_lines
+    targets = profile_bench_big_1min_13025_lines, profile_bench_post_decode_big
 }
 
 criterion_main!(benches, slow_bench);
@@ -0,0 +1,21 @@
+// rock-server is the ingestion server of the profiles for continuous
+// profiling, see the server package:
+//
+//     rock-server [host:port] [directory]
+//
+// It listens on 0.0.0.0:4040 and stores the profiles in ./profiles by default.
+
+use core::server;
+use core::server::store::{Retention, Store};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let address = args.get(1).map_or("0.0.0.0:4040", |a| a.as_str());
+    let directory = args.get(2).map_or("profiles", |d| d.as_str());
+    let result = Store::open(directory)
+        .and_then(|store| server::serve(address, store, Retention::default()));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,6 @@
+// rock is a pprof compatible command line for the profiles read by the crate,
+// see the driver package for its options.
+
+fn main() {
+    std::process::exit(core::driver::main(std::env::args().collect()));
+}
@@ -0,0 +1,267 @@
+// Package callgrind writes profiles in the callgrind format read by
+// KCachegrind and QCachegrind (https://valgrind.org/docs/manual/cl-format.html).
+// Every sample type is an event. The self cost of a function is the value of
+// the samples where it is the leaf, the inclusive cost of a call is the value
+// of the samples where the caller calls the callee.
+
+use crate::profile::location::Location;
+use crate::profile::Profile;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+
+const CREATOR: &str = "rock";
+
+// FunctionCosts are the costs of a function, by source line.
+#[derive(Debug, Default)]
+struct FunctionCosts {
+    file: String,
+    name: String,
+    // self cost by line
+    self_cost: BTreeMap<i64, Vec<i64>>,
+    // calls by caller line and callee
+    calls: BTreeMap<(i64, usize), Call>,
+}
+
+#[derive(Debug)]
+struct Call {
+    // number of samples the call is part of
+    count: i64,
+    // line of the callee where the call lands
+    target_line: i64,
+    // inclusive cost
+    cost: Vec<i64>,
+}
+
+// Names compresses the repeated file and function names: the first use
+// of a name is written as "(id) name", the next ones as "(id)".
+#[derive(Debug, Default)]
+struct Names {
+    ids: HashMap<String, usize>,
+}
+
+impl Names {
+    fn compress(&mut self, name: &str) -> String {
+        if let Some(id) = self.ids.get(name) {
+            return format!("({})", id);
+        }
+        let id = self.ids.len() + 1;
+        self.ids.insert(name.to_string(), id);
+        format!("({}) {}", id, name)
+    }
+}
+
+// frames returns the (file, function, line) of every frame of the location,
+// the caller first.
+fn frames(loc: &Location) -> Vec<(String, String, i64)> {
+    if loc.line.is_empty() {
+        let file = loc
+            .mapping
+            .as_ref()
+            .map(|m| m.filename.clone())
+            .unwrap_or_default();
+        return vec![(file, format!("{:#x}", loc.address), 0)];
+    }
+
+    loc.line
+        .iter()
+        .rev()
+        .map(|l| (l.function.filename.clone(), l.function.name.clone(), l.line))
+        .collect()
+}
+
+fn add(dst: &mut [i64], src: &[i64]) {
+    for (d, s) in dst.iter_mut().zip(src.iter()) {
+        *d += s;
+    }
+}
+
+// costs aggregates the self and call costs of the sample stacks by function,
+// in the order of the first appearance of the functions.
+fn costs(p: &Profile) -> Vec<FunctionCosts> {
+    let n = p.sample_type.len();
+    let mut functions: Vec<FunctionCosts> = vec![];
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+
+    for s in p.sample.iter() {
+        let mut stack: Vec<(usize, i64)> = vec![];
+        for loc in s.location.iter().rev() {
+            for (file, name, line) in frames(loc) {
+                let key = (file, name);
+                let i = match index.get(&key) {
+                    Some(i) => *i,
+                    None => {
+                        functions.push(FunctionCosts {
+                            file: key.0.clone(),
+                            name: key.1.clone(),
+                            ..Default::default()
+                        });
+                        index.insert(key, functions.len() - 1);
+                        functions.len() - 1
+                    }
+                };
+                stack.push((i, line));
+            }
+        }
+
+        let (leaf, line) = match stack.last() {
+            Some(leaf) => *leaf,
+            None => continue,
+        };
+        add(
+            functions[leaf]
+                .self_cost
+                .entry(line)
+                .or_insert_with(|| vec![0; n]),
+            &s.value,
+        );
+
+        // recursive calls are counted once per sample, so inclusive
+        // costs do not exceed the total
+        let mut seen = HashSet::new();
+        for w in stack.windows(2) {
+            let ((caller, caller_line), (callee, callee_line)) = (w[0], w[1]);
+            if !seen.insert((caller, caller_line, callee)) {
+                continue;
+            }
+            let call = functions[caller]
+                .calls
+                .entry((caller_line, callee))
+                .or_insert_with(|| Call {
+                    count: 0,
+                    target_line: callee_line,
+                    cost: vec![0; n],
+                });
+            call.count += 1;
+            add(&mut call.cost, &s.value);
+        }
+    }
+
+    functions
+}
+
+fn cost_line(position: i64, cost: &[i64]) -> String {
+    let mut line = position.to_string();
+    for c in cost {
+        line.push(' ');
+        line.push_str(&c.to_string());
+    }
+    line
+}
+
+// write_callgrind writes the profile in the callgrind format.
+pub fn write_callgrind<W: io::Write>(p: &Profile, mut w: W) -> io::Result<()> {
+    let events: Vec<String> = p
+        .sample_type
+        .iter()
+        .map(|st| st.r#type.replace(char::is_whitespace, "_"))
+        .collect();
+
+    writeln!(w, "# callgrind format")?;
+    writeln!(w, "version: 1")?;
+    writeln!(w, "creator: {}", CREATOR)?;
+    writeln!(w, "positions: line")?;
+    for (event, st) in events.iter().zip(p.sample_type.iter()) {
+        writeln!(w, "event: {} : {} ({})", event, st.r#type, st.unit)?;
+    }
+    writeln!(w, "events: {}", events.join(" "))?;
+
+    let mut summary = vec![0; events.len()];
+    for s in p.sample.iter() {
+        add(&mut summary, &s.value);
+    }
+    let summary: Vec<String> = summary.iter().map(i64::to_string).collect();
+    writeln!(w, "summary: {}", summary.join(" "))?;
+
+    let functions = costs(p);
+    let mut files = Names::default();
+    let mut names = Names::default();
+    for f in functions.iter() {
+        writeln!(w)?;
+        writeln!(w, "fl={}", files.compress(&f.file))?;
+        writeln!(w, "fn={}", names.compress(&f.name))?;
+        for (line, cost) in f.self_cost.iter() {
+            writeln!(w, "{}", cost_line(*line, cost))?;
+        }
+        for ((line, callee), call) in f.calls.iter() {
+            let callee = &functions[*callee];
+            writeln!(w, "cfl={}", files.compress(&callee.file))?;
+            writeln!(w, "cfn={}", names.compress(&callee.name))?;
+            writeln!(w, "calls={} {}", call.count, call.target_line)?;
+            writeln!(w, "{}", cost_line(*line, &call.cost))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::callgrind::write_callgrind;
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::builder::ProfileBuilder;
+    use std::fs;
+
+    #[test]
+    fn test_write_callgrind() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count")
+            .add_sample_type("cpu", "nanoseconds");
+        let main = b.add_function("main", "main", "main.go", 1);
+        let work = b.add_function("work", "work", "work.go", 10);
+        let sum = b.add_function("sum", "sum", "work.go", 30);
+        let l_main = b.add_location(0, 0x1000, &[(main, 3)]);
+        let l_work = b.add_location(0, 0x2000, &[(sum, 31), (work, 12)]);
+        let l_rec = b.add_location(0, 0x2010, &[(work, 14)]);
+        b.add_sample(vec![l_work, l_main], vec![2, 20]);
+        b.add_sample(vec![l_rec, l_rec, l_main], vec![1, 10]);
+        let p = b.build().unwrap();
+
+        let mut out = vec![];
+        write_callgrind(&p, &mut out).unwrap();
+        let want = "# callgrind format
+version: 1
+creator: rock
+positions: line
+event: samples : samples (count)
+event: cpu : cpu (nanoseconds)
+events: samples cpu
+summary: 3 30
+
+fl=(1) main.go
+fn=(1) main
+cfl=(2) work.go
+cfn=(2) work
+calls=2 12
+3 3 30
+
+fl=(2)
+fn=(2)
+14 1 10
+cfl=(2)
+cfn=(3) sum
+calls=1 31
+12 2 20
+cfl=(2)
+cfn=(2)
+calls=1 14
+14 1 10
+
+fl=(2)
+fn=(3)
+31 2 20
+";
+        assert_eq!(String::from_utf8(out).unwrap(), want);
+    }
+
+    #[test]
+    fn test_write_callgrind_decoded() {
+        let mut data = fs::read("tests/CPU.pb.gz").unwrap();
+        let p = Buffer::decode(&mut data).unwrap();
+
+        let mut out = vec![];
+        write_callgrind(&p, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("events: samples cpu\n"));
+        assert!(out.contains("\nfn=(1) "));
+    }
+}
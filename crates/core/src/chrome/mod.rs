@@ -0,0 +1,390 @@
+// Package chrome imports the CPU profiles recorded by V8: the `.cpuprofile`
+// files written by Node.js (--cpu-prof) and Chrome DevTools, and the
+// "Profile"/"ProfileChunk" events of the Trace Event format used by
+// chrome://tracing and the DevTools performance panel.
+
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// name of the root node of every V8 profile, it is not a real frame
+const ROOT_FUNCTION: &str = "(root)";
+const ANONYMOUS_FUNCTION: &str = "(anonymous)";
+
+#[derive(Debug, Clone, Default)]
+struct CallFrame {
+    function_name: String,
+    url: String,
+    // zero based, -1 when unknown
+    line_number: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    call_frame: CallFrame,
+    parent: Option<u64>,
+}
+
+// CpuProfile is the V8 CPU profile: a tree of call frames and the list of
+// sampled nodes, all times are in microseconds.
+#[derive(Debug, Default)]
+struct CpuProfile {
+    nodes: HashMap<u64, Node>,
+    samples: Vec<u64>,
+    time_deltas: Vec<i64>,
+    start_time: i64,
+    end_time: i64,
+    // the process and thread of the trace events
+    pid: Option<i64>,
+    tid: Option<i64>,
+}
+
+// is_cpuprofile reports whether the data looks like a V8 CPU profile or
+// a trace with V8 profile events.
+pub fn is_cpuprofile(data: &[u8]) -> bool {
+    let start = match data.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b) => *b,
+        None => return false,
+    };
+    let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+    (start == b'{' || start == b'[')
+        && (head.contains("\"nodes\"")
+            || head.contains("\"traceEvents\"")
+            || head.contains("\"ProfileChunk\""))
+}
+
+// parse_cpuprofile converts a V8 CPU profile, or all the V8 profiles of a
+// trace, into a profile with samples/count and cpu/nanoseconds sample types.
+// The weight of a sample is the time until the next sample (or the end of the
+// profile). Functions take their name, file and line from the call frames,
+// and the profile start and end times become time_nanos and duration_nanos.
+// The samples of a trace are labeled with the pid and tid of their thread.
+pub fn parse_cpuprofile(data: &[u8]) -> Result<Profile, RockError> {
+    let v: Value = serde_json::from_slice(data).map_err(|err| RockError::UnrecognizedFormat {
+        reason: format!("parsing cpuprofile json: {}", err),
+    })?;
+
+    let profiles = if v.get("nodes").is_some() {
+        vec![parse_profile(&v)?]
+    } else {
+        let events = match v.get("traceEvents").unwrap_or(&v) {
+            Value::Array(events) => events,
+            _ => {
+                return Err(RockError::UnrecognizedFormat {
+                    reason: String::from("json is neither a cpuprofile nor a trace"),
+                })
+            }
+        };
+        parse_trace_events(events)?
+    };
+    if profiles.is_empty() {
+        return Err(RockError::UnrecognizedFormat {
+            reason: String::from("trace has no V8 profile events"),
+        });
+    }
+
+    let mut b = ProfileBuilder::new();
+    b.add_sample_type("samples", "count")
+        .add_sample_type("cpu", "nanoseconds");
+
+    let start = profiles.iter().map(|p| p.start_time).min().unwrap_or(0);
+    let end = profiles.iter().map(|p| p.end_time).max().unwrap_or(0);
+    let samples: usize = profiles.iter().map(|p| p.samples.len()).sum();
+    let period = if samples > 0 {
+        (end - start) * 1000 / samples as i64
+    } else {
+        0
+    };
+    b.period_type("cpu", "nanoseconds", period)
+        .time_nanos(start * 1000)
+        .duration_nanos((end - start) * 1000);
+
+    for p in profiles.iter() {
+        add_samples(&mut b, p)?;
+    }
+    b.build()
+}
+
+// add_samples adds the samples of a V8 profile to the builder, aggregating
+// the samples of the same node.
+fn add_samples(b: &mut ProfileBuilder, p: &CpuProfile) -> Result<(), RockError> {
+    // timestamps of the samples, the time deltas are relative to the previous sample
+    let mut times = Vec::with_capacity(p.samples.len());
+    let mut t = p.start_time;
+    for i in 0..p.samples.len() {
+        t += p.time_deltas.get(i).copied().unwrap_or(0);
+        times.push(t);
+    }
+
+    let mut weights: Vec<(u64, i64, i64)> = vec![];
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for (i, node) in p.samples.iter().enumerate() {
+        let next = times.get(i + 1).copied().unwrap_or(p.end_time);
+        let weight = (next - times[i]).max(0) * 1000;
+        let i = *index.entry(*node).or_insert_with(|| {
+            weights.push((*node, 0, 0));
+            weights.len() - 1
+        });
+        weights[i].1 += 1;
+        weights[i].2 += weight;
+    }
+
+    let mut locations: HashMap<u64, u64> = HashMap::new();
+    for (node, count, cpu) in weights {
+        let mut stack = vec![];
+        let mut id = Some(node);
+        while let Some(current) = id {
+            let n = p
+                .nodes
+                .get(&current)
+                .ok_or_else(|| RockError::MalformedProfile {
+                    reason: format!("cpuprofile references unknown node {}", current),
+                })?;
+            if stack.len() > p.nodes.len() {
+                return Err(RockError::MalformedProfile {
+                    reason: format!("cpuprofile node {} is part of a cycle", current),
+                });
+            }
+            if n.parent.is_some() || n.call_frame.function_name != ROOT_FUNCTION {
+                let loc = match locations.get(&current) {
+                    Some(loc) => *loc,
+                    None => {
+                        let loc = add_location(b, &n.call_frame);
+                        locations.insert(current, loc);
+                        loc
+                    }
+                };
+                stack.push(loc);
+            }
+            id = n.parent;
+        }
+
+        let s = b.add_sample(stack, vec![count, cpu]);
+        if let Some(pid) = p.pid {
+            s.num_label.insert(String::from("pid"), vec![pid]);
+        }
+        if let Some(tid) = p.tid {
+            s.num_label.insert(String::from("tid"), vec![tid]);
+        }
+    }
+    Ok(())
+}
+
+fn add_location(b: &mut ProfileBuilder, f: &CallFrame) -> u64 {
+    let name = if f.function_name.is_empty() {
+        ANONYMOUS_FUNCTION
+    } else {
+        &f.function_name
+    };
+    let line = if f.line_number >= 0 {
+        f.line_number + 1
+    } else {
+        0
+    };
+    let function = b.add_function(name, name, &f.url, line);
+    b.add_location(0, 0, &[(function, line)])
+}
+
+fn parse_profile(v: &Value) -> Result<CpuProfile, RockError> {
+    let mut p = CpuProfile {
+        start_time: int(v, "startTime")?,
+        end_time: int(v, "endTime")?,
+        ..Default::default()
+    };
+    add_nodes(&mut p, array(v, "nodes")?)?;
+    for s in array(v, "samples")? {
+        p.samples.push(as_id(s)?);
+    }
+    for d in v
+        .get("timeDeltas")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        p.time_deltas.push(as_int(d)?);
+    }
+    Ok(p)
+}
+
+// add_nodes adds the nodes to the profile tree. The parent of a node is given
+// either by the children of its parent (cpuprofile) or by its "parent" field
+// (trace events).
+fn add_nodes(p: &mut CpuProfile, nodes: &[Value]) -> Result<(), RockError> {
+    let mut parents = vec![];
+    for n in nodes {
+        let id = as_id(n.get("id").unwrap_or(&Value::Null))?;
+        let cf = n.get("callFrame").unwrap_or(&Value::Null);
+        let node = p.nodes.entry(id).or_default();
+        node.call_frame = CallFrame {
+            function_name: string(cf, "functionName"),
+            url: string(cf, "url"),
+            line_number: cf.get("lineNumber").and_then(Value::as_i64).unwrap_or(-1),
+        };
+        if let Some(parent) = n.get("parent") {
+            node.parent = Some(as_id(parent)?);
+        }
+        for c in n
+            .get("children")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            parents.push((as_id(c)?, id));
+        }
+    }
+    for (child, parent) in parents {
+        p.nodes.entry(child).or_default().parent = Some(parent);
+    }
+    Ok(())
+}
+
+// parse_trace_events collects the V8 profiles of a trace. A profile starts with
+// a "Profile" event and continues in "ProfileChunk" events with the same id,
+// each chunk adds nodes, samples and time deltas.
+fn parse_trace_events(events: &[Value]) -> Result<Vec<CpuProfile>, RockError> {
+    let mut order: Vec<String> = vec![];
+    let mut profiles: HashMap<String, CpuProfile> = HashMap::new();
+    for e in events {
+        let name = e.get("name").and_then(Value::as_str).unwrap_or_default();
+        if name != "Profile" && name != "ProfileChunk" {
+            continue;
+        }
+        let id = match e.get("id") {
+            Some(Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => String::new(),
+        };
+        let p = profiles.entry(id.clone()).or_insert_with(|| {
+            order.push(id);
+            CpuProfile {
+                pid: e.get("pid").and_then(Value::as_i64),
+                tid: e.get("tid").and_then(Value::as_i64),
+                ..Default::default()
+            }
+        });
+
+        let data = e
+            .get("args")
+            .and_then(|a| a.get("data"))
+            .unwrap_or(&Value::Null);
+        if let Some(t) = data.get("startTime") {
+            p.start_time = as_int(t)?;
+            p.end_time = p.end_time.max(p.start_time);
+        }
+        if let Some(t) = data.get("endTime") {
+            p.end_time = as_int(t)?;
+        }
+        if let Some(cpu) = data.get("cpuProfile") {
+            if let Some(nodes) = cpu.get("nodes").and_then(Value::as_array) {
+                add_nodes(p, nodes)?;
+            }
+            for s in cpu
+                .get("samples")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                p.samples.push(as_id(s)?);
+            }
+        }
+        for d in data
+            .get("timeDeltas")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let d = as_int(d)?;
+            p.time_deltas.push(d);
+        }
+    }
+
+    let mut result = vec![];
+    for id in order {
+        let mut p = profiles.remove(&id).unwrap();
+        // without an end time the profile ends with its last sample
+        let last = p.start_time + p.time_deltas.iter().sum::<i64>();
+        p.end_time = p.end_time.max(last);
+        if !p.samples.is_empty() {
+            result.push(p);
+        }
+    }
+    Ok(result)
+}
+
+fn array<'a>(v: &'a Value, key: &str) -> Result<&'a [Value], RockError> {
+    match v.get(key) {
+        Some(Value::Array(a)) => Ok(a),
+        _ => Err(RockError::MalformedProfile {
+            reason: format!("cpuprofile has no {} array", key),
+        }),
+    }
+}
+
+fn int(v: &Value, key: &str) -> Result<i64, RockError> {
+    as_int(v.get(key).unwrap_or(&Value::Null))
+}
+
+fn as_int(v: &Value) -> Result<i64, RockError> {
+    // times are sometimes written as floating point numbers
+    match v.as_i64().or_else(|| v.as_f64().map(|f| f as i64)) {
+        Some(i) => Ok(i),
+        None => Err(RockError::MalformedProfile {
+            reason: format!("cpuprofile: expected a number, got {}", v),
+        }),
+    }
+}
+
+fn as_id(v: &Value) -> Result<u64, RockError> {
+    match v.as_u64() {
+        Some(id) => Ok(id),
+        None => Err(RockError::MalformedProfile {
+            reason: format!("cpuprofile: expected a node id, got {}", v),
+        }),
+    }
+}
+
+fn string(v: &Value, key: &str) -> String {
+    v.get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chrome::{is_cpuprofile, parse_cpuprofile};
+    use crate::profile::errors::RockError;
+    use std::fs;
+
+    #[test]
+    fn parse_cpuprofile_golden() {
+        for file in ["tests/chrome/node.cpuprofile", "tests/chrome/trace.json"] {
+            let data = fs::read(file).unwrap();
+            assert!(is_cpuprofile(&data), "{}", file);
+
+            let p = parse_cpuprofile(&data).unwrap();
+            let want = fs::read_to_string(format!("{}.string", file)).unwrap();
+            assert_eq!(p.to_string(), want, "{}", file);
+        }
+    }
+
+    #[test]
+    fn parse_cpuprofile_errors() {
+        assert!(matches!(
+            parse_cpuprofile(b"heap profile: 1: 2 [3: 4] @ heapprofile"),
+            Err(RockError::UnrecognizedFormat { .. })
+        ));
+        assert!(matches!(
+            parse_cpuprofile(br#"{"nodes": [], "startTime": 0, "endTime": 1}"#),
+            Err(RockError::MalformedProfile { .. })
+        ));
+        assert!(matches!(
+            parse_cpuprofile(
+                br#"{"nodes": [{"id": 1, "callFrame": {}}], "samples": [2], "startTime": 0, "endTime": 1}"#
+            ),
+            Err(RockError::MalformedProfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,948 @@
+// Package columnar is a storage format for many profiles of the same
+// programs. The strings, mappings, functions, locations, stacks and label
+// sets are deduplicated across the profiles in tables shared by all of
+// them, so a function seen in thousands of profiles is stored once. The
+// samples are stored in columns, a row per sample:
+//
+//     stack     -- index of the stack in the stack table
+//     timestamp -- time of the profile of the sample, in nanoseconds
+//     labels    -- index of the label set in the label set table
+//     values    -- a column per value of the samples, the n-th column
+//                  holding the n-th sample type of their profile
+//
+// and the profiles (sample types, period, time...) are headers referencing
+// their contiguous range of rows. The first entry of every table is the
+// zero value, index 0 means "unset" as in OTLP. The encoding of a Table is
+// the Table message of src/proto/columnar.proto.
+
+use crate::otlp::{fields, string, varints};
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::encoder::Encoder;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Mapping {
+    pub memory_start: u64,
+    pub memory_limit: u64,
+    pub memory_offset: u64,
+    pub filename: u32,
+    pub build_id: u32,
+    pub has_functions: bool,
+    pub has_filenames: bool,
+    pub has_line_numbers: bool,
+    pub has_inline_frames: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Function {
+    pub name: u32,
+    pub system_name: u32,
+    pub filename: u32,
+    pub start_line: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Line {
+    pub function: u32,
+    pub line: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub mapping: u32,
+    pub address: u64,
+    // the callee first, the caller last
+    pub lines: Vec<Line>,
+    pub is_folded: bool,
+}
+
+// Label is a label of a sample: a string value, or a number and its unit.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label {
+    pub key: u32,
+    pub str: u32,
+    pub num: i64,
+    pub unit: u32,
+    pub is_num: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValueType {
+    pub r#type: u32,
+    pub unit: u32,
+}
+
+// ProfileHeader holds the attributes of a profile and the range of its
+// samples in the columns.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileHeader {
+    pub sample_types: Vec<ValueType>,
+    pub period_type: Option<ValueType>,
+    pub period: i64,
+    pub time_nanos: i64,
+    pub duration_nanos: i64,
+    pub comments: Vec<u32>,
+    pub drop_frames: u32,
+    pub keep_frames: u32,
+    pub default_sample_type: u32,
+    pub first_sample: u64,
+    pub sample_count: u64,
+}
+
+// Samples are the columns of the samples of the profiles.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Samples {
+    pub stack: Vec<u32>,
+    pub timestamp: Vec<i64>,
+    pub labels: Vec<u32>,
+    pub values: Vec<Vec<i64>>,
+}
+
+impl Samples {
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+// Dedup is a table of unique entries with their index.
+#[derive(Debug, Clone)]
+struct Dedup<T: Clone + Eq + Hash> {
+    table: Vec<T>,
+    index: HashMap<T, u32>,
+}
+
+impl<T: Clone + Eq + Hash + Default> Dedup<T> {
+    fn new() -> Self {
+        Dedup::from(vec![T::default()])
+    }
+
+    fn from(table: Vec<T>) -> Self {
+        let mut index = HashMap::with_capacity(table.len());
+        for (i, v) in table.iter().enumerate() {
+            index.entry(v.clone()).or_insert(i as u32);
+        }
+        Dedup { table, index }
+    }
+
+    fn get(&mut self, v: T) -> u32 {
+        if let Some(i) = self.index.get(&v) {
+            return *i;
+        }
+        self.table.push(v.clone());
+        self.index.insert(v, self.table.len() as u32 - 1);
+        self.table.len() as u32 - 1
+    }
+}
+
+impl<T: Clone + Eq + Hash + Default> Default for Dedup<T> {
+    fn default() -> Self {
+        Dedup::new()
+    }
+}
+
+// Table holds profiles with shared symbol tables and columnar samples.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    strings: Dedup<String>,
+    mappings: Dedup<Mapping>,
+    functions: Dedup<Function>,
+    locations: Dedup<Location>,
+    // location indices of the stacks, the leaf first
+    stacks: Dedup<Vec<u32>>,
+    // label sets, sorted
+    label_sets: Dedup<Vec<Label>>,
+    profiles: Vec<ProfileHeader>,
+    samples: Samples,
+}
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        self.strings.table == other.strings.table
+            && self.mappings.table == other.mappings.table
+            && self.functions.table == other.functions.table
+            && self.locations.table == other.locations.table
+            && self.stacks.table == other.stacks.table
+            && self.label_sets.table == other.label_sets.table
+            && self.profiles == other.profiles
+            && self.samples == other.samples
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table::default()
+    }
+
+    // from_profiles returns the table of the profiles, in order.
+    pub fn from_profiles(profiles: &[Profile]) -> Self {
+        let mut t = Table::new();
+        for p in profiles.iter() {
+            t.append(p);
+        }
+        t
+    }
+
+    // len returns the number of profiles of the table.
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+
+    pub fn strings(&self) -> &[String] {
+        &self.strings.table
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings.table
+    }
+
+    pub fn functions(&self) -> &[Function] {
+        &self.functions.table
+    }
+
+    pub fn locations(&self) -> &[Location] {
+        &self.locations.table
+    }
+
+    pub fn stacks(&self) -> &[Vec<u32>] {
+        &self.stacks.table
+    }
+
+    pub fn label_sets(&self) -> &[Vec<Label>] {
+        &self.label_sets.table
+    }
+
+    pub fn profiles(&self) -> &[ProfileHeader] {
+        &self.profiles
+    }
+
+    pub fn samples(&self) -> &Samples {
+        &self.samples
+    }
+
+    // append adds the profile to the table and returns its index.
+    pub fn append(&mut self, p: &Profile) -> usize {
+        let mut mappings = HashMap::new();
+        for m in p.mapping.iter() {
+            let row = Mapping {
+                memory_start: m.memory_start,
+                memory_limit: m.memory_limit,
+                memory_offset: m.memory_offset,
+                filename: self.strings.get(m.filename.clone()),
+                build_id: self.strings.get(m.build_id.clone()),
+                has_functions: m.has_function,
+                has_filenames: m.has_filenames,
+                has_line_numbers: m.has_line_numbers,
+                has_inline_frames: m.has_inline_frames,
+            };
+            mappings.insert(m.id, self.mappings.get(row));
+        }
+
+        let mut functions = HashMap::new();
+        for f in p.function.iter() {
+            let row = Function {
+                name: self.strings.get(f.name.clone()),
+                system_name: self.strings.get(f.system_name.clone()),
+                filename: self.strings.get(f.filename.clone()),
+                start_line: f.start_line,
+            };
+            functions.insert(f.id, self.functions.get(row));
+        }
+
+        let mut locations = HashMap::new();
+        for l in p.location.iter() {
+            let row = Location {
+                mapping: mappings.get(&l.mapping_index).copied().unwrap_or(0),
+                address: l.address,
+                lines: l
+                    .line
+                    .iter()
+                    .map(|line| Line {
+                        function: functions.get(&line.function_index).copied().unwrap_or(0),
+                        line: line.line,
+                    })
+                    .collect(),
+                is_folded: l.is_folder,
+            };
+            locations.insert(l.id, self.locations.get(row));
+        }
+
+        let value_type =
+            |strings: &mut Dedup<String>, vt: &crate::profile::value_type::ValueType| ValueType {
+                r#type: strings.get(vt.r#type.clone()),
+                unit: strings.get(vt.unit.clone()),
+            };
+        let header = ProfileHeader {
+            sample_types: p
+                .sample_type
+                .iter()
+                .map(|st| value_type(&mut self.strings, st))
+                .collect(),
+            period_type: p
+                .period_type
+                .as_ref()
+                .map(|pt| value_type(&mut self.strings, pt)),
+            period: p.period,
+            time_nanos: p.time_nanos,
+            duration_nanos: p.duration_nanos,
+            comments: p
+                .comments
+                .iter()
+                .map(|c| self.strings.get(c.clone()))
+                .collect(),
+            drop_frames: self.strings.get(p.drop_frames.clone()),
+            keep_frames: self.strings.get(p.keep_frames.clone()),
+            default_sample_type: self.strings.get(p.default_sample_type.clone()),
+            first_sample: self.samples.len() as u64,
+            sample_count: p.sample.len() as u64,
+        };
+
+        let columns = header.sample_types.len();
+        while self.samples.values.len() < columns {
+            self.samples.values.push(vec![0; self.samples.len()]);
+        }
+        for s in p.sample.iter() {
+            let stack: Vec<u32> = s
+                .location_index
+                .iter()
+                .filter_map(|id| locations.get(id).copied())
+                .collect();
+            let stack = self.stacks.get(stack);
+            let labels = self.label_set(s);
+            self.samples.stack.push(stack);
+            self.samples.timestamp.push(p.time_nanos);
+            self.samples.labels.push(labels);
+            for (i, column) in self.samples.values.iter_mut().enumerate() {
+                column.push(s.value.get(i).copied().unwrap_or(0));
+            }
+        }
+
+        self.profiles.push(header);
+        self.profiles.len() - 1
+    }
+
+    fn label_set(&mut self, s: &crate::profile::sample::Sample) -> u32 {
+        let mut labels = vec![];
+        for (key, values) in s.label.iter() {
+            let key = self.strings.get(key.clone());
+            for v in values.iter() {
+                labels.push(Label {
+                    key,
+                    str: self.strings.get(v.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        for (key, values) in s.num_label.iter() {
+            let units = s.num_unit_label.get(key);
+            let k = self.strings.get(key.clone());
+            for (i, v) in values.iter().enumerate() {
+                let unit = units.and_then(|u| u.get(i)).cloned().unwrap_or_default();
+                labels.push(Label {
+                    key: k,
+                    num: *v,
+                    unit: self.strings.get(unit),
+                    is_num: true,
+                    ..Default::default()
+                });
+            }
+        }
+        // the order of the values of a key is kept by the stable sort
+        labels.sort_by_key(|l| (l.key, l.is_num));
+        self.label_sets.get(labels)
+    }
+
+    // profile returns the i-th profile of the table.
+    pub fn profile(&self, i: usize) -> Result<Profile, RockError> {
+        let h = get(&self.profiles, i as u64, "profile")?;
+        let s = |i: u32| -> Result<&str, RockError> {
+            get(&self.strings.table, i as u64, "string").map(String::as_str)
+        };
+
+        let mut b = ProfileBuilder::new();
+        for st in h.sample_types.iter() {
+            b.add_sample_type(s(st.r#type)?, s(st.unit)?);
+        }
+        if let Some(pt) = &h.period_type {
+            b.period_type(s(pt.r#type)?, s(pt.unit)?, h.period);
+        }
+        b.time_nanos(h.time_nanos)
+            .duration_nanos(h.duration_nanos)
+            .drop_frames(s(h.drop_frames)?)
+            .keep_frames(s(h.keep_frames)?)
+            .default_sample_type(s(h.default_sample_type)?);
+        for c in h.comments.iter() {
+            b.add_comment(s(*c)?);
+        }
+
+        // builder ids of the rows of the tables used by the profile
+        let mut mapping_ids: HashMap<u32, u64> = HashMap::new();
+        let mut function_ids: HashMap<u32, u64> = HashMap::new();
+        let mut location_ids: HashMap<u32, u64> = HashMap::new();
+        let mut folded = vec![];
+
+        let start = h.first_sample as usize;
+        let Some(end) = start
+            .checked_add(h.sample_count as usize)
+            .filter(|end| *end <= self.samples.len())
+        else {
+            return Err(RockError::MalformedProfile {
+                reason: format!(
+                    "samples {}..{} of profile {} out of range, table has {} samples",
+                    start,
+                    start.saturating_add(h.sample_count as usize),
+                    i,
+                    self.samples.len()
+                ),
+            });
+        };
+        for row in start..end {
+            let stack = get(&self.stacks.table, self.samples.stack[row] as u64, "stack")?;
+            let mut ids = Vec::with_capacity(stack.len());
+            for l in stack.iter() {
+                if let Some(id) = location_ids.get(l) {
+                    ids.push(*id);
+                    continue;
+                }
+                let loc = get(&self.locations.table, *l as u64, "location")?;
+                let mapping = match loc.mapping {
+                    0 => 0,
+                    m => match mapping_ids.get(&m) {
+                        Some(id) => *id,
+                        None => {
+                            let row = get(&self.mappings.table, m as u64, "mapping")?;
+                            let id = b.add_mapping(crate::profile::mapping::Mapping {
+                                memory_start: row.memory_start,
+                                memory_limit: row.memory_limit,
+                                memory_offset: row.memory_offset,
+                                filename: s(row.filename)?.to_string(),
+                                build_id: s(row.build_id)?.to_string(),
+                                has_function: row.has_functions,
+                                has_filenames: row.has_filenames,
+                                has_line_numbers: row.has_line_numbers,
+                                has_inline_frames: row.has_inline_frames,
+                                ..Default::default()
+                            });
+                            mapping_ids.insert(m, id);
+                            id
+                        }
+                    },
+                };
+                let mut lines = Vec::with_capacity(loc.lines.len());
+                for line in loc.lines.iter() {
+                    let f = match function_ids.get(&line.function) {
+                        Some(id) => *id,
+                        None => {
+                            let row = get(&self.functions.table, line.function as u64, "function")?;
+                            let id = b.add_function(
+                                s(row.name)?,
+                                s(row.system_name)?,
+                                s(row.filename)?,
+                                row.start_line,
+                            );
+                            function_ids.insert(line.function, id);
+                            id
+                        }
+                    };
+                    lines.push((f, line.line));
+                }
+                let id = b.add_location(mapping, loc.address, &lines);
+                if loc.is_folded {
+                    folded.push(id);
+                }
+                location_ids.insert(*l, id);
+                ids.push(id);
+            }
+
+            let values = self.samples.values[..h.sample_types.len()]
+                .iter()
+                .map(|column| column[row])
+                .collect();
+            let labels = get(
+                &self.label_sets.table,
+                self.samples.labels[row] as u64,
+                "label set",
+            )?;
+            let sample = b.add_sample(ids, values);
+            for l in labels.iter() {
+                let key = s(l.key)?.to_string();
+                if l.is_num {
+                    sample.num_label.entry(key.clone()).or_default().push(l.num);
+                    let unit = s(l.unit)?;
+                    if !unit.is_empty() {
+                        sample
+                            .num_unit_label
+                            .entry(key)
+                            .or_default()
+                            .push(unit.to_string());
+                    }
+                } else {
+                    sample
+                        .label
+                        .entry(key)
+                        .or_default()
+                        .push(s(l.str)?.to_string());
+                }
+            }
+        }
+
+        let mut p = b.build()?;
+        for l in p.location.iter_mut() {
+            l.is_folder = folded.contains(&l.id);
+        }
+        p.link();
+        Ok(p)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.repeated_string(1, &self.strings.table);
+        for m in self.mappings.table.iter() {
+            e.message(2, |e| {
+                e.uint64(1, m.memory_start);
+                e.uint64(2, m.memory_limit);
+                e.uint64(3, m.memory_offset);
+                e.uint64(4, m.filename as u64);
+                e.uint64(5, m.build_id as u64);
+                e.bool(6, m.has_functions);
+                e.bool(7, m.has_filenames);
+                e.bool(8, m.has_line_numbers);
+                e.bool(9, m.has_inline_frames);
+            });
+        }
+        for f in self.functions.table.iter() {
+            e.message(3, |e| {
+                e.uint64(1, f.name as u64);
+                e.uint64(2, f.system_name as u64);
+                e.uint64(3, f.filename as u64);
+                e.int64(4, f.start_line);
+            });
+        }
+        for l in self.locations.table.iter() {
+            e.message(4, |e| {
+                e.uint64(1, l.mapping as u64);
+                e.uint64(2, l.address);
+                for line in l.lines.iter() {
+                    e.message(3, |e| {
+                        e.uint64(1, line.function as u64);
+                        e.int64(2, line.line);
+                    });
+                }
+                e.bool(4, l.is_folded);
+            });
+        }
+        for s in self.stacks.table.iter() {
+            e.message(5, |e| e.packed_uint64(1, &widen(s)));
+        }
+        for set in self.label_sets.table.iter() {
+            e.message(6, |e| {
+                for l in set.iter() {
+                    e.message(1, |e| {
+                        e.uint64(1, l.key as u64);
+                        e.uint64(2, l.str as u64);
+                        e.int64(3, l.num);
+                        e.uint64(4, l.unit as u64);
+                        e.bool(5, l.is_num);
+                    });
+                }
+            });
+        }
+        for h in self.profiles.iter() {
+            e.message(7, |e| {
+                for st in h.sample_types.iter() {
+                    e.message(1, |e| st.encode(e));
+                }
+                if let Some(pt) = &h.period_type {
+                    e.message(2, |e| pt.encode(e));
+                }
+                e.int64(3, h.period);
+                e.int64(4, h.time_nanos);
+                e.int64(5, h.duration_nanos);
+                e.packed_uint64(6, &widen(&h.comments));
+                e.uint64(7, h.drop_frames as u64);
+                e.uint64(8, h.keep_frames as u64);
+                e.uint64(9, h.default_sample_type as u64);
+                e.uint64(10, h.first_sample);
+                e.uint64(11, h.sample_count);
+            });
+        }
+        e.message(8, |e| {
+            e.packed_uint64(1, &widen(&self.samples.stack));
+            e.packed_int64(2, &self.samples.timestamp);
+            e.packed_uint64(3, &widen(&self.samples.labels));
+            for column in self.samples.values.iter() {
+                e.message(4, |e| e.packed_int64(1, column));
+            }
+        });
+        e.finish()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut strings = vec![];
+        let mut mappings = vec![];
+        let mut functions = vec![];
+        let mut locations = vec![];
+        let mut stacks = vec![];
+        let mut label_sets = vec![];
+        let mut profiles = vec![];
+        let mut samples = Samples::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => strings.push(string(bytes)?),
+                2 => {
+                    let mut m = Mapping::default();
+                    fields(&bytes, |buf, _| {
+                        match buf.field {
+                            1 => m.memory_start = buf.u64,
+                            2 => m.memory_limit = buf.u64,
+                            3 => m.memory_offset = buf.u64,
+                            4 => m.filename = buf.u64 as u32,
+                            5 => m.build_id = buf.u64 as u32,
+                            6 => m.has_functions = buf.u64 != 0,
+                            7 => m.has_filenames = buf.u64 != 0,
+                            8 => m.has_line_numbers = buf.u64 != 0,
+                            9 => m.has_inline_frames = buf.u64 != 0,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    mappings.push(m);
+                }
+                3 => {
+                    let mut f = Function::default();
+                    fields(&bytes, |buf, _| {
+                        match buf.field {
+                            1 => f.name = buf.u64 as u32,
+                            2 => f.system_name = buf.u64 as u32,
+                            3 => f.filename = buf.u64 as u32,
+                            4 => f.start_line = buf.u64 as i64,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    functions.push(f);
+                }
+                4 => {
+                    let mut l = Location::default();
+                    fields(&bytes, |buf, bytes| {
+                        match buf.field {
+                            1 => l.mapping = buf.u64 as u32,
+                            2 => l.address = buf.u64,
+                            3 => {
+                                let mut line = Line::default();
+                                fields(&bytes, |buf, _| {
+                                    match buf.field {
+                                        1 => line.function = buf.u64 as u32,
+                                        2 => line.line = buf.u64 as i64,
+                                        _ => {}
+                                    }
+                                    Ok(())
+                                })?;
+                                l.lines.push(line);
+                            }
+                            4 => l.is_folded = buf.u64 != 0,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    locations.push(l);
+                }
+                5 => {
+                    let mut stack = vec![];
+                    fields(&bytes, |buf, bytes| {
+                        if buf.field == 1 {
+                            stack.extend(varints(buf, bytes)?.into_iter().map(|v| v as u32));
+                        }
+                        Ok(())
+                    })?;
+                    stacks.push(stack);
+                }
+                6 => {
+                    let mut set = vec![];
+                    fields(&bytes, |buf, bytes| {
+                        if buf.field == 1 {
+                            let mut l = Label::default();
+                            fields(&bytes, |buf, _| {
+                                match buf.field {
+                                    1 => l.key = buf.u64 as u32,
+                                    2 => l.str = buf.u64 as u32,
+                                    3 => l.num = buf.u64 as i64,
+                                    4 => l.unit = buf.u64 as u32,
+                                    5 => l.is_num = buf.u64 != 0,
+                                    _ => {}
+                                }
+                                Ok(())
+                            })?;
+                            set.push(l);
+                        }
+                        Ok(())
+                    })?;
+                    label_sets.push(set);
+                }
+                7 => profiles.push(ProfileHeader::decode(&bytes)?),
+                8 => {
+                    fields(&bytes, |buf, bytes| {
+                        match buf.field {
+                            1 => samples
+                                .stack
+                                .extend(varints(buf, bytes)?.into_iter().map(|v| v as u32)),
+                            2 => samples
+                                .timestamp
+                                .extend(varints(buf, bytes)?.into_iter().map(|v| v as i64)),
+                            3 => samples
+                                .labels
+                                .extend(varints(buf, bytes)?.into_iter().map(|v| v as u32)),
+                            4 => {
+                                let mut column = vec![];
+                                fields(&bytes, |buf, bytes| {
+                                    if buf.field == 1 {
+                                        column.extend(
+                                            varints(buf, bytes)?.into_iter().map(|v| v as i64),
+                                        );
+                                    }
+                                    Ok(())
+                                })?;
+                                samples.values.push(column);
+                            }
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        let rows = samples.len();
+        if samples.timestamp.len() != rows
+            || samples.labels.len() != rows
+            || samples.values.iter().any(|c| c.len() != rows)
+        {
+            return Err(RockError::MalformedProfile {
+                reason: String::from("sample columns of different lengths"),
+            });
+        }
+        // the tables start with their zero value, omitted if empty
+        if strings.is_empty() {
+            strings.push(String::new());
+        }
+        zero(&mut mappings);
+        zero(&mut functions);
+        zero(&mut locations);
+        zero(&mut stacks);
+        zero(&mut label_sets);
+
+        Ok(Table {
+            strings: Dedup::from(strings),
+            mappings: Dedup::from(mappings),
+            functions: Dedup::from(functions),
+            locations: Dedup::from(locations),
+            stacks: Dedup::from(stacks),
+            label_sets: Dedup::from(label_sets),
+            profiles,
+            samples,
+        })
+    }
+}
+
+impl ValueType {
+    fn encode(&self, e: &mut Encoder) {
+        e.uint64(1, self.r#type as u64);
+        e.uint64(2, self.unit as u64);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut vt = ValueType::default();
+        fields(data, |buf, _| {
+            match buf.field {
+                1 => vt.r#type = buf.u64 as u32,
+                2 => vt.unit = buf.u64 as u32,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(vt)
+    }
+}
+
+impl ProfileHeader {
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut h = ProfileHeader::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => h.sample_types.push(ValueType::decode(&bytes)?),
+                2 => h.period_type = Some(ValueType::decode(&bytes)?),
+                3 => h.period = buf.u64 as i64,
+                4 => h.time_nanos = buf.u64 as i64,
+                5 => h.duration_nanos = buf.u64 as i64,
+                6 => h
+                    .comments
+                    .extend(varints(buf, bytes)?.into_iter().map(|v| v as u32)),
+                7 => h.drop_frames = buf.u64 as u32,
+                8 => h.keep_frames = buf.u64 as u32,
+                9 => h.default_sample_type = buf.u64 as u32,
+                10 => h.first_sample = buf.u64,
+                11 => h.sample_count = buf.u64,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(h)
+    }
+}
+
+fn zero<T: Default>(table: &mut Vec<T>) {
+    if table.is_empty() {
+        table.push(T::default());
+    }
+}
+
+fn widen(v: &[u32]) -> Vec<u64> {
+    v.iter().map(|x| *x as u64).collect()
+}
+
+// get returns the entry of a table, or an error if the index is out of range.
+fn get<'a, T>(table: &'a [T], index: u64, name: &str) -> Result<&'a T, RockError> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| table.get(i))
+        .ok_or_else(|| RockError::MalformedProfile {
+            reason: format!(
+                "{} index {} out of range, table has {} entries",
+                name,
+                index,
+                table.len()
+            ),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::columnar::Table;
+    use crate::driver::read_profile;
+    use crate::profile::Profile;
+
+    // canonical returns the samples of the profile with their stacks of
+    // function names and lines, sorted, to compare profiles with different
+    // ids.
+    fn canonical(p: &Profile) -> Vec<String> {
+        let mut samples: Vec<String> =
+            p.sample
+                .iter()
+                .map(|s| {
+                    let stack: Vec<String> = s
+                        .location
+                        .iter()
+                        .map(|l| {
+                            let lines: Vec<String> = l
+                                .line
+                                .iter()
+                                .map(|line| format!("{}:{}", line.function.name, line.line))
+                                .collect();
+                            let mapping = l.mapping.as_ref().map_or("", |m| m.filename.as_str());
+                            format!("{:x}@{}[{}]", l.address, mapping, lines.join(","))
+                        })
+                        .collect();
+                    let mut labels: Vec<String> =
+                        s.label
+                            .iter()
+                            .map(|(k, v)| format!("{}={:?}", k, v))
+                            .chain(s.num_label.iter().map(|(k, v)| {
+                                format!("{}#{:?}{:?}", k, v, s.num_unit_label.get(k))
+                            }))
+                            .collect();
+                    labels.sort();
+                    format!("{:?} {} {}", s.value, stack.join(";"), labels.join(","))
+                })
+                .collect();
+        samples.sort();
+        samples
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cpu = read_profile("tests/CPU.pb.gz").unwrap();
+        let heap = read_profile("tests/HEAP.pb.gz").unwrap();
+        let table = Table::from_profiles(&[cpu.clone(), heap.clone(), cpu.clone()]);
+        assert_eq!(table.len(), 3);
+
+        for (i, want) in [&cpu, &heap, &cpu].iter().enumerate() {
+            let got = table.profile(i).unwrap();
+            assert_eq!(canonical(&got), canonical(want));
+            let types = |p: &Profile| -> Vec<(String, String)> {
+                p.sample_type
+                    .iter()
+                    .chain(p.period_type.iter())
+                    .map(|vt| (vt.r#type.clone(), vt.unit.clone()))
+                    .collect()
+            };
+            assert_eq!(types(&got), types(want));
+            assert_eq!(got.period, want.period);
+            assert_eq!(got.time_nanos, want.time_nanos);
+            assert_eq!(got.duration_nanos, want.duration_nanos);
+            assert_eq!(got.comments, want.comments);
+            assert_eq!(got.default_sample_type, want.default_sample_type);
+        }
+        assert!(table.profile(3).is_err());
+
+        let decoded = Table::decode(&table.encode()).unwrap();
+        assert_eq!(decoded, table);
+        assert_eq!(canonical(&decoded.profile(1).unwrap()), canonical(&heap));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let cpu = read_profile("tests/CPU.pb.gz").unwrap();
+        let once = Table::from_profiles(std::slice::from_ref(&cpu));
+        let twice = Table::from_profiles(&[cpu.clone(), cpu.clone()]);
+
+        // the second profile only adds its samples and header
+        assert_eq!(twice.strings().len(), once.strings().len());
+        assert_eq!(twice.functions().len(), once.functions().len());
+        assert_eq!(twice.locations().len(), once.locations().len());
+        assert_eq!(twice.stacks().len(), once.stacks().len());
+        assert_eq!(twice.label_sets().len(), once.label_sets().len());
+        assert_eq!(twice.samples().len(), 2 * cpu.sample.len());
+        assert_eq!(twice.profiles()[1].first_sample, cpu.sample.len() as u64);
+
+        assert!(twice.encode().len() < 2 * cpu.write_uncompressed().len());
+
+        // the columns of the values are as wide as the widest profile
+        let heap = read_profile("tests/HEAP.pb.gz").unwrap();
+        let mixed = Table::from_profiles(&[cpu.clone(), heap.clone()]);
+        assert_eq!(mixed.samples().values.len(), heap.sample_type.len().max(2));
+        assert!(mixed
+            .samples()
+            .values
+            .iter()
+            .all(|c| c.len() == cpu.sample.len() + heap.sample.len()));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let cpu = read_profile("tests/CPU.pb.gz").unwrap();
+        let mut table = Table::from_profiles(&[cpu]);
+        table.samples.stack[0] = 1 << 20;
+        assert!(table.profile(0).is_err());
+
+        let mut overflow = table.clone();
+        overflow.profiles[0].first_sample = 1;
+        overflow.profiles[0].sample_count = u64::MAX;
+        assert!(overflow.profile(0).is_err());
+
+        table.samples.labels.pop();
+        assert!(Table::decode(&table.encode()).is_err());
+        assert!(Table::decode(&[]).unwrap().is_empty());
+    }
+}
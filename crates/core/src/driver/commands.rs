@@ -0,0 +1,191 @@
+use crate::driver::settings::Settings;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use crate::report::Report;
+use regex::Regex;
+use std::io::Write;
+use std::process::{Command as Process, Stdio};
+
+// number of nodes of the graphs when the nodecount option is not set
+const GRAPH_NODE_COUNT: usize = 80;
+
+// Command is a report generated from a profile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // top with the maximum number of nodes shown, if any
+    Top(Option<usize>),
+    Tree,
+    // peek and list take the regexp of the functions shown
+    Peek(String),
+    List(String),
+    Dot,
+    Svg,
+    Flame,
+    Raw,
+    Proto,
+    Traces,
+}
+
+// COMMANDS are the names of the commands with their description.
+pub const COMMANDS: [(&str, &str); 10] = [
+    ("top", "Outputs the top entries in text form"),
+    ("tree", "Outputs a text rendering of the call graph"),
+    (
+        "peek",
+        "Outputs the callers and callees of the functions matching the regexp",
+    ),
+    (
+        "list",
+        "Outputs the annotated source of the functions matching the regexp",
+    ),
+    ("dot", "Outputs the call graph in the graphviz dot format"),
+    (
+        "svg",
+        "Outputs the call graph as a SVG image (requires graphviz)",
+    ),
+    (
+        "flame",
+        "Outputs the folded stacks of the flame graph tools",
+    ),
+    ("raw", "Outputs a text representation of the raw profile"),
+    (
+        "proto",
+        "Outputs the profile in the compressed protobuf format",
+    ),
+    ("traces", "Outputs all the samples in text form"),
+];
+
+impl Command {
+    // parse returns the command of its name and argument, "top10" is
+    // accepted for "top" with a node count.
+    pub fn parse(name: &str, arg: &str) -> Result<Command, RockError> {
+        let needs_arg = |cmd: fn(String) -> Command| {
+            if arg.is_empty() {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("{} requires a regexp argument", name),
+                });
+            }
+            Ok(cmd(arg.to_string()))
+        };
+
+        match name {
+            "tree" => Ok(Command::Tree),
+            "peek" => needs_arg(Command::Peek),
+            "list" => needs_arg(Command::List),
+            "dot" => Ok(Command::Dot),
+            "svg" => Ok(Command::Svg),
+            "flame" => Ok(Command::Flame),
+            "raw" => Ok(Command::Raw),
+            "proto" => Ok(Command::Proto),
+            "traces" => Ok(Command::Traces),
+            _ => match name.strip_prefix("top") {
+                Some("") => Ok(Command::Top(None)),
+                Some(n) => n.parse().map(|n| Command::Top(Some(n))).map_err(|_| {
+                    RockError::InvalidArgument {
+                        reason: format!("unknown command {}", name),
+                    }
+                }),
+                None => Err(RockError::InvalidArgument {
+                    reason: format!("unknown command {}", name),
+                }),
+            },
+        }
+    }
+}
+
+// generate writes the report of the command for the profile, filtered and
+// aggregated following the settings.
+pub fn generate<W: Write>(
+    p: &Profile,
+    settings: &Settings,
+    cmd: &Command,
+    mut w: W,
+) -> Result<(), RockError> {
+    let p = settings.filter(p)?;
+    match cmd {
+        Command::Raw => return Ok(w.write_all(p.to_string().as_bytes())?),
+        Command::Proto => return Ok(p.write(w)?),
+        _ => {}
+    }
+
+    let mut options = settings.report_options(&p)?;
+    match cmd {
+        Command::Top(Some(n)) => options.node_count = Some(*n),
+        Command::Dot | Command::Svg if options.node_count.is_none() => {
+            options.node_count = Some(GRAPH_NODE_COUNT)
+        }
+        _ => {}
+    }
+    let r = Report::new(&p, options)?;
+
+    match cmd {
+        Command::Top(_) => r.top(w)?,
+        Command::Tree => r.tree(w)?,
+        Command::Peek(re) => r.peek(w, &compile(re)?)?,
+        Command::List(re) => r.list(w, &compile(re)?)?,
+        Command::Dot => r.dot(w)?,
+        Command::Svg => {
+            let mut dot = vec![];
+            r.dot(&mut dot)?;
+            w.write_all(&svg(&dot)?)?;
+        }
+        Command::Flame => r.folded(w)?,
+        Command::Traces => r.traces(w)?,
+        Command::Raw | Command::Proto => unreachable!(),
+    }
+    Ok(())
+}
+
+fn compile(re: &str) -> Result<Regex, RockError> {
+    Regex::new(re).map_err(|e| RockError::InvalidFilter {
+        reason: e.to_string(),
+    })
+}
+
+// svg renders the graph with the dot command of graphviz.
+pub(crate) fn svg(dot: &[u8]) -> Result<Vec<u8>, RockError> {
+    let mut child = Process::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| RockError::IoFailed {
+            reason: format!("failed to execute dot, is graphviz installed? {}", e),
+        })?;
+
+    let mut stdin = child.stdin.take().ok_or_else(|| RockError::IoFailed {
+        reason: String::from("failed to open the standard input of dot"),
+    })?;
+    stdin.write_all(dot)?;
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(RockError::IoFailed {
+            reason: format!(
+                "dot failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::commands::Command;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("top", "").unwrap(), Command::Top(None));
+        assert_eq!(Command::parse("top10", "").unwrap(), Command::Top(Some(10)));
+        assert_eq!(
+            Command::parse("list", "main").unwrap(),
+            Command::List(String::from("main"))
+        );
+        assert!(Command::parse("list", "").is_err());
+        assert!(Command::parse("topx", "").is_err());
+        assert!(Command::parse("web", "").is_err());
+    }
+}
@@ -0,0 +1,450 @@
+use crate::driver::read_profile;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// prefix of the profiles served by net/http/pprof
+pub const PPROF_PATH: &str = "/debug/pprof";
+
+// time added to the duration of the profile to wait for the response
+const TIMEOUT_MARGIN: Duration = Duration::from_secs(60);
+// duration of the CPU profiles when the seconds are not given
+const DEFAULT_SECONDS: u64 = 30;
+
+// Endpoint is a profile served by net/http/pprof.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Profile,
+    Heap,
+    Allocs,
+    Mutex,
+    Block,
+    Goroutine,
+}
+
+pub const ENDPOINTS: [Endpoint; 6] = [
+    Endpoint::Profile,
+    Endpoint::Heap,
+    Endpoint::Allocs,
+    Endpoint::Mutex,
+    Endpoint::Block,
+    Endpoint::Goroutine,
+];
+
+impl Endpoint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Endpoint::Profile => "profile",
+            Endpoint::Heap => "heap",
+            Endpoint::Allocs => "allocs",
+            Endpoint::Mutex => "mutex",
+            Endpoint::Block => "block",
+            Endpoint::Goroutine => "goroutine",
+        }
+    }
+
+    // url returns the address of the endpoint on the host (host:port or
+    // scheme://host:port). The seconds are given to the CPU profile, and to
+    // the others to get the delta of the period.
+    pub fn url(&self, host: &str, seconds: Option<u64>) -> String {
+        let host = host.trim_end_matches('/');
+        let mut url = if host.contains("://") {
+            format!("{}{}/{}", host, PPROF_PATH, self.name())
+        } else {
+            format!("http://{}{}/{}", host, PPROF_PATH, self.name())
+        };
+        if let Some(seconds) = seconds {
+            url.push_str(&format!("?seconds={}", seconds));
+        }
+        url
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = RockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ENDPOINTS
+            .iter()
+            .find(|e| e.name() == s)
+            .copied()
+            .ok_or_else(|| RockError::InvalidArgument {
+                reason: format!("unknown pprof endpoint {}", s),
+            })
+    }
+}
+
+// Options of the fetch of a profile.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // duration of the profile
+    pub seconds: Option<u64>,
+    // limit of the request, the duration of the profile and a minute by
+    // default
+    pub timeout: Option<Duration>,
+    // directory of the fetched profiles, $PPROF_TMPDIR or $HOME/pprof by
+    // default
+    pub directory: Option<PathBuf>,
+}
+
+impl Options {
+    fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or_else(|| {
+            Duration::from_secs(self.seconds.unwrap_or(DEFAULT_SECONDS)) + TIMEOUT_MARGIN
+        })
+    }
+
+    fn directory(&self) -> PathBuf {
+        if let Some(dir) = &self.directory {
+            return dir.clone();
+        }
+        match env::var_os("PPROF_TMPDIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(env::temp_dir)
+                .join("pprof"),
+        }
+    }
+}
+
+// is_url reports whether the source of a profile is to be fetched: an URL or,
+// when no such file exists, a host:port with an optional path.
+pub fn is_url(source: &str) -> bool {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return true;
+    }
+    if Path::new(source).exists() {
+        return false;
+    }
+    let host = source.split('/').next().unwrap_or_default();
+    match host.rsplit_once(':') {
+        Some((name, port)) => {
+            !name.contains(':') && !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+// profile_url returns the URL of the profile named by the source like pprof:
+// the scheme defaults to http, the path to the CPU profile and a path
+// naming an endpoint (host:port/heap) is one of net/http/pprof.
+pub fn profile_url(source: &str, seconds: Option<u64>) -> Result<String, RockError> {
+    let (scheme, rest) = match source.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest),
+        None => ("http", source),
+    };
+    if scheme != "http" && scheme != "https" {
+        return Err(RockError::InvalidArgument {
+            reason: format!("unsupported scheme {} of {}", scheme, source),
+        });
+    }
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if host.is_empty() {
+        return Err(RockError::InvalidArgument {
+            reason: format!("no host in {}", source),
+        });
+    }
+
+    let host = format!("{}://{}", scheme, host);
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let path = path.trim_end_matches('/');
+    if path.is_empty() {
+        return Ok(Endpoint::Profile.url(&host, seconds));
+    }
+    if let Ok(endpoint) = path.trim_start_matches('/').parse::<Endpoint>() {
+        return Ok(endpoint.url(&host, seconds));
+    }
+
+    let mut url = format!("{}{}", host, path);
+    let mut params: Vec<String> = query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .filter(|p| !p.is_empty())
+        // the seconds of the source are replaced by those given
+        .filter(|p| seconds.is_none() || !p.starts_with("seconds="))
+        .map(String::from)
+        .collect();
+    if let Some(seconds) = seconds {
+        params.push(format!("seconds={}", seconds));
+    }
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+    Ok(url)
+}
+
+// fetch returns the body of the response to a GET of the URL. The statuses
+// other than 200 are HttpFailed errors, with the message of the server.
+pub fn fetch(url: &str, timeout: Duration) -> Result<Vec<u8>, RockError> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => {
+            // the errors of net/http/pprof are text, the pages of the
+            // other servers are not worth printing
+            let text = response.status_text().to_string();
+            let plain = response.content_type() == "text/plain";
+            let body = response.into_string().unwrap_or_default();
+            let mut reason = format!("{}: server response: {} {}", url, status, text);
+            if plain && !body.trim().is_empty() {
+                reason.push_str(&format!(" - {}", body.trim()));
+            }
+            return Err(RockError::HttpFailed { reason });
+        }
+        Err(ureq::Error::Transport(t)) => {
+            if timed_out(&t) {
+                return Err(RockError::FetchTimedOut {
+                    reason: format!("{}: no response after {:?}", url, timeout),
+                });
+            }
+            // the message of the transport errors holds the URL
+            return Err(RockError::HttpFailed {
+                reason: t.to_string(),
+            });
+        }
+    };
+
+    let mut body = vec![];
+    match response.into_reader().read_to_end(&mut body) {
+        Ok(_) => Ok(body),
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => Err(RockError::FetchTimedOut {
+            reason: format!("{}: incomplete response after {:?}", url, timeout),
+        }),
+        Err(e) => Err(RockError::HttpFailed {
+            reason: format!("{}: {}", url, e),
+        }),
+    }
+}
+
+// timed_out reports whether the transport error is caused by a timeout.
+fn timed_out(t: &ureq::Transport) -> bool {
+    let mut source = t.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+// save writes the fetched profile to a new file of the directory, named
+// like those of pprof: pprof.<host>.<endpoint>.<NNN>.pb.gz.
+pub fn save(data: &[u8], directory: &Path, url: &str) -> Result<PathBuf, RockError> {
+    fs::create_dir_all(directory).map_err(|e| RockError::IoFailed {
+        reason: format!("{}: {}", directory.display(), e),
+    })?;
+
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = path.split('?').next().unwrap_or_default();
+    let name = path
+        .rsplit('/')
+        .find(|s| !s.is_empty())
+        .unwrap_or(Endpoint::Profile.name());
+    let prefix = format!("pprof.{}.{}", sanitize(host), sanitize(name));
+
+    for n in 1.. {
+        let file = directory.join(format!("{}.{:03}.pb.gz", prefix, n));
+        match OpenOptions::new().write(true).create_new(true).open(&file) {
+            Ok(mut f) => {
+                f.write_all(data)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(RockError::IoFailed {
+                    reason: format!("{}: {}", file.display(), e),
+                })
+            }
+        }
+    }
+    unreachable!()
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// fetch_profile fetches the profile of the source (see profile_url), saves
+// the raw response to the profile directory and decodes it. The path of the
+// saved profile is returned with it.
+pub fn fetch_profile(source: &str, options: &Options) -> Result<(Profile, PathBuf), RockError> {
+    let url = profile_url(source, options.seconds)?;
+    let data = fetch(&url, options.timeout())?;
+    let file = save(&data, &options.directory(), &url)?;
+    let p = read_profile(&file.to_string_lossy())?;
+    Ok((p, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::fetch::{
+        fetch, fetch_profile, is_url, profile_url, save, Endpoint, Options,
+    };
+    use crate::profile::errors::RockError;
+    use std::fs;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use tiny_http::{Response, Server};
+
+    // serve answers the requests of the stub server with the profile on the
+    // CPU profile endpoint and 404 otherwise, sending the URLs requested.
+    fn serve() -> (String, mpsc::Receiver<String>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let url = request.url().to_string();
+                tx.send(url.clone()).unwrap();
+                if url.starts_with("/debug/pprof/profile") {
+                    let data = fs::read("tests/CPU.pb.gz").unwrap();
+                    request.respond(Response::from_data(data)).unwrap();
+                } else {
+                    let response = Response::from_string("Unknown profile").with_status_code(404);
+                    request.respond(response).unwrap();
+                }
+            }
+        });
+        (address, rx)
+    }
+
+    #[test]
+    fn test_profile_url() {
+        let cases = [
+            (
+                "localhost:6060",
+                None,
+                "http://localhost:6060/debug/pprof/profile",
+            ),
+            (
+                "localhost:6060",
+                Some(5),
+                "http://localhost:6060/debug/pprof/profile?seconds=5",
+            ),
+            (
+                "localhost:6060/heap",
+                None,
+                "http://localhost:6060/debug/pprof/heap",
+            ),
+            (
+                "https://host/debug/pprof/block?debug=0",
+                Some(10),
+                "https://host/debug/pprof/block?debug=0&seconds=10",
+            ),
+            (
+                "http://host:8080/custom/profile?seconds=1",
+                Some(2),
+                "http://host:8080/custom/profile?seconds=2",
+            ),
+        ];
+        for (source, seconds, url) in cases {
+            assert_eq!(profile_url(source, seconds).unwrap(), url);
+        }
+        assert!(profile_url("ftp://host", None).is_err());
+
+        assert_eq!(
+            Endpoint::Goroutine.url("host:6060", None),
+            "http://host:6060/debug/pprof/goroutine"
+        );
+        assert_eq!("mutex".parse::<Endpoint>().unwrap(), Endpoint::Mutex);
+
+        assert!(is_url("localhost:6060"));
+        assert!(is_url("http://localhost/debug/pprof/heap"));
+        assert!(!is_url("tests/CPU.pb.gz"));
+        assert!(!is_url("profile.pb.gz"));
+    }
+
+    #[test]
+    fn test_fetch_profile() {
+        let (address, requests) = serve();
+        let dir = std::env::temp_dir().join(format!("rock-fetch-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let options = Options {
+            seconds: Some(1),
+            timeout: Some(Duration::from_secs(10)),
+            directory: Some(dir.clone()),
+        };
+
+        let (p, file) = fetch_profile(&address, &options).unwrap();
+        assert_eq!(requests.recv().unwrap(), "/debug/pprof/profile?seconds=1");
+        assert_eq!(p.sample.len(), 107);
+        let name = format!(
+            "pprof.{}.profile.001.pb.gz",
+            address.replace(['.', ':'], "_")
+        );
+        assert_eq!(file, dir.join(name));
+        assert_eq!(
+            fs::read(&file).unwrap(),
+            fs::read("tests/CPU.pb.gz").unwrap()
+        );
+
+        // the saved profiles are not overwritten
+        let (_, file) = fetch_profile(&address, &options).unwrap();
+        assert!(file.to_string_lossy().ends_with(".profile.002.pb.gz"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fetch_errors() {
+        let (address, _requests) = serve();
+        let url = Endpoint::Heap.url(&address, None);
+        match fetch(&url, Duration::from_secs(10)) {
+            Err(RockError::HttpFailed { reason }) => {
+                assert!(reason.ends_with("server response: 404 Not Found - Unknown profile"))
+            }
+            r => panic!("unexpected result {:?}", r.map(|d| d.len())),
+        }
+
+        // a server which never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = vec![];
+                let _ = stream.read_to_end(&mut buf);
+            }
+        });
+        let url = Endpoint::Profile.url(&address, Some(1));
+        assert!(matches!(
+            fetch(&url, Duration::from_millis(200)),
+            Err(RockError::FetchTimedOut { .. })
+        ));
+
+        let dir = std::env::temp_dir().join(format!("rock-fetch-save-test-{}", std::process::id()));
+        let file = save(b"data", &dir, "https://host/debug/pprof/heap?gc=1").unwrap();
+        assert_eq!(file, dir.join("pprof.host.heap.001.pb.gz"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
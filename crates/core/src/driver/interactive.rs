@@ -0,0 +1,428 @@
+use crate::driver::commands::{self, Command, COMMANDS};
+use crate::driver::settings::{Settings, GRANULARITIES, OPTIONS};
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const PROMPT: &str = "(rock) ";
+// history of the commands, in the home directory
+const HISTORY_FILE: &str = ".rock_history";
+
+// commands of the shell besides the reports and the options
+const SHELL_COMMANDS: [(&str, &str); 4] = [
+    ("help", "Shows the commands and the options"),
+    ("options", "Shows the values of the options"),
+    ("reset", "Restores the options given on the command line"),
+    ("quit", "Exits the shell (also exit or Ctrl-D)"),
+];
+
+// Flow tells the shell whether to read another command.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+// Shell runs the commands of the interactive mode on a profile. The options
+// set by the commands (focus=main, lines, sample_index=alloc_space) are kept
+// for the next ones.
+#[derive(Debug)]
+pub struct Shell {
+    profile: Profile,
+    settings: Settings,
+    // settings of the command line, restored by reset
+    initial: Settings,
+}
+
+impl Shell {
+    pub fn new(profile: Profile, settings: Settings) -> Shell {
+        Shell {
+            profile,
+            initial: settings.clone(),
+            settings,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    // execute runs a command line of the shell:
+    // name=value, or name for the granularities and booleans, sets an option
+    // top10 main -runtime generates a report, the extra arguments of top,
+    //     tree, dot, svg, flame, raw and traces focus on (or ignore, with a
+    //     leading '-') the matching functions for this report only
+    // list regexp, peek regexp show the matching functions
+    // any report followed by > file is written to the file
+    pub fn execute<W: Write>(&mut self, line: &str, mut out: W) -> Result<Flow, RockError> {
+        let (words, redirect) = split_redirect(line)?;
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Ok(Flow::Continue),
+        };
+
+        match name {
+            "quit" | "exit" => return Ok(Flow::Quit),
+            "help" | "?" => {
+                write_help(&mut out)?;
+                return Ok(Flow::Continue);
+            }
+            "options" => {
+                for (name, value) in self.settings.values() {
+                    writeln!(out, "  {:<14} = {}", name, value)?;
+                }
+                return Ok(Flow::Continue);
+            }
+            "reset" => {
+                self.settings = self.initial.clone();
+                return Ok(Flow::Continue);
+            }
+            _ => {}
+        }
+
+        if let Some((option, _)) = name.split_once('=') {
+            let value = line.split_once('=').map_or("", |(_, v)| v.trim());
+            self.settings.set(option, value)?;
+            return Ok(Flow::Continue);
+        }
+        if OPTIONS.contains(&name) || GRANULARITIES.iter().any(|(g, _)| *g == name) {
+            return match args {
+                [] => self.settings.set(name, "true").map(|_| Flow::Continue),
+                [value] => self.settings.set(name, value).map(|_| Flow::Continue),
+                _ => Err(RockError::InvalidArgument {
+                    reason: format!("too many values for option {}", name),
+                }),
+            };
+        }
+
+        let mut settings = self.settings.clone();
+        let cmd = match name {
+            "peek" | "list" => Command::parse(name, &args.join(" "))?,
+            _ => {
+                for arg in args {
+                    match arg.strip_prefix('-') {
+                        Some(ignore) => settings.set("ignore", &join(&settings.ignore, ignore))?,
+                        None => settings.set("focus", &join(&settings.focus, arg))?,
+                    }
+                }
+                Command::parse(name, "")?
+            }
+        };
+
+        let file = redirect.or_else(|| Some(settings.output.clone()).filter(|o| !o.is_empty()));
+        match file {
+            Some(file) => {
+                let mut w = io::BufWriter::new(fs::File::create(&file)?);
+                commands::generate(&self.profile, &settings, &cmd, &mut w)?;
+                w.flush()?;
+                writeln!(out, "Generated {}", file)?;
+            }
+            None if cmd == Command::Proto => {
+                return Err(RockError::InvalidArgument {
+                    reason: String::from("proto needs an output file: proto > profile.pb.gz"),
+                })
+            }
+            None => commands::generate(&self.profile, &settings, &cmd, &mut out)?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    // completions returns the words completed at the start of a line.
+    fn completions(&self) -> Vec<String> {
+        let mut words: Vec<String> = COMMANDS
+            .iter()
+            .chain(SHELL_COMMANDS.iter())
+            .map(|(name, _)| name.to_string())
+            .chain(OPTIONS.iter().map(|name| name.to_string()))
+            .chain(GRANULARITIES.iter().map(|(name, _)| name.to_string()))
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+
+    // functions returns the names of the functions of the profile, escaped
+    // to be completed in regexps.
+    fn functions(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .profile
+            .function
+            .iter()
+            .filter(|f| !f.name.is_empty())
+            .map(|f| regex::escape(&f.name))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// run reads the commands from the terminal until quit or the end of the
+// input, with the history of the previous sessions.
+pub fn run(profile: Profile, settings: Settings) -> Result<(), RockError> {
+    let mut shell = Shell::new(profile, settings);
+    let mut editor: Editor<Completion, FileHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(Completion {
+        words: shell.completions(),
+        functions: shell.functions(),
+    }));
+
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(history) = history.as_ref() {
+        // there is no history on the first run
+        let _ = editor.load_history(history);
+    }
+
+    println!("Entering interactive mode (type \"help\" for commands, \"quit\" to exit)");
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e)),
+        };
+        if !line.trim().is_empty() {
+            editor
+                .add_history_entry(line.as_str())
+                .map_err(readline_error)?;
+        }
+
+        match shell.execute(&line, io::stdout().lock()) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(history) = history.as_ref() {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("failed to save the history to {}: {}", history.display(), e);
+        }
+    }
+    Ok(())
+}
+
+fn readline_error(e: ReadlineError) -> RockError {
+    RockError::IoFailed {
+        reason: e.to_string(),
+    }
+}
+
+// split_redirect splits a command line into its words and the file of a
+// trailing "> file" redirection, if any. A '>' inside a word, as in the
+// generic function names of Rust or C++, is not a redirection.
+fn split_redirect(line: &str) -> Result<(Vec<&str>, Option<String>), RockError> {
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [.., ">", file] => {
+            let file = file.to_string();
+            words.truncate(words.len() - 2);
+            Ok((words, Some(file)))
+        }
+        [.., ">"] => Err(RockError::InvalidArgument {
+            reason: format!("expected a file after >: {}", line.trim()),
+        }),
+        _ => Ok((words, None)),
+    }
+}
+
+// join adds a regexp to the one of an option as an alternative.
+fn join(current: &str, re: &str) -> String {
+    if current.is_empty() {
+        return re.to_string();
+    }
+    format!("{}|{}", current, re)
+}
+
+fn write_help<W: Write>(w: &mut W) -> io::Result<()> {
+    writeln!(w, "Commands:")?;
+    for (name, help) in COMMANDS.iter().chain(SHELL_COMMANDS.iter()) {
+        writeln!(w, "  {:<14} {}", name, help)?;
+    }
+    writeln!(w)?;
+    writeln!(
+        w,
+        "  top, tree, dot, svg, flame, raw and traces accept regexps to focus on"
+    )?;
+    writeln!(
+        w,
+        "  (or ignore with a leading '-'), a report followed by > file is written"
+    )?;
+    writeln!(w, "  to the file. topN shows the N first entries.")?;
+    writeln!(w)?;
+    writeln!(w, "Options (name=value):")?;
+    writeln!(w, "  {}", OPTIONS.join(" "))?;
+    writeln!(w)?;
+    writeln!(w, "Granularities:")?;
+    let granularities: Vec<&str> = GRANULARITIES.iter().map(|(name, _)| *name).collect();
+    writeln!(w, "  {}", granularities.join(" "))
+}
+
+// Completion completes the commands and options at the start of a line and
+// the function names in their arguments.
+#[derive(Debug)]
+struct Completion {
+    words: Vec<String>,
+    functions: Vec<String>,
+}
+
+impl Completion {
+    // candidates returns the start of the completed word and its completions.
+    fn candidates(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line
+            .rfind(|c: char| c.is_whitespace() || c == '=')
+            .map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let words = if start == 0 {
+            &self.words
+        } else {
+            &self.functions
+        };
+        let candidates = words
+            .iter()
+            .filter(|w| w.starts_with(word))
+            .cloned()
+            .collect();
+        (start, candidates)
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::interactive::{split_redirect, Completion, Flow, Shell};
+    use crate::driver::read_profile;
+    use crate::driver::settings::Settings;
+    use crate::report::Granularity;
+
+    fn shell() -> Shell {
+        Shell::new(
+            read_profile("tests/CPU.pb.gz").unwrap(),
+            Settings::default(),
+        )
+    }
+
+    fn execute(shell: &mut Shell, line: &str) -> String {
+        let mut out = vec![];
+        assert_eq!(shell.execute(line, &mut out).unwrap(), Flow::Continue);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_options() {
+        let mut s = shell();
+        execute(&mut s, "lines");
+        execute(&mut s, "focus=main\\.");
+        execute(&mut s, "nodecount 3");
+        assert_eq!(s.settings().granularity, Granularity::Lines);
+        assert_eq!(s.settings().focus, "main\\.");
+        assert_eq!(s.settings().node_count, Some(3));
+        assert!(execute(&mut s, "options").contains("  focus          = main\\."));
+
+        execute(&mut s, "reset");
+        assert_eq!(s.settings().granularity, Granularity::Functions);
+        assert!(s.settings().focus.is_empty());
+
+        let mut out = vec![];
+        assert!(s.execute("sample_index=missing", &mut out).is_ok());
+        assert!(s.execute("nodecount=x", &mut out).is_err());
+        assert!(s.execute("web", &mut out).is_err());
+        assert_eq!(s.execute("quit", &mut out).unwrap(), Flow::Quit);
+    }
+
+    #[test]
+    fn test_reports() {
+        let mut s = shell();
+        let top = execute(&mut s, "top5");
+        assert!(top.contains("Showing top 5 nodes out of"));
+        assert_eq!(
+            top.lines().count(),
+            top.lines().position(|l| l.contains("flat%")).unwrap() + 6
+        );
+
+        // the focus of top only applies to this report
+        let top = execute(&mut s, "top main.main -runtime");
+        assert!(top.lines().any(|l| l.ends_with("main.main.func2")));
+        assert!(!top.lines().any(|l| l.ends_with("runtime.memmove")));
+        assert!(s.settings().focus.is_empty());
+
+        let list = execute(&mut s, "list main.main.func2");
+        assert!(list.starts_with("Total: "));
+
+        let mut out = vec![];
+        assert!(s.execute("proto", &mut out).is_err());
+        let file = std::env::temp_dir().join(format!(
+            "rock-interactive-test-{}.pb.gz",
+            std::process::id()
+        ));
+        let out = execute(&mut s, &format!("proto > {}", file.display()));
+        assert!(out.starts_with("Generated "));
+        assert!(std::fs::metadata(&file).unwrap().len() > 0);
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn test_split_redirect() {
+        let (words, file) = split_redirect("top main > out.txt").unwrap();
+        assert_eq!(words, vec!["top", "main"]);
+        assert_eq!(file.as_deref(), Some("out.txt"));
+
+        let (words, file) = split_redirect("list std::vec::Vec<T>::push").unwrap();
+        assert_eq!(words, vec!["list", "std::vec::Vec<T>::push"]);
+        assert_eq!(file, None);
+
+        let (words, file) = split_redirect("peek Option<Vec<u8>> > out.txt").unwrap();
+        assert_eq!(words, vec!["peek", "Option<Vec<u8>>"]);
+        assert_eq!(file.as_deref(), Some("out.txt"));
+
+        assert!(split_redirect("top >").is_err());
+    }
+
+    #[test]
+    fn test_completion() {
+        let s = shell();
+        let c = Completion {
+            words: s.completions(),
+            functions: s.functions(),
+        };
+        assert_eq!(c.candidates("to"), (0, vec![String::from("top")]));
+        assert_eq!(c.candidates("gran").1, vec![String::from("granularity")]);
+
+        let (start, candidates) = c.candidates("list main\\.main");
+        assert_eq!(start, 5);
+        assert!(candidates.contains(&String::from("main\\.main\\.func2")));
+        assert_eq!(c.candidates("focus=main\\.main\\.f").0, 6);
+    }
+}
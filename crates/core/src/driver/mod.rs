@@ -1 +1,510 @@
+// Package driver implements the rock command line, compatible with the one
+// of pprof to replace `go tool pprof` where there is no Go toolchain:
+//
+//     rock [options] [-base profile | -diff_base profile] profile...
+//
+// settings -- the options of the reports and the filters of the samples
+// commands -- the report commands and the generation of their output
+// interactive -- the shell started when no output format is given
+// webui -- the web interface served with -http
+// fetch -- the profiles fetched from the /debug/pprof handlers of Go programs
+//
+// The profiles are read in any format recognized by the crate (protobuf,
+// legacy text, chrome cpuprofile, perf script or perf.data), or fetched
+// from URLs and host:port sources, and merged.
+// Like pprof, the flags are accepted with a single dash (-top) as well as
+// with two (--top).
 
+pub mod commands;
+pub mod fetch;
+pub mod interactive;
+pub mod settings;
+pub mod webui;
+
+use crate::chrome;
+use crate::perf;
+use crate::profile::buffer::{Buffer, Decoder};
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use crate::report::BASE_LABEL;
+use crate::symbolizer::{self, store::SymbolStore};
+use clap::error::ErrorKind;
+use clap::{Arg, ArgAction, ArgMatches};
+use commands::{Command, COMMANDS};
+use settings::{Settings, GRANULARITIES};
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+// main runs the command line and returns the exit code of the process.
+pub fn main(args: Vec<String>) -> i32 {
+    let stdout = io::stdout();
+    match run(args, stdout.lock()) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+// run parses the command line arguments (the program name first) and writes
+// the requested report to the output, or to the file of the -output flag.
+// Without an output format, the interactive shell or the web interface
+// (-http) is started.
+pub fn run<W: Write>(args: Vec<String>, mut out: W) -> Result<(), RockError> {
+    let matches = match cli().try_get_matches_from(normalize_args(args)) {
+        Ok(m) => m,
+        Err(e) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => {
+            return Ok(write!(out, "{}", e)?);
+        }
+        Err(e) => {
+            return Err(RockError::InvalidArgument {
+                reason: e.to_string(),
+            })
+        }
+    };
+
+    let settings = settings(&matches)?;
+    let cmd = command(&matches)?;
+    let p = load(&matches)?;
+    let cmd = match (cmd, matches.get_one::<String>("http")) {
+        (Some(_), Some(_)) => {
+            return Err(RockError::InvalidArgument {
+                reason: String::from("-http cannot be used with an output format"),
+            })
+        }
+        (None, Some(address)) => return webui::serve(address, p, settings),
+        (None, None) => return interactive::run(p, settings),
+        (Some(cmd), None) => cmd,
+    };
+
+    if settings.output.is_empty() {
+        return commands::generate(&p, &settings, &cmd, out);
+    }
+    let mut w = io::BufWriter::new(fs::File::create(&settings.output)?);
+    commands::generate(&p, &settings, &cmd, &mut w)?;
+    Ok(w.flush()?)
+}
+
+// normalize_args turns the pprof style flags (-top, -nodecount=10) into the
+// long flags of clap (--top, --nodecount=10).
+fn normalize_args(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let single_dash = arg.len() > 2
+                && arg.starts_with('-')
+                && !arg.starts_with("--")
+                && !arg[1..].starts_with(|c: char| c.is_ascii_digit());
+            if i > 0 && single_dash {
+                return format!("-{}", arg);
+            }
+            arg
+        })
+        .collect()
+}
+
+fn cli() -> clap::Command<'static> {
+    let flag = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .help(help)
+            .action(ArgAction::SetTrue)
+    };
+    let option = |name: &'static str, value: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .value_name(value)
+            .help(help)
+            .takes_value(true)
+    };
+
+    let mut cmd = clap::Command::new("rock")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Analyzes profiles, with the command line of pprof")
+        .arg(
+            Arg::new("profile")
+                .help("Profiles to analyze, merged into one")
+                .required(true)
+                .multiple_values(true),
+        );
+
+    for (name, help) in COMMANDS {
+        cmd = match name {
+            "peek" | "list" => cmd.arg(option(name, "regexp", help)),
+            _ => cmd.arg(flag(name, help)),
+        };
+    }
+
+    cmd.arg(option("output", "file", "Write the report to the file"))
+        .arg(option(
+            "http",
+            "host:port",
+            "Serves a web interface on the address (:8080 for localhost)",
+        ))
+        .arg(option(
+            "sample_index",
+            "index",
+            "Sample value to report, by index or type",
+        ))
+        .arg(flag(
+            "functions",
+            "Aggregate at the function level (default)",
+        ))
+        .arg(flag(
+            "filefunctions",
+            "Aggregate at the function and file level",
+        ))
+        .arg(flag("files", "Aggregate at the file level"))
+        .arg(flag("lines", "Aggregate at the source code line level"))
+        .arg(flag("addresses", "Aggregate at the address level"))
+        .arg(option(
+            "focus",
+            "regexp",
+            "Restricts to samples going through a node matching the regexp",
+        ))
+        .arg(option(
+            "ignore",
+            "regexp",
+            "Skips samples going through a node matching the regexp",
+        ))
+        .arg(option(
+            "hide",
+            "regexp",
+            "Skips the nodes matching the regexp",
+        ))
+        .arg(option(
+            "show",
+            "regexp",
+            "Only shows the nodes matching the regexp",
+        ))
+        .arg(option(
+            "show_from",
+            "regexp",
+            "Drops the functions above the first one matching the regexp",
+        ))
+        .arg(option(
+            "tagfocus",
+            "filter",
+            "Restricts to samples with a label matching the filter",
+        ))
+        .arg(option(
+            "tagignore",
+            "filter",
+            "Skips samples with a label matching the filter",
+        ))
+        .arg(option("nodecount", "n", "Maximum number of nodes to show"))
+        .arg(option("nodefraction", "f", "Hides the nodes below f*total"))
+        .arg(option("edgefraction", "f", "Hides the edges below f*total"))
+        .arg(flag("cum", "Sorts the entries by their cumulative value"))
+        .arg(option(
+            "base",
+            "profile",
+            "Subtracts the profile from the profiles to analyze",
+        ))
+        .arg(
+            option(
+                "diff_base",
+                "profile",
+                "Compares the profiles to analyze with the profile",
+            )
+            .conflicts_with("base"),
+        )
+        .arg(option(
+            "seconds",
+            "n",
+            "Duration of the profiles fetched from URLs",
+        ))
+        .arg(option(
+            "timeout",
+            "n",
+            "Timeout in seconds of the fetch of the profiles",
+        ))
+        .arg(
+            option(
+                "symbolize",
+                "mode",
+                "Symbolization of the profiles: local or none",
+            )
+            .default_value("local"),
+        )
+        .arg(
+            option(
+                "demangle",
+                "mode",
+                "Demangling of the symbols: none, full or simplified",
+            )
+            .default_value("simplified"),
+        )
+}
+
+fn settings(m: &ArgMatches) -> Result<Settings, RockError> {
+    let mut s = Settings::default();
+    for (name, _) in GRANULARITIES {
+        if m.get_flag(name) {
+            s.set(name, "true")?;
+        }
+    }
+    if m.get_flag("cum") {
+        s.set("cum", "true")?;
+    }
+    for name in settings::OPTIONS {
+        if let Some(value) = m.try_get_one::<String>(name).ok().flatten() {
+            s.set(name, value)?;
+        }
+    }
+    Ok(s)
+}
+
+// command returns the output format of the command line, if any.
+fn command(m: &ArgMatches) -> Result<Option<Command>, RockError> {
+    let mut cmds = vec![];
+    for (name, _) in COMMANDS {
+        match name {
+            "peek" | "list" => {
+                if let Some(re) = m.get_one::<String>(name) {
+                    cmds.push(Command::parse(name, re)?);
+                }
+            }
+            _ if m.get_flag(name) => cmds.push(Command::parse(name, "")?),
+            _ => {}
+        }
+    }
+
+    match cmds.len() {
+        0 => Ok(None),
+        1 => Ok(Some(cmds.remove(0))),
+        _ => Err(RockError::InvalidArgument {
+            reason: String::from("only one output format can be specified"),
+        }),
+    }
+}
+
+// load reads, merges and symbolizes the profiles of the command line,
+// subtracting the -base or -diff_base profile.
+fn load(m: &ArgMatches) -> Result<Profile, RockError> {
+    let sources: Vec<&String> = m
+        .get_many::<String>("profile")
+        .into_iter()
+        .flatten()
+        .collect();
+    let fetch = fetch_options(m)?;
+    let mut profiles = sources
+        .iter()
+        .map(|source| read_source(source, &fetch))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let base = m.get_one::<String>("base").map(|b| (b, false));
+    let diff_base = m.get_one::<String>("diff_base").map(|b| (b, true));
+    if let Some((path, diff)) = base.or(diff_base) {
+        let mut b = read_source(path, &fetch)?;
+        b.scale_n(&vec![-1.0; b.sample_type.len()])?;
+        if diff {
+            for s in b.sample.iter_mut() {
+                s.label
+                    .insert(BASE_LABEL.to_string(), vec![String::from("true")]);
+            }
+        }
+        profiles.push(b);
+    }
+
+    let mut p = if profiles.len() == 1 {
+        profiles.remove(0)
+    } else {
+        Profile::merge(&profiles)?
+    };
+
+    let mode = m
+        .get_one::<String>("symbolize")
+        .map_or("local", |s| s.as_str());
+    match mode {
+        "none" => {}
+        "local" => {
+            let opts = symbolizer::Options {
+                store: SymbolStore::from_env(),
+                demangle: m
+                    .get_one::<String>("demangle")
+                    .map_or("", |s| s.as_str())
+                    .parse()?,
+            };
+            symbolizer::symbolize(&mut p, &opts)?;
+        }
+        _ => {
+            return Err(RockError::InvalidArgument {
+                reason: format!("unknown symbolize mode {:?}, expected local or none", mode),
+            })
+        }
+    }
+    Ok(p)
+}
+
+// read_source reads the profile of a file, or fetches it if the source is
+// an URL.
+fn read_source(source: &str, options: &fetch::Options) -> Result<Profile, RockError> {
+    if !fetch::is_url(source) {
+        return read_profile(source);
+    }
+    eprintln!(
+        "Fetching profile over HTTP from {}",
+        fetch::profile_url(source, options.seconds)?
+    );
+    let (p, file) = fetch::fetch_profile(source, options)?;
+    eprintln!("Saved profile in {}", file.display());
+    Ok(p)
+}
+
+fn fetch_options(m: &ArgMatches) -> Result<fetch::Options, RockError> {
+    let seconds = |name: &str| -> Result<Option<u64>, RockError> {
+        m.get_one::<String>(name)
+            .map(|v| {
+                v.parse().map_err(|_| RockError::InvalidArgument {
+                    reason: format!("invalid -{} {}", name, v),
+                })
+            })
+            .transpose()
+    };
+    Ok(fetch::Options {
+        seconds: seconds("seconds")?,
+        timeout: seconds("timeout")?.map(Duration::from_secs),
+        directory: None,
+    })
+}
+
+// read_profile reads a profile file, detecting its format.
+pub fn read_profile(path: &str) -> Result<Profile, RockError> {
+    let mut data = fs::read(path).map_err(|e| RockError::IoFailed {
+        reason: format!("{}: {}", path, e),
+    })?;
+
+    if chrome::is_cpuprofile(&data) {
+        return chrome::parse_cpuprofile(&data);
+    }
+    if perf::data::is_perf_data(&data) {
+        return perf::data::parse_perf_data(&data);
+    }
+    if perf::script::is_perf_script(&data) {
+        return perf::script::parse_perf_script(&data);
+    }
+    Buffer::decode(&mut data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::{normalize_args, read_profile, run};
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::errors::RockError;
+
+    fn rock(args: &[&str]) -> Result<String, RockError> {
+        let mut argv = vec![String::from("rock")];
+        argv.extend(args.iter().map(|a| a.to_string()));
+        let mut out = vec![];
+        run(argv, &mut out)?;
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+
+    #[test]
+    fn test_normalize_args() {
+        let args = [
+            "rock",
+            "-top",
+            "-nodecount=10",
+            "--cum",
+            "-o",
+            "-1",
+            "cpu.pb.gz",
+        ];
+        assert_eq!(
+            normalize_args(args.iter().map(|a| a.to_string()).collect()),
+            vec![
+                "rock",
+                "--top",
+                "--nodecount=10",
+                "--cum",
+                "-o",
+                "-1",
+                "cpu.pb.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_top() {
+        let out = rock(&["-top", "-nodecount=5", "-symbolize=none", "tests/CPU.pb.gz"]).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines.contains(&"Type: cpu"));
+        assert!(lines.contains(&"      flat  flat%   sum%        cum   cum%"));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("Showing top 5 nodes out of")));
+        assert_eq!(
+            lines.len() - lines.iter().position(|l| l.contains("flat%")).unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_base() {
+        let out = rock(&[
+            "-top",
+            "-symbolize=none",
+            "-base",
+            "tests/CPU.pb.gz",
+            "tests/CPU.pb.gz",
+        ])
+        .unwrap();
+        assert!(out.contains("Showing nodes accounting for 0, 0% of 0 total"));
+
+        let out = rock(&[
+            "-top",
+            "-symbolize=none",
+            "-diff_base=tests/CPU.pb.gz",
+            "tests/CPU.pb.gz",
+        ])
+        .unwrap();
+        assert!(out.contains("Showing nodes accounting for 0, 0% of "));
+        assert!(!out.contains("of 0 total"));
+    }
+
+    #[test]
+    fn test_proto() {
+        let dir = std::env::temp_dir().join(format!("rock-driver-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("cpu.pb.gz");
+        rock(&[
+            "-proto",
+            "-symbolize=none",
+            "-output",
+            output.to_str().unwrap(),
+            "tests/CPU.pb.gz",
+        ])
+        .unwrap();
+
+        let mut data = std::fs::read(&output).unwrap();
+        let p = Buffer::decode(&mut data).unwrap();
+        let original = read_profile("tests/CPU.pb.gz").unwrap();
+        assert_eq!(p.to_string(), original.to_string());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            rock(&["-top", "-tree", "tests/CPU.pb.gz"]),
+            Err(RockError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            rock(&["-top", "-unknown", "tests/CPU.pb.gz"]),
+            Err(RockError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            rock(&["-top", "-focus=(", "tests/CPU.pb.gz"]),
+            Err(RockError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            rock(&["-top", "tests/missing.pb.gz"]),
+            Err(RockError::IoFailed { .. })
+        ));
+        assert!(rock(&["-help"]).unwrap().contains("-diff_base"));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::profile::errors::RockError;
+use crate::profile::filter::TagFilter;
+use crate::profile::Profile;
+use crate::report::{self, Granularity};
+use regex::Regex;
+
+// granularity options, with the names of the pprof flags
+pub const GRANULARITIES: [(&str, Granularity); 5] = [
+    ("functions", Granularity::Functions),
+    ("filefunctions", Granularity::FileFunctions),
+    ("files", Granularity::Files),
+    ("lines", Granularity::Lines),
+    ("addresses", Granularity::Addresses),
+];
+
+// names of the options accepted by Settings::set
+pub const OPTIONS: [&str; 15] = [
+    "sample_index",
+    "granularity",
+    "nodecount",
+    "nodefraction",
+    "edgefraction",
+    "cum",
+    "focus",
+    "ignore",
+    "hide",
+    "show",
+    "show_from",
+    "tagfocus",
+    "tagignore",
+    "output",
+    "flat",
+];
+
+// Settings are the options of the reports: the sample value, the
+// granularity, the filters applied to the samples and where the output
+// goes. Empty strings leave an option unset.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub sample_index: String,
+    pub granularity: Granularity,
+    pub node_count: Option<usize>,
+    pub node_fraction: f64,
+    pub edge_fraction: f64,
+    pub cum: bool,
+    pub focus: String,
+    pub ignore: String,
+    pub hide: String,
+    pub show: String,
+    pub show_from: String,
+    pub tag_focus: String,
+    pub tag_ignore: String,
+    // file the reports are written to, standard output if empty
+    pub output: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let o = report::Options::default();
+        Settings {
+            sample_index: String::new(),
+            granularity: o.granularity,
+            node_count: o.node_count,
+            node_fraction: o.node_fraction,
+            edge_fraction: o.edge_fraction,
+            cum: o.cum,
+            focus: String::new(),
+            ignore: String::new(),
+            hide: String::new(),
+            show: String::new(),
+            show_from: String::new(),
+            tag_focus: String::new(),
+            tag_ignore: String::new(),
+            output: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    // set sets an option from its name and value as they are written on the
+    // command line. The granularities can also be set by name, as booleans.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), RockError> {
+        if let Some((_, g)) = GRANULARITIES.iter().find(|(n, _)| *n == name) {
+            if parse_bool(name, value)? {
+                self.granularity = *g;
+            }
+            return Ok(());
+        }
+
+        match name {
+            "sample_index" => self.sample_index = value.to_string(),
+            "granularity" => {
+                self.granularity = match GRANULARITIES.iter().find(|(n, _)| *n == value) {
+                    Some((_, g)) => *g,
+                    None => return Err(invalid(name, value)),
+                }
+            }
+            "nodecount" => {
+                self.node_count = match value.parse::<i64>() {
+                    Ok(n) if n < 0 => None,
+                    Ok(n) => Some(n as usize),
+                    Err(_) => return Err(invalid(name, value)),
+                }
+            }
+            "nodefraction" => self.node_fraction = parse_fraction(name, value)?,
+            "edgefraction" => self.edge_fraction = parse_fraction(name, value)?,
+            "cum" => self.cum = parse_bool(name, value)?,
+            "flat" => self.cum = !parse_bool(name, value)?,
+            "focus" => self.focus = regex_option(name, value)?,
+            "ignore" => self.ignore = regex_option(name, value)?,
+            "hide" => self.hide = regex_option(name, value)?,
+            "show" => self.show = regex_option(name, value)?,
+            "show_from" => self.show_from = regex_option(name, value)?,
+            "tagfocus" => self.tag_focus = value.to_string(),
+            "tagignore" => self.tag_ignore = value.to_string(),
+            "output" => self.output = value.to_string(),
+            _ => {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("unknown option {}", name),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    // values returns the options with their current values, as accepted by set.
+    pub fn values(&self) -> Vec<(&'static str, String)> {
+        let granularity = GRANULARITIES
+            .iter()
+            .find(|(_, g)| *g == self.granularity)
+            .map_or("", |(n, _)| n);
+        vec![
+            ("sample_index", self.sample_index.clone()),
+            ("granularity", granularity.to_string()),
+            (
+                "nodecount",
+                self.node_count
+                    .map_or(String::from("-1"), |n| n.to_string()),
+            ),
+            ("nodefraction", self.node_fraction.to_string()),
+            ("edgefraction", self.edge_fraction.to_string()),
+            ("cum", self.cum.to_string()),
+            ("focus", self.focus.clone()),
+            ("ignore", self.ignore.clone()),
+            ("hide", self.hide.clone()),
+            ("show", self.show.clone()),
+            ("show_from", self.show_from.clone()),
+            ("tagfocus", self.tag_focus.clone()),
+            ("tagignore", self.tag_ignore.clone()),
+            ("output", self.output.clone()),
+        ]
+    }
+
+    // filter returns a copy of the profile with the samples filtered by the
+    // show_from, focus, ignore, hide, show, tagfocus and tagignore options.
+    pub fn filter(&self, p: &Profile) -> Result<Profile, RockError> {
+        let mut p = p.clone();
+
+        if let Some(re) = compile(&self.show_from)? {
+            if !p.show_from(&re) {
+                eprintln!(
+                    "show_from expression matched no samples: {}",
+                    self.show_from
+                );
+            }
+        }
+
+        let (focus, ignore) = (compile(&self.focus)?, compile(&self.ignore)?);
+        let (hide, show) = (compile(&self.hide)?, compile(&self.show)?);
+        let (fm, im, hm, hnm) = p.filter_samples_by_name(
+            focus.as_ref(),
+            ignore.as_ref(),
+            hide.as_ref(),
+            show.as_ref(),
+        );
+        for (name, value, matched) in [
+            ("focus", &self.focus, fm),
+            ("ignore", &self.ignore, im),
+            ("hide", &self.hide, hm),
+            ("show", &self.show, hnm),
+        ] {
+            if !value.is_empty() && !matched {
+                eprintln!("{} expression matched no samples: {}", name, value);
+            }
+        }
+
+        if !self.tag_focus.is_empty() || !self.tag_ignore.is_empty() {
+            let (units, _) = p.num_label_units()?;
+            let tag_filter = |value: &str| -> Result<Option<TagFilter>, RockError> {
+                if value.is_empty() {
+                    return Ok(None);
+                }
+                TagFilter::new(value, units.clone()).map(Some)
+            };
+            let (focus, ignore) = (tag_filter(&self.tag_focus)?, tag_filter(&self.tag_ignore)?);
+            let (fm, im) = p.filter_samples_by_tag(focus.as_ref(), ignore.as_ref());
+            if focus.is_some() && !fm {
+                eprintln!("tagfocus expression matched no samples: {}", self.tag_focus);
+            }
+            if ignore.is_some() && !im {
+                eprintln!(
+                    "tagignore expression matched no samples: {}",
+                    self.tag_ignore
+                );
+            }
+        }
+
+        Ok(p)
+    }
+
+    // report_options returns the options of the reports of the profile.
+    pub fn report_options(&self, p: &Profile) -> Result<report::Options, RockError> {
+        Ok(report::Options {
+            sample_index: report::sample_index(p, &self.sample_index)?,
+            granularity: self.granularity,
+            node_count: self.node_count,
+            node_fraction: self.node_fraction,
+            edge_fraction: self.edge_fraction,
+            cum: self.cum,
+        })
+    }
+}
+
+fn invalid(name: &str, value: &str) -> RockError {
+    RockError::InvalidArgument {
+        reason: format!("invalid value {:?} for option {}", value, name),
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, RockError> {
+    match value {
+        "" | "true" | "t" | "yes" | "y" | "1" => Ok(true),
+        "false" | "f" | "no" | "n" | "0" => Ok(false),
+        _ => Err(invalid(name, value)),
+    }
+}
+
+fn parse_fraction(name: &str, value: &str) -> Result<f64, RockError> {
+    match value.parse::<f64>() {
+        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err(invalid(name, value)),
+    }
+}
+
+// regex_option checks that the value of a regexp option compiles.
+fn regex_option(name: &str, value: &str) -> Result<String, RockError> {
+    compile(value).map_err(|e| RockError::InvalidArgument {
+        reason: format!("option {}: {}", name, e),
+    })?;
+    Ok(value.to_string())
+}
+
+fn compile(re: &str) -> Result<Option<Regex>, RockError> {
+    if re.is_empty() {
+        return Ok(None);
+    }
+    Regex::new(re)
+        .map(Some)
+        .map_err(|e| RockError::InvalidFilter {
+            reason: e.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::settings::Settings;
+    use crate::report::Granularity;
+
+    #[test]
+    fn test_set() {
+        let mut s = Settings::default();
+        s.set("lines", "").unwrap();
+        assert_eq!(s.granularity, Granularity::Lines);
+        s.set("granularity", "files").unwrap();
+        assert_eq!(s.granularity, Granularity::Files);
+        s.set("nodecount", "10").unwrap();
+        assert_eq!(s.node_count, Some(10));
+        s.set("nodecount", "-1").unwrap();
+        assert_eq!(s.node_count, None);
+        s.set("cum", "true").unwrap();
+        assert!(s.cum);
+        s.set("focus", "main\\.").unwrap();
+        assert_eq!(s.focus, "main\\.");
+
+        let values = s.values();
+        assert!(values.contains(&("granularity", String::from("files"))));
+        assert!(values.contains(&("nodecount", String::from("-1"))));
+        for (name, value) in values {
+            s.set(name, &value).unwrap();
+        }
+
+        assert!(s.set("focus", "(").is_err());
+        assert!(s.set("nodefraction", "2").is_err());
+        assert!(s.set("granularity", "bytes").is_err());
+        assert!(s.set("unknown", "1").is_err());
+    }
+}
@@ -0,0 +1,504 @@
+// Package webui serves the reports of a profile to a browser, started by the
+// -http flag of the command line:
+// /            -- the call graph (rendered with the dot command of graphviz)
+// /flamegraph  -- the flame graph
+// /top         -- the top table
+// /peek        -- the callers and callees of the functions matching f
+// /source      -- the annotated source of the functions matching f
+//
+// The filters and options of the reports are the query parameters of the
+// pages (focus, hide, sample_index, granularity...), the pages are rendered
+// by the server from the profile loaded in memory. The page template and
+// the stylesheet are embedded in the binary: nothing is loaded from other
+// hosts, so the UI works without internet access.
+
+use crate::driver::commands;
+use crate::driver::settings::{Settings, GRANULARITIES};
+use crate::measurement;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use crate::report::flame::escape;
+use crate::report::Report;
+use regex::Regex;
+use std::io::Cursor;
+use tiny_http::{Header, Server};
+
+const PAGE: &str = include_str!("assets/page.html");
+const STYLE: &str = include_str!("assets/style.css");
+
+// pages of the UI: path, title
+const PAGES: [(&str, &str); 5] = [
+    ("/", "Graph"),
+    ("/flamegraph", "Flame Graph"),
+    ("/top", "Top"),
+    ("/peek", "Peek"),
+    ("/source", "Source"),
+];
+
+// options of the filter controls: name, label
+const CONTROLS: [(&str, &str); 8] = [
+    ("focus", "Focus"),
+    ("ignore", "Ignore"),
+    ("hide", "Hide"),
+    ("show", "Show"),
+    ("show_from", "Show from"),
+    ("tagfocus", "Tag focus"),
+    ("tagignore", "Tag ignore"),
+    ("nodecount", "Nodes"),
+];
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn html(body: String) -> Response {
+        Response {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+}
+
+// WebUi renders the pages of a profile, the settings of the command line
+// being the defaults of the query parameters.
+#[derive(Debug)]
+pub struct WebUi {
+    profile: Profile,
+    settings: Settings,
+}
+
+impl WebUi {
+    pub fn new(profile: Profile, settings: Settings) -> WebUi {
+        WebUi { profile, settings }
+    }
+
+    // handle returns the response to a GET of the url (path and query).
+    pub fn handle(&self, url: &str) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let params = parse_query(query);
+
+        match path {
+            "/static/style.css" => {
+                return Response {
+                    status: 200,
+                    content_type: "text/css; charset=utf-8",
+                    body: STYLE.as_bytes().to_vec(),
+                }
+            }
+            _ if !PAGES.iter().any(|(p, _)| *p == path) => {
+                return Response {
+                    status: 404,
+                    content_type: "text/plain; charset=utf-8",
+                    body: format!("{} not found\n", path).into_bytes(),
+                }
+            }
+            _ => {}
+        }
+
+        let mut settings = self.settings.clone();
+        let mut errors = vec![];
+        for (name, value) in params.iter() {
+            if name == "f" || value.is_empty() {
+                continue;
+            }
+            if let Err(e) = settings.set(name, value) {
+                errors.push(e.to_string());
+            }
+        }
+        let f = param(&params, "f");
+
+        let (labels, body) = match self.render(path, &settings, f) {
+            Ok(page) => page,
+            Err(e) => {
+                errors.push(e.to_string());
+                (String::new(), String::new())
+            }
+        };
+        Response::html(self.page(path, &params, &labels, &body, &errors))
+    }
+
+    // render returns the labels of the profile and the content of a page.
+    fn render(
+        &self,
+        path: &str,
+        settings: &Settings,
+        f: &str,
+    ) -> Result<(String, String), RockError> {
+        let p = settings.filter(&self.profile)?;
+        let mut options = settings.report_options(&p)?;
+        if path == "/" && options.node_count.is_none() {
+            options.node_count = Some(80);
+        }
+        let r = Report::new(&p, options)?;
+        let mut labels = vec![];
+        r.write_header(&mut labels)?;
+        let labels = escape(&String::from_utf8_lossy(&labels));
+
+        let body = match path {
+            "/" => {
+                let mut dot = vec![];
+                r.dot(&mut dot)?;
+                match commands::svg(&dot) {
+                    Ok(svg) => String::from_utf8_lossy(&svg).to_string(),
+                    Err(e) => format!(
+                        "<div class=\"error\">{}</div><pre>{}</pre>",
+                        escape(&e.to_string()),
+                        escape(&String::from_utf8_lossy(&dot))
+                    ),
+                }
+            }
+            "/flamegraph" => {
+                let mut svg = vec![];
+                r.flame_svg(&mut svg)?;
+                String::from_utf8_lossy(&svg).to_string()
+            }
+            "/top" => self.top(&r),
+            _ if f.is_empty() => String::from(
+                "<p>Enter a regexp of function names in the f field, or pick a function in the top table.</p>",
+            ),
+            _ => {
+                let re = Regex::new(f).map_err(|e| RockError::InvalidFilter {
+                    reason: e.to_string(),
+                })?;
+                let mut out = vec![];
+                if path == "/peek" {
+                    r.peek(&mut out, &re)?;
+                } else {
+                    r.list(&mut out, &re)?;
+                }
+                format!("<pre>{}</pre>", escape(&String::from_utf8_lossy(&out)))
+            }
+        };
+        Ok((labels, body))
+    }
+
+    fn top(&self, r: &Report) -> String {
+        let mut rows = vec![String::from(
+            "<table class=\"top\"><tr><th>Flat</th><th>Flat%</th><th>Sum%</th><th>Cum</th><th>Cum%</th><th>Name</th></tr>",
+        )];
+        let mut sum = 0;
+        for n in r.graph().nodes.iter() {
+            sum += n.flat;
+            let name = n.info.printable_name();
+            let f = encode(&format!("^{}$", regex::escape(&n.info.name)));
+            let links = if n.info.name.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "<a href=\"/source?f={}\">source</a><a href=\"/peek?f={}\">peek</a>",
+                    f, f
+                )
+            };
+            rows.push(format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"name\">{}{}</td></tr>",
+                escape(&r.format_value(n.flat)),
+                measurement::percentage(n.flat, r.total()),
+                measurement::percentage(sum, r.total()),
+                escape(&r.format_value(n.cum)),
+                measurement::percentage(n.cum, r.total()),
+                escape(&name),
+                links
+            ));
+        }
+        rows.push(String::from("</table>"));
+        rows.join("\n")
+    }
+
+    fn page(
+        &self,
+        path: &str,
+        params: &[(String, String)],
+        labels: &str,
+        body: &str,
+        errors: &[String],
+    ) -> String {
+        // the navigation keeps the options, not the function regexp
+        let query: Vec<String> = params
+            .iter()
+            .filter(|(name, value)| name != "f" && !value.is_empty())
+            .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+            .collect();
+        let query = if query.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", query.join("&amp;"))
+        };
+        let nav: Vec<String> = PAGES
+            .iter()
+            .map(|(p, title)| {
+                let class = if *p == path { " class=\"active\"" } else { "" };
+                format!("<a href=\"{}{}\"{}>{}</a>", p, query, class, title)
+            })
+            .collect();
+        let title = PAGES
+            .iter()
+            .find(|(p, _)| *p == path)
+            .map_or("", |(_, t)| t);
+        let error = errors
+            .iter()
+            .map(|e| format!("<div class=\"error\">{}</div>", escape(e)))
+            .collect::<String>();
+
+        PAGE.replace("{{title}}", title)
+            .replace("{{nav}}", &nav.join(""))
+            .replace("{{path}}", path)
+            .replace("{{controls}}", &self.controls(params))
+            .replace("{{error}}", &error)
+            .replace("{{labels}}", labels)
+            .replace("{{body}}", body)
+    }
+
+    fn controls(&self, params: &[(String, String)]) -> String {
+        let mut controls = vec![];
+
+        let sample_index = param(params, "sample_index");
+        let mut options = vec![String::from("<option value=\"\">default</option>")];
+        for st in self.profile.sample_type.iter() {
+            let selected = if st.r#type == sample_index {
+                " selected"
+            } else {
+                ""
+            };
+            options.push(format!(
+                "<option{}>{}</option>",
+                selected,
+                escape(&st.r#type)
+            ));
+        }
+        controls.push(format!(
+            "<label>Sample<select name=\"sample_index\">{}</select></label>",
+            options.join("")
+        ));
+
+        let granularity = param(params, "granularity");
+        let mut options = vec![String::from("<option value=\"\">default</option>")];
+        for (name, _) in GRANULARITIES {
+            let selected = if name == granularity { " selected" } else { "" };
+            options.push(format!("<option{}>{}</option>", selected, name));
+        }
+        controls.push(format!(
+            "<label>Granularity<select name=\"granularity\">{}</select></label>",
+            options.join("")
+        ));
+
+        for (name, label) in CONTROLS {
+            let class = if name == "nodecount" {
+                " class=\"short\""
+            } else {
+                ""
+            };
+            controls.push(format!(
+                "<label>{}<input name=\"{}\" value=\"{}\"{}></label>",
+                label,
+                name,
+                escape(param(params, name)),
+                class
+            ));
+        }
+        controls.push(format!(
+            "<label>Function (f)<input name=\"f\" value=\"{}\"></label>",
+            escape(param(params, "f"))
+        ));
+        controls.join("\n")
+    }
+}
+
+// serve serves the UI on the address (host:port, or :port for localhost)
+// until the process is stopped.
+pub fn serve(address: &str, profile: Profile, settings: Settings) -> Result<(), RockError> {
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("localhost:{}", port),
+        None => address.to_string(),
+    };
+    let server = Server::http(&address).map_err(|e| RockError::IoFailed {
+        reason: format!("failed to listen on {}: {}", address, e),
+    })?;
+    println!("Serving web UI on http://{}", address);
+    run(&server, &WebUi::new(profile, settings))
+}
+
+fn run(server: &Server, ui: &WebUi) -> Result<(), RockError> {
+    for request in server.incoming_requests() {
+        let response = ui.handle(request.url());
+        let content_type =
+            Header::from_bytes("Content-Type", response.content_type).map_err(|_| {
+                RockError::Unknown {
+                    reason: String::from("invalid content type"),
+                }
+            })?;
+        let length = response.body.len();
+        let r = tiny_http::Response::new(
+            response.status.into(),
+            vec![content_type],
+            Cursor::new(response.body),
+            Some(length),
+            None,
+        );
+        if let Err(e) = request.respond(r) {
+            eprintln!("failed to send the response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> &'a str {
+    params
+        .iter()
+        .rev()
+        .find(|(n, _)| n == name)
+        .map_or("", |(_, v)| v.as_str())
+}
+
+// parse_query decodes the parameters of a query string.
+pub(crate) fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (name, value) = p.split_once('=').unwrap_or((p, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+// decode decodes a form encoded query component.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = |b: u8| (b as char).to_digit(16);
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(h), Some(l)) => {
+                        decoded.push((h * 16 + l) as u8);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// encode encodes a query component.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::read_profile;
+    use crate::driver::settings::Settings;
+    use crate::driver::webui::{decode, encode, parse_query, run, WebUi};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use tiny_http::Server;
+
+    fn ui() -> WebUi {
+        WebUi::new(
+            read_profile("tests/CPU.pb.gz").unwrap(),
+            Settings::default(),
+        )
+    }
+
+    fn body(ui: &WebUi, url: &str) -> String {
+        let r = ui.handle(url);
+        assert_eq!(r.status, 200, "{}", url);
+        String::from_utf8(r.body).unwrap()
+    }
+
+    #[test]
+    fn test_query() {
+        assert_eq!(decode("main%5C.main+x%2"), "main\\.main x%2");
+        assert_eq!(
+            decode(&encode("^runtime\\.(*mcache)$ é")),
+            "^runtime\\.(*mcache)$ é"
+        );
+        assert_eq!(
+            parse_query("focus=a%7Cb&f=&cum"),
+            vec![
+                (String::from("focus"), String::from("a|b")),
+                (String::from("f"), String::new()),
+                (String::from("cum"), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pages() {
+        let ui = ui();
+
+        let top = body(&ui, "/top?focus=main%5C.main&nodecount=3");
+        assert!(top.contains(
+            "<a href=\"/flamegraph?focus=main%5C.main&amp;nodecount=3\">Flame Graph</a>"
+        ));
+        assert!(top.contains("<input name=\"focus\" value=\"main\\.main\"></label>"));
+        assert!(top.contains("Showing top 3 nodes out of"));
+        assert_eq!(top.matches("<tr><td>").count(), 3);
+        assert!(top.contains("<a href=\"/source?f=%5Eruntime%5C.memmove%24\">source</a>"));
+        assert!(!top.contains("cdn") && !top.contains("https://"));
+
+        let flame = body(&ui, "/flamegraph?sample_index=samples");
+        assert!(flame.contains("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(flame.contains("<option selected>samples</option>"));
+
+        let source = body(&ui, "/source?f=main%5C.main%5C.func2");
+        assert!(source.contains("ROUTINE ======================== main.main.func2"));
+        let peek = body(&ui, "/peek?f=main%5C.main%5C.func2&granularity=lines");
+        assert!(peek.contains("calls calls% + context"));
+        assert!(peek.contains("main.main.func2 /home/"));
+
+        // the graph falls back to the dot source without graphviz
+        let graph = body(&ui, "/");
+        assert!(graph.contains("<svg") || graph.contains("digraph"));
+
+        let error = body(&ui, "/top?focus=%28");
+        assert!(error.contains("<div class=\"error\">"));
+
+        assert_eq!(
+            ui.handle("/static/style.css").content_type,
+            "text/css; charset=utf-8"
+        );
+        assert_eq!(ui.handle("/missing").status, 404);
+    }
+
+    #[test]
+    fn test_server() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        thread::spawn(move || run(&server, &ui()));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET /top HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(response.contains("<table class=\"top\">"));
+    }
+}
@@ -1,5 +1,15 @@
 #![warn(missing_debug_implementations)]
 
+pub mod callgrind;
+pub mod chrome;
+pub mod columnar;
 pub mod driver;
 pub mod graph;
+pub mod measurement;
+pub mod otlp;
+pub mod perf;
 pub mod profile;
+pub mod report;
+pub mod server;
+pub mod speedscope;
+pub mod symbolizer;
@@ -0,0 +1,623 @@
+// Package measurement export utility functions to manipulate/format performance profile sample values.
+// It understands the units stored in ValueType::unit and Sample::num_unit_label,
+// scales values between compatible units (ns/us/ms/s, B/kB/MB/GB) and picks
+// common units across a set of profiles.
+
+use crate::profile::errors::RockError;
+use crate::profile::value_type::ValueType;
+use crate::profile::Profile;
+
+// Label keys which values are memory sizes, used to infer the unit of a
+// numeric label when the profile does not specify it.
+const MEMORY_LABEL_KEYS: [&str; 3] = ["alignment", "request", "bytes"];
+
+// Unit is a unit of measurement with a canonical name, a set of aliases
+// which can be used to refer to it and a factor relative to the smallest
+// unit of the same type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    pub canonical_name: &'static str,
+    aliases: &'static [&'static str],
+    pub factor: f64,
+}
+
+// UnitType is a family of compatible units, for example memory sizes or time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitType {
+    pub default_unit: Unit,
+    pub units: &'static [Unit],
+}
+
+const BYTE: Unit = Unit {
+    canonical_name: "B",
+    aliases: &["b", "byte"],
+    factor: 1.0,
+};
+
+const SECOND: Unit = Unit {
+    canonical_name: "s",
+    aliases: &[],
+    factor: 1e9,
+};
+
+const GCU: Unit = Unit {
+    canonical_name: "GCU",
+    aliases: &[],
+    factor: 1.0,
+};
+
+// UNIT_TYPES contains all known unit families, in order of lookup.
+pub const UNIT_TYPES: [UnitType; 3] = [
+    UnitType {
+        default_unit: BYTE,
+        units: &[
+            BYTE,
+            Unit {
+                canonical_name: "kB",
+                aliases: &["kb", "kbyte", "kilobyte"],
+                factor: (1u64 << 10) as f64,
+            },
+            Unit {
+                canonical_name: "MB",
+                aliases: &["mb", "mbyte", "megabyte"],
+                factor: (1u64 << 20) as f64,
+            },
+            Unit {
+                canonical_name: "GB",
+                aliases: &["gb", "gbyte", "gigabyte"],
+                factor: (1u64 << 30) as f64,
+            },
+            Unit {
+                canonical_name: "TB",
+                aliases: &["tb", "tbyte", "terabyte"],
+                factor: (1u64 << 40) as f64,
+            },
+            Unit {
+                canonical_name: "PB",
+                aliases: &["pb", "pbyte", "petabyte"],
+                factor: (1u64 << 50) as f64,
+            },
+        ],
+    },
+    UnitType {
+        default_unit: SECOND,
+        units: &[
+            Unit {
+                canonical_name: "ns",
+                aliases: &["ns", "nanosecond"],
+                factor: 1.0,
+            },
+            Unit {
+                canonical_name: "us",
+                aliases: &["μs", "us", "microsecond"],
+                factor: 1e3,
+            },
+            Unit {
+                canonical_name: "ms",
+                aliases: &["ms", "millisecond"],
+                factor: 1e6,
+            },
+            Unit {
+                canonical_name: "s",
+                aliases: &["s", "sec", "second"],
+                factor: 1e9,
+            },
+            Unit {
+                canonical_name: "hrs",
+                aliases: &["hour", "hr"],
+                factor: 3600e9,
+            },
+        ],
+    },
+    UnitType {
+        default_unit: GCU,
+        units: &[
+            Unit {
+                canonical_name: "n*GCU",
+                aliases: &["nanogcu"],
+                factor: 1e-9,
+            },
+            Unit {
+                canonical_name: "u*GCU",
+                aliases: &["microgcu"],
+                factor: 1e-6,
+            },
+            Unit {
+                canonical_name: "m*GCU",
+                aliases: &["milligcu"],
+                factor: 1e-3,
+            },
+            Unit {
+                canonical_name: "GCU",
+                aliases: &["gcu"],
+                factor: 1.0,
+            },
+            Unit {
+                canonical_name: "k*GCU",
+                aliases: &["kilogcu"],
+                factor: 1e3,
+            },
+            Unit {
+                canonical_name: "M*GCU",
+                aliases: &["megagcu"],
+                factor: 1e6,
+            },
+            Unit {
+                canonical_name: "G*GCU",
+                aliases: &["gigagcu"],
+                factor: 1e9,
+            },
+            Unit {
+                canonical_name: "T*GCU",
+                aliases: &["teragcu"],
+                factor: 1e12,
+            },
+            Unit {
+                canonical_name: "P*GCU",
+                aliases: &["petagcu"],
+                factor: 1e15,
+            },
+        ],
+    },
+];
+
+impl UnitType {
+    // find_by_alias returns the unit associated with the specified alias. It returns
+    // None if the unit with such alias is not found.
+    fn find_by_alias(&self, alias: &str) -> Option<&Unit> {
+        self.units.iter().find(|u| u.aliases.contains(&alias))
+    }
+
+    // sniff_unit simplifies the input alias ("Bytes" -> "byte", "Seconds" -> "second")
+    // and returns the unit associated with it.
+    pub fn sniff_unit(&self, unit: &str) -> Option<&Unit> {
+        let mut unit = unit.to_lowercase();
+        if unit.chars().count() > 2 {
+            if let Some(stripped) = unit.strip_suffix('s') {
+                unit = stripped.to_string();
+            }
+        }
+        self.find_by_alias(&unit)
+    }
+
+    // auto_scale takes in the value with units of the base unit and returns
+    // that value scaled to a reasonable unit if a reasonable unit is found.
+    fn auto_scale(&self, value: f64) -> Option<(f64, &'static str)> {
+        let mut f = 0.0;
+        let mut unit = "";
+        for u in self.units.iter() {
+            if u.factor >= f && (value / u.factor) >= 1.0 {
+                f = u.factor;
+                unit = u.canonical_name;
+            }
+        }
+        if f == 0.0 {
+            return None;
+        }
+        Some((value / f, unit))
+    }
+
+    // convert_unit converts a value from the from_unit to the to_unit, autoscaling
+    // the value if the to_unit is "minimum" or "auto".
+    // Returns None if the from_unit does not belong to this unit type.
+    fn convert_unit(&self, value: i64, from_unit: &str, to_unit: &str) -> Option<(f64, String)> {
+        let from = self.sniff_unit(from_unit)?;
+        let v = value as f64 * from.factor;
+
+        if to_unit == "minimum" || to_unit == "auto" {
+            if let Some((v, u)) = self.auto_scale(v) {
+                return Some((v, u.to_string()));
+            }
+            return Some((
+                v / self.default_unit.factor,
+                self.default_unit.canonical_name.to_string(),
+            ));
+        }
+
+        match self.sniff_unit(to_unit) {
+            Some(to) => Some((v / to.factor, to.canonical_name.to_string())),
+            None => Some((
+                v / self.default_unit.factor,
+                self.default_unit.canonical_name.to_string(),
+            )),
+        }
+    }
+}
+
+// is_known_unit returns whether the unit belongs to one of the known unit types.
+pub fn is_known_unit(unit: &str) -> bool {
+    UNIT_TYPES.iter().any(|ut| ut.sniff_unit(unit).is_some())
+}
+
+// label_key_unit infers the unit of a numeric label from its key, used when
+// the profile does not carry an explicit num_unit for the label.
+// Keys known to describe allocation sizes are measured in bytes, any other key
+// is used as its own unit ("kilobytes", "seconds", "requests").
+pub fn label_key_unit(key: &str) -> String {
+    if MEMORY_LABEL_KEYS.contains(&key) {
+        return String::from("bytes");
+    }
+    key.to_string()
+}
+
+// scale a measurement from a unit to a different unit and returns
+// the scaled value and the target unit. The returned target unit
+// will be empty if uninteresting (could be skipped).
+pub fn scale(value: i64, from_unit: &str, to_unit: &str) -> (f64, String) {
+    // Avoid infinite recursion on overflow.
+    if value < 0 && value != i64::MIN {
+        let (v, u) = scale(-value, from_unit, to_unit);
+        return (-v, u);
+    }
+
+    for ut in UNIT_TYPES.iter() {
+        if let Some((v, u)) = ut.convert_unit(value, from_unit, to_unit) {
+            return (v, u);
+        }
+    }
+
+    // Skip non-interesting units.
+    match to_unit {
+        "count" | "sample" | "unit" | "minimum" | "auto" => (value as f64, String::new()),
+        _ => (value as f64, to_unit.to_string()),
+    }
+}
+
+// label returns the label used to describe a quantity, autoscaled to
+// the most suitable unit.
+pub fn label(value: i64, unit: &str) -> String {
+    scaled_label(value, unit, "auto")
+}
+
+// scaled_label scales the passed-in measurement (if necessary) and
+// returns the label used to describe a float measurement.
+pub fn scaled_label(value: i64, from_unit: &str, to_unit: &str) -> String {
+    let (v, u) = scale(value, from_unit, to_unit);
+    let formatted = format!("{:.2}", v);
+    let sv = formatted.strip_suffix(".00").unwrap_or(&formatted);
+    if sv == "0" || sv == "-0" {
+        return String::from("0");
+    }
+    format!("{}{}", sv, u)
+}
+
+// percentage computes the percentage of total of a value, and encodes
+// it as a string. At least two digits of precision are printed.
+pub fn percentage(value: i64, total: i64) -> String {
+    let mut ratio = 0.0;
+    if total != 0 {
+        ratio = (value as f64 / total as f64).abs() * 100.0;
+    }
+
+    if (99.95..=100.05).contains(&ratio) {
+        return String::from("  100%");
+    }
+    if ratio >= 1.0 {
+        return format!("{:5.2}%", ratio);
+    }
+    format!("{:>5}%", format_significant(ratio, 2))
+}
+
+// format_significant formats a value with the given number of significant
+// digits, dropping trailing zeros (the equivalent of %g in go).
+fn format_significant(value: f64, digits: i32) -> String {
+    if value == 0.0 {
+        return String::from("0");
+    }
+
+    let exp = value.abs().log10().floor() as i32;
+    if exp < -4 || exp >= digits {
+        let formatted = format!("{:.*e}", (digits - 1) as usize, value);
+        let (mantissa, exp) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+        let exp: i32 = exp.parse().unwrap_or(0);
+        return format!(
+            "{}e{}{:02}",
+            trim_zeros(mantissa),
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        );
+    }
+
+    let formatted = format!("{:.*}", (digits - 1 - exp).max(0) as usize, value);
+    trim_zeros(&formatted).to_string()
+}
+
+fn trim_zeros(s: &str) -> &str {
+    if !s.contains('.') {
+        return s;
+    }
+    s.trim_end_matches('0').trim_end_matches('.')
+}
+
+// compatible_value_types returns whether two value types describe the same
+// measurement, possibly in different (but convertible) units.
+pub fn compatible_value_types(v1: &ValueType, v2: &ValueType) -> bool {
+    // Remove trailing 's' to permit minor mismatches.
+    if v1.r#type.trim_end_matches('s') != v2.r#type.trim_end_matches('s') {
+        return false;
+    }
+
+    if v1.unit == v2.unit {
+        return true;
+    }
+
+    UNIT_TYPES
+        .iter()
+        .any(|ut| ut.sniff_unit(&v1.unit).is_some() && ut.sniff_unit(&v2.unit).is_some())
+}
+
+// common_value_type returns the finest type from a set of compatible
+// types. None is returned if there is less than two types to compare.
+pub fn common_value_type(ts: &[&ValueType]) -> Result<Option<ValueType>, RockError> {
+    if ts.len() <= 1 {
+        return Ok(None);
+    }
+
+    let mut min_type = ts[0];
+    for t in ts[1..].iter() {
+        if !compatible_value_types(min_type, t) {
+            return Err(RockError::IncompatibleUnits {
+                reason: format!(
+                    "incompatible types: {}/{} {}/{}",
+                    min_type.r#type, min_type.unit, t.r#type, t.unit
+                ),
+            });
+        }
+
+        let (ratio, _) = scale(1, &t.unit, &min_type.unit);
+        if ratio < 1.0 {
+            min_type = t;
+        }
+    }
+
+    Ok(Some(min_type.clone()))
+}
+
+// scale_profiles updates the units in a set of profiles to make them
+// compatible. It scales the profiles to the smallest unit to preserve
+// data.
+pub fn scale_profiles(profiles: &mut [Profile]) -> Result<(), RockError> {
+    if profiles.is_empty() {
+        return Ok(());
+    }
+
+    let period_types: Vec<&ValueType> = profiles
+        .iter()
+        .filter_map(|p| p.period_type.as_ref())
+        .collect();
+    let period_type =
+        common_value_type(&period_types).map_err(|err| RockError::IncompatibleUnits {
+            reason: format!("period type: {}", err),
+        })?;
+
+    // Identify common sample types
+    let num_sample_types = profiles[0].sample_type.len();
+    for p in profiles[1..].iter() {
+        if p.sample_type.len() != num_sample_types {
+            return Err(RockError::IncompatibleUnits {
+                reason: format!(
+                    "inconsistent samples type count: {} != {}",
+                    num_sample_types,
+                    p.sample_type.len()
+                ),
+            });
+        }
+    }
+
+    let mut sample_type: Vec<Option<ValueType>> = Vec::with_capacity(num_sample_types);
+    for i in 0..num_sample_types {
+        let types: Vec<&ValueType> = profiles.iter().map(|p| &p.sample_type[i]).collect();
+        sample_type.push(common_value_type(&types).map_err(|err| {
+            RockError::IncompatibleUnits {
+                reason: format!("sample types: {}", err),
+            }
+        })?);
+    }
+
+    for p in profiles.iter_mut() {
+        if let (Some(pt), Some(common)) = (p.period_type.as_mut(), period_type.as_ref()) {
+            let (period, _) = scale(p.period, &pt.unit, &common.unit);
+            p.period = period as i64;
+            pt.unit = common.unit.clone();
+        }
+
+        let mut ratios = Vec::with_capacity(p.sample_type.len());
+        for (i, st) in p.sample_type.iter_mut().enumerate() {
+            match &sample_type[i] {
+                None => ratios.push(1.0),
+                Some(common) => {
+                    let (ratio, _) = scale(1, &st.unit, &common.unit);
+                    ratios.push(ratio);
+                    st.unit = common.unit.clone();
+                }
+            }
+        }
+
+        p.scale_n(&ratios)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::measurement::{
+        common_value_type, label, percentage, scale, scale_profiles, scaled_label,
+    };
+    use crate::profile::sample::Sample;
+    use crate::profile::value_type::ValueType;
+    use crate::profile::Profile;
+
+    fn vt(t: &str, u: &str) -> ValueType {
+        ValueType {
+            r#type: t.to_string(),
+            unit: u.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        struct ScaleTest {
+            value: i64,
+            from_unit: &'static str,
+            to_unit: &'static str,
+            want_value: f64,
+            want_unit: &'static str,
+        }
+
+        let tests = vec![
+            ScaleTest {
+                value: 1,
+                from_unit: "s",
+                to_unit: "ms",
+                want_value: 1000.0,
+                want_unit: "ms",
+            },
+            ScaleTest {
+                value: 1,
+                from_unit: "kilobyte",
+                to_unit: "B",
+                want_value: 1024.0,
+                want_unit: "B",
+            },
+            ScaleTest {
+                value: 1,
+                from_unit: "kilobytes",
+                to_unit: "B",
+                want_value: 1024.0,
+                want_unit: "B",
+            },
+            ScaleTest {
+                value: 1,
+                from_unit: "B",
+                to_unit: "minimum",
+                want_value: 1.0,
+                want_unit: "B",
+            },
+            ScaleTest {
+                value: 2048,
+                from_unit: "bytes",
+                to_unit: "auto",
+                want_value: 2.0,
+                want_unit: "kB",
+            },
+            ScaleTest {
+                value: 1_500_000,
+                from_unit: "nanoseconds",
+                to_unit: "auto",
+                want_value: 1.5,
+                want_unit: "ms",
+            },
+            ScaleTest {
+                value: 3,
+                from_unit: "hours",
+                to_unit: "seconds",
+                want_value: 10800.0,
+                want_unit: "s",
+            },
+            ScaleTest {
+                value: 10,
+                from_unit: "count",
+                to_unit: "auto",
+                want_value: 10.0,
+                want_unit: "",
+            },
+            ScaleTest {
+                value: 10,
+                from_unit: "requests",
+                to_unit: "requests",
+                want_value: 10.0,
+                want_unit: "requests",
+            },
+            ScaleTest {
+                value: -2048,
+                from_unit: "bytes",
+                to_unit: "auto",
+                want_value: -2.0,
+                want_unit: "kB",
+            },
+            ScaleTest {
+                value: 1000,
+                from_unit: "milligcu",
+                to_unit: "auto",
+                want_value: 1.0,
+                want_unit: "GCU",
+            },
+        ];
+
+        for test in tests {
+            let (v, u) = scale(test.value, test.from_unit, test.to_unit);
+            assert!(
+                (v - test.want_value).abs() < 1e-9 && u == test.want_unit,
+                "scale({}, {}, {}) = {} {}, want {} {}",
+                test.value,
+                test.from_unit,
+                test.to_unit,
+                v,
+                u,
+                test.want_value,
+                test.want_unit
+            );
+        }
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(label(0, "bytes"), "0");
+        assert_eq!(label(1536, "bytes"), "1.50kB");
+        assert_eq!(label(10_000_000, "nanoseconds"), "10ms");
+        assert_eq!(label(42, "count"), "42");
+        assert_eq!(scaled_label(2_500_000_000, "ns", "s"), "2.50s");
+        assert_eq!(scaled_label(3, "kilobytes", "B"), "3072B");
+    }
+
+    #[test]
+    fn test_percentage() {
+        assert_eq!(percentage(1, 1), "  100%");
+        assert_eq!(percentage(1, 3), "33.33%");
+        assert_eq!(percentage(1, 1000), "  0.1%");
+        assert_eq!(percentage(1, 30000), "0.0033%");
+        assert_eq!(percentage(1, 10_000_000), "1e-05%");
+        assert_eq!(percentage(0, 10), "    0%");
+        assert_eq!(percentage(1, 0), "    0%");
+    }
+
+    #[test]
+    fn test_common_value_type() {
+        let ms = vt("cpu", "milliseconds");
+        let ns = vt("cpu", "nanoseconds");
+        let bytes = vt("space", "bytes");
+
+        assert_eq!(common_value_type(&[&ms]).unwrap(), None);
+        assert_eq!(common_value_type(&[&ms, &ns]).unwrap(), Some(ns.clone()));
+        assert_eq!(common_value_type(&[&ns, &ms]).unwrap(), Some(ns.clone()));
+        assert!(common_value_type(&[&ms, &bytes]).is_err());
+    }
+
+    #[test]
+    fn test_scale_profiles() {
+        let profile = |unit: &str, period: i64| {
+            let mut p = Profile::default();
+            p.sample_type = vec![vt("cpu", unit)];
+            p.period_type = Some(vt("cpu", unit));
+            p.period = period;
+            p
+        };
+
+        let mut p1 = profile("milliseconds", 10);
+        p1.sample.push(Sample {
+            value: vec![3],
+            ..Default::default()
+        });
+        let p2 = profile("nanoseconds", 10_000_000);
+
+        let mut profiles = vec![p1, p2];
+        scale_profiles(&mut profiles).unwrap();
+
+        assert_eq!(profiles[0].period, 10_000_000);
+        assert_eq!(profiles[0].sample_type[0].unit, "nanoseconds");
+        assert_eq!(profiles[0].sample[0].value, vec![3_000_000]);
+        assert_eq!(profiles[1].period, 10_000_000);
+    }
+}
@@ -0,0 +1,1109 @@
+// Package otlp converts profiles to and from the OpenTelemetry profiles signal
+// (OTLP ProfilesData), the protocol buffer messages of src/proto/otlp_profiles.proto.
+//
+// OTLP keeps strings, mappings, locations, functions, stacks and attributes in
+// a dictionary shared by all the profiles of a message, and a profile has a
+// single sample type. So a pprof profile with several sample types becomes a
+// ProfilesData with one OTLP profile per sample type, and the OTLP profiles of
+// a ProfilesData become the sample types of a single pprof profile. Sample
+// labels are stored as attributes, the build id of a mapping as the
+// BUILD_ID_ATTRIBUTE attribute. The comments, drop_frames and keep_frames of a
+// pprof profile have no OTLP equivalent and are lost in the conversion, the
+// symbolization flags of the mappings are recomputed from the locations.
+
+use crate::profile;
+use crate::profile::buffer::{decode_field, decode_fixed64, decode_varint, Buffer, WireTypes};
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::encoder::Encoder;
+use crate::profile::errors::RockError;
+use std::collections::HashMap;
+
+pub const BUILD_ID_ATTRIBUTE: &str = "process.executable.build_id.gnu";
+const SCOPE_NAME: &str = "rock";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProfilesData {
+    pub resource_profiles: Vec<ResourceProfiles>,
+    pub dictionary: ProfilesDictionary,
+}
+
+// ProfilesDictionary holds the tables referenced by index from the profiles.
+// The first entry of every table is the zero value.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProfilesDictionary {
+    pub mapping_table: Vec<Mapping>,
+    pub location_table: Vec<Location>,
+    pub function_table: Vec<Function>,
+    pub link_table: Vec<Link>,
+    pub string_table: Vec<String>,
+    pub attribute_table: Vec<KeyValueAndUnit>,
+    pub stack_table: Vec<Stack>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResourceProfiles {
+    // attributes of the Resource
+    pub attributes: Vec<KeyValue>,
+    pub scope_profiles: Vec<ScopeProfiles>,
+    pub schema_url: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScopeProfiles {
+    // name and version of the InstrumentationScope
+    pub scope_name: String,
+    pub scope_version: String,
+    pub profiles: Vec<Profile>,
+    pub schema_url: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    pub sample_type: ValueType,
+    pub samples: Vec<Sample>,
+    pub time_unix_nano: u64,
+    pub duration_nano: u64,
+    pub period_type: Option<ValueType>,
+    pub period: i64,
+    pub attribute_indices: Vec<i32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValueType {
+    pub type_strindex: i32,
+    pub unit_strindex: i32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sample {
+    pub stack_index: i32,
+    pub values: Vec<i64>,
+    pub attribute_indices: Vec<i32>,
+    pub link_index: i32,
+    pub timestamps_unix_nano: Vec<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mapping {
+    pub memory_start: u64,
+    pub memory_limit: u64,
+    pub file_offset: u64,
+    pub filename_strindex: i32,
+    pub attribute_indices: Vec<i32>,
+}
+
+// Stack lists the locations of a call stack, the leaf first.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stack {
+    pub location_indices: Vec<i32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Location {
+    pub mapping_index: i32,
+    pub address: u64,
+    pub lines: Vec<Line>,
+    pub attribute_indices: Vec<i32>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Line {
+    pub function_index: i32,
+    pub line: i64,
+    pub column: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Function {
+    pub name_strindex: i32,
+    pub system_name_strindex: i32,
+    pub filename_strindex: i32,
+    pub start_line: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Link {
+    pub trace_id: Vec<u8>,
+    pub span_id: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KeyValueAndUnit {
+    pub key_strindex: i32,
+    pub value: AnyValue,
+    pub unit_strindex: i32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: AnyValue,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum AnyValue {
+    #[default]
+    Empty,
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Bytes(Vec<u8>),
+}
+
+// encode converts the profile into an encoded OTLP ProfilesData message.
+pub fn encode(p: &profile::Profile) -> Vec<u8> {
+    ProfilesData::from(p).encode()
+}
+
+// decode converts an encoded OTLP ProfilesData message into a profile.
+pub fn decode(data: &[u8]) -> Result<profile::Profile, RockError> {
+    profile::Profile::try_from(&ProfilesData::decode(data)?)
+}
+
+// fields calls f with every field of the message: the buffer holds the field
+// number, wire type and scalar value, the bytes the content of length
+// delimited fields.
+pub(crate) fn fields(
+    data: &[u8],
+    mut f: impl FnMut(&Buffer, Vec<u8>) -> Result<(), RockError>,
+) -> Result<(), RockError> {
+    let mut data = data.to_vec();
+    let mut buf = Buffer::default();
+    while !data.is_empty() {
+        let bytes = decode_field(&mut buf, &mut data)?;
+        f(&buf, bytes)?;
+    }
+    Ok(())
+}
+
+// varints returns the values of a repeated varint field, packed or not.
+pub(crate) fn varints(buf: &Buffer, mut bytes: Vec<u8>) -> Result<Vec<u64>, RockError> {
+    if buf.r#type != WireTypes::WireBytes {
+        return Ok(vec![buf.u64]);
+    }
+    let mut v = vec![];
+    while !bytes.is_empty() {
+        v.push(decode_varint(&mut bytes)? as u64);
+    }
+    Ok(v)
+}
+
+fn indices(buf: &Buffer, bytes: Vec<u8>) -> Result<Vec<i32>, RockError> {
+    Ok(varints(buf, bytes)?.into_iter().map(|v| v as i32).collect())
+}
+
+// fixed64s returns the values of a repeated fixed64 field, packed or not.
+fn fixed64s(buf: &Buffer, bytes: Vec<u8>) -> Result<Vec<u64>, RockError> {
+    if buf.r#type != WireTypes::WireBytes {
+        return Ok(vec![buf.u64]);
+    }
+    if !bytes.len().is_multiple_of(8) {
+        return Err(RockError::DecodeFieldFailed {
+            reason: String::from("packed fixed64 field length is not a multiple of 8"),
+        });
+    }
+    Ok(bytes.chunks(8).map(decode_fixed64).collect())
+}
+
+pub(crate) fn string(bytes: Vec<u8>) -> Result<String, RockError> {
+    String::from_utf8(bytes).map_err(|err| RockError::DecodeFieldFailed {
+        reason: format!("invalid utf-8 string: {}", err),
+    })
+}
+
+fn encode_indices(e: &mut Encoder, field: u64, v: &[i32]) {
+    let v: Vec<i64> = v.iter().map(|x| *x as i64).collect();
+    e.packed_int64(field, &v);
+}
+
+impl ProfilesData {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        for rp in self.resource_profiles.iter() {
+            e.message(1, |e| rp.encode(e));
+        }
+        e.message(2, |e| self.dictionary.encode(e));
+        e.finish()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut d = ProfilesData::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => d.resource_profiles.push(ResourceProfiles::decode(&bytes)?),
+                2 => d.dictionary = ProfilesDictionary::decode(&bytes)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(d)
+    }
+}
+
+impl ProfilesDictionary {
+    fn encode(&self, e: &mut Encoder) {
+        for m in self.mapping_table.iter() {
+            e.message(1, |e| m.encode(e));
+        }
+        for l in self.location_table.iter() {
+            e.message(2, |e| l.encode(e));
+        }
+        for f in self.function_table.iter() {
+            e.message(3, |e| f.encode(e));
+        }
+        for l in self.link_table.iter() {
+            e.message(4, |e| {
+                e.bytes(1, &l.trace_id);
+                e.bytes(2, &l.span_id);
+            });
+        }
+        e.repeated_string(5, &self.string_table);
+        for a in self.attribute_table.iter() {
+            e.message(6, |e| {
+                e.int64(1, a.key_strindex as i64);
+                e.message(2, |e| a.value.encode(e));
+                e.int64(3, a.unit_strindex as i64);
+            });
+        }
+        for s in self.stack_table.iter() {
+            e.message(7, |e| encode_indices(e, 1, &s.location_indices));
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut d = ProfilesDictionary::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => d.mapping_table.push(Mapping::decode(&bytes)?),
+                2 => d.location_table.push(Location::decode(&bytes)?),
+                3 => d.function_table.push(Function::decode(&bytes)?),
+                4 => {
+                    let mut l = Link::default();
+                    fields(&bytes, |buf, bytes| {
+                        match buf.field {
+                            1 => l.trace_id = bytes,
+                            2 => l.span_id = bytes,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    d.link_table.push(l);
+                }
+                5 => d.string_table.push(string(bytes)?),
+                6 => {
+                    let mut a = KeyValueAndUnit::default();
+                    fields(&bytes, |buf, bytes| {
+                        match buf.field {
+                            1 => a.key_strindex = buf.u64 as i32,
+                            2 => a.value = AnyValue::decode(&bytes)?,
+                            3 => a.unit_strindex = buf.u64 as i32,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    d.attribute_table.push(a);
+                }
+                7 => {
+                    let mut s = Stack::default();
+                    fields(&bytes, |buf, bytes| {
+                        if buf.field == 1 {
+                            s.location_indices.extend(indices(buf, bytes)?);
+                        }
+                        Ok(())
+                    })?;
+                    d.stack_table.push(s);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(d)
+    }
+}
+
+impl ResourceProfiles {
+    fn encode(&self, e: &mut Encoder) {
+        e.message(1, |e| {
+            for kv in self.attributes.iter() {
+                e.message(1, |e| kv.encode(e));
+            }
+        });
+        for sp in self.scope_profiles.iter() {
+            e.message(2, |e| sp.encode(e));
+        }
+        e.string(3, &self.schema_url);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut rp = ResourceProfiles::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => fields(&bytes, |buf, bytes| {
+                    if buf.field == 1 {
+                        rp.attributes.push(KeyValue::decode(&bytes)?);
+                    }
+                    Ok(())
+                })?,
+                2 => rp.scope_profiles.push(ScopeProfiles::decode(&bytes)?),
+                3 => rp.schema_url = string(bytes)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(rp)
+    }
+}
+
+impl ScopeProfiles {
+    fn encode(&self, e: &mut Encoder) {
+        e.message(1, |e| {
+            e.string(1, &self.scope_name);
+            e.string(2, &self.scope_version);
+        });
+        for p in self.profiles.iter() {
+            e.message(2, |e| p.encode(e));
+        }
+        e.string(3, &self.schema_url);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut sp = ScopeProfiles::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => fields(&bytes, |buf, bytes| {
+                    match buf.field {
+                        1 => sp.scope_name = string(bytes)?,
+                        2 => sp.scope_version = string(bytes)?,
+                        _ => {}
+                    }
+                    Ok(())
+                })?,
+                2 => sp.profiles.push(Profile::decode(&bytes)?),
+                3 => sp.schema_url = string(bytes)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(sp)
+    }
+}
+
+impl Profile {
+    fn encode(&self, e: &mut Encoder) {
+        e.message(1, |e| self.sample_type.encode(e));
+        for s in self.samples.iter() {
+            e.message(2, |e| {
+                e.int64(1, s.stack_index as i64);
+                e.packed_int64(2, &s.values);
+                encode_indices(e, 3, &s.attribute_indices);
+                e.int64(4, s.link_index as i64);
+                e.packed_fixed64(5, &s.timestamps_unix_nano);
+            });
+        }
+        e.fixed64(3, self.time_unix_nano);
+        e.uint64(4, self.duration_nano);
+        if let Some(pt) = &self.period_type {
+            e.message(5, |e| pt.encode(e));
+        }
+        e.int64(6, self.period);
+        encode_indices(e, 11, &self.attribute_indices);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut p = Profile::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => p.sample_type = ValueType::decode(&bytes)?,
+                2 => {
+                    let mut s = Sample::default();
+                    fields(&bytes, |buf, bytes| {
+                        match buf.field {
+                            1 => s.stack_index = buf.u64 as i32,
+                            2 => s
+                                .values
+                                .extend(varints(buf, bytes)?.into_iter().map(|v| v as i64)),
+                            3 => s.attribute_indices.extend(indices(buf, bytes)?),
+                            4 => s.link_index = buf.u64 as i32,
+                            5 => s.timestamps_unix_nano.extend(fixed64s(buf, bytes)?),
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    p.samples.push(s);
+                }
+                3 => p.time_unix_nano = buf.u64,
+                4 => p.duration_nano = buf.u64,
+                5 => p.period_type = Some(ValueType::decode(&bytes)?),
+                6 => p.period = buf.u64 as i64,
+                11 => p.attribute_indices.extend(indices(buf, bytes)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(p)
+    }
+}
+
+impl ValueType {
+    fn encode(&self, e: &mut Encoder) {
+        e.int64(1, self.type_strindex as i64);
+        e.int64(2, self.unit_strindex as i64);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut vt = ValueType::default();
+        fields(data, |buf, _| {
+            match buf.field {
+                1 => vt.type_strindex = buf.u64 as i32,
+                2 => vt.unit_strindex = buf.u64 as i32,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(vt)
+    }
+}
+
+impl Mapping {
+    fn encode(&self, e: &mut Encoder) {
+        e.uint64(1, self.memory_start);
+        e.uint64(2, self.memory_limit);
+        e.uint64(3, self.file_offset);
+        e.int64(4, self.filename_strindex as i64);
+        encode_indices(e, 5, &self.attribute_indices);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut m = Mapping::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => m.memory_start = buf.u64,
+                2 => m.memory_limit = buf.u64,
+                3 => m.file_offset = buf.u64,
+                4 => m.filename_strindex = buf.u64 as i32,
+                5 => m.attribute_indices.extend(indices(buf, bytes)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(m)
+    }
+}
+
+impl Location {
+    fn encode(&self, e: &mut Encoder) {
+        e.int64(1, self.mapping_index as i64);
+        e.uint64(2, self.address);
+        for l in self.lines.iter() {
+            e.message(3, |e| {
+                e.int64(1, l.function_index as i64);
+                e.int64(2, l.line);
+                e.int64(3, l.column);
+            });
+        }
+        encode_indices(e, 4, &self.attribute_indices);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut l = Location::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => l.mapping_index = buf.u64 as i32,
+                2 => l.address = buf.u64,
+                3 => {
+                    let mut line = Line::default();
+                    fields(&bytes, |buf, _| {
+                        match buf.field {
+                            1 => line.function_index = buf.u64 as i32,
+                            2 => line.line = buf.u64 as i64,
+                            3 => line.column = buf.u64 as i64,
+                            _ => {}
+                        }
+                        Ok(())
+                    })?;
+                    l.lines.push(line);
+                }
+                4 => l.attribute_indices.extend(indices(buf, bytes)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(l)
+    }
+}
+
+impl Function {
+    fn encode(&self, e: &mut Encoder) {
+        e.int64(1, self.name_strindex as i64);
+        e.int64(2, self.system_name_strindex as i64);
+        e.int64(3, self.filename_strindex as i64);
+        e.int64(4, self.start_line);
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut f = Function::default();
+        fields(data, |buf, _| {
+            match buf.field {
+                1 => f.name_strindex = buf.u64 as i32,
+                2 => f.system_name_strindex = buf.u64 as i32,
+                3 => f.filename_strindex = buf.u64 as i32,
+                4 => f.start_line = buf.u64 as i64,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(f)
+    }
+}
+
+impl KeyValue {
+    fn encode(&self, e: &mut Encoder) {
+        e.string(1, &self.key);
+        e.message(2, |e| self.value.encode(e));
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut kv = KeyValue::default();
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => kv.key = string(bytes)?,
+                2 => kv.value = AnyValue::decode(&bytes)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(kv)
+    }
+}
+
+impl AnyValue {
+    // encode writes the oneof field, even if it has the default value.
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            AnyValue::Empty => {}
+            AnyValue::String(s) => e.put_bytes(1, s.as_bytes()),
+            AnyValue::Bool(b) => e.put_uint64(2, *b as u64),
+            AnyValue::Int(i) => e.put_uint64(3, *i as u64),
+            AnyValue::Double(d) => e.put_fixed64(4, d.to_bits()),
+            AnyValue::Bytes(b) => e.put_bytes(7, b),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, RockError> {
+        let mut v = AnyValue::Empty;
+        fields(data, |buf, bytes| {
+            match buf.field {
+                1 => v = AnyValue::String(string(bytes)?),
+                2 => v = AnyValue::Bool(buf.u64 != 0),
+                3 => v = AnyValue::Int(buf.u64 as i64),
+                4 => v = AnyValue::Double(f64::from_bits(buf.u64)),
+                7 => v = AnyValue::Bytes(bytes),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(v)
+    }
+}
+
+// Strings builds the string table of the dictionary.
+#[derive(Debug)]
+struct Strings {
+    table: Vec<String>,
+    index: HashMap<String, i32>,
+}
+
+impl Strings {
+    fn new() -> Self {
+        Strings {
+            table: vec![String::new()],
+            index: HashMap::from([(String::new(), 0)]),
+        }
+    }
+
+    fn get(&mut self, s: &str) -> i32 {
+        if let Some(i) = self.index.get(s) {
+            return *i;
+        }
+        self.table.push(s.to_string());
+        self.index
+            .insert(s.to_string(), self.table.len() as i32 - 1);
+        self.table.len() as i32 - 1
+    }
+}
+
+// Attributes builds the attribute table of the dictionary.
+#[derive(Debug)]
+struct Attributes {
+    table: Vec<KeyValueAndUnit>,
+    // key, formatted value and unit of the attributes
+    index: HashMap<(i32, String, i32), i32>,
+}
+
+impl Attributes {
+    fn new() -> Self {
+        Attributes {
+            table: vec![KeyValueAndUnit::default()],
+            index: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key_strindex: i32, value: AnyValue, unit_strindex: i32) -> i32 {
+        let key = (key_strindex, format!("{:?}", value), unit_strindex);
+        if let Some(i) = self.index.get(&key) {
+            return *i;
+        }
+        self.table.push(KeyValueAndUnit {
+            key_strindex,
+            value,
+            unit_strindex,
+        });
+        self.index.insert(key, self.table.len() as i32 - 1);
+        self.table.len() as i32 - 1
+    }
+}
+
+impl From<&profile::Profile> for ProfilesData {
+    fn from(p: &profile::Profile) -> Self {
+        let mut strings = Strings::new();
+        let mut attributes = Attributes::new();
+        let mut d = ProfilesDictionary {
+            mapping_table: vec![Mapping::default()],
+            location_table: vec![Location::default()],
+            function_table: vec![Function::default()],
+            link_table: vec![Link::default()],
+            stack_table: vec![Stack::default()],
+            ..Default::default()
+        };
+
+        let mut mappings = HashMap::new();
+        for m in p.mapping.iter() {
+            let mut attribute_indices = vec![];
+            if !m.build_id.is_empty() {
+                let key = strings.get(BUILD_ID_ATTRIBUTE);
+                attribute_indices.push(attributes.get(
+                    key,
+                    AnyValue::String(m.build_id.clone()),
+                    0,
+                ));
+            }
+            d.mapping_table.push(Mapping {
+                memory_start: m.memory_start,
+                memory_limit: m.memory_limit,
+                file_offset: m.memory_offset,
+                filename_strindex: strings.get(&m.filename),
+                attribute_indices,
+            });
+            mappings.insert(m.id, d.mapping_table.len() as i32 - 1);
+        }
+
+        let mut functions = HashMap::new();
+        for f in p.function.iter() {
+            d.function_table.push(Function {
+                name_strindex: strings.get(&f.name),
+                system_name_strindex: strings.get(&f.system_name),
+                filename_strindex: strings.get(&f.filename),
+                start_line: f.start_line,
+            });
+            functions.insert(f.id, d.function_table.len() as i32 - 1);
+        }
+
+        let mut locations = HashMap::new();
+        for l in p.location.iter() {
+            d.location_table.push(Location {
+                mapping_index: mappings.get(&l.mapping_index).copied().unwrap_or(0),
+                address: l.address,
+                lines: l
+                    .line
+                    .iter()
+                    .map(|line| Line {
+                        function_index: functions.get(&line.function_index).copied().unwrap_or(0),
+                        line: line.line,
+                        column: 0,
+                    })
+                    .collect(),
+                attribute_indices: vec![],
+            });
+            locations.insert(l.id, d.location_table.len() as i32 - 1);
+        }
+
+        // the stack and attributes of every sample
+        let mut stacks: HashMap<Vec<i32>, i32> = HashMap::from([(vec![], 0)]);
+        let mut samples = vec![];
+        for s in p.sample.iter() {
+            let stack: Vec<i32> = s
+                .location_index
+                .iter()
+                .filter_map(|id| locations.get(id).copied())
+                .collect();
+            let stack_index = match stacks.get(&stack) {
+                Some(i) => *i,
+                None => {
+                    d.stack_table.push(Stack {
+                        location_indices: stack.clone(),
+                    });
+                    let i = d.stack_table.len() as i32 - 1;
+                    stacks.insert(stack, i);
+                    i
+                }
+            };
+
+            let mut attribute_indices = vec![];
+            let mut keys: Vec<&String> = s.label.keys().chain(s.num_label.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let k = strings.get(key);
+                for v in s.label.get(key).into_iter().flatten() {
+                    attribute_indices.push(attributes.get(k, AnyValue::String(v.clone()), 0));
+                }
+                let units = s.num_unit_label.get(key);
+                for (i, v) in s.num_label.get(key).into_iter().flatten().enumerate() {
+                    let unit = units.and_then(|u| u.get(i)).map_or(0, |u| strings.get(u));
+                    attribute_indices.push(attributes.get(k, AnyValue::Int(*v), unit));
+                }
+            }
+            samples.push((stack_index, attribute_indices));
+        }
+
+        let value_type = |strings: &mut Strings, vt: &profile::value_type::ValueType| ValueType {
+            type_strindex: strings.get(&vt.r#type),
+            unit_strindex: strings.get(&vt.unit),
+        };
+        let period_type = p
+            .period_type
+            .as_ref()
+            .map(|pt| value_type(&mut strings, pt));
+
+        let mut profiles = vec![];
+        for (i, st) in p.sample_type.iter().enumerate() {
+            profiles.push(Profile {
+                sample_type: value_type(&mut strings, st),
+                samples: p
+                    .sample
+                    .iter()
+                    .zip(samples.iter())
+                    .filter(|(s, _)| s.value[i] != 0)
+                    .map(|(s, (stack_index, attribute_indices))| Sample {
+                        stack_index: *stack_index,
+                        values: vec![s.value[i]],
+                        attribute_indices: attribute_indices.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                time_unix_nano: p.time_nanos as u64,
+                duration_nano: p.duration_nanos as u64,
+                period_type: period_type.clone(),
+                period: p.period,
+                attribute_indices: vec![],
+            });
+        }
+
+        d.string_table = strings.table;
+        d.attribute_table = attributes.table;
+        ProfilesData {
+            resource_profiles: vec![ResourceProfiles {
+                scope_profiles: vec![ScopeProfiles {
+                    scope_name: SCOPE_NAME.to_string(),
+                    profiles,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            dictionary: d,
+        }
+    }
+}
+
+// get returns the entry of a dictionary table, or an error if the index is out of range.
+fn get<'a, T>(table: &'a [T], index: i32, name: &str) -> Result<&'a T, RockError> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| table.get(i))
+        .ok_or_else(|| RockError::MalformedProfile {
+            reason: format!(
+                "{} index {} out of range, table has {} entries",
+                name,
+                index,
+                table.len()
+            ),
+        })
+}
+
+impl TryFrom<&ProfilesData> for profile::Profile {
+    type Error = RockError;
+
+    fn try_from(data: &ProfilesData) -> Result<Self, Self::Error> {
+        let d = &data.dictionary;
+        let s = |i: i32| -> Result<&str, RockError> {
+            get(&d.string_table, i, "string").map(String::as_str)
+        };
+
+        let mut b = ProfileBuilder::new();
+
+        // builder ids of the table entries, index 0 stands for none
+        let mut mapping_ids = vec![0];
+        for m in d.mapping_table.iter().skip(1) {
+            let mut build_id = String::new();
+            for a in m.attribute_indices.iter() {
+                let a = get(&d.attribute_table, *a, "attribute")?;
+                if let (true, AnyValue::String(v)) = (
+                    s(a.key_strindex)?.starts_with("process.executable.build_id"),
+                    &a.value,
+                ) {
+                    build_id = v.clone();
+                }
+            }
+            mapping_ids.push(b.add_mapping(profile::mapping::Mapping {
+                memory_start: m.memory_start,
+                memory_limit: m.memory_limit,
+                memory_offset: m.file_offset,
+                filename: s(m.filename_strindex)?.to_string(),
+                build_id,
+                ..Default::default()
+            }));
+        }
+
+        let mut function_ids = vec![0];
+        for f in d.function_table.iter().skip(1) {
+            function_ids.push(b.add_function(
+                s(f.name_strindex)?,
+                s(f.system_name_strindex)?,
+                s(f.filename_strindex)?,
+                f.start_line,
+            ));
+        }
+
+        // symbolization flags of the mappings: functions, filenames, line numbers, inline frames
+        let mut flags: HashMap<u64, (bool, bool, bool, bool)> = HashMap::new();
+        let mut location_ids = vec![0];
+        for l in d.location_table.iter().skip(1) {
+            let mapping = *get(&mapping_ids, l.mapping_index, "mapping")?;
+            let mut lines = vec![];
+            let mut has_filenames = !l.lines.is_empty();
+            for line in l.lines.iter() {
+                let f = get(&d.function_table, line.function_index, "function")?;
+                has_filenames = has_filenames && f.filename_strindex != 0;
+                lines.push((
+                    *get(&function_ids, line.function_index, "function")?,
+                    line.line,
+                ));
+            }
+
+            let f = flags.entry(mapping).or_insert((true, true, true, false));
+            f.0 = f.0 && !lines.is_empty();
+            f.1 = f.1 && has_filenames;
+            f.2 = f.2 && !lines.is_empty() && lines.iter().all(|(_, line)| *line > 0);
+            f.3 = f.3 || lines.len() > 1;
+
+            location_ids.push(b.add_location(mapping, l.address, &lines));
+        }
+        for (id, (has_function, has_filenames, has_line_numbers, has_inline_frames)) in flags {
+            if let Some(m) = b.mapping(id) {
+                m.has_function = has_function;
+                m.has_filenames = has_filenames;
+                m.has_line_numbers = has_line_numbers;
+                m.has_inline_frames = has_inline_frames;
+            }
+        }
+
+        let profiles: Vec<&Profile> = data
+            .resource_profiles
+            .iter()
+            .flat_map(|rp| rp.scope_profiles.iter())
+            .flat_map(|sp| sp.profiles.iter())
+            .collect();
+        if profiles.is_empty() {
+            return Err(RockError::MalformedProfile {
+                reason: String::from("ProfilesData has no profiles"),
+            });
+        }
+
+        let mut start = u64::MAX;
+        let mut end = 0;
+        for p in profiles.iter() {
+            b.add_sample_type(
+                s(p.sample_type.type_strindex)?,
+                s(p.sample_type.unit_strindex)?,
+            );
+            if p.time_unix_nano != 0 {
+                start = start.min(p.time_unix_nano);
+                end = end.max(p.time_unix_nano.saturating_add(p.duration_nano));
+            }
+        }
+        if start != u64::MAX {
+            b.time_nanos(start as i64)
+                .duration_nanos((end - start) as i64);
+        }
+        if let Some(p) = profiles.iter().find(|p| p.period_type.is_some()) {
+            let pt = p.period_type.as_ref().unwrap();
+            b.period_type(s(pt.type_strindex)?, s(pt.unit_strindex)?, p.period);
+        }
+
+        // samples with the same stack and attributes in different profiles
+        // are the values of a single pprof sample
+        let mut samples: Vec<(i32, Vec<i32>, Vec<i64>)> = vec![];
+        let mut index: HashMap<(i32, Vec<i32>), usize> = HashMap::new();
+        for (i, p) in profiles.iter().enumerate() {
+            for sample in p.samples.iter() {
+                let mut attributes = sample.attribute_indices.clone();
+                attributes.sort_unstable();
+                let key = (sample.stack_index, attributes);
+                let j = *index.entry(key.clone()).or_insert_with(|| {
+                    samples.push((key.0, key.1, vec![0; profiles.len()]));
+                    samples.len() - 1
+                });
+                samples[j].2[i] += sample.values.iter().sum::<i64>();
+            }
+        }
+
+        for (stack_index, attributes, values) in samples {
+            let stack = get(&d.stack_table, stack_index, "stack")?;
+            let locations = stack
+                .location_indices
+                .iter()
+                .map(|i| get(&location_ids, *i, "location").copied())
+                .collect::<Result<Vec<u64>, RockError>>()?;
+
+            let mut labels = vec![];
+            for a in attributes {
+                let a = get(&d.attribute_table, a, "attribute")?;
+                labels.push((s(a.key_strindex)?, &a.value, s(a.unit_strindex)?));
+            }
+
+            let sample = b.add_sample(locations, values);
+            for (key, value, unit) in labels {
+                let value = match value {
+                    AnyValue::Int(v) => {
+                        sample
+                            .num_label
+                            .entry(key.to_string())
+                            .or_default()
+                            .push(*v);
+                        if !unit.is_empty() {
+                            sample
+                                .num_unit_label
+                                .entry(key.to_string())
+                                .or_default()
+                                .push(unit.to_string());
+                        }
+                        continue;
+                    }
+                    AnyValue::Empty => continue,
+                    AnyValue::String(v) => v.clone(),
+                    AnyValue::Bool(v) => v.to_string(),
+                    AnyValue::Double(v) => v.to_string(),
+                    AnyValue::Bytes(v) => v.iter().map(|b| format!("{:02x}", b)).collect(),
+                };
+                sample.label.entry(key.to_string()).or_default().push(value);
+            }
+        }
+
+        b.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::otlp::{self, AnyValue, ProfilesData};
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::errors::RockError;
+    use crate::profile::mapping::Mapping;
+    use std::fs;
+
+    // collector stands for an OTel collector: it decodes the exported
+    // message and checks the invariants of the dictionary.
+    fn collector(data: &[u8]) -> ProfilesData {
+        let d = ProfilesData::decode(data).unwrap();
+        assert_eq!(d.dictionary.string_table[0], "");
+        assert!(d.dictionary.stack_table[0].location_indices.is_empty());
+        assert_eq!(d.dictionary.mapping_table[0], Default::default());
+        assert_eq!(d.dictionary.location_table[0], Default::default());
+        assert_eq!(d.dictionary.function_table[0], Default::default());
+        assert_eq!(d.dictionary.attribute_table[0], Default::default());
+        d
+    }
+
+    #[test]
+    fn test_otlp_round_trip() {
+        let mut data = fs::read("tests/CPU.pb.gz").unwrap();
+        let p = Buffer::decode(&mut data).unwrap();
+
+        let exported = otlp::encode(&p);
+        let d = collector(&exported);
+        let profiles = &d.resource_profiles[0].scope_profiles[0].profiles;
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(
+            d.dictionary.string_table[profiles[1].sample_type.type_strindex as usize],
+            "cpu"
+        );
+        assert_eq!(d.encode(), exported);
+
+        let mut got = otlp::decode(&exported).unwrap();
+        // OTLP has no symbolization flags, they are derived from the locations
+        assert!(got.mapping[0].has_function && got.mapping[0].has_line_numbers);
+        got.mapping[0].has_filenames = p.mapping[0].has_filenames;
+        got.mapping[0].has_line_numbers = p.mapping[0].has_line_numbers;
+        assert_eq!(got.to_string(), p.to_string());
+    }
+
+    #[test]
+    fn test_otlp_labels() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("alloc_objects", "count")
+            .add_sample_type("alloc_space", "bytes");
+        let m = b.add_mapping(Mapping {
+            memory_start: 0x1000,
+            memory_limit: 0x2000,
+            filename: String::from("/bin/app"),
+            build_id: String::from("abcdef"),
+            ..Default::default()
+        });
+        let l = b.add_location(m, 0x1010, &[]);
+        let s = b.add_sample(vec![l], vec![0, 512]);
+        s.label
+            .insert(String::from("thread"), vec![String::from("main")]);
+        s.num_label.insert(String::from("bytes"), vec![512]);
+        s.num_unit_label
+            .insert(String::from("bytes"), vec![String::from("bytes")]);
+        let p = b.build().unwrap();
+
+        let d = collector(&otlp::encode(&p));
+        let profiles = &d.resource_profiles[0].scope_profiles[0].profiles;
+        // zero values are not exported
+        assert!(profiles[0].samples.is_empty());
+        let attributes: Vec<&AnyValue> = profiles[1].samples[0]
+            .attribute_indices
+            .iter()
+            .map(|i| &d.dictionary.attribute_table[*i as usize].value)
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![&AnyValue::Int(512), &AnyValue::String(String::from("main"))]
+        );
+
+        let got = otlp::decode(&d.encode()).unwrap();
+        assert_eq!(got.to_string(), p.to_string());
+        assert_eq!(got.mapping[0].build_id, "abcdef");
+    }
+
+    #[test]
+    fn test_otlp_decode_errors() {
+        assert!(matches!(
+            otlp::decode(&ProfilesData::default().encode()),
+            Err(RockError::MalformedProfile { .. })
+        ));
+
+        let mut p = ProfileBuilder::new();
+        p.add_sample_type("samples", "count");
+        p.add_sample(vec![], vec![1]);
+        let mut d = ProfilesData::from(&p.build().unwrap());
+        d.resource_profiles[0].scope_profiles[0].profiles[0].samples[0].stack_index = 7;
+        assert!(matches!(
+            otlp::decode(&d.encode()),
+            Err(RockError::MalformedProfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,892 @@
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::errors::RockError;
+use crate::profile::mapping::Mapping;
+use crate::profile::Profile;
+use std::collections::HashMap;
+
+// "PERFILE2" read as a little endian u64
+const MAGIC: u64 = 0x32454c4946524550;
+const KERNEL_DSO: &str = "[kernel.kallsyms]";
+// pid used by perf for the kernel mappings
+const KERNEL_PID: u32 = u32::MAX;
+
+// perf_event_header.type
+const PERF_RECORD_MMAP: u32 = 1;
+const PERF_RECORD_COMM: u32 = 3;
+const PERF_RECORD_FORK: u32 = 7;
+const PERF_RECORD_SAMPLE: u32 = 9;
+const PERF_RECORD_MMAP2: u32 = 10;
+
+// perf_event_header.misc
+const PERF_RECORD_MISC_MMAP_BUILD_ID: u16 = 1 << 14;
+const PERF_RECORD_MISC_BUILD_ID_SIZE: u16 = 1 << 15;
+
+// perf_event_attr.sample_type, in the order of the fields of a sample record
+const PERF_SAMPLE_IP: u64 = 1 << 0;
+const PERF_SAMPLE_TID: u64 = 1 << 1;
+const PERF_SAMPLE_TIME: u64 = 1 << 2;
+const PERF_SAMPLE_ADDR: u64 = 1 << 3;
+const PERF_SAMPLE_READ: u64 = 1 << 4;
+const PERF_SAMPLE_CALLCHAIN: u64 = 1 << 5;
+const PERF_SAMPLE_ID: u64 = 1 << 6;
+const PERF_SAMPLE_CPU: u64 = 1 << 7;
+const PERF_SAMPLE_PERIOD: u64 = 1 << 8;
+const PERF_SAMPLE_STREAM_ID: u64 = 1 << 9;
+const PERF_SAMPLE_IDENTIFIER: u64 = 1 << 16;
+
+// perf_event_attr.read_format
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_ID: u64 = 1 << 2;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+const PERF_FORMAT_LOST: u64 = 1 << 4;
+
+// perf_event_attr flags bit, set when sample_period holds a frequency
+const ATTR_FLAG_FREQ: u64 = 1 << 10;
+
+// callchain entries at and above PERF_CONTEXT_MAX mark the context
+// (kernel, user, guest) of the following addresses
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+// feature sections stored after the data section
+const HEADER_BUILD_ID: usize = 2;
+const HEADER_EVENT_DESC: usize = 12;
+
+// perf_event_attr.type
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+
+// Cursor reads the integers of a perf.data file in its byte order.
+#[derive(Debug, Clone)]
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], RockError> {
+        match self.data.get(self.pos..self.pos.saturating_add(n)) {
+            Some(b) => {
+                self.pos += n;
+                Ok(b)
+            }
+            None => Err(RockError::MalformedProfile {
+                reason: format!("perf.data truncated at offset {}", self.pos),
+            }),
+        }
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), RockError> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, RockError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RockError> {
+        let b = self.bytes(2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, RockError> {
+        let b = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&mut self) -> Result<u64, RockError> {
+        let b = self.bytes(8)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+
+    // section returns a cursor over the perf_file_section {offset, size}
+    // read at the current position. The offset is relative to the cursor data,
+    // so sections must be read through a cursor over the whole file.
+    fn section(&mut self) -> Result<Cursor<'a>, RockError> {
+        let offset = self.u64()? as usize;
+        let size = self.u64()? as usize;
+        let mut c = self.at(offset);
+        let data = c.bytes(size)?;
+        Ok(Cursor {
+            data,
+            pos: 0,
+            big_endian: self.big_endian,
+        })
+    }
+
+    fn at(&self, pos: usize) -> Cursor<'a> {
+        Cursor {
+            data: self.data,
+            pos,
+            big_endian: self.big_endian,
+        }
+    }
+
+    // string reads a NUL terminated string padded to the end of the cursor,
+    // as the file names of the mmap and comm records.
+    fn string(&mut self) -> String {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let end = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        self.pos = self.data.len();
+        String::from_utf8_lossy(&rest[..end]).to_string()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Attr {
+    r#type: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    ids: Vec<u64>,
+    name: Option<String>,
+}
+
+impl Attr {
+    fn parse(c: &mut Cursor) -> Result<Attr, RockError> {
+        let start = c.pos;
+        let r#type = c.u32()?;
+        let size = c.u32()? as usize;
+        let attr = Attr {
+            r#type,
+            config: c.u64()?,
+            sample_period: c.u64()?,
+            sample_type: c.u64()?,
+            read_format: c.u64()?,
+            flags: c.u64()?,
+            ..Default::default()
+        };
+        c.pos = start + size;
+        Ok(attr)
+    }
+
+    // event_name returns the name recorded in the header or, if absent,
+    // the name of the generic hardware and software events.
+    fn event_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let name = match (self.r#type, self.config) {
+            (PERF_TYPE_HARDWARE, 0) => "cycles",
+            (PERF_TYPE_HARDWARE, 1) => "instructions",
+            (PERF_TYPE_HARDWARE, 2) => "cache-references",
+            (PERF_TYPE_HARDWARE, 3) => "cache-misses",
+            (PERF_TYPE_HARDWARE, 4) => "branches",
+            (PERF_TYPE_HARDWARE, 5) => "branch-misses",
+            (PERF_TYPE_SOFTWARE, 0) => "cpu-clock",
+            (PERF_TYPE_SOFTWARE, 1) => "task-clock",
+            (PERF_TYPE_SOFTWARE, 2) => "page-faults",
+            (PERF_TYPE_SOFTWARE, 3) => "context-switches",
+            (PERF_TYPE_SOFTWARE, 4) => "cpu-migrations",
+            (t, c) => return format!("event-{}:{:#x}", t, c),
+        };
+        name.to_string()
+    }
+
+    // unit of the sample periods: the clock events count nanoseconds.
+    fn unit(&self) -> &'static str {
+        match (self.r#type, self.config) {
+            (PERF_TYPE_SOFTWARE, 0) | (PERF_TYPE_SOFTWARE, 1) => "nanoseconds",
+            _ => "count",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Mmap {
+    start: u64,
+    len: u64,
+    pgoff: u64,
+    filename: String,
+    build_id: Option<String>,
+}
+
+#[derive(Debug)]
+struct SampleRecord {
+    attr: usize,
+    pid: u32,
+    tid: u32,
+    time: Option<u64>,
+    period: u64,
+    // leaf first
+    ips: Vec<u64>,
+}
+
+// is_perf_data reports whether the data starts with the perf.data file magic.
+pub fn is_perf_data(data: &[u8]) -> bool {
+    data.len() >= 8 && {
+        let magic = u64::from_le_bytes(data[..8].try_into().unwrap());
+        magic == MAGIC || magic.swap_bytes() == MAGIC
+    }
+}
+
+// parse_perf_data reads a perf.data file written by `perf record`. The profile
+// has a mapping for every mmap-ed file a sample points to, with the memory
+// range, file offset and build id from the MMAP2 records or the build id header,
+// and an unsymbolized location for every sampled address. As in the import of
+// `perf script` output, the pid, tid and command name are the sample labels,
+// the first sample type counts the samples and the next ones sum the periods of
+// each event.
+pub fn parse_perf_data(data: &[u8]) -> Result<Profile, RockError> {
+    if !is_perf_data(data) {
+        return Err(RockError::UnrecognizedFormat {
+            reason: String::from("missing perf.data magic"),
+        });
+    }
+
+    let mut c = Cursor {
+        data,
+        pos: 8,
+        big_endian: u64::from_le_bytes(data[..8].try_into().unwrap()) != MAGIC,
+    };
+    let _header_size = c.u64()?;
+    let attr_size = c.u64()? as usize;
+    let attrs_offset = c.u64()? as usize;
+    let attrs_size = c.u64()? as usize;
+    // the feature sections follow the data section
+    let features_offset = {
+        let mut d = c.clone();
+        (d.u64()? as usize).saturating_add(d.u64()? as usize)
+    };
+    let mut data_section = c.section()?;
+    let _event_types = c.section()?;
+    let mut features = [0u64; 4];
+    for f in features.iter_mut() {
+        *f = c.u64()?;
+    }
+
+    if attr_size < 64 {
+        return Err(RockError::MalformedProfile {
+            reason: format!("invalid perf.data attr size {}", attr_size),
+        });
+    }
+    let mut attrs = vec![];
+    for i in 0..attrs_size / attr_size {
+        let start = i
+            .checked_mul(attr_size)
+            .and_then(|offset| attrs_offset.checked_add(offset));
+        // the attr is followed by the section with its event ids
+        let ids_start = start.and_then(|start| start.checked_add(attr_size - 16));
+        let (Some(start), Some(ids_start)) = (start, ids_start) else {
+            return Err(RockError::MalformedProfile {
+                reason: format!("perf.data attr {} offset overflows", i),
+            });
+        };
+        let mut attr = Attr::parse(&mut c.at(start))?;
+        let mut ids = c.at(ids_start).section()?;
+        while !ids.is_empty() {
+            attr.ids.push(ids.u64()?);
+        }
+        attrs.push(attr);
+    }
+    if attrs.is_empty() {
+        return Err(RockError::MalformedProfile {
+            reason: String::from("perf.data has no event attributes"),
+        });
+    }
+
+    // one feature section for every bit set in the header
+    let mut build_ids = HashMap::new();
+    let mut table = c.at(features_offset);
+    for bit in 0..256 {
+        if features[bit / 64] & (1 << (bit % 64)) == 0 {
+            continue;
+        }
+        let mut section = table.section()?;
+        match bit {
+            HEADER_BUILD_ID => parse_build_ids(&mut section, &mut build_ids)?,
+            HEADER_EVENT_DESC => parse_event_desc(&mut section, &mut attrs)?,
+            _ => {}
+        }
+    }
+
+    let mut comms: HashMap<u32, String> = HashMap::new();
+    // all the mmaps of the file, and the indexes of the mmaps of each process
+    let mut mmaps: Vec<Mmap> = vec![];
+    let mut process_mmaps: HashMap<u32, Vec<usize>> = HashMap::new();
+    // samples with the command name and the mmap of every address
+    let mut samples: Vec<(SampleRecord, String, Vec<Option<usize>>)> = vec![];
+    while !data_section.is_empty() {
+        let r#type = data_section.u32()?;
+        let misc = data_section.u16()?;
+        let size = data_section.u16()? as usize;
+        if size < 8 {
+            return Err(RockError::MalformedProfile {
+                reason: format!("invalid perf.data record size {}", size),
+            });
+        }
+        let mut r = Cursor {
+            data: data_section.bytes(size - 8)?,
+            pos: 0,
+            big_endian: c.big_endian,
+        };
+
+        match r#type {
+            PERF_RECORD_COMM => {
+                let _pid = r.u32()?;
+                let tid = r.u32()?;
+                comms.insert(tid, r.string());
+            }
+            PERF_RECORD_MMAP | PERF_RECORD_MMAP2 => {
+                let pid = r.u32()?;
+                let _tid = r.u32()?;
+                let mut m = Mmap {
+                    start: r.u64()?,
+                    len: r.u64()?,
+                    pgoff: r.u64()?,
+                    filename: String::new(),
+                    build_id: None,
+                };
+                if r#type == PERF_RECORD_MMAP2 {
+                    if misc & PERF_RECORD_MISC_MMAP_BUILD_ID != 0 {
+                        let size = r.u8()? as usize;
+                        r.skip(3)?;
+                        m.build_id = Some(hex(&r.bytes(20)?[..size.min(20)]));
+                    } else {
+                        // maj, min, ino, ino_generation
+                        r.skip(24)?;
+                    }
+                    // prot, flags
+                    r.skip(8)?;
+                }
+                m.filename = r.string();
+                if m.filename.starts_with(KERNEL_DSO) {
+                    m.filename = KERNEL_DSO.to_string();
+                }
+                mmaps.push(m);
+                process_mmaps.entry(pid).or_default().push(mmaps.len() - 1);
+            }
+            PERF_RECORD_FORK => {
+                let pid = r.u32()?;
+                let ppid = r.u32()?;
+                if pid != ppid && !process_mmaps.contains_key(&pid) {
+                    if let Some(parent) = process_mmaps.get(&ppid).cloned() {
+                        process_mmaps.insert(pid, parent);
+                    }
+                }
+            }
+            PERF_RECORD_SAMPLE => {
+                let s = parse_sample(&mut r, &attrs)?;
+                let comm = comms.get(&s.tid).cloned().unwrap_or_default();
+                // addresses are resolved with the mmaps known when the sample was taken
+                let resolved = s
+                    .ips
+                    .iter()
+                    .map(|ip| find_mmap(&mmaps, &process_mmaps, s.pid, *ip))
+                    .collect();
+                samples.push((s, comm, resolved));
+            }
+            _ => {}
+        }
+    }
+
+    build_profile(&attrs, &samples, &mmaps, &build_ids)
+}
+
+// parse_sample reads the fields of a PERF_RECORD_SAMPLE up to the callchain,
+// the fields after it are not used.
+fn parse_sample(r: &mut Cursor, attrs: &[Attr]) -> Result<SampleRecord, RockError> {
+    let attr = sample_attr(r.clone(), attrs)?;
+    let st = attrs[attr].sample_type;
+
+    let mut s = SampleRecord {
+        attr,
+        pid: 0,
+        tid: 0,
+        time: None,
+        period: 1,
+        ips: vec![],
+    };
+    if attrs[attr].flags & ATTR_FLAG_FREQ == 0 && attrs[attr].sample_period != 0 {
+        s.period = attrs[attr].sample_period;
+    }
+
+    if st & PERF_SAMPLE_IDENTIFIER != 0 {
+        r.skip(8)?;
+    }
+    let ip = if st & PERF_SAMPLE_IP != 0 {
+        Some(r.u64()?)
+    } else {
+        None
+    };
+    if st & PERF_SAMPLE_TID != 0 {
+        s.pid = r.u32()?;
+        s.tid = r.u32()?;
+    }
+    if st & PERF_SAMPLE_TIME != 0 {
+        s.time = Some(r.u64()?);
+    }
+    for field in [
+        PERF_SAMPLE_ADDR,
+        PERF_SAMPLE_ID,
+        PERF_SAMPLE_STREAM_ID,
+        PERF_SAMPLE_CPU,
+    ] {
+        if st & field != 0 {
+            r.skip(8)?;
+        }
+    }
+    if st & PERF_SAMPLE_PERIOD != 0 {
+        s.period = r.u64()?;
+    }
+    if st & PERF_SAMPLE_READ != 0 {
+        skip_read_values(r, attrs[attr].read_format)?;
+    }
+
+    if st & PERF_SAMPLE_CALLCHAIN != 0 {
+        let nr = r.u64()?;
+        for _ in 0..nr {
+            let addr = r.u64()?;
+            if addr >= PERF_CONTEXT_MAX {
+                continue;
+            }
+            // the callers are return addresses, moved back into the call
+            // instruction as pprof and perf script do
+            if s.ips.is_empty() {
+                s.ips.push(addr);
+            } else {
+                s.ips.push(addr.saturating_sub(1));
+            }
+        }
+    } else if let Some(ip) = ip {
+        s.ips.push(ip);
+    }
+    Ok(s)
+}
+
+// sample_attr returns the index of the attr a sample belongs to, looking up
+// the event id stored in the sample when the file has more than one event.
+fn sample_attr(mut r: Cursor, attrs: &[Attr]) -> Result<usize, RockError> {
+    if attrs.len() == 1 {
+        return Ok(0);
+    }
+
+    // the position of the id is the same for all the events of a file
+    let st = attrs[0].sample_type;
+    let id = if st & PERF_SAMPLE_IDENTIFIER != 0 {
+        r.u64()?
+    } else if st & PERF_SAMPLE_ID != 0 {
+        for field in [
+            PERF_SAMPLE_IP,
+            PERF_SAMPLE_TID,
+            PERF_SAMPLE_TIME,
+            PERF_SAMPLE_ADDR,
+        ] {
+            if st & field != 0 {
+                r.skip(8)?;
+            }
+        }
+        r.u64()?
+    } else {
+        return Err(RockError::MalformedProfile {
+            reason: String::from("perf.data samples of several events have no event id"),
+        });
+    };
+
+    attrs
+        .iter()
+        .position(|a| a.ids.contains(&id))
+        .ok_or_else(|| RockError::MalformedProfile {
+            reason: format!("perf.data sample has unknown event id {}", id),
+        })
+}
+
+// skip_read_values skips the counter values of a PERF_SAMPLE_READ sample.
+fn skip_read_values(r: &mut Cursor, read_format: u64) -> Result<(), RockError> {
+    let mut value_size = 8;
+    for field in [PERF_FORMAT_ID, PERF_FORMAT_LOST] {
+        if read_format & field != 0 {
+            value_size += 8;
+        }
+    }
+    let times = [
+        PERF_FORMAT_TOTAL_TIME_ENABLED,
+        PERF_FORMAT_TOTAL_TIME_RUNNING,
+    ]
+    .iter()
+    .filter(|f| read_format & **f != 0)
+    .count();
+
+    if read_format & PERF_FORMAT_GROUP != 0 {
+        let nr = r.u64()? as usize;
+        r.skip(times * 8)?;
+        r.skip(nr.saturating_mul(value_size))
+    } else {
+        r.skip(value_size + times * 8)
+    }
+}
+
+// find_mmap returns the most recent mmap of the process, or of the kernel,
+// containing the address.
+fn find_mmap(
+    mmaps: &[Mmap],
+    process_mmaps: &HashMap<u32, Vec<usize>>,
+    pid: u32,
+    addr: u64,
+) -> Option<usize> {
+    [pid, KERNEL_PID].iter().find_map(|pid| {
+        process_mmaps.get(pid)?.iter().rev().copied().find(|i| {
+            let m = &mmaps[*i];
+            addr >= m.start && addr - m.start < m.len
+        })
+    })
+}
+
+// parse_build_ids reads the build_id_event entries of the HEADER_BUILD_ID
+// feature section into a map from file name to build id.
+fn parse_build_ids(
+    c: &mut Cursor,
+    build_ids: &mut HashMap<String, String>,
+) -> Result<(), RockError> {
+    while !c.is_empty() {
+        let _type = c.u32()?;
+        let misc = c.u16()?;
+        let size = c.u16()? as usize;
+        if size < 8 {
+            return Err(RockError::MalformedProfile {
+                reason: format!("invalid perf.data build id size {}", size),
+            });
+        }
+        let mut e = Cursor {
+            data: c.bytes(size - 8)?,
+            pos: 0,
+            big_endian: c.big_endian,
+        };
+        let _pid = e.u32()?;
+        let id = e.bytes(24)?;
+        let len = if misc & PERF_RECORD_MISC_BUILD_ID_SIZE != 0 {
+            (id[20] as usize).min(20)
+        } else {
+            20
+        };
+        build_ids.insert(e.string(), hex(&id[..len]));
+    }
+    Ok(())
+}
+
+// parse_event_desc reads the event names of the HEADER_EVENT_DESC feature
+// section. The events are stored in the same order as the attrs.
+fn parse_event_desc(c: &mut Cursor, attrs: &mut [Attr]) -> Result<(), RockError> {
+    let nr = c.u32()? as usize;
+    let attr_size = c.u32()? as usize;
+    for i in 0..nr {
+        c.skip(attr_size)?;
+        let nr_ids = c.u32()? as usize;
+        let len = c.u32()? as usize;
+        let name = Cursor {
+            data: c.bytes(len)?,
+            pos: 0,
+            big_endian: c.big_endian,
+        }
+        .string();
+        c.skip(nr_ids.saturating_mul(8))?;
+        if let Some(attr) = attrs.get_mut(i) {
+            attr.name = Some(name);
+        }
+    }
+    Ok(())
+}
+
+fn build_profile(
+    attrs: &[Attr],
+    samples: &[(SampleRecord, String, Vec<Option<usize>>)],
+    mmaps: &[Mmap],
+    build_ids: &HashMap<String, String>,
+) -> Result<Profile, RockError> {
+    let mut b = ProfileBuilder::new();
+    b.add_sample_type("samples", "count");
+    for attr in attrs.iter() {
+        b.add_sample_type(&attr.event_name(), attr.unit());
+    }
+
+    let times: Vec<u64> = samples.iter().filter_map(|(s, _, _)| s.time).collect();
+    if let (Some(start), Some(end)) = (times.iter().min(), times.iter().max()) {
+        b.duration_nanos((end - start) as i64);
+    }
+
+    // mappings are created for the mmaps used by samples only
+    let mut mapping_ids: HashMap<(u64, u64, u64, &str), u64> = HashMap::new();
+
+    // identical stacks of the same thread are aggregated into a single sample
+    let mut aggregated: Vec<(Vec<u64>, &SampleRecord, &str, Vec<i64>)> = vec![];
+    let mut index: HashMap<(Vec<u64>, u32, u32, &str), usize> = HashMap::new();
+    for (s, comm, resolved) in samples.iter() {
+        let mut locations = vec![];
+        for (ip, mmap) in s.ips.iter().zip(resolved.iter()) {
+            let mapping_id = match mmap {
+                None => 0,
+                Some(i) => {
+                    let m = &mmaps[*i];
+                    let key = (m.start, m.len, m.pgoff, m.filename.as_str());
+                    *mapping_ids.entry(key).or_insert_with(|| {
+                        b.add_mapping(Mapping {
+                            memory_start: m.start,
+                            memory_limit: m.start.saturating_add(m.len),
+                            memory_offset: m.pgoff,
+                            filename: m.filename.clone(),
+                            build_id: m
+                                .build_id
+                                .clone()
+                                .or_else(|| build_ids.get(&m.filename).cloned())
+                                .unwrap_or_default(),
+                            ..Default::default()
+                        })
+                    })
+                }
+            };
+            locations.push(b.add_location(mapping_id, *ip, &[]));
+        }
+
+        let key = (locations.clone(), s.pid, s.tid, comm.as_str());
+        let i = *index.entry(key).or_insert_with(|| {
+            aggregated.push((locations, s, comm, vec![0; attrs.len() + 1]));
+            aggregated.len() - 1
+        });
+        aggregated[i].3[0] += 1;
+        aggregated[i].3[s.attr + 1] += s.period as i64;
+    }
+
+    for (locations, s, comm, values) in aggregated {
+        let sample = b.add_sample(locations, values);
+        sample
+            .label
+            .insert(String::from("comm"), vec![comm.to_string()]);
+        sample
+            .num_label
+            .insert(String::from("pid"), vec![s.pid as i64]);
+        sample
+            .num_label
+            .insert(String::from("tid"), vec![s.tid as i64]);
+    }
+
+    b.build()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::perf::data::{is_perf_data, parse_perf_data, MAGIC};
+    use crate::profile::errors::RockError;
+    use std::fs;
+
+    const CONTEXT_KERNEL: u64 = -128i64 as u64;
+    const CONTEXT_USER: u64 = -512i64 as u64;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut b = s.as_bytes().to_vec();
+        b.resize((s.len() / 8 + 1) * 8, 0);
+        b
+    }
+
+    fn record(r#type: u32, misc: u16, body: Vec<u8>) -> Vec<u8> {
+        let mut b = r#type.to_le_bytes().to_vec();
+        b.extend(misc.to_le_bytes());
+        b.extend(((body.len() + 8) as u16).to_le_bytes());
+        b.extend(body);
+        b
+    }
+
+    fn words(words: &[u64]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn comm(pid: u32, tid: u32, name: &str) -> Vec<u8> {
+        let mut b = pid.to_le_bytes().to_vec();
+        b.extend(tid.to_le_bytes());
+        b.extend(string(name));
+        record(3, 0, b)
+    }
+
+    fn mmap2(
+        pid: u32,
+        addr: u64,
+        len: u64,
+        pgoff: u64,
+        build_id: Option<u8>,
+        name: &str,
+    ) -> Vec<u8> {
+        let mut b = pid.to_le_bytes().to_vec();
+        b.extend(pid.to_le_bytes());
+        b.extend(words(&[addr, len, pgoff]));
+        let misc = match build_id {
+            Some(byte) => {
+                b.extend([20, 0, 0, 0]);
+                b.extend([byte; 20]);
+                1 << 14
+            }
+            None => {
+                b.extend([0; 24]);
+                0
+            }
+        };
+        b.extend([0; 8]);
+        b.extend(string(name));
+        record(10, misc, b)
+    }
+
+    // sample with IDENTIFIER, IP, TID, TIME, PERIOD and CALLCHAIN fields
+    fn sample(id: u64, pid: u32, tid: u32, time: u64, period: u64, callchain: &[u64]) -> Vec<u8> {
+        let ip = *callchain.iter().find(|a| **a < CONTEXT_USER).unwrap();
+        let mut b = words(&[id, ip]);
+        b.extend(pid.to_le_bytes());
+        b.extend(tid.to_le_bytes());
+        b.extend(words(&[time, period, callchain.len() as u64]));
+        b.extend(words(callchain));
+        record(9, 2, b)
+    }
+
+    // perf_data writes a perf.data file with a cpu-clock and a cycles event,
+    // the build id of libc in the header and the event names.
+    fn perf_data() -> Vec<u8> {
+        let sample_type = 1 << 16 | 1 | 1 << 1 | 1 << 2 | 1 << 8 | 1 << 5;
+
+        let mut data = vec![];
+        data.extend(comm(100, 100, "myapp"));
+        data.extend(comm(100, 101, "worker"));
+        data.extend(mmap2(
+            100,
+            0x400000,
+            0x10000,
+            0,
+            Some(0xab),
+            "/usr/bin/myapp",
+        ));
+        data.extend(mmap2(
+            100,
+            0x7f0000000000,
+            0x200000,
+            0x1000,
+            None,
+            "/lib/libc.so.6",
+        ));
+        let mut kernel = u32::MAX.to_le_bytes().to_vec();
+        kernel.extend(u32::MAX.to_le_bytes());
+        kernel.extend(words(&[0xffffffff81000000, 0x1000000, 0xffffffff81000000]));
+        kernel.extend(string("[kernel.kallsyms]_text"));
+        data.extend(record(1, 1, kernel));
+        let user = [CONTEXT_USER, 0x401010, 0x400500, 0x7f0000001234];
+        data.extend(sample(1, 100, 100, 1_000_000_000, 250000, &user));
+        data.extend(sample(1, 100, 100, 1_000_250_000, 250000, &user));
+        data.extend(sample(
+            2,
+            100,
+            101,
+            1_000_500_000,
+            10000,
+            &[
+                CONTEXT_KERNEL,
+                0xffffffff81001000,
+                CONTEXT_USER,
+                0x400600,
+                0x7fff00000000,
+            ],
+        ));
+
+        let mut build_id = (-1i32).to_le_bytes().to_vec();
+        build_id.extend([0xcd; 20]);
+        build_id.extend([0; 4]);
+        build_id.extend(string("/lib/libc.so.6"));
+        let build_ids = record(67, 0, build_id);
+
+        let mut event_desc = words(&[]);
+        event_desc.extend(2u32.to_le_bytes());
+        event_desc.extend(64u32.to_le_bytes());
+        for (name, id) in [("cpu-clock", 1u64), ("cycles:u", 2)] {
+            event_desc.extend([0; 64]);
+            event_desc.extend(1u32.to_le_bytes());
+            event_desc.extend((string(name).len() as u32).to_le_bytes());
+            event_desc.extend(string(name));
+            event_desc.extend(id.to_le_bytes());
+        }
+
+        // header, two attrs and their ids, data, feature sections
+        let attrs_offset = 104u64;
+        let ids_offset = attrs_offset + 2 * 80;
+        let data_offset = ids_offset + 16;
+        let features_offset = data_offset + data.len() as u64;
+        let build_ids_offset = features_offset + 2 * 16;
+        let event_desc_offset = build_ids_offset + build_ids.len() as u64;
+
+        let mut f = words(&[
+            MAGIC,
+            104,
+            80,
+            attrs_offset,
+            160,
+            data_offset,
+            data.len() as u64,
+            0,
+            0,
+        ]);
+        f.extend(words(&[1 << 2 | 1 << 12, 0, 0, 0]));
+        for (r#type, config, id) in [(1u32, 0u64, 0u64), (0, 0, 1)] {
+            f.extend(r#type.to_le_bytes());
+            f.extend(64u32.to_le_bytes());
+            f.extend(words(&[config, 4000, sample_type, 0, 1 << 10, 0, 0]));
+            f.extend(words(&[ids_offset + id * 8, 8]));
+        }
+        f.extend(words(&[1, 2]));
+        f.extend(data);
+        f.extend(words(&[build_ids_offset, build_ids.len() as u64]));
+        f.extend(words(&[event_desc_offset, event_desc.len() as u64]));
+        f.extend(build_ids);
+        f.extend(event_desc);
+        f
+    }
+
+    #[test]
+    fn parse_perf_data_golden() {
+        let data = perf_data();
+        assert!(is_perf_data(&data));
+
+        let p = parse_perf_data(&data).unwrap();
+        let want = fs::read_to_string("tests/perf/perf.data.string").unwrap();
+        assert_eq!(p.to_string(), want);
+        assert_eq!(p.mapping[0].build_id, "ab".repeat(20));
+    }
+
+    #[test]
+    fn parse_perf_data_errors() {
+        assert!(matches!(
+            parse_perf_data(b"not a perf.data file"),
+            Err(RockError::UnrecognizedFormat { .. })
+        ));
+
+        let data = perf_data();
+        assert!(matches!(
+            parse_perf_data(&data[..200]),
+            Err(RockError::MalformedProfile { .. })
+        ));
+
+        // attrs offset at the end of the address space
+        let mut overflow = data.clone();
+        overflow[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            parse_perf_data(&overflow),
+            Err(RockError::MalformedProfile { .. })
+        ));
+    }
+}
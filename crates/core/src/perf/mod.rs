@@ -0,0 +1,6 @@
+// Import of the profiles recorded by the Linux perf tool.
+// data -- perf.data files written by `perf record`
+// script -- text output of the `perf script` command
+
+pub mod data;
+pub mod script;
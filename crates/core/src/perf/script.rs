@@ -0,0 +1,350 @@
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::errors::RockError;
+use crate::profile::mapping::Mapping;
+use crate::profile::Profile;
+use regex::Regex;
+use std::collections::HashMap;
+
+// Header line of a record, for example:
+// "myapp 1234/1235 [002] 12345.678950:     250000 cpu-clock:u: "
+// The command name may contain spaces, the tid, cpu, timestamp and period
+// are only printed when the corresponding fields were requested.
+const HEADER: &str = r"^(\S.*?)\s+(\d+)(?:/(\d+))?\s+(?:\[(\d+)\]\s+)?(?:(\d+\.\d+):\s+)?(?:(\d+)\s+)?(\S+?):?(?:\s+(.*))?$";
+
+// DSO names perf uses when the object of an address is not known.
+const UNKNOWN_DSO: &str = "[unknown]";
+const UNKNOWN_SYMBOL: &str = "[unknown]";
+
+// events measured in nanoseconds, all the other events are counted
+const CLOCK_EVENTS: [&str; 2] = ["cpu-clock", "task-clock"];
+
+#[derive(Debug)]
+struct Record {
+    comm: String,
+    pid: i64,
+    tid: i64,
+    time_nanos: Option<i64>,
+    period: i64,
+    event: String,
+    // leaf first
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    address: u64,
+    symbol: Option<String>,
+    dso: String,
+}
+
+// is_perf_script reports whether the data looks like the output of `perf script`:
+// the first record header is followed by an indented stack frame.
+pub fn is_perf_script(data: &[u8]) -> bool {
+    let header = Regex::new(HEADER).unwrap();
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.starts_with('#'));
+
+    match (lines.next(), lines.next()) {
+        (Some(h), Some(f)) => header.is_match(h) && parse_frame(f).is_some(),
+        _ => false,
+    }
+}
+
+// parse_perf_script parses the text produced by `perf script` into a profile.
+// Every DSO becomes a mapping and every distinct address of a DSO a location.
+// The pid, tid and command name of the sampled thread are kept as sample labels.
+// The first sample type counts the samples, the next ones sum the periods of
+// each recorded event.
+pub fn parse_perf_script(data: &[u8]) -> Result<Profile, RockError> {
+    let records = parse_records(data)?;
+    if records.is_empty() {
+        return Err(RockError::UnrecognizedFormat {
+            reason: String::from("no perf script samples found"),
+        });
+    }
+
+    let mut b = ProfileBuilder::new();
+    b.add_sample_type("samples", "count");
+
+    let mut events: Vec<&str> = vec![];
+    for r in records.iter() {
+        if !events.contains(&r.event.as_str()) {
+            events.push(&r.event);
+        }
+    }
+    for event in events.iter() {
+        // the event name may carry modifiers, as in "cpu-clock:u"
+        let name = event.split(':').next().unwrap_or_default();
+        let unit = if CLOCK_EVENTS.contains(&name) {
+            "nanoseconds"
+        } else {
+            "count"
+        };
+        b.add_sample_type(event, unit);
+    }
+
+    let times: Vec<i64> = records.iter().filter_map(|r| r.time_nanos).collect();
+    if let (Some(start), Some(end)) = (times.iter().min(), times.iter().max()) {
+        b.duration_nanos(end - start);
+    }
+
+    let mappings = add_mappings(&mut b, &records);
+
+    // identical stacks of the same thread are aggregated into a single sample
+    let mut samples: Vec<(Vec<u64>, &Record, Vec<i64>)> = vec![];
+    let mut index: HashMap<(Vec<u64>, i64, i64, &str), usize> = HashMap::new();
+    for r in records.iter() {
+        let locations: Vec<u64> = r
+            .frames
+            .iter()
+            .map(|f| {
+                let mapping_id = mappings.get(f.dso.as_str()).copied().unwrap_or(0);
+                let lines = match &f.symbol {
+                    Some(symbol) => vec![(b.add_function(symbol, symbol, "", 0), 0)],
+                    None => vec![],
+                };
+                b.add_location(mapping_id, f.address, &lines)
+            })
+            .collect();
+
+        let event = events.iter().position(|e| *e == r.event).unwrap();
+        let key = (locations.clone(), r.pid, r.tid, r.comm.as_str());
+        let i = *index.entry(key).or_insert_with(|| {
+            samples.push((locations, r, vec![0; events.len() + 1]));
+            samples.len() - 1
+        });
+        samples[i].2[0] += 1;
+        samples[i].2[event + 1] += r.period;
+    }
+
+    for (locations, r, values) in samples {
+        let s = b.add_sample(locations, values);
+        s.label.insert(String::from("comm"), vec![r.comm.clone()]);
+        s.num_label.insert(String::from("pid"), vec![r.pid]);
+        s.num_label.insert(String::from("tid"), vec![r.tid]);
+    }
+
+    b.build()
+}
+
+// add_mappings creates a mapping for every known DSO, covering the addresses
+// sampled in it, and returns the mapping ids by DSO name.
+fn add_mappings<'a>(b: &mut ProfileBuilder, records: &'a [Record]) -> HashMap<&'a str, u64> {
+    let mut order: Vec<&str> = vec![];
+    // lowest address, highest address, all frames symbolized
+    let mut ranges: HashMap<&str, (u64, u64, bool)> = HashMap::new();
+    for f in records.iter().flat_map(|r| r.frames.iter()) {
+        if f.dso.is_empty() || f.dso == UNKNOWN_DSO {
+            continue;
+        }
+        let range = ranges.entry(&f.dso).or_insert_with(|| {
+            order.push(&f.dso);
+            (f.address, f.address, true)
+        });
+        range.0 = range.0.min(f.address);
+        range.1 = range.1.max(f.address);
+        range.2 = range.2 && f.symbol.is_some();
+    }
+
+    let mut ids = HashMap::new();
+    for dso in order {
+        let (start, end, symbolized) = ranges[dso];
+        let id = b.add_mapping(Mapping {
+            memory_start: start,
+            memory_limit: end.saturating_add(1),
+            filename: dso.to_string(),
+            has_function: symbolized,
+            ..Default::default()
+        });
+        ids.insert(dso, id);
+    }
+    ids
+}
+
+fn parse_records(data: &[u8]) -> Result<Vec<Record>, RockError> {
+    let header = Regex::new(HEADER).unwrap();
+    let text = String::from_utf8_lossy(data);
+
+    let mut records: Vec<Record> = vec![];
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            let frame = match parse_frame(line) {
+                Some(frame) => frame,
+                // source lines and other extra fields printed under the frames
+                None => continue,
+            };
+            match records.last_mut() {
+                Some(r) => r.frames.push(frame),
+                None => {
+                    return Err(RockError::MalformedProfile {
+                        reason: format!("line {}: stack frame without a sample header", n + 1),
+                    })
+                }
+            }
+            continue;
+        }
+
+        let caps = match header.captures(line) {
+            Some(caps) => caps,
+            None if records.is_empty() => {
+                return Err(RockError::UnrecognizedFormat {
+                    reason: format!("line {}: not a perf script sample header", n + 1),
+                })
+            }
+            None => {
+                return Err(RockError::MalformedProfile {
+                    reason: format!("line {}: unexpected line {:?}", n + 1, line),
+                })
+            }
+        };
+
+        let number = |i: usize| -> Result<Option<i64>, RockError> {
+            match caps.get(i) {
+                None => Ok(None),
+                Some(m) => {
+                    m.as_str()
+                        .parse()
+                        .map(Some)
+                        .map_err(|err| RockError::MalformedProfile {
+                            reason: format!("line {}: {}: {}", n + 1, m.as_str(), err),
+                        })
+                }
+            }
+        };
+
+        let pid = number(2)?.unwrap_or_default();
+        let mut r = Record {
+            comm: caps[1].to_string(),
+            pid,
+            tid: number(3)?.unwrap_or(pid),
+            time_nanos: caps.get(5).map(|t| parse_timestamp(t.as_str())),
+            period: number(6)?.unwrap_or(1),
+            event: caps[7].to_string(),
+            frames: vec![],
+        };
+
+        // without a callchain the sampled address follows the event name
+        if let Some(frame) = caps.get(8).and_then(|rest| parse_frame(rest.as_str())) {
+            r.frames.push(frame);
+        }
+        records.push(r);
+    }
+
+    Ok(records)
+}
+
+// parse_frame parses a stack frame line: "address symbol+offset (dso)".
+// Symbols may contain parentheses themselves, so the DSO is taken from
+// the last balanced group of the line.
+fn parse_frame(line: &str) -> Option<Frame> {
+    let line = line.trim();
+    let (address, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let address = u64::from_str_radix(address, 16).ok()?;
+    let rest = rest.trim();
+
+    let (symbol, dso) = match rest.strip_suffix(')') {
+        Some(inner) => {
+            let mut depth = 0;
+            let mut open = None;
+            for (i, c) in inner.char_indices().rev() {
+                match c {
+                    ')' => depth += 1,
+                    '(' if depth == 0 => {
+                        open = Some(i);
+                        break;
+                    }
+                    '(' => depth -= 1,
+                    _ => {}
+                }
+            }
+            match open {
+                Some(i) => (inner[..i].trim(), &inner[i + 1..]),
+                None => (rest, ""),
+            }
+        }
+        None => (rest, ""),
+    };
+
+    let symbol = strip_offset(symbol);
+    Some(Frame {
+        address,
+        symbol: if symbol.is_empty() || symbol == UNKNOWN_SYMBOL {
+            None
+        } else {
+            Some(symbol.to_string())
+        },
+        dso: dso.trim_end_matches(" (deleted)").to_string(),
+    })
+}
+
+// strip_offset removes the "+0x1f" suffix printed by perf after the symbol name.
+fn strip_offset(symbol: &str) -> &str {
+    match symbol.rsplit_once("+0x") {
+        Some((name, offset)) if u64::from_str_radix(offset, 16).is_ok() => name,
+        _ => symbol,
+    }
+}
+
+// parse_timestamp converts the "seconds.fraction" timestamp to nanoseconds.
+fn parse_timestamp(t: &str) -> i64 {
+    let (secs, frac) = t.split_once('.').unwrap_or((t, ""));
+    let secs: i64 = secs.parse().unwrap_or_default();
+    let frac = format!("{:0<9}", &frac[..frac.len().min(9)]);
+    secs * 1_000_000_000 + frac.parse::<i64>().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::perf::script::{is_perf_script, parse_frame, parse_perf_script};
+    use crate::profile::errors::RockError;
+    use std::fs;
+
+    #[test]
+    fn parse_perf_script_golden() {
+        let data = fs::read("tests/perf/script.txt").unwrap();
+        assert!(is_perf_script(&data));
+
+        let p = parse_perf_script(&data).unwrap();
+        let want = fs::read_to_string("tests/perf/script.txt.string").unwrap();
+        assert_eq!(p.to_string(), want);
+    }
+
+    #[test]
+    fn parse_perf_script_frames() {
+        let f = parse_frame(
+            "\t    7f1a2b3c4d5e std::vector<int>::push_back(int const&)+0x1e (/tmp/app (deleted))",
+        )
+        .unwrap();
+        assert_eq!(f.address, 0x7f1a2b3c4d5e);
+        assert_eq!(
+            f.symbol.as_deref(),
+            Some("std::vector<int>::push_back(int const&)")
+        );
+        assert_eq!(f.dso, "/tmp/app");
+
+        let f = parse_frame("\t          400123 [unknown] ([unknown])").unwrap();
+        assert_eq!(f.symbol, None);
+        assert!(parse_frame("  main.c:12").is_none());
+    }
+
+    #[test]
+    fn parse_perf_script_errors() {
+        assert!(!is_perf_script(
+            b"heap profile: 1: 2 [3: 4] @ heapprofile\n"
+        ));
+        assert!(matches!(
+            parse_perf_script(b""),
+            Err(RockError::UnrecognizedFormat { .. })
+        ));
+        assert!(matches!(
+            parse_perf_script(b"\t400123 main (/bin/app)\n"),
+            Err(RockError::MalformedProfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+// async_io decodes profiles read from tokio readers. The profile is read and
+// gzip decompressed asynchronously, then decoded, resolved (post_decode) and
+// validated on the blocking thread pool of the runtime, so large profiles do
+// not block the executor. It must be called from a tokio runtime.
+
+use async_compression::tokio::bufread::GzipDecoder;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+use crate::profile::buffer::{Buffer, Decoder};
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+
+// decode reads the profile from r, gzipped or not, and decodes it like
+// Buffer::decode.
+pub async fn decode<R: AsyncRead + Unpin>(r: R) -> Result<Profile, RockError> {
+    let mut r = BufReader::new(r);
+    let mut data = vec![];
+    // https://tools.ietf.org/html/rfc1952#page-5
+    if r.fill_buf().await?.starts_with(&[0x1f, 0x8b]) {
+        GzipDecoder::new(r)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|err| RockError::ProfileUncompressFailed {
+                reason: err.to_string(),
+            })?;
+    } else {
+        r.read_to_end(&mut data).await?;
+    }
+
+    tokio::task::spawn_blocking(move || Buffer::decode(&mut data))
+        .await
+        .map_err(|err| RockError::Unknown {
+            reason: err.to_string(),
+        })?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::async_io::decode;
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::errors::RockError;
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_decode() {
+        for path in ["tests/CPU.pb.gz", "tests/HEAP.pb.gz", "tests/encoded"] {
+            let data = std::fs::read(path).unwrap();
+            let want = Buffer::decode(&mut data.clone()).unwrap();
+            let got = block_on(decode(data.as_slice())).unwrap();
+            assert_eq!(got, want, "{}", path);
+        }
+
+        let data = std::fs::read("tests/CPU.pb.gz").unwrap();
+        let err = block_on(decode(&data[..data.len() / 2])).unwrap_err();
+        assert!(
+            matches!(err, RockError::ProfileUncompressFailed { .. }),
+            "{}",
+            err
+        );
+        assert!(block_on(decode(&b"\x12\x05garbage"[..])).is_err());
+    }
+}
@@ -1,10 +1,12 @@
+use std::collections::HashSet;
 use std::io::{BufReader, Read};
 use std::ops::{Shl, Shr};
 
 use flate2::read::GzDecoder;
 
 use crate::profile::errors::RockError;
-use crate::profile::Profile;
+use crate::profile::stream::read_field;
+use crate::profile::{legacy_profile, Profile};
 use std::convert::From;
 use std::string::ToString;
 
@@ -23,14 +25,18 @@ pub enum WireTypes {
     WireFixed32 = 5,
 }
 
-impl From<usize> for WireTypes {
-    fn from(var: usize) -> Self {
+impl TryFrom<usize> for WireTypes {
+    type Error = RockError;
+
+    fn try_from(var: usize) -> Result<Self, Self::Error> {
         match var {
-            0 => WireTypes::WireVarint,
-            1 => WireTypes::WireFixed64,
-            2 => WireTypes::WireBytes,
-            5 => WireTypes::WireFixed32,
-            _ => panic!("unknown WireType"),
+            0 => Ok(WireTypes::WireVarint),
+            1 => Ok(WireTypes::WireFixed64),
+            2 => Ok(WireTypes::WireBytes),
+            5 => Ok(WireTypes::WireFixed32),
+            _ => Err(RockError::DecodeFieldFailed {
+                reason: format!("unknown wire type {}", var),
+            }),
         }
     }
 }
@@ -61,6 +67,9 @@ impl Decoder for Buffer {
             let mut gz_decoder = GzDecoder::new(BufReader::new(data.as_slice()));
             let res = gz_decoder.read_to_end(&mut uncompressed);
             return match res {
+                Ok(_) if legacy_profile::is_legacy_profile(&uncompressed) => {
+                    legacy_profile::parse_legacy(&uncompressed)
+                }
                 Ok(_) => {
                     let mut b = Buffer {
                         field: 0,
@@ -70,12 +79,10 @@ impl Decoder for Buffer {
                     };
 
                     let mut p = Profile::default();
-                    decode_message(&mut b, &mut uncompressed, &mut p);
-                    p.post_decode();
-                    match p.validate() {
-                        Ok(_) => Ok(p),
-                        Err(err) => panic!("{}", err),
-                    }
+                    decode_message(&mut b, &mut uncompressed, &mut p)?;
+                    p.post_decode()?;
+                    p.validate()?;
+                    Ok(p)
                 }
                 Err(err) => Err(RockError::ProfileUncompressFailed {
                     reason: err.to_string(),
@@ -83,6 +90,11 @@ impl Decoder for Buffer {
             };
         }
 
+        // legacy text profiles are neither gzipped nor protobuf encoded
+        if legacy_profile::is_legacy_profile(data) {
+            return legacy_profile::parse_legacy(data);
+        }
+
         // data is not compressed, just copy to struct
         let mut b = Buffer {
             field: 0,
@@ -92,19 +104,132 @@ impl Decoder for Buffer {
         };
         // data not in the buffer, since the data in the buffer used for internal processing
         let mut p = Profile::default();
-        decode_message(&mut b, data, &mut p);
-        p.post_decode();
-        match p.validate() {
-            Ok(_) => Ok(p),
-            Err(err) => panic!("{}", err),
+        decode_message(&mut b, data, &mut p)?;
+        p.post_decode()?;
+        p.validate()?;
+        Ok(p)
+    }
+}
+
+impl Buffer {
+    // decode_all decodes every profile of data, where decode fails with
+    // "concatenated profiles detected": the members of a multi-member gzip
+    // file, and profiles appended to each other, compressed or not. A new
+    // profile starts where a field of the profile is repeated (time_nanos,
+    // period, a second string table...); it is cut where the field numbers
+    // start over, as the fields of a profile are written in order. Malformed
+    // profiles are errors.
+    pub fn decode_all(data: &[u8]) -> Result<Vec<Profile>, RockError> {
+        let mut profiles = vec![];
+        for member in gzip_members(data)? {
+            if legacy_profile::is_legacy_profile(&member) {
+                profiles.push(legacy_profile::parse_legacy(&member)?);
+                continue;
+            }
+            for segment in split_profiles(&member)? {
+                profiles.push(Buffer::decode(&mut segment.to_vec())?);
+            }
+        }
+        if profiles.is_empty() {
+            return Err(RockError::MalformedProfile {
+                reason: String::from("empty profile"),
+            });
+        }
+        Ok(profiles)
+    }
+
+    // decode_merged decodes the profiles of data like decode_all and merges
+    // them into one, they must be compatible.
+    pub fn decode_merged(data: &[u8]) -> Result<Profile, RockError> {
+        Profile::merge(&Buffer::decode_all(data)?)
+    }
+}
+
+fn is_gzip(data: &[u8]) -> bool {
+    // https://tools.ietf.org/html/rfc1952#page-5
+    data.len() > 2 && data[0] == 0x1f && data[1] == 0x8b
+}
+
+// gzip_members returns the decompressed members of gzipped data, or data
+// if it is not gzipped. Zeros padding the last member are ignored.
+fn gzip_members(data: &[u8]) -> Result<Vec<Vec<u8>>, RockError> {
+    if !is_gzip(data) {
+        return Ok(vec![data.to_vec()]);
+    }
+    let mut members = vec![];
+    let mut rest = data;
+    while is_gzip(rest) {
+        let mut decoder = flate2::bufread::GzDecoder::new(rest);
+        let mut member = vec![];
+        decoder
+            .read_to_end(&mut member)
+            .map_err(|err| RockError::ProfileUncompressFailed {
+                reason: err.to_string(),
+            })?;
+        rest = decoder.into_inner();
+        members.push(member);
+    }
+    if rest.iter().any(|b| *b != 0) {
+        return Err(RockError::ProfileUncompressFailed {
+            reason: format!("{} bytes of trailing data after gzip members", rest.len()),
+        });
+    }
+    Ok(members)
+}
+
+// split_profiles returns the profiles concatenated in data.
+fn split_profiles(data: &[u8]) -> Result<Vec<&[u8]>, RockError> {
+    let mut profiles = vec![];
+    let mut start = 0;
+    'profiles: loop {
+        let mut r = &data[start..];
+        // offset of the last field numbered lower than the previous one
+        let mut restart = None;
+        let mut last = 0;
+        let mut singular = HashSet::new();
+        let mut strings = 0;
+        loop {
+            let offset = data.len() - r.len();
+            let Some((buf, bytes)) = read_field(&mut r)? else {
+                if offset > start {
+                    profiles.push(&data[start..]);
+                }
+                break 'profiles;
+            };
+            let repeated = match buf.field {
+                // string_table[0] is always "", another "" starts a new table
+                6 => {
+                    strings += 1;
+                    strings > 1 && bytes.is_empty()
+                }
+                7..=12 | 14 => !singular.insert(buf.field),
+                _ => false,
+            };
+            if repeated {
+                let end = restart.unwrap_or(offset);
+                profiles.push(&data[start..end]);
+                start = end;
+                continue 'profiles;
+            }
+            if buf.field < last {
+                restart = Some(offset);
+            }
+            last = buf.field;
         }
     }
+    Ok(profiles)
 }
 
 #[inline]
-pub fn decode_message(buf: &mut Buffer, data: &mut Vec<u8>, profile: &mut Profile) {
+pub fn decode_message(
+    buf: &mut Buffer,
+    data: &mut Vec<u8>,
+    profile: &mut Profile,
+) -> Result<(), RockError> {
     if buf.r#type != WireTypes::WireBytes {
-        panic!("WireTypes not Equal WireBytes");
+        return Err(RockError::DecodeFieldFailed {
+            reason: String::from("a message must have the bytes wire type"),
+        });
     }
 
     while !data.is_empty() {
@@ -112,16 +237,10 @@ pub fn decode_message(buf: &mut Buffer, data: &mut Vec<u8>, profile: &mut Profil
         // 1. We pass whole data and buffer to the decode_field function
         // 2. As the result we get main data (which drained to the buffer size) and buffer with that drained data filled with other fields
         // 3. We also calculate field, type and u64 fields to pass it to Profile::decode_profile function
-        let mut res = decode_field(buf, data);
-        match res {
-            Ok(ref mut buf_data) => {
-                Profile::decode_profile_field(profile, buf, buf_data);
-            }
-            Err(err) => {
-                panic!("{}", err);
-            }
-        }
+        let mut buf_data = decode_field(buf, data)?;
+        Profile::decode_profile_field(profile, buf, &mut buf_data)?;
     }
+    Ok(())
 }
 
 // decode_field is used to decode fields from incoming data
@@ -138,7 +257,7 @@ pub fn decode_field(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Vec<u8>, Roc
             // after right shift -> 1011, this is field number in proto
             // then we're doing AND operation and getting 7 bits
             buf.field = varint.shr(3);
-            buf.r#type = WireTypes::from(varint & 7);
+            buf.r#type = WireTypes::try_from(varint & 7)?;
             buf.u64 = 0;
 
             let mut buf_data = vec![];
@@ -197,9 +316,7 @@ pub fn decode_field(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Vec<u8>, Roc
                 }
             }
         }
-        Err(err) => {
-            panic!("{}", err);
-        }
+        Err(err) => Err(err),
     }
 }
 
@@ -249,33 +366,34 @@ pub fn decode_varint(data: &mut Vec<u8>) -> Result<usize, RockError> {
 /// Decode WireType -- 1, Fixed64
 #[inline]
 pub fn decode_fixed64(p: &[u8]) -> u64 {
-    ((p[0])
-        | (p[1].shl(8))
-        | (p[2].shl(16))
-        | (p[3].shl(24))
-        | (p[4].shl(32))
-        | (p[5].shl(40))
-        | (p[6].shl(48))
-        | (p[7].shl(56))) as u64
+    u64::from_le_bytes([p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7]])
 }
 
 /// Decode WireType -- 5, Fixed32
 #[inline]
 pub fn decode_fixed32(p: &[u8]) -> u32 {
-    (p[0] | p[1].shl(8) | p[2].shl(16) | p[3].shl(24)) as u32
+    u32::from_le_bytes([p[0], p[1], p[2], p[3]])
 }
 
 #[inline]
-pub fn decode_string(v: &[u8]) -> String {
-    std::str::from_utf8(v).unwrap().to_string()
+pub fn decode_string(v: &[u8]) -> Result<String, RockError> {
+    std::str::from_utf8(v)
+        .map(String::from)
+        .map_err(|err| RockError::MalformedProfile {
+            reason: format!("string table: {}", err),
+        })
 }
 
 #[cfg(test)]
 mod profile_test {
     use std::collections::HashMap;
-    use std::io::Read;
+    use std::io::{Read, Write};
+
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
 
-    use crate::profile::buffer::Decoder;
+    use crate::profile::buffer::{Buffer, Decoder};
 
     #[test]
     fn parse() {
@@ -322,4 +440,84 @@ mod profile_test {
             }
         }
     }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(vec![], Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn uncompressed(path: &str) -> Vec<u8> {
+        let mut data = vec![];
+        GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn decode_errors() {
+        let malformed: [&[u8]; 6] = [
+            // unknown wire type
+            b"\x0f",
+            // string_table[0] is not empty
+            b"\x32\x01a",
+            // invalid UTF-8 string
+            b"\x32\x00\x32\x01\xff",
+            // time_nanos twice
+            b"\x32\x00\x48\x01\x48\x02",
+            // drop_frames out of the string table
+            b"\x32\x00\x38\x05",
+            // sample of an unknown location
+            b"\x32\x00\x12\x04\x08\x05\x10\x01",
+        ];
+        for data in malformed {
+            assert!(Buffer::decode(&mut data.to_vec()).is_err(), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn decode_all() {
+        let cpu_gz = std::fs::read("tests/CPU.pb.gz").unwrap();
+        let heap_gz = std::fs::read("tests/HEAP.pb.gz").unwrap();
+        let cpu = Buffer::decode(&mut cpu_gz.clone()).unwrap();
+        let heap = Buffer::decode(&mut heap_gz.clone()).unwrap();
+        let (cpu_raw, heap_raw) = (
+            uncompressed("tests/CPU.pb.gz"),
+            uncompressed("tests/HEAP.pb.gz"),
+        );
+
+        assert_eq!(Buffer::decode_all(&cpu_gz).unwrap(), vec![cpu.clone()]);
+        assert_eq!(Buffer::decode_all(&cpu_raw).unwrap(), vec![cpu.clone()]);
+
+        // multi-member gzip
+        let members = [cpu_gz.clone(), heap_gz.clone(), vec![0; 8]].concat();
+        assert_eq!(
+            Buffer::decode_all(&members).unwrap(),
+            vec![cpu.clone(), heap.clone()]
+        );
+
+        // profiles appended to each other, as written by Go and by rock
+        let want = vec![cpu.clone(), heap.clone(), cpu.clone()];
+        let appended = [cpu_raw.clone(), heap_raw.clone(), cpu_raw.clone()].concat();
+        assert_eq!(Buffer::decode_all(&appended).unwrap(), want);
+        assert!(Buffer::decode(&mut appended.clone()).is_err());
+        assert_eq!(Buffer::decode_all(&gzip(&appended)).unwrap(), want);
+        let written = [heap.write_uncompressed(), cpu.write_uncompressed()].concat();
+        let got = Buffer::decode_all(&written).unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].to_string(), heap.to_string());
+        assert_eq!(got[1].to_string(), cpu.to_string());
+
+        let merged = Buffer::decode_merged(&[cpu_gz.clone(), cpu_gz.clone()].concat()).unwrap();
+        let total =
+            |p: &crate::profile::Profile| -> i64 { p.sample.iter().map(|s| s.value[1]).sum() };
+        assert_eq!(total(&merged), 2 * total(&cpu));
+        assert!(Buffer::decode_merged(&members).is_err());
+
+        assert!(Buffer::decode_all(&[]).is_err());
+        assert!(Buffer::decode_all(&cpu_gz[..cpu_gz.len() / 2]).is_err());
+        assert!(Buffer::decode_all(&[cpu_gz.clone(), b"garbage".to_vec()].concat()).is_err());
+        assert!(Buffer::decode_all(&cpu_raw[..cpu_raw.len() - 3]).is_err());
+    }
 }
@@ -0,0 +1,241 @@
+use crate::profile::errors::RockError;
+use crate::profile::function::Function;
+use crate::profile::line::Line;
+use crate::profile::location::Location;
+use crate::profile::mapping::Mapping;
+use crate::profile::sample::Sample;
+use crate::profile::value_type::ValueType;
+use crate::profile::Profile;
+use std::collections::HashMap;
+
+// key to deduplicate functions: name, system_name, filename, start_line
+type FunctionKey = (String, String, String, i64);
+// key to deduplicate locations: mapping id, address, (function id, line) pairs
+type LocationKey = (u64, u64, Vec<(u64, i64)>);
+
+// ProfileBuilder is used by the parsers of non-protobuf formats to construct
+// a Profile. It assigns ids, deduplicates functions and locations and, when
+// the profile is built, links the copies of mappings, functions and locations
+// kept by locations and samples.
+#[derive(Debug, Default)]
+pub struct ProfileBuilder {
+    profile: Profile,
+    functions: HashMap<FunctionKey, u64>,
+    locations: HashMap<LocationKey, u64>,
+}
+
+impl ProfileBuilder {
+    pub fn new() -> Self {
+        ProfileBuilder::default()
+    }
+
+    pub fn add_sample_type(&mut self, r#type: &str, unit: &str) -> &mut Self {
+        self.profile.sample_type.push(value_type(r#type, unit));
+        self
+    }
+
+    pub fn period_type(&mut self, r#type: &str, unit: &str, period: i64) -> &mut Self {
+        self.profile.period_type = Some(value_type(r#type, unit));
+        self.profile.period = period;
+        self
+    }
+
+    pub fn time_nanos(&mut self, time_nanos: i64) -> &mut Self {
+        self.profile.time_nanos = time_nanos;
+        self
+    }
+
+    pub fn duration_nanos(&mut self, duration_nanos: i64) -> &mut Self {
+        self.profile.duration_nanos = duration_nanos;
+        self
+    }
+
+    pub fn add_comment(&mut self, comment: &str) -> &mut Self {
+        self.profile.comments.push(comment.to_string());
+        self
+    }
+
+    pub fn drop_frames(&mut self, drop_frames: &str) -> &mut Self {
+        self.profile.drop_frames = drop_frames.to_string();
+        self
+    }
+
+    pub fn keep_frames(&mut self, keep_frames: &str) -> &mut Self {
+        self.profile.keep_frames = keep_frames.to_string();
+        self
+    }
+
+    pub fn default_sample_type(&mut self, r#type: &str) -> &mut Self {
+        self.profile.default_sample_type = r#type.to_string();
+        self
+    }
+
+    // add_mapping adds the mapping to the profile and returns its id.
+    // The id of the passed mapping is ignored.
+    pub fn add_mapping(&mut self, mut mapping: Mapping) -> u64 {
+        mapping.id = self.profile.mapping.len() as u64 + 1;
+        self.profile.mapping.push(mapping);
+        self.profile.mapping.len() as u64
+    }
+
+    // mapping returns the mapping with the given id, to update the symbolization
+    // flags (has_functions and so on) while the profile is being built.
+    pub fn mapping(&mut self, id: u64) -> Option<&mut Mapping> {
+        self.profile.mapping.iter_mut().find(|m| m.id == id)
+    }
+
+    // add_function returns the id of the function with the given attributes,
+    // adding it to the profile if it was not seen before.
+    pub fn add_function(
+        &mut self,
+        name: &str,
+        system_name: &str,
+        filename: &str,
+        start_line: i64,
+    ) -> u64 {
+        let key = (
+            name.to_string(),
+            system_name.to_string(),
+            filename.to_string(),
+            start_line,
+        );
+        if let Some(id) = self.functions.get(&key) {
+            return *id;
+        }
+
+        let id = self.profile.function.len() as u64 + 1;
+        self.profile.function.push(Function {
+            id,
+            name: key.0.clone(),
+            system_name: key.1.clone(),
+            filename: key.2.clone(),
+            start_line,
+            ..Default::default()
+        });
+        self.functions.insert(key, id);
+        id
+    }
+
+    // add_location returns the id of the location with the given mapping (0 if unknown),
+    // address and lines, adding it to the profile if it was not seen before.
+    // Lines are (function id, line number) pairs, the callee first, the caller last.
+    pub fn add_location(&mut self, mapping_id: u64, address: u64, lines: &[(u64, i64)]) -> u64 {
+        let key = (mapping_id, address, lines.to_vec());
+        if let Some(id) = self.locations.get(&key) {
+            return *id;
+        }
+
+        let id = self.profile.location.len() as u64 + 1;
+        self.profile.location.push(Location {
+            id,
+            mapping_index: mapping_id,
+            address,
+            line: lines
+                .iter()
+                .map(|(function_id, line)| Line {
+                    line: *line,
+                    function_index: *function_id,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+        self.locations.insert(key, id);
+        id
+    }
+
+    // add_sample adds a sample with the given location ids (leaf first) and values.
+    // The returned sample can be used to attach labels.
+    pub fn add_sample(&mut self, locations: Vec<u64>, values: Vec<i64>) -> &mut Sample {
+        self.profile.sample.push(Sample {
+            value: values,
+            location_index: locations,
+            ..Default::default()
+        });
+        self.profile.sample.last_mut().unwrap()
+    }
+
+    // build links and validates the profile.
+    pub fn build(self) -> Result<Profile, RockError> {
+        let mut p = self.profile;
+        p.link();
+        p.validate()?;
+        Ok(p)
+    }
+}
+
+fn value_type(r#type: &str, unit: &str) -> ValueType {
+    ValueType {
+        r#type: r#type.to_string(),
+        unit: unit.to_string(),
+        ..Default::default()
+    }
+}
+
+impl Profile {
+    // link resolves mapping, function and location ids into the copies stored
+    // in locations and samples. It should be called every time the mapping,
+    // function or location tables are changed, so samples see the same data
+    // as the tables.
+    pub fn link(&mut self) {
+        let mappings: HashMap<u64, Mapping> =
+            self.mapping.iter().map(|m| (m.id, m.clone())).collect();
+        let functions: HashMap<u64, Function> =
+            self.function.iter().map(|f| (f.id, f.clone())).collect();
+
+        for loc in self.location.iter_mut() {
+            loc.mapping = mappings.get(&loc.mapping_index).cloned();
+            for line in loc.line.iter_mut() {
+                line.function = functions
+                    .get(&line.function_index)
+                    .cloned()
+                    .unwrap_or_default();
+            }
+        }
+
+        let locations: HashMap<u64, &Location> = self.location.iter().map(|l| (l.id, l)).collect();
+        for s in self.sample.iter_mut() {
+            s.location = s
+                .location_index
+                .iter()
+                .filter_map(|id| locations.get(id).map(|l| (*l).clone()))
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::mapping::Mapping;
+
+    #[test]
+    fn test_builder_dedup_and_link() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count");
+        let m = b.add_mapping(Mapping {
+            memory_start: 0x1000,
+            memory_limit: 0x2000,
+            filename: String::from("/bin/app"),
+            ..Default::default()
+        });
+        let main = b.add_function("main", "main", "main.c", 1);
+        let foo = b.add_function("foo", "foo", "main.c", 10);
+        assert_eq!(b.add_function("foo", "foo", "main.c", 10), foo);
+
+        let l1 = b.add_location(m, 0x1010, &[(foo, 12)]);
+        let l2 = b.add_location(m, 0x1020, &[(main, 3)]);
+        assert_eq!(b.add_location(m, 0x1010, &[(foo, 12)]), l1);
+
+        b.add_sample(vec![l1, l2], vec![5]);
+        let p = b.build().unwrap();
+
+        assert_eq!(p.location.len(), 2);
+        assert_eq!(p.function.len(), 2);
+        assert_eq!(p.sample[0].location[0].line[0].function.name, "foo");
+        assert_eq!(
+            p.sample[0].location[1].mapping.as_ref().unwrap().filename,
+            "/bin/app"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::profile::buffer::WireTypes;
+
+// Encoder writes protocol buffer messages, the counterpart of decode_field.
+// As in proto3, scalar fields with the default value (0, "", false)
+// are not written, embedded messages are always written.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.data.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.data.push(v as u8);
+    }
+
+    fn tag(&mut self, field: u64, wire: WireTypes) {
+        self.varint(field << 3 | wire as u64);
+    }
+
+    pub fn uint64(&mut self, field: u64, v: u64) {
+        if v != 0 {
+            self.put_uint64(field, v);
+        }
+    }
+
+    // put_uint64 writes the field even if it has the default value,
+    // as needed for the members of a oneof.
+    pub fn put_uint64(&mut self, field: u64, v: u64) {
+        self.tag(field, WireTypes::WireVarint);
+        self.varint(v);
+    }
+
+    // int64 writes the int64 and int32 fields, negative values take 10 bytes.
+    pub fn int64(&mut self, field: u64, v: i64) {
+        self.uint64(field, v as u64);
+    }
+
+    pub fn bool(&mut self, field: u64, v: bool) {
+        self.uint64(field, v as u64);
+    }
+
+    pub fn fixed64(&mut self, field: u64, v: u64) {
+        if v != 0 {
+            self.put_fixed64(field, v);
+        }
+    }
+
+    pub fn put_fixed64(&mut self, field: u64, v: u64) {
+        self.tag(field, WireTypes::WireFixed64);
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn double(&mut self, field: u64, v: f64) {
+        self.fixed64(field, v.to_bits());
+    }
+
+    pub fn bytes(&mut self, field: u64, v: &[u8]) {
+        if !v.is_empty() {
+            self.put_bytes(field, v);
+        }
+    }
+
+    pub fn put_bytes(&mut self, field: u64, v: &[u8]) {
+        self.tag(field, WireTypes::WireBytes);
+        self.varint(v.len() as u64);
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn string(&mut self, field: u64, v: &str) {
+        self.bytes(field, v.as_bytes());
+    }
+
+    // repeated_string writes every string, including the empty ones,
+    // so the positions of a string table are kept.
+    pub fn repeated_string(&mut self, field: u64, v: &[String]) {
+        for s in v {
+            self.put_bytes(field, s.as_bytes());
+        }
+    }
+
+    // packed_uint64 writes a packed repeated varint field.
+    pub fn packed_uint64(&mut self, field: u64, v: &[u64]) {
+        let mut e = Encoder::new();
+        for x in v {
+            e.varint(*x);
+        }
+        self.bytes(field, &e.data);
+    }
+
+    // packed_fixed64 writes a packed repeated fixed64 field.
+    pub fn packed_fixed64(&mut self, field: u64, v: &[u64]) {
+        let data: Vec<u8> = v.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.bytes(field, &data);
+    }
+
+    pub fn packed_int64(&mut self, field: u64, v: &[i64]) {
+        let v: Vec<u64> = v.iter().map(|x| *x as u64).collect();
+        self.packed_uint64(field, &v);
+    }
+
+    // message writes an embedded message produced by f.
+    pub fn message(&mut self, field: u64, f: impl FnOnce(&mut Encoder)) {
+        let mut e = Encoder::new();
+        f(&mut e);
+        self.put_bytes(field, &e.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{decode_field, Buffer, WireTypes};
+    use crate::profile::encoder::Encoder;
+
+    #[test]
+    fn test_encoder_decode_field() {
+        let mut e = Encoder::new();
+        e.uint64(1, 300);
+        e.int64(2, -1);
+        e.fixed64(3, 0x0102030405060708);
+        e.string(4, "rock");
+        e.uint64(5, 0);
+        e.message(6, |e| e.packed_uint64(1, &[1, 2, 150]));
+        let mut data = e.finish();
+
+        let mut buf = Buffer::default();
+        let mut fields = vec![];
+        while !data.is_empty() {
+            let bytes = decode_field(&mut buf, &mut data).unwrap();
+            fields.push((buf.field, buf.r#type, buf.u64, bytes));
+        }
+
+        assert_eq!(
+            fields,
+            vec![
+                (1, WireTypes::WireVarint, 300, vec![]),
+                (2, WireTypes::WireVarint, u64::MAX, vec![]),
+                (3, WireTypes::WireFixed64, 0x0102030405060708, vec![]),
+                (4, WireTypes::WireBytes, 0, b"rock".to_vec()),
+                (6, WireTypes::WireBytes, 0, vec![0x0a, 4, 1, 2, 0x96, 1]),
+            ]
+        );
+    }
+}
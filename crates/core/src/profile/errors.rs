@@ -12,6 +12,33 @@ pub enum RockError {
     ValidationFailed {
         reason: String,
     },
+    IncompatibleUnits {
+        reason: String,
+    },
+    InvalidFilter {
+        reason: String,
+    },
+    UnrecognizedFormat {
+        reason: String,
+    },
+    MalformedProfile {
+        reason: String,
+    },
+    SymbolizationFailed {
+        reason: String,
+    },
+    InvalidArgument {
+        reason: String,
+    },
+    IoFailed {
+        reason: String,
+    },
+    FetchTimedOut {
+        reason: String,
+    },
+    HttpFailed {
+        reason: String,
+    },
     #[allow(dead_code)]
     Unknown {
         reason: String,
@@ -24,11 +51,36 @@ impl fmt::Display for RockError {
             RockError::ValidationFailed { reason } => {
                 write!(f, "Profile validation failed, reason: {}", reason)
             }
+            RockError::IncompatibleUnits { reason } => {
+                write!(f, "Incompatible units, reason: {}", reason)
+            }
+            RockError::InvalidFilter { reason } => write!(f, "Invalid filter, reason: {}", reason),
+            RockError::UnrecognizedFormat { reason } => {
+                write!(f, "Unrecognized profile format, reason: {}", reason)
+            }
+            RockError::MalformedProfile { reason } => {
+                write!(f, "Malformed profile, reason: {}", reason)
+            }
+            RockError::SymbolizationFailed { reason } => {
+                write!(f, "Symbolization failed, reason: {}", reason)
+            }
+            RockError::InvalidArgument { reason } => {
+                write!(f, "Invalid argument, reason: {}", reason)
+            }
+            RockError::IoFailed { reason } => write!(f, "I/O failed, reason: {}", reason),
+            RockError::FetchTimedOut { reason } => {
+                write!(f, "Fetch timed out, reason: {}", reason)
+            }
+            RockError::HttpFailed { reason } => {
+                write!(f, "HTTP request failed, reason: {}", reason)
+            }
             RockError::Unknown { reason } => write!(f, "Unknown error, reason: {}", reason),
             RockError::ProfileUncompressFailed { reason } => {
                 write!(f, "Failed to read compressed data. Error: {}", reason)
             }
-            _ => panic!("Unknown type of error"),
+            RockError::DecodeFieldFailed { reason } => {
+                write!(f, "Failed to decode a field, reason: {}", reason)
+            }
         }
     }
 }
@@ -45,7 +97,38 @@ impl From<RockError> for std::io::Error {
             RockError::ValidationFailed { reason } => {
                 std::io::Error::new(std::io::ErrorKind::Other, reason)
             }
+            RockError::IncompatibleUnits { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, reason)
+            }
+            RockError::InvalidFilter { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, reason)
+            }
+            RockError::UnrecognizedFormat { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+            }
+            RockError::MalformedProfile { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+            }
+            RockError::SymbolizationFailed { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+            }
+            RockError::InvalidArgument { reason } => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, reason)
+            }
+            RockError::IoFailed { reason } => std::io::Error::other(reason),
+            RockError::FetchTimedOut { reason } => {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, reason)
+            }
+            RockError::HttpFailed { reason } => std::io::Error::other(reason),
             RockError::Unknown { reason } => std::io::Error::new(std::io::ErrorKind::Other, reason),
         }
     }
 }
+
+impl From<std::io::Error> for RockError {
+    fn from(e: std::io::Error) -> Self {
+        RockError::IoFailed {
+            reason: e.to_string(),
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use crate::measurement;
+use crate::profile::errors::RockError;
+use crate::profile::location::Location;
+use crate::profile::sample::Sample;
+use crate::profile::Profile;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+// TagFilter selects samples by their labels. It is created from the user
+// provided strings like "bytes=1kb:2mb", "thread=worker.*" or "32kb".
+// Numeric values are compared after the conversion to a common unit, so
+// "1mb" matches a label with value 1024 and unit "kilobytes".
+#[derive(Debug, Clone)]
+pub struct TagFilter {
+    // label key to check, None means any key
+    key: Option<String>,
+    matcher: TagMatcher,
+    // units of the numeric labels, as returned by Profile::num_label_units
+    num_label_units: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+enum TagMatcher {
+    // inclusive range of the numeric label values, expressed in the unit
+    Range {
+        low: Option<f64>,
+        high: Option<f64>,
+        unit: String,
+    },
+    // regular expressions matched against the string label values
+    Regexp(Vec<Regex>),
+}
+
+impl TagFilter {
+    // new compiles the filter value. If the value (after the optional "key=" prefix)
+    // looks like a range, it is interpreted as range over numeric labels, otherwise
+    // as a comma separated list of regular expressions over string labels.
+    pub fn new(value: &str, num_label_units: HashMap<String, String>) -> Result<Self, RockError> {
+        let (key, value) = match value.split_once('=') {
+            Some((key, value)) => (Some(key.to_string()), value),
+            None => (None, value),
+        };
+
+        if let Some(matcher) = parse_tag_filter_range(value) {
+            return Ok(TagFilter {
+                key,
+                matcher,
+                num_label_units,
+            });
+        }
+
+        let mut rfx = vec![];
+        for tagf in value.split(',') {
+            match Regex::new(tagf) {
+                Ok(rx) => rfx.push(rx),
+                Err(err) => {
+                    return Err(RockError::InvalidFilter {
+                        reason: format!("parsing {} regexp: {}", value, err),
+                    })
+                }
+            }
+        }
+
+        Ok(TagFilter {
+            key,
+            matcher: TagMatcher::Regexp(rfx),
+            num_label_units,
+        })
+    }
+
+    // matches reports whether the sample is selected by the filter.
+    pub fn matches(&self, s: &Sample) -> bool {
+        match &self.matcher {
+            TagMatcher::Range { low, high, unit } => {
+                let label_filter = |key: &str, vals: &[i64]| {
+                    let label_unit = self
+                        .num_label_units
+                        .get(key)
+                        .map(String::as_str)
+                        .unwrap_or(key);
+
+                    vals.iter().any(|v| {
+                        let (sv, su) = measurement::scale(*v, label_unit, unit);
+                        su == *unit
+                            && low.is_none_or(|low| sv >= low)
+                            && high.is_none_or(|high| sv <= high)
+                    })
+                };
+
+                match &self.key {
+                    None => s
+                        .num_label
+                        .iter()
+                        .any(|(key, vals)| label_filter(key, vals)),
+                    Some(key) => match s.num_label.get(key) {
+                        None => false,
+                        Some(vals) => label_filter(key, vals),
+                    },
+                }
+            }
+            TagMatcher::Regexp(rfx) => match &self.key {
+                // every regexp should match some key:value pair of the sample
+                None => rfx.iter().all(|rx| {
+                    s.label.iter().any(|(key, vals)| {
+                        vals.iter()
+                            .any(|val| rx.is_match(&format!("{}:{}", key, val)))
+                    })
+                }),
+                Some(key) => match s.label.get(key) {
+                    None => false,
+                    Some(vals) => rfx.iter().any(|rx| vals.iter().any(|val| rx.is_match(val))),
+                },
+            },
+        }
+    }
+}
+
+// parse_tag_filter_range returns a range matcher if the filter describes a range.
+// It can recognize strings of the form:
+// "32kb" -- matches values == 32kb
+// ":64kb" -- matches values <= 64kb
+// "4mb:" -- matches values >= 4mb
+// "12kb:64mb" -- matches values between 12kb and 64mb (both included).
+fn parse_tag_filter_range(filter: &str) -> Option<TagMatcher> {
+    static RANGE_RX: OnceLock<Regex> = OnceLock::new();
+    let range_rx =
+        RANGE_RX.get_or_init(|| Regex::new(r"([+-]?[[:digit:]]+)([[:alpha:]]+)?").unwrap());
+    let ranges: Vec<regex::Captures> = range_rx.captures_iter(filter).take(2).collect();
+    if ranges.is_empty() {
+        return None;
+    }
+
+    let bound = |c: &regex::Captures, to_unit: Option<&str>| -> Option<(f64, String)> {
+        let v: i64 = c.get(1)?.as_str().parse().ok()?;
+        let unit = c.get(2).map_or("", |u| u.as_str());
+        Some(measurement::scale(v, unit, to_unit.unwrap_or(unit)))
+    };
+
+    let first = ranges[0].get(0)?.as_str();
+    let (value, unit) = bound(&ranges[0], None)?;
+
+    if ranges.len() == 1 {
+        let (low, high) = if filter == first {
+            (Some(value), Some(value))
+        } else if filter == format!("{}:", first) {
+            (Some(value), None)
+        } else if filter == format!(":{}", first) {
+            (None, Some(value))
+        } else {
+            return None;
+        };
+        return Some(TagMatcher::Range { low, high, unit });
+    }
+
+    if filter != format!("{}:{}", first, ranges[1].get(0)?.as_str()) {
+        return None;
+    }
+
+    let (value2, unit2) = bound(&ranges[1], Some(&unit))?;
+    if unit != unit2 {
+        return None;
+    }
+
+    Some(TagMatcher::Range {
+        low: Some(value),
+        high: Some(value2),
+        unit,
+    })
+}
+
+impl Profile {
+    // filter_samples_by_tag removes all samples from the profile, except
+    // those that match focus and do not match the ignore filter.
+    // Returns whether the focus and ignore filters matched any sample.
+    pub fn filter_samples_by_tag(
+        &mut self,
+        focus: Option<&TagFilter>,
+        ignore: Option<&TagFilter>,
+    ) -> (bool, bool) {
+        let mut fm = false;
+        let mut im = false;
+
+        self.sample.retain(|s| {
+            let focused = focus.is_none_or(|f| f.matches(s));
+            let ignored = ignore.is_some_and(|i| i.matches(s));
+            fm = fm || focused;
+            im = im || ignored;
+            focused && !ignored
+        });
+
+        (fm, im)
+    }
+
+    // filter_samples_by_name filters the samples by the names of their frames,
+    // matched against the function names and files, or the mapping file for
+    // unsymbolized locations:
+    // focus -- keeps the samples with a matching frame
+    // ignore -- removes the samples with a matching frame
+    // hide -- removes the matching frames
+    // show -- keeps only the matching frames
+    // Returns whether each filter matched any frame.
+    pub fn filter_samples_by_name(
+        &mut self,
+        focus: Option<&Regex>,
+        ignore: Option<&Regex>,
+        hide: Option<&Regex>,
+        show: Option<&Regex>,
+    ) -> (bool, bool, bool, bool) {
+        let (mut fm, mut im, mut hm, mut hnm) = (false, false, false, false);
+
+        if hide.is_some() || show.is_some() {
+            for loc in self.location.iter_mut() {
+                let mapping = loc.mapping.as_ref().map_or("", |m| m.filename.as_str());
+                if loc.line.is_empty() {
+                    let hidden = hide.is_some_and(|re| re.is_match(mapping));
+                    let shown = show.is_none_or(|re| re.is_match(mapping));
+                    hm = hm || hidden;
+                    hnm = hnm || (show.is_some() && shown);
+                    if hidden || !shown {
+                        loc.id = 0;
+                    }
+                    continue;
+                }
+                loc.line.retain(|l| {
+                    let name = |re: &Regex| {
+                        re.is_match(&l.function.name) || re.is_match(&l.function.filename)
+                    };
+                    let hidden = hide.is_some_and(name);
+                    let shown = show.is_none_or(name);
+                    hm = hm || hidden;
+                    hnm = hnm || (show.is_some() && shown);
+                    !hidden && shown
+                });
+                if loc.line.is_empty() {
+                    loc.id = 0;
+                }
+            }
+            self.location.retain(|l| l.id != 0);
+            let kept: HashSet<u64> = self.location.iter().map(|l| l.id).collect();
+            for s in self.sample.iter_mut() {
+                s.location_index.retain(|id| kept.contains(id));
+            }
+            self.link();
+        }
+
+        if focus.is_some() || ignore.is_some() {
+            self.sample.retain(|s| {
+                let focused = focus.is_none_or(|re| s.location.iter().any(|l| matches_name(l, re)));
+                let ignored =
+                    ignore.is_some_and(|re| s.location.iter().any(|l| matches_name(l, re)));
+                fm = fm || (focus.is_some() && focused);
+                im = im || ignored;
+                focused && !ignored
+            });
+        }
+
+        (fm, im, hm, hnm)
+    }
+
+    // show_from drops the frames above the outermost frame matching the
+    // function name regexp, the samples without such a frame are removed.
+    // Returns whether any frame matched.
+    pub fn show_from(&mut self, re: &Regex) -> bool {
+        // the number of lines of each location up to the outermost match
+        let mut matched = HashMap::new();
+        for loc in self.location.iter_mut() {
+            if let Some(i) = loc.line.iter().rposition(|l| re.is_match(&l.function.name)) {
+                loc.line.truncate(i + 1);
+                matched.insert(loc.id, ());
+            }
+        }
+        if matched.is_empty() {
+            self.sample.clear();
+            return false;
+        }
+
+        self.sample.retain_mut(|s| {
+            match s
+                .location_index
+                .iter()
+                .rposition(|id| matched.contains_key(id))
+            {
+                Some(i) => {
+                    s.location_index.truncate(i + 1);
+                    true
+                }
+                None => false,
+            }
+        });
+        self.link();
+        true
+    }
+}
+
+// matches_name reports whether a frame of the location matches the regexp.
+fn matches_name(l: &Location, re: &Regex) -> bool {
+    if l.line
+        .iter()
+        .any(|line| re.is_match(&line.function.name) || re.is_match(&line.function.filename))
+    {
+        return true;
+    }
+    l.mapping.as_ref().is_some_and(|m| re.is_match(&m.filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::filter::TagFilter;
+    use crate::profile::sample::Sample;
+    use crate::profile::Profile;
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    fn sample(key: &str, value: i64, unit: &str) -> Sample {
+        let mut s = Sample {
+            value: vec![1],
+            ..Default::default()
+        };
+        s.num_label.insert(key.to_string(), vec![value]);
+        if !unit.is_empty() {
+            s.num_unit_label
+                .insert(key.to_string(), vec![unit.to_string()]);
+        }
+        s
+    }
+
+    #[test]
+    fn test_tag_filter_range() {
+        let mut p = Profile::default();
+        p.sample.push(sample("bytes", 512, ""));
+        p.sample.push(sample("bytes", 4096, ""));
+        p.sample.push(sample("alloc", 3, "kilobytes"));
+        p.sample.push(sample("alloc", 2048, "kilobytes"));
+
+        let (units, _) = p.num_label_units().unwrap();
+
+        struct RangeTest {
+            filter: &'static str,
+            want: Vec<bool>,
+        }
+
+        let tests = vec![
+            RangeTest {
+                filter: "512b",
+                want: vec![true, false, false, false],
+            },
+            RangeTest {
+                filter: "1kb:",
+                want: vec![false, true, true, true],
+            },
+            RangeTest {
+                filter: ":4kb",
+                want: vec![true, true, true, false],
+            },
+            RangeTest {
+                filter: "alloc=1kb:1mb",
+                want: vec![false, false, true, false],
+            },
+            RangeTest {
+                filter: "bytes=4kb",
+                want: vec![false, true, false, false],
+            },
+        ];
+
+        for test in tests {
+            let tf = TagFilter::new(test.filter, units.clone()).unwrap();
+            let got: Vec<bool> = p.sample.iter().map(|s| tf.matches(s)).collect();
+            assert_eq!(got, test.want, "filter {}", test.filter);
+        }
+    }
+
+    fn stacks(p: &Profile) -> Vec<Vec<String>> {
+        p.sample
+            .iter()
+            .map(|s| {
+                s.location
+                    .iter()
+                    .flat_map(|l| l.line.iter().map(|l| l.function.name.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn names_profile() -> Profile {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count");
+        let main = b.add_function("main", "main", "main.go", 0);
+        let work = b.add_function("work", "work", "work.go", 0);
+        let sum = b.add_function("sum", "sum", "work.go", 0);
+        let log = b.add_function("log", "log", "log.go", 0);
+        let l_main = b.add_location(0, 0x10, &[(main, 0)]);
+        let l_work = b.add_location(0, 0x20, &[(sum, 0), (work, 0)]);
+        let l_log = b.add_location(0, 0x30, &[(log, 0)]);
+        b.add_sample(vec![l_work, l_main], vec![1]);
+        b.add_sample(vec![l_log, l_main], vec![1]);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn test_filter_samples_by_name() {
+        let rx = |s: &str| Regex::new(s).unwrap();
+
+        let mut p = names_profile();
+        let (fm, im, _, _) = p.filter_samples_by_name(Some(&rx("work")), None, None, None);
+        assert!(fm && !im);
+        assert_eq!(stacks(&p), vec![vec!["sum", "work", "main"]]);
+
+        let mut p = names_profile();
+        p.filter_samples_by_name(None, Some(&rx("log.go")), None, None);
+        assert_eq!(stacks(&p), vec![vec!["sum", "work", "main"]]);
+
+        let mut p = names_profile();
+        let (_, _, hm, _) = p.filter_samples_by_name(None, None, Some(&rx("^(sum|log)$")), None);
+        assert!(hm);
+        assert_eq!(stacks(&p), vec![vec!["work", "main"], vec!["main"]]);
+
+        let mut p = names_profile();
+        let (_, _, _, hnm) = p.filter_samples_by_name(None, None, None, Some(&rx("main")));
+        assert!(hnm);
+        assert_eq!(stacks(&p), vec![vec!["main"], vec!["main"]]);
+    }
+
+    #[test]
+    fn test_show_from() {
+        let mut p = names_profile();
+        assert!(p.show_from(&Regex::new("^work$").unwrap()));
+        assert_eq!(stacks(&p), vec![vec!["sum", "work"]]);
+
+        let mut p = names_profile();
+        assert!(!p.show_from(&Regex::new("missing").unwrap()));
+        assert!(p.sample.is_empty());
+    }
+
+    #[test]
+    fn test_filter_samples_by_tag() {
+        let mut p = Profile::default();
+        for thread in ["main", "worker-1", "worker-2"] {
+            let mut s = Sample {
+                value: vec![1],
+                ..Default::default()
+            };
+            s.label
+                .insert(String::from("thread"), vec![thread.to_string()]);
+            p.sample.push(s);
+        }
+
+        let focus = TagFilter::new("thread=worker.*", HashMap::new()).unwrap();
+        let ignore = TagFilter::new("thread:worker-2", HashMap::new()).unwrap();
+        let (fm, im) = p.filter_samples_by_tag(Some(&focus), Some(&ignore));
+
+        assert!(fm && im);
+        assert_eq!(p.sample.len(), 1);
+        assert_eq!(p.sample[0].label["thread"], vec![String::from("worker-1")]);
+    }
+}
@@ -1,8 +1,14 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::Decoder;
 use std::default::Default;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Function {
     // Unique nonzero id for the function.
     pub id: u64,
@@ -18,15 +24,18 @@ pub struct Function {
 
     // HELPERS
     // Index into string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub name_index: i64,
     // Index into string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub system_name_index: i64,
     // Index into string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub filename_index: i64,
 }
 
 impl Decoder<Function> for Function {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Function {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Function, RockError> {
         let mut func = Function::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -56,15 +65,15 @@ impl Decoder<Function> for Function {
                             func.start_line = buf.u64 as i64;
                         }
                         _ => {
-                            panic!("Unknown function type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown function field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        func
+        Ok(func)
     }
 }
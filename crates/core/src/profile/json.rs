@@ -0,0 +1,122 @@
+// JSON form of the profile model, available with the `serde` feature.
+//
+// The JSON is normalized like profile.proto: mappings, functions and
+// locations are stored once in their tables and referenced by id, the
+// copies kept by samples, locations and lines are not serialized.
+// Strings are stored inline, so the string table and the string table
+// indexes are left out as well:
+//
+// {
+//   "sample_type": [{"type": "samples", "unit": "count"}, ...],
+//   "sample": [{"location_id": [2, 1], "value": [1, 10],
+//               "label": {"thread": ["main"]}, "num_label": {"bytes": [512]},
+//               "num_unit_label": {"bytes": ["bytes"]}}, ...],
+//   "mapping": [{"id": 1, "memory_start": 4194304, "memory_limit": 8388608,
+//                "memory_offset": 0, "filename": "/bin/app", "build_id": "",
+//                "has_function": true, ...}, ...],
+//   "location": [{"id": 1, "mapping_id": 1, "address": 4198400,
+//                 "line": [{"function_id": 1, "line": 12}], "is_folder": false}, ...],
+//   "function": [{"id": 1, "name": "main", "system_name": "main",
+//                 "filename": "main.go", "start_line": 10}, ...],
+//   "drop_frames": "", "keep_frames": "", "time_nanos": 0, "duration_nanos": 0,
+//   "period_type": {"type": "cpu", "unit": "nanoseconds"}, "period": 10000000,
+//   "comments": [], "default_sample_type": ""
+// }
+//
+// Missing fields take their default values. Profile::from_json resolves the
+// ids (see Profile::link) and validates the result, so the round trip
+// Profile::from_json(&p.to_json()?) gives back a profile equivalent to p.
+
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+
+impl Profile {
+    // to_json serializes the profile into its normalized JSON form.
+    pub fn to_json(&self) -> Result<Vec<u8>, RockError> {
+        serde_json::to_vec(self).map_err(|err| RockError::MalformedProfile {
+            reason: format!("serializing profile to json: {}", err),
+        })
+    }
+
+    // from_json reads a profile from its normalized JSON form, links the ids
+    // of the samples, locations and lines to the tables and validates it.
+    pub fn from_json(data: &[u8]) -> Result<Profile, RockError> {
+        let mut p: Profile =
+            serde_json::from_slice(data).map_err(|err| RockError::MalformedProfile {
+                reason: format!("parsing profile json: {}", err),
+            })?;
+
+        if p.sample_type.is_empty() && !p.sample.is_empty() {
+            return Err(RockError::MalformedProfile {
+                reason: String::from("profile json has samples but no sample types"),
+            });
+        }
+        if let Some(s) = p
+            .sample
+            .iter()
+            .find(|s| s.value.len() != p.sample_type.len())
+        {
+            return Err(RockError::MalformedProfile {
+                reason: format!(
+                    "sample has {} values vs. {} types",
+                    s.value.len(),
+                    p.sample_type.len()
+                ),
+            });
+        }
+
+        p.link();
+        p.validate()?;
+        Ok(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::errors::RockError;
+    use crate::profile::Profile;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_json_round_trip() {
+        for file in ["tests/CPU.pb.gz", "tests/HEAP.pb.gz"] {
+            let mut data = fs::read(file).unwrap();
+            let p = Buffer::decode(&mut data).unwrap();
+
+            let json = p.to_json().unwrap();
+            let v: Value = serde_json::from_slice(&json).unwrap();
+            // samples reference locations by id only
+            assert!(v["sample"][0].get("location").is_none(), "{}", file);
+            assert!(v["sample"][0]["location_id"].is_array(), "{}", file);
+            assert!(v.get("string_table").is_none(), "{}", file);
+
+            let got = Profile::from_json(&json).unwrap();
+            assert_eq!(got.to_string(), p.to_string(), "{}", file);
+            // the copies are linked again, without the string table indexes
+            let names = |p: &Profile| -> Vec<String> {
+                p.sample[0].location[0]
+                    .line
+                    .iter()
+                    .map(|l| l.function.name.clone())
+                    .collect()
+            };
+            assert_eq!(names(&got), names(&p), "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_from_json_errors() {
+        assert!(matches!(
+            Profile::from_json(b"{\"sample\": 1}"),
+            Err(RockError::MalformedProfile { .. })
+        ));
+        assert!(matches!(
+            Profile::from_json(
+                br#"{"sample_type": [{"type": "samples", "unit": "count"}], "sample": [{"value": [1, 2]}]}"#
+            ),
+            Err(RockError::MalformedProfile { .. })
+        ));
+    }
+}
@@ -1,7 +1,13 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::Decoder;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Label {
     pub key_index: i64, // Index into string table
 
@@ -21,7 +27,7 @@ pub struct Label {
 }
 
 impl Decoder<Label> for Label {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Label {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Label, RockError> {
         let mut lb = Label::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -44,16 +50,16 @@ impl Decoder<Label> for Label {
                             lb.num_unit_index = buf.u64 as i64;
                         }
                         _ => {
-                            panic!("Unknown label type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown label field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
 
-        lb
+        Ok(lb)
     }
 }
@@ -0,0 +1,972 @@
+// Parsers for the legacy text profile formats produced by the Go runtime
+// (debug=1 endpoints of net/http/pprof and older Go binaries):
+// heap profiles ("heap profile: ... @ heap/1048576"), contention and mutex
+// profiles ("--- contention:", "--- mutex:") and count profiles such as
+// goroutine and threadcreate ("goroutine profile: total N").
+// The "#" comment lines printed by the Go runtime after every stack are used
+// to symbolize the locations of the profile.
+
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::errors::RockError;
+use crate::profile::mapping::Mapping;
+use crate::profile::Profile;
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::Lines;
+use std::sync::OnceLock;
+
+const HEAP_HEADER: &str =
+    r"heap profile: *(\d+): *(\d+) *\[ *(\d+): *(\d+) *\] *@ *(heap[_a-z0-9]*)/?(\d*)";
+const HEAP_SAMPLE: &str = r"(-?\d+): *(-?\d+) *\[ *(\d+): *(\d+) *] @([ x0-9a-f]*)";
+const GROWTH_HEADER: &str = r"heap profile: *(\d+): *(\d+) *\[ *(\d+): *(\d+) *\] @ growthz?";
+const FRAGMENTATION_HEADER: &str =
+    r"heap profile: *(\d+): *(\d+) *\[ *(\d+): *(\d+) *\] @ fragmentationz?";
+const CONTENTION_SAMPLE: &str = r"(\d+) *(\d+) @([ x0-9a-f]*)";
+const COUNT_START: &str = r"\A(\S+) profile: total \d+\z";
+const COUNT: &str = r"\A(\d+) @(( 0x[0-9a-f]+)*)\z";
+const HEX_NUMBER: &str = r"0x[0-9a-f]+";
+
+// Regular expressions to parse process mappings. Support the format used by Linux /proc/.../maps and other tools.
+// Recommended format:
+// Start   End     object file name     offset(optional)   linker build id
+// 0x40000-0x80000 /path/to/binary      (@FF00)            abc123456
+const PROC_MAPS: &str = r"^\s*(?:0x)?([[:xdigit:]]+)[\s-]?\s*(?:0x)?([[:xdigit:]]+):?(?:\s+([-rwxp]+))?(?:\s+([[:xdigit:]]+))?\s+[[:xdigit:]]+:[[:xdigit:]]+\s+[[:digit:]]+(?:\s+(\S+))?";
+const BRIEF_MAPS: &str = r"^\s*(?:0x)?([[:xdigit:]]+)[\s-]?\s*(?:0x)?([[:xdigit:]]+):?(?:\s+([-rwxp]+))?(?:\s+(\S+))?(?:\s+\(@([[:xdigit:]]+)\))?(?:\s+([[:xdigit:]]+))?";
+// Regular expression to parse log data, of the form:
+// ... file:line] msg...
+const LOG_INFO: &str = r"^[^\[\]]+:[0-9]+]\s";
+// Regular expression for the shared libraries, used to find the main binary
+const LIB: &str = r"([.]so$|[.]so[._][0-9]+)";
+
+// regex returns the regexp of a constant pattern, compiled on first use.
+macro_rules! regex {
+    ($pattern:expr) => {{
+        static RX: OnceLock<Regex> = OnceLock::new();
+        RX.get_or_init(|| Regex::new($pattern).unwrap())
+    }};
+}
+
+// the patterns used by several parsers share their regexp
+fn count_start_rx() -> &'static Regex {
+    regex!(COUNT_START)
+}
+
+fn heap_header_rx() -> &'static Regex {
+    regex!(HEAP_HEADER)
+}
+
+const MEMORY_MAP_SENTINELS: [&str; 2] = ["--- Memory map: ---", "MAPPED_LIBRARIES:"];
+
+// Frames to drop from heap profiles: allocation routines of the different runtimes.
+const ALLOC_RX: &str = concat!(
+    // POSIX entry points.
+    r"calloc|cfree|malloc|free|memalign|do_memalign|(__)?posix_memalign|pvalloc|valloc|realloc|",
+    // TC malloc.
+    r"tcmalloc::.*|tc_calloc|tc_cfree|tc_malloc|tc_free|tc_memalign|tc_posix_memalign|tc_pvalloc|",
+    r"tc_valloc|tc_realloc|tc_new|tc_delete|tc_newarray|tc_deletearray|tc_new_nothrow|tc_newarray_nothrow|",
+    // Memory-allocation routines on OS X.
+    r"malloc_zone_malloc|malloc_zone_calloc|malloc_zone_valloc|malloc_zone_realloc|malloc_zone_memalign|malloc_zone_free|",
+    // Go runtime
+    r"runtime\..*|",
+    // Other misc. memory allocation routines
+    r"BaseArena::.*|(::)?do_malloc_no_errno|(::)?do_malloc_pages|(::)?do_malloc|DoSampledAllocation|",
+    r"MallocedMemBlock::MallocedMemBlock|_M_allocate|__builtin_(vec_)?delete|__builtin_(vec_)?new|",
+    r"__gnu_cxx::new_allocator::allocate|__libc_malloc|__malloc_alloc_template::allocate|allocate|",
+    r"cpp_alloc|operator new(\[\])?|simple_alloc::allocate"
+);
+
+// Preserve Go runtime frames that appear in the middle/bottom of the stack.
+const ALLOC_SKIP_RX: &str = r"runtime\.panic|runtime\.reflectcall|runtime\.call[0-9]*";
+
+const CPU_PROFILER_RX: &str = concat!(
+    r"ProfileData::Add|ProfileData::prof_handler|CpuProfiler::prof_handler|",
+    r"__pthread_sighandler|__restore"
+);
+
+const LOCK_RX: &str = concat!(
+    r"RecordLockProfileData|(base::)?RecordLockProfileData.*|(base::)?SubmitMutexProfileData.*|",
+    r"(base::)?SubmitSpinLockProfileData.*|(base::Mutex::)?AwaitCommon.*|(base::Mutex::)?Unlock.*|",
+    r"(base::Mutex::)?UnlockSlow.*|(base::Mutex::)?ReaderUnlock.*|(base::MutexLock::)?~MutexLock.*|",
+    r"(Mutex::)?AwaitCommon.*|(Mutex::)?Unlock.*|(Mutex::)?UnlockSlow.*|(Mutex::)?ReaderUnlock.*|",
+    r"(MutexLock::)?~MutexLock.*|(SpinLock::)?Unlock.*|(SpinLock::)?SlowUnlock.*|(SpinLockHolder::)?~SpinLockHolder.*"
+);
+
+// Frame is a symbolized frame from the "#" comments printed after a stack.
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    function: String,
+    file: String,
+    line: i64,
+}
+
+#[derive(Debug, Default)]
+struct LegacySample {
+    value: Vec<i64>,
+    // addresses, already adjusted to point to the call instruction
+    addrs: Vec<u64>,
+    num_label: Vec<(String, i64)>,
+}
+
+// LegacyProfile is the parsed content of the text profile, before it is converted
+// to the Profile.
+#[derive(Debug, Default)]
+struct LegacyProfile {
+    period_type: (String, String),
+    period: i64,
+    sample_type: Vec<(String, String)>,
+    duration_nanos: i64,
+    samples: Vec<LegacySample>,
+    // symbolized frames by address, inlined functions first
+    frames: HashMap<u64, Vec<Frame>>,
+    mappings: Vec<Mapping>,
+    drop_frames: &'static str,
+    keep_frames: &'static str,
+}
+
+type LegacyParser = fn(&str) -> Result<LegacyProfile, RockError>;
+
+fn unrecognized() -> RockError {
+    RockError::UnrecognizedFormat {
+        reason: String::from("unrecognized legacy profile format"),
+    }
+}
+
+fn malformed(reason: String) -> RockError {
+    RockError::MalformedProfile { reason }
+}
+
+fn is_space_or_comment(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+// is_memory_map_sentinel returns true if the string contains one of the
+// known sentinels for memory map information.
+fn is_memory_map_sentinel(line: &str) -> bool {
+    MEMORY_MAP_SENTINELS.iter().any(|s| line.contains(s))
+}
+
+// is_legacy_profile returns whether the data looks like one of the supported
+// legacy text profiles. Used to choose the parser before the protobuf decoding.
+pub fn is_legacy_profile(data: &[u8]) -> bool {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return false,
+    };
+
+    let first = match text.lines().find(|l| !is_space_or_comment(l)) {
+        Some(line) => line.trim_end(),
+        None => return false,
+    };
+
+    first.starts_with("heap profile:")
+        || first.starts_with("--- contentionz ")
+        || first.starts_with("--- mutex:")
+        || first.starts_with("--- contention:")
+        || count_start_rx().is_match(first)
+}
+
+// parse_legacy parses one of the legacy text profile formats.
+pub fn parse_legacy(data: &[u8]) -> Result<Profile, RockError> {
+    let text = std::str::from_utf8(data).map_err(|_| unrecognized())?;
+
+    let parsers: [LegacyParser; 3] = [parse_heap, parse_go_count, parse_contention];
+
+    for parser in parsers.iter() {
+        match parser(text) {
+            Ok(p) => return p.into_profile(),
+            Err(RockError::UnrecognizedFormat { .. }) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(unrecognized())
+}
+
+// parse_go_count parses a Go count profile (e.g., threadcreate or goroutine).
+fn parse_go_count(text: &str) -> Result<LegacyProfile, RockError> {
+    let count_rx = regex!(COUNT);
+
+    let mut lines = text.lines();
+    // Skip comments at the beginning of the file.
+    let header = lines
+        .by_ref()
+        .find(|l| !is_space_or_comment(l))
+        .ok_or_else(unrecognized)?;
+    let profile_type = match count_start_rx().captures(header.trim_end()) {
+        Some(c) => c[1].to_string(),
+        None => return Err(unrecognized()),
+    };
+
+    let mut p = LegacyProfile {
+        period_type: (profile_type.clone(), String::from("count")),
+        period: 1,
+        sample_type: vec![(profile_type, String::from("count"))],
+        drop_frames: CPU_PROFILER_RX,
+        ..Default::default()
+    };
+
+    let mut current: Vec<u64> = vec![];
+    let mut frames: Vec<(u64, Frame)> = vec![];
+    let mut tail = None;
+    for line in lines.by_ref() {
+        if line.starts_with('#') {
+            parse_frame_comment(line, &mut frames);
+            continue;
+        }
+        if is_space_or_comment(line) {
+            continue;
+        }
+
+        p.add_frames(&current, &mut frames);
+        if line.starts_with("---") {
+            tail = Some(line);
+            break;
+        }
+
+        let c = count_rx
+            .captures(line.trim_end())
+            .ok_or_else(|| malformed(format!("malformed count sample: {}", line)))?;
+        let n: i64 = c[1]
+            .parse()
+            .map_err(|_| malformed(format!("malformed count sample: {}", line)))?;
+        current = parse_hex_addresses(&c[2])?
+            .into_iter()
+            .map(|a| a.wrapping_sub(1))
+            .collect();
+
+        p.samples.push(LegacySample {
+            value: vec![n],
+            addrs: current.clone(),
+            ..Default::default()
+        });
+    }
+    p.add_frames(&current, &mut frames);
+
+    p.parse_additional_sections(tail, lines)?;
+    Ok(p)
+}
+
+// parse_heap parses a heap legacy or a growthz profile.
+fn parse_heap(text: &str) -> Result<LegacyProfile, RockError> {
+    let mut lines = text.lines();
+    let header = lines.next().ok_or_else(unrecognized)?;
+
+    let mut p = LegacyProfile {
+        period_type: (String::from("space"), String::from("bytes")),
+        drop_frames: ALLOC_RX,
+        keep_frames: ALLOC_SKIP_RX,
+        ..Default::default()
+    };
+
+    let mut sampling = "";
+    let mut has_alloc = false;
+    if heap_header_rx().is_match(header) {
+        let (s, period, alloc) = parse_heap_header(header)?;
+        sampling = s;
+        p.period = period;
+        has_alloc = alloc;
+    } else if regex!(GROWTH_HEADER).is_match(header)
+        || regex!(FRAGMENTATION_HEADER).is_match(header)
+    {
+        p.period = 1;
+    } else {
+        return Err(unrecognized());
+    }
+
+    let types: &[(&str, &str)] = if has_alloc {
+        // Put alloc before inuse so that default pprof selection
+        // will prefer inuse_space.
+        &[
+            ("alloc_objects", "count"),
+            ("alloc_space", "bytes"),
+            ("inuse_objects", "count"),
+            ("inuse_space", "bytes"),
+        ]
+    } else {
+        &[("objects", "count"), ("space", "bytes")]
+    };
+    p.sample_type = types
+        .iter()
+        .map(|(t, u)| (t.to_string(), u.to_string()))
+        .collect();
+
+    let sample_rx = regex!(HEAP_SAMPLE);
+    let mut current: Vec<u64> = vec![];
+    let mut frames: Vec<(u64, Frame)> = vec![];
+    let mut tail = None;
+    for line in lines.by_ref() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            parse_frame_comment(line, &mut frames);
+            continue;
+        }
+        if is_space_or_comment(line) {
+            continue;
+        }
+
+        p.add_frames(&current, &mut frames);
+        if is_memory_map_sentinel(line) {
+            tail = Some(line);
+            break;
+        }
+
+        let (value, blocksize, addrs) =
+            parse_heap_sample(sample_rx, line, p.period, sampling, has_alloc)?;
+        current = addrs.into_iter().map(|a| a.wrapping_sub(1)).collect();
+
+        p.samples.push(LegacySample {
+            value,
+            addrs: current.clone(),
+            num_label: vec![(String::from("bytes"), blocksize)],
+        });
+    }
+    p.add_frames(&current, &mut frames);
+
+    p.parse_additional_sections(tail, lines)?;
+    Ok(p)
+}
+
+// parse_heap_header returns the sampling, the sampling period and whether the
+// profile contains allocation samples.
+fn parse_heap_header(line: &str) -> Result<(&'static str, i64, bool), RockError> {
+    let header = heap_header_rx().captures(line).ok_or_else(unrecognized)?;
+
+    let mut period: i64 = 0;
+    if !header[6].is_empty() {
+        period = header[6].parse().map_err(|_| unrecognized())?;
+    }
+
+    let has_alloc = (header[3] != header[1] && &header[3] != "0")
+        || (header[4] != header[2] && &header[4] != "0");
+
+    match &header[5] {
+        "heapz_v2" | "heap_v2" => Ok(("v2", period, has_alloc)),
+        "heapprofile" => Ok(("", 1, has_alloc)),
+        "heap" => Ok(("v2", period / 2, has_alloc)),
+        _ => Err(unrecognized()),
+    }
+}
+
+// parse_heap_sample parses a single row from a heap profile, returns the values,
+// the block size and the addresses of the stack.
+fn parse_heap_sample(
+    sample_rx: &Regex,
+    line: &str,
+    rate: i64,
+    sampling: &str,
+    include_alloc: bool,
+) -> Result<(Vec<i64>, i64, Vec<u64>), RockError> {
+    let sample_data = sample_rx
+        .captures(line)
+        .ok_or_else(|| malformed(format!("unexpected heap sample: {}", line)))?;
+
+    let mut value = vec![];
+    let mut blocksize = 0;
+    let mut add_values = |count: &str, size: &str, label: &str| -> Result<(), RockError> {
+        let mut count: i64 = count
+            .parse()
+            .map_err(|err| malformed(format!("malformed sample: {}: {}", line, err)))?;
+        let mut size: i64 = size
+            .parse()
+            .map_err(|err| malformed(format!("malformed sample: {}: {}", line, err)))?;
+        if count == 0 && size != 0 {
+            return Err(malformed(format!(
+                "{} count was 0 but {} bytes was {}",
+                label, label, size
+            )));
+        }
+        if count != 0 {
+            blocksize = size / count;
+            if sampling == "v2" {
+                let (c, s) = scale_heap_sample(count, size, rate);
+                count = c;
+                size = s;
+            }
+        }
+        value.push(count);
+        value.push(size);
+        Ok(())
+    };
+
+    if include_alloc {
+        add_values(&sample_data[3], &sample_data[4], "allocation")?;
+    }
+    add_values(&sample_data[1], &sample_data[2], "inuse")?;
+
+    let addrs = parse_hex_addresses(&sample_data[5])?;
+    Ok((value, blocksize, addrs))
+}
+
+// scale_heap_sample adjusts the data from a heapz Sample to
+// account for its probability of appearing in the collected
+// data. heapz profiles are a sampling of the memory allocations
+// requests in a program. We estimate the unsampled value by dividing
+// each collected sample by its probability of appearing in the
+// profile. heapz v2 profiles rely on a poisson process to determine
+// which samples to collect, based on the desired average collection
+// rate R. The probability of a sample of size S to appear in that
+// profile is 1-exp(-S/R).
+fn scale_heap_sample(count: i64, size: i64, rate: i64) -> (i64, i64) {
+    if count == 0 || size == 0 {
+        return (0, 0);
+    }
+
+    if rate <= 1 {
+        // if rate==1 all samples were collected so no adjustment is needed.
+        // if rate<1 treat as unknown and skip scaling.
+        return (count, size);
+    }
+
+    let avg_size = size as f64 / count as f64;
+    let scale = 1.0 / (1.0 - (-avg_size / rate as f64).exp());
+
+    ((count as f64 * scale) as i64, (size as f64 * scale) as i64)
+}
+
+// parse_contention parses a mutex or contention profile. There are 2 cases:
+// "--- contentionz " for legacy C++ profiles (and backwards compatibility)
+// "--- mutex:" or "--- contention:" for profiles generated by the Go runtime.
+fn parse_contention(text: &str) -> Result<LegacyProfile, RockError> {
+    let mut lines = text.lines();
+    let header = lines.next().ok_or_else(unrecognized)?;
+    if !(header.starts_with("--- contentionz ")
+        || header.starts_with("--- mutex:")
+        || header.starts_with("--- contention:"))
+    {
+        return Err(unrecognized());
+    }
+
+    let mut p = LegacyProfile {
+        period_type: (String::from("contentions"), String::from("count")),
+        period: 1,
+        sample_type: vec![
+            (String::from("contentions"), String::from("count")),
+            (String::from("delay"), String::from("nanoseconds")),
+        ],
+        drop_frames: LOCK_RX,
+        ..Default::default()
+    };
+
+    let mut cpu_hz: i64 = 0;
+    let mut first_sample = None;
+    // Parse text of the form "attribute = value" before the samples.
+    for line in lines.by_ref() {
+        let line = line.trim();
+        if is_space_or_comment(line) {
+            continue;
+        }
+        let (key, val) = match line.split_once('=') {
+            Some((key, val)) if !line.starts_with("---") => (key.trim(), val.trim()),
+            _ => {
+                first_sample = Some(line);
+                break;
+            }
+        };
+
+        match key {
+            "cycles/second" => cpu_hz = parse_int(val).ok_or_else(unrecognized)?,
+            "sampling period" => p.period = parse_int(val).ok_or_else(unrecognized)?,
+            "ms since reset" => {
+                let ms = parse_int(val).ok_or_else(unrecognized)?;
+                p.duration_nanos = ms.checked_mul(1_000_000).ok_or_else(unrecognized)?;
+            }
+            // CPP contentionz profiles don't have format and resolution.
+            "format" | "resolution" => return Err(unrecognized()),
+            "discarded samples" => {}
+            _ => return Err(unrecognized()),
+        }
+    }
+
+    let sample_rx = regex!(CONTENTION_SAMPLE);
+    let mut current: Vec<u64> = vec![];
+    let mut frames: Vec<(u64, Frame)> = vec![];
+    let mut tail = None;
+    for line in first_sample.into_iter().chain(lines.by_ref()) {
+        let line = line.trim();
+        if line.starts_with('#') {
+            parse_frame_comment(line, &mut frames);
+            continue;
+        }
+        if is_space_or_comment(line) {
+            continue;
+        }
+
+        p.add_frames(&current, &mut frames);
+        if line.starts_with("---") {
+            tail = Some(line);
+            break;
+        }
+
+        let (value, addrs) = parse_contention_sample(sample_rx, line, p.period, cpu_hz)?;
+        current = addrs.into_iter().map(|a| a.wrapping_sub(1)).collect();
+        p.samples.push(LegacySample {
+            value,
+            addrs: current.clone(),
+            ..Default::default()
+        });
+    }
+    p.add_frames(&current, &mut frames);
+
+    p.parse_additional_sections(tail, lines)?;
+    Ok(p)
+}
+
+// parse_contention_sample parses a single row from a contention profile
+// into the values and addresses.
+fn parse_contention_sample(
+    sample_rx: &Regex,
+    line: &str,
+    period: i64,
+    cpu_hz: i64,
+) -> Result<(Vec<i64>, Vec<u64>), RockError> {
+    let sample_data = sample_rx.captures(line).ok_or_else(unrecognized)?;
+
+    let mut v1: i64 = sample_data[1]
+        .parse()
+        .map_err(|err| malformed(format!("malformed sample: {}: {}", line, err)))?;
+    let mut v2: i64 = sample_data[2]
+        .parse()
+        .map_err(|err| malformed(format!("malformed sample: {}: {}", line, err)))?;
+
+    // Unsample values if period and cpuHz are available.
+    // - Delays are scaled to cycles and then to nanoseconds.
+    // - Contentions are scaled to cycles.
+    if period > 0 {
+        if cpu_hz > 0 {
+            let cpu_ghz = cpu_hz as f64 / 1e9;
+            v1 = (v1 as f64 * period as f64 / cpu_ghz) as i64;
+        }
+        v2 *= period;
+    }
+
+    let addrs = parse_hex_addresses(&sample_data[3])?;
+    Ok((vec![v2, v1], addrs))
+}
+
+// parse_int parses decimal, hex (0x) and octal (0) integers, like strconv.ParseInt(s, 0, 64)
+fn parse_int(s: &str) -> Option<i64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    if s.len() > 1 && s.starts_with('0') {
+        return i64::from_str_radix(&s[1..], 8).ok();
+    }
+    s.parse().ok()
+}
+
+// parse_hex_addresses extracts hex numbers from a string, attempts to convert
+// each to an unsigned 64-bit number and returns the resulting numbers,
+// or an error if the string contains hex numbers which are too large to
+// handle (which means a malformed profile).
+fn parse_hex_addresses(s: &str) -> Result<Vec<u64>, RockError> {
+    let mut addrs = vec![];
+    for m in regex!(HEX_NUMBER).find_iter(s) {
+        match u64::from_str_radix(&m.as_str()[2..], 16) {
+            Ok(addr) => addrs.push(addr),
+            Err(_) => {
+                return Err(malformed(format!(
+                    "failed to parse as hex 64-bit number: {}",
+                    m.as_str()
+                )))
+            }
+        }
+    }
+    Ok(addrs)
+}
+
+// parse_frame_comment parses the "#\t0x4c0f4c\tmain.main+0x2c\t/app/main.go:12" lines
+// printed by the Go runtime after each stack. Lines without a symbol are skipped.
+fn parse_frame_comment(line: &str, frames: &mut Vec<(u64, Frame)>) {
+    let mut fields = line.trim_start_matches('#').split('\t').map(str::trim);
+    let mut fields = fields.by_ref().filter(|f| !f.is_empty());
+
+    let pc = match fields
+        .next()
+        .and_then(|f| f.strip_prefix("0x"))
+        .and_then(|f| u64::from_str_radix(f, 16).ok())
+    {
+        Some(pc) => pc,
+        None => return,
+    };
+    let function = match fields.next() {
+        Some(f) => match f.rsplit_once("+0x") {
+            Some((name, _)) => name.to_string(),
+            None => f.to_string(),
+        },
+        None => return,
+    };
+    let (file, line) = match fields.next().and_then(|f| f.rsplit_once(':')) {
+        Some((file, line)) => (file.to_string(), line.parse().unwrap_or(0)),
+        None => (String::new(), 0),
+    };
+
+    frames.push((
+        pc,
+        Frame {
+            function,
+            file,
+            line,
+        },
+    ));
+}
+
+impl LegacyProfile {
+    // add_frames records the frames parsed from the comments of the last stack.
+    // Consecutive frames with the same pc are inlined calls. The frames are
+    // attached to the stack addresses, the first seen symbolization wins.
+    fn add_frames(&mut self, stack: &[u64], frames: &mut Vec<(u64, Frame)>) {
+        for (pc, frame) in frames.drain(..) {
+            // The runtime reports the pc of the call instruction, the same as the
+            // adjusted address. Accept the return address as well.
+            let addr = if stack.contains(&pc) {
+                pc
+            } else if stack.contains(&pc.wrapping_sub(1)) {
+                pc.wrapping_sub(1)
+            } else {
+                continue;
+            };
+
+            let entry = self.frames.entry(addr).or_default();
+            if !entry.contains(&frame) {
+                entry.push(frame);
+            }
+        }
+    }
+
+    // parse_additional_sections parses any additional sections in the
+    // profile, ignoring any unrecognized sections. Only the memory map is used.
+    fn parse_additional_sections(
+        &mut self,
+        tail: Option<&str>,
+        mut lines: Lines,
+    ) -> Result<(), RockError> {
+        if !tail.is_some_and(is_memory_map_sentinel) && !lines.any(is_memory_map_sentinel) {
+            return Ok(());
+        }
+        self.mappings = parse_proc_maps(lines)?;
+        Ok(())
+    }
+
+    // into_profile builds the Profile from the parsed data: associates the addresses
+    // with the mappings and the symbolized frames.
+    fn into_profile(mut self) -> Result<Profile, RockError> {
+        massage_mappings(&mut self.mappings);
+
+        let mut b = ProfileBuilder::new();
+        b.period_type(&self.period_type.0, &self.period_type.1, self.period);
+        for (t, u) in self.sample_type.iter() {
+            b.add_sample_type(t, u);
+        }
+        b.duration_nanos(self.duration_nanos);
+        b.drop_frames(self.drop_frames);
+        b.keep_frames(self.keep_frames);
+
+        let mut mappings = self.mappings.clone();
+        for m in mappings.iter_mut() {
+            m.id = b.add_mapping(m.clone());
+        }
+        let mut fake_mapping = None;
+        // mapping id -> whether all the locations of the mapping are symbolized
+        let mut symbolized: HashMap<u64, bool> = HashMap::new();
+        let mut locations: HashMap<u64, u64> = HashMap::new();
+
+        for s in self.samples.iter() {
+            let mut ids = Vec::with_capacity(s.addrs.len());
+            for addr in s.addrs.iter() {
+                if let Some(id) = locations.get(addr) {
+                    ids.push(*id);
+                    continue;
+                }
+
+                let mut mapping_id = 0;
+                if *addr != 0 {
+                    mapping_id = match find_mapping(&mut mappings, *addr, &mut b) {
+                        Some(id) => id,
+                        // If there is still no mapping, create a fake one.
+                        // This is important for the Go legacy handler, which produced
+                        // no mappings.
+                        None => *fake_mapping.get_or_insert_with(|| {
+                            b.add_mapping(Mapping {
+                                memory_limit: u64::MAX,
+                                ..Default::default()
+                            })
+                        }),
+                    };
+                }
+
+                let lines: Vec<(u64, i64)> = match self.frames.get(addr) {
+                    None => vec![],
+                    Some(frames) => frames
+                        .iter()
+                        .map(|f| (b.add_function(&f.function, &f.function, &f.file, 0), f.line))
+                        .collect(),
+                };
+                *symbolized.entry(mapping_id).or_insert(true) &= !lines.is_empty();
+
+                let id = b.add_location(mapping_id, *addr, &lines);
+                locations.insert(*addr, id);
+                ids.push(id);
+            }
+
+            let sample = b.add_sample(ids, s.value.clone());
+            for (key, value) in s.num_label.iter() {
+                sample.num_label.insert(key.clone(), vec![*value]);
+            }
+        }
+
+        for (id, all) in symbolized {
+            if let Some(m) = b.mapping(id) {
+                m.has_function = all;
+                m.has_filenames = all;
+                m.has_line_numbers = all;
+            }
+        }
+
+        b.build()
+    }
+}
+
+// find_mapping returns the id of the mapping containing the address.
+fn find_mapping(mappings: &mut [Mapping], addr: u64, b: &mut ProfileBuilder) -> Option<u64> {
+    if let Some(m) = mappings
+        .iter()
+        .find(|m| m.memory_start <= addr && addr < m.memory_limit)
+    {
+        return Some(m.id);
+    }
+
+    // Work around legacy handlers failing to encode the first
+    // part of mappings split into adjacent ranges.
+    for m in mappings.iter_mut() {
+        if m.memory_offset != 0
+            && m.memory_start.wrapping_sub(m.memory_offset) <= addr
+            && addr < m.memory_start
+        {
+            m.memory_start -= m.memory_offset;
+            m.memory_offset = 0;
+            if let Some(bm) = b.mapping(m.id) {
+                bm.memory_start = m.memory_start;
+                bm.memory_offset = 0;
+            }
+            return Some(m.id);
+        }
+    }
+
+    None
+}
+
+// parse_proc_maps parses a memory map in the format of /proc/self/maps.
+fn parse_proc_maps(lines: Lines) -> Result<Vec<Mapping>, RockError> {
+    let proc_maps_rx = regex!(PROC_MAPS);
+    let brief_maps_rx = regex!(BRIEF_MAPS);
+    let log_info_rx = regex!(LOG_INFO);
+
+    let mut mappings = vec![];
+    let mut attrs: Vec<(String, String)> = vec![];
+
+    for line in lines {
+        // remove the log prefix generated by glog
+        let mut line = match log_info_rx.find(line) {
+            Some(m) => line[m.end()..].to_string(),
+            None => line.to_string(),
+        };
+        for (attr, value) in attrs.iter() {
+            line = line.replace(attr, value);
+        }
+
+        match parse_mapping_entry(proc_maps_rx, brief_maps_rx, &line)? {
+            Some(Some(m)) => mappings.push(m),
+            // skipped non-executable entry
+            Some(None) => {}
+            None => {
+                // Recognize assignments of the form: attr=value, and replace
+                // $attr with value on subsequent mappings.
+                if let Some((attr, value)) = line.split_once('=') {
+                    attrs.push((format!("${}", attr.trim()), value.trim().to_string()));
+                }
+            }
+        }
+    }
+
+    Ok(mappings)
+}
+
+// parse_mapping_entry returns None if the line is not recognized as a mapping,
+// Some(None) for non executable mappings.
+fn parse_mapping_entry(
+    proc_maps_rx: &Regex,
+    brief_maps_rx: &Regex,
+    line: &str,
+) -> Result<Option<Option<Mapping>>, RockError> {
+    let get = |c: &regex::Captures, i: usize| c.get(i).map_or("", |m| m.as_str()).to_string();
+
+    let (start, end, perm, offset, file, build_id) = if let Some(c) = proc_maps_rx.captures(line) {
+        (
+            get(&c, 1),
+            get(&c, 2),
+            get(&c, 3),
+            get(&c, 4),
+            get(&c, 5),
+            String::new(),
+        )
+    } else if let Some(c) = brief_maps_rx.captures(line) {
+        (
+            get(&c, 1),
+            get(&c, 2),
+            get(&c, 3),
+            get(&c, 5),
+            get(&c, 4),
+            get(&c, 6),
+        )
+    } else {
+        return Ok(None);
+    };
+
+    if !perm.is_empty() && !perm.contains('x') {
+        // Skip non-executable entries.
+        return Ok(Some(None));
+    }
+
+    let parse = |s: &str| u64::from_str_radix(s, 16).ok();
+    let mut mapping = Mapping {
+        filename: file,
+        build_id,
+        ..Default::default()
+    };
+    mapping.memory_start = match parse(&start) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    mapping.memory_limit = match parse(&end) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if !offset.is_empty() {
+        mapping.memory_offset = match parse(&offset) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+    }
+
+    Ok(Some(Some(mapping)))
+}
+
+// massage_mappings merges adjacent regions with matching names, moves the
+// main binary to the top of the list and fixes the well known issues of
+// the legacy profile handlers.
+fn massage_mappings(mappings: &mut Vec<Mapping>) {
+    // Merge adjacent regions with matching names, checking that the offsets match
+    if mappings.len() > 1 {
+        let mut merged: Vec<Mapping> = vec![mappings[0].clone()];
+        for m in mappings[1..].iter() {
+            let lm = merged.last_mut().unwrap();
+            if adjacent(lm, m) {
+                lm.memory_limit = m.memory_limit;
+                if !m.filename.is_empty() {
+                    lm.filename = m.filename.clone();
+                }
+                if !m.build_id.is_empty() {
+                    lm.build_id = m.build_id.clone();
+                }
+                continue;
+            }
+            merged.push(m.clone());
+        }
+        *mappings = merged;
+    }
+
+    // Use heuristics to identify main binary and move it to the top of the list of mappings
+    let lib_rx = regex!(LIB);
+    if let Some(i) = mappings.iter().position(|m| {
+        let file = m.filename.replace("(deleted)", "");
+        let file = file.trim();
+        !file.is_empty() && !lib_rx.is_match(file) && !file.starts_with('[')
+    }) {
+        mappings.swap(0, i);
+    }
+
+    if let Some(m) = mappings.first() {
+        // Remove the initial mapping if named '/anon_hugepage' and has a
+        // consecutive adjacent mapping.
+        if m.filename.starts_with("/anon_hugepage")
+            && mappings.len() > 1
+            && m.memory_limit == mappings[1].memory_start
+        {
+            mappings.remove(0);
+        }
+    }
+
+    // Subtract the offset from the start of the main mapping if it
+    // ends up at a recognizable start address.
+    if let Some(m) = mappings.first_mut() {
+        const EXPECTED_START: u64 = 0x400000;
+        if m.memory_start.wrapping_sub(m.memory_offset) == EXPECTED_START {
+            m.memory_start = EXPECTED_START;
+            m.memory_offset = 0;
+        }
+    }
+}
+
+fn adjacent(m1: &Mapping, m2: &Mapping) -> bool {
+    if !m1.filename.is_empty() && !m2.filename.is_empty() && m1.filename != m2.filename {
+        return false;
+    }
+    if !m1.build_id.is_empty() && !m2.build_id.is_empty() && m1.build_id != m2.build_id {
+        return false;
+    }
+    if m1.memory_limit != m2.memory_start {
+        return false;
+    }
+    if m1.memory_offset != 0 && m2.memory_offset != 0 {
+        let offset = m1.memory_offset + (m1.memory_limit - m1.memory_start);
+        if offset != m2.memory_offset {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::legacy_profile::{is_legacy_profile, parse_legacy};
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_legacy_golden() {
+        // key - path to the legacy profile
+        // value - path to related golden file
+        let mut test_data = HashMap::<&str, &str>::new();
+        test_data.insert("tests/legacy/heap", "tests/legacy/heap.string");
+        test_data.insert("tests/legacy/goroutine", "tests/legacy/goroutine.string");
+        test_data.insert(
+            "tests/legacy/threadcreate",
+            "tests/legacy/threadcreate.string",
+        );
+        test_data.insert("tests/legacy/mutex", "tests/legacy/mutex.string");
+        test_data.insert("tests/legacy/contention", "tests/legacy/contention.string");
+
+        for (k, v) in test_data.iter() {
+            let mut data = std::fs::read(k).unwrap();
+            let golden = std::fs::read_to_string(v).unwrap();
+
+            assert!(is_legacy_profile(&data), "{} is not detected", k);
+            let p = Buffer::decode(&mut data).unwrap();
+            assert_eq!(p.to_string().trim_end(), golden.trim_end(), "{}", k);
+        }
+    }
+
+    #[test]
+    fn parse_legacy_errors() {
+        assert!(!is_legacy_profile(b"\x0a\x04\x08\x01\x10\x02"));
+        assert!(parse_legacy(b"not a profile").is_err());
+        assert!(parse_legacy(b"goroutine profile: total 1\n1 @ zzz\n").is_err());
+        assert!(parse_legacy(b"heap profile: 1: 2 [3: 4] @ heap/2\n0: 5 [0: 0] @ 0x1\n").is_err());
+        // the duration in nanoseconds overflows
+        let contention = std::fs::read_to_string("tests/legacy/contention").unwrap();
+        let overflow =
+            contention.replace("ms since reset = 60000", "ms since reset = 0x7fffffffffff");
+        assert!(parse_legacy(overflow.as_bytes()).is_err());
+    }
+}
@@ -1,18 +1,26 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::{function, Decoder};
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Line {
     // Line number in source code.
     pub line: i64,
     // The id of the corresponding profile.Function for this line.
+    #[cfg_attr(feature = "serde", serde(rename = "function_id"))]
     pub function_index: u64,
     // HELPERS
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub function: function::Function,
 }
 
 impl Decoder<Line> for Line {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Line {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Line, RockError> {
         let mut line = Line::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -27,15 +35,15 @@ impl Decoder<Line> for Line {
                             line.line = buf.u64 as i64;
                         }
                         _ => {
-                            panic!("Unknown line type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown line field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        line
+        Ok(line)
     }
 }
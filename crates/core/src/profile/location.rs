@@ -1,9 +1,15 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::mapping::Mapping;
 use crate::profile::{function, line, Decoder};
 use std::fmt::Write as _;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 // Describes function and line table debug information.
 pub struct Location {
     // Unique nonzero id for the location.  A profile could use
@@ -12,6 +18,7 @@ pub struct Location {
     // The id of the corresponding profile.Mapping for this location.
     // It can be unset if the mapping is unknown or not applicable for
     // this profile type.
+    #[cfg_attr(feature = "serde", serde(rename = "mapping_id"))]
     pub mapping_index: u64,
     // The instruction address for this location, if available.  It
     // should be within [Mapping.memory_start...Mapping.memory_limit]
@@ -34,11 +41,12 @@ pub struct Location {
     // profile changes.
     pub is_folder: bool,
     //HELPER
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub mapping: Option<Mapping>,
 }
 
 impl Decoder<Location> for Location {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Location {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Location, RockError> {
         let mut loc = Location::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -59,7 +67,7 @@ impl Decoder<Location> for Location {
                         // repeated Line line = 4
                         4 => {
                             // todo!(why buf copied twice) ?????
-                            loc.line.push(line::Line::decode(buf, buf_data));
+                            loc.line.push(line::Line::decode(buf, buf_data)?);
                         }
                         5 => {
                             if buf.u64 == 0 {
@@ -69,16 +77,16 @@ impl Decoder<Location> for Location {
                             }
                         }
                         _ => {
-                            panic!("Unknown location type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown location field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        loc
+        Ok(loc)
     }
 }
 
@@ -1,10 +1,16 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::Decoder;
 use std::default::Default;
 
 // TMP
 // mapping corresponds to Profile.Mapping
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Mapping {
     // Unique nonzero id for the mapping.
     pub id: u64,
@@ -31,13 +37,15 @@ pub struct Mapping {
     pub has_inline_frames: bool,
 
     // Index into string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub filename_index: i64,
     // Index into string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub build_id_index: i64,
 }
 
 impl Decoder<Mapping> for Mapping {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Mapping {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Mapping, RockError> {
         let mut mapping = Mapping::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -97,16 +105,16 @@ impl Decoder<Mapping> for Mapping {
                             }
                         }
                         _ => {
-                            panic!("Unknown mapping type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown mapping field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        mapping
+        Ok(mapping)
     }
 }
 
@@ -0,0 +1,250 @@
+// Merging of profiles, as done by pprof for multiple sources and for the
+// -base and -diff_base profiles: mappings, functions and locations are
+// deduplicated, samples with the same stack and labels are combined by
+// summing their values.
+
+use crate::profile::builder::ProfileBuilder;
+use crate::profile::errors::RockError;
+use crate::profile::sample::Sample;
+use crate::profile::Profile;
+use std::collections::HashMap;
+
+// key to deduplicate mappings: start, limit, offset, filename, build id
+type MappingKey = (u64, u64, u64, String, String);
+
+// label_key is a canonical form of the labels of a sample.
+fn label_key(s: &Sample) -> String {
+    let mut labels: Vec<String> = s
+        .label
+        .iter()
+        .map(|(k, v)| format!("{}={:?}", k, v))
+        .chain(
+            s.num_label
+                .iter()
+                .map(|(k, v)| format!("{}#{:?}{:?}", k, v, s.num_unit_label.get(k))),
+        )
+        .collect();
+    labels.sort();
+    labels.join(";")
+}
+
+impl Profile {
+    // merge combines the profiles into a single one. The profiles must have
+    // the same sample types and period type, the result has the earliest
+    // time, the sum of the durations and the other attributes of the first
+    // profile.
+    pub fn merge(profiles: &[Profile]) -> Result<Profile, RockError> {
+        let first = match profiles.first() {
+            Some(p) => p,
+            None => {
+                return Err(RockError::ValidationFailed {
+                    reason: String::from("no profiles to merge"),
+                })
+            }
+        };
+        for p in profiles[1..].iter() {
+            first.compatible(p)?;
+        }
+
+        let mut b = ProfileBuilder::new();
+        for st in first.sample_type.iter() {
+            b.add_sample_type(&st.r#type, &st.unit);
+        }
+        if let Some(pt) = &first.period_type {
+            b.period_type(&pt.r#type, &pt.unit, first.period);
+        }
+        b.drop_frames(&first.drop_frames)
+            .keep_frames(&first.keep_frames)
+            .default_sample_type(&first.default_sample_type);
+
+        let mut comments = vec![];
+        let mut time_nanos = 0;
+        let mut duration_nanos = 0;
+        let mut mappings: HashMap<MappingKey, u64> = HashMap::new();
+        // aggregated samples: location ids, values, the first sample of the group
+        let mut samples: Vec<(Vec<u64>, Vec<i64>, &Sample)> = vec![];
+        let mut index: HashMap<(Vec<u64>, String), usize> = HashMap::new();
+
+        for p in profiles.iter() {
+            for c in p.comments.iter() {
+                if !comments.contains(c) {
+                    comments.push(c.clone());
+                }
+            }
+            if p.time_nanos != 0 && (time_nanos == 0 || p.time_nanos < time_nanos) {
+                time_nanos = p.time_nanos;
+            }
+            duration_nanos += p.duration_nanos;
+
+            let mut mapping_ids = HashMap::new();
+            for m in p.mapping.iter() {
+                let key = (
+                    m.memory_start,
+                    m.memory_limit,
+                    m.memory_offset,
+                    m.filename.clone(),
+                    m.build_id.clone(),
+                );
+                let id = *mappings
+                    .entry(key)
+                    .or_insert_with(|| b.add_mapping(m.clone()));
+                mapping_ids.insert(m.id, id);
+            }
+
+            let mut function_ids = HashMap::new();
+            for f in p.function.iter() {
+                let id = b.add_function(&f.name, &f.system_name, &f.filename, f.start_line);
+                function_ids.insert(f.id, id);
+            }
+
+            let mut location_ids = HashMap::new();
+            for l in p.location.iter() {
+                let lines: Vec<(u64, i64)> = l
+                    .line
+                    .iter()
+                    .map(|line| {
+                        (
+                            function_ids.get(&line.function_index).copied().unwrap_or(0),
+                            line.line,
+                        )
+                    })
+                    .collect();
+                let mapping = mapping_ids.get(&l.mapping_index).copied().unwrap_or(0);
+                location_ids.insert(l.id, b.add_location(mapping, l.address, &lines));
+            }
+
+            for s in p.sample.iter() {
+                let locations: Vec<u64> = s
+                    .location_index
+                    .iter()
+                    .filter_map(|id| location_ids.get(id).copied())
+                    .collect();
+                let key = (locations, label_key(s));
+                match index.get(&key) {
+                    Some(i) => {
+                        for (v, sv) in samples[*i].1.iter_mut().zip(s.value.iter()) {
+                            *v += sv;
+                        }
+                    }
+                    None => {
+                        index.insert(key.clone(), samples.len());
+                        samples.push((key.0, s.value.clone(), s));
+                    }
+                }
+            }
+        }
+
+        for c in comments.iter() {
+            b.add_comment(c);
+        }
+        b.time_nanos(time_nanos).duration_nanos(duration_nanos);
+        for (locations, values, s) in samples {
+            let sample = b.add_sample(locations, values);
+            sample.label = s.label.clone();
+            sample.num_label = s.num_label.clone();
+            sample.num_unit_label = s.num_unit_label.clone();
+        }
+        b.build()
+    }
+
+    // compatible checks that the samples of the profiles can be combined.
+    pub fn compatible(&self, other: &Profile) -> Result<(), RockError> {
+        let types = |p: &Profile| -> Vec<(String, String)> {
+            p.sample_type
+                .iter()
+                .map(|st| (st.r#type.clone(), st.unit.clone()))
+                .collect()
+        };
+        if types(self) != types(other) {
+            return Err(RockError::IncompatibleUnits {
+                reason: format!(
+                    "incompatible sample types {:?} and {:?}",
+                    types(self),
+                    types(other)
+                ),
+            });
+        }
+
+        let period_type = |p: &Profile| {
+            p.period_type
+                .as_ref()
+                .map(|pt| (pt.r#type.clone(), pt.unit.clone()))
+        };
+        if period_type(self) != period_type(other) {
+            return Err(RockError::IncompatibleUnits {
+                reason: format!(
+                    "incompatible period types {:?} and {:?}",
+                    period_type(self),
+                    period_type(other)
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::errors::RockError;
+    use crate::profile::Profile;
+    use std::fs;
+
+    #[test]
+    fn test_merge() {
+        let mut data = fs::read("tests/CPU.pb.gz").unwrap();
+        let p = Buffer::decode(&mut data).unwrap();
+
+        let merged = Profile::merge(&[p.clone(), p.clone()]).unwrap();
+        assert_eq!(merged.sample.len(), p.sample.len());
+        assert_eq!(merged.location.len(), p.location.len());
+        assert_eq!(merged.mapping.len(), p.mapping.len());
+        assert_eq!(merged.duration_nanos, 2 * p.duration_nanos);
+        let total = |p: &Profile| p.sample.iter().map(|s| s.value[1]).sum::<i64>();
+        assert_eq!(total(&merged), 2 * total(&p));
+
+        // merging a profile with its negation cancels the values
+        let mut negated = p.clone();
+        negated.scale_n(&[-1.0, -1.0]).unwrap();
+        let diff = Profile::merge(&[p.clone(), negated]).unwrap();
+        assert!(diff.sample.iter().all(|s| s.value == vec![0, 0]));
+    }
+
+    #[test]
+    fn test_merge_labels() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count");
+        let f = b.add_function("main", "main", "", 0);
+        let l = b.add_location(0, 0x10, &[(f, 0)]);
+        b.add_sample(vec![l], vec![1])
+            .label
+            .insert(String::from("thread"), vec![String::from("a")]);
+        b.add_sample(vec![l], vec![2])
+            .label
+            .insert(String::from("thread"), vec![String::from("b")]);
+        b.add_sample(vec![l], vec![4])
+            .label
+            .insert(String::from("thread"), vec![String::from("a")]);
+        let p = b.build().unwrap();
+
+        let merged = Profile::merge(&[p]).unwrap();
+        let values: Vec<i64> = merged.sample.iter().map(|s| s.value[0]).collect();
+        assert_eq!(values, vec![5, 2]);
+    }
+
+    #[test]
+    fn test_merge_incompatible() {
+        let mut cpu = fs::read("tests/CPU.pb.gz").unwrap();
+        let mut heap = fs::read("tests/HEAP.pb.gz").unwrap();
+        let profiles = [
+            Buffer::decode(&mut cpu).unwrap(),
+            Buffer::decode(&mut heap).unwrap(),
+        ];
+        assert!(matches!(
+            Profile::merge(&profiles),
+            Err(RockError::IncompatibleUnits { .. })
+        ));
+        assert!(Profile::merge(&[]).is_err());
+    }
+}
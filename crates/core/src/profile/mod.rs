@@ -1,24 +1,58 @@
+use crate::measurement;
 use crate::profile::buffer::{decode_string, decode_varint, Buffer, WireTypes};
 use crate::profile::errors::RockError;
 use chrono::NaiveDateTime;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use std::borrow::Borrow;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+#[cfg(feature = "async")]
+pub mod async_io;
 pub mod buffer;
-mod errors;
-pub(crate) mod function;
-mod label;
-pub(crate) mod line;
-pub(crate) mod location;
-mod mapping;
-mod sample;
-mod value_type;
+pub mod builder;
+pub mod encoder;
+pub mod errors;
+pub mod filter;
+pub mod function;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod label;
+pub mod legacy_profile;
+pub mod line;
+pub mod location;
+pub mod mapping;
+pub mod merge;
+pub mod sample;
+pub mod stream;
+pub mod value_type;
+pub mod writer;
 
 const NSEC_IN_SECOND: i64 = 1_000_000_000;
 
+// PARALLEL_MIN_LEN is the number of samples or locations resolved by a task
+// of the parallel post_decode, smaller profiles are resolved on one thread.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_LEN: usize = 512;
+
+// for_each_mut calls f on every item. With the parallel feature, large slices
+// are split across the threads of the rayon pool, unless it has only one
+// thread where the hand-off costs more than it saves.
+fn for_each_mut<T: Send>(items: &mut [T], f: impl Fn(&mut T) + Sync + Send) {
+    #[cfg(feature = "parallel")]
+    if items.len() >= 2 * PARALLEL_MIN_LEN && rayon::current_num_threads() > 1 {
+        items
+            .par_iter_mut()
+            .with_min_len(PARALLEL_MIN_LEN)
+            .for_each(f);
+        return;
+    }
+    items.iter_mut().for_each(f);
+}
+
 pub trait Decoder<T> {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> T;
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<T, RockError>;
 }
 
 // TODO ADD OPTIONAL TO THE STRUCT FIELDS
@@ -26,6 +60,11 @@ pub trait Decoder<T> {
 // Profile is an in-memory representation of profile.proto
 
 #[derive(Clone, Default, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Profile {
     // A description of the samples associated with each Sample.value.
     // For a cpu profile this might be:
@@ -35,53 +74,58 @@ pub struct Profile {
     // If one of the values represents the number of events represented
     // by the sample, by convention it should be at index 0 and use
     // sample_type.unit == "count".
-    sample_type: Vec<value_type::ValueType>,
+    pub(crate) sample_type: Vec<value_type::ValueType>,
     // The set of samples recorded in this profile.
-    sample: Vec<sample::Sample>,
+    pub(crate) sample: Vec<sample::Sample>,
     // Mapping from address ranges to the image/binary/library mapped
     // into that address range.  mapping[0] will be the main binary.
-    mapping: Vec<mapping::Mapping>,
+    pub(crate) mapping: Vec<mapping::Mapping>,
     // Useful program location
     pub location: Vec<location::Location>,
     // Functions referenced by locations
-    function: Vec<function::Function>,
+    pub(crate) function: Vec<function::Function>,
     // A common table for strings referenced by various messages.
     // string_table[0] must always be "".
+    #[cfg_attr(feature = "serde", serde(skip))]
     string_table: Vec<String>,
     // frames with Function.function_name fully matching the following
     // regexp will be dropped from the samples, along with their successors.
-    drop_frames: String,
+    pub(crate) drop_frames: String,
     // Index into string table.
     // frames with Function.function_name fully matching the following
     // regexp will be kept, even if it matches drop_functions.
-    keep_frames: String, // Index into string table.
+    pub(crate) keep_frames: String, // Index into string table.
 
     // The following fields are informational, do not affect
     // interpretation of results.
     // Time of collection (UTC) represented as nanoseconds past the epoch.
-    time_nanos: i64,
+    pub(crate) time_nanos: i64,
     // Duration of the profile, if a duration makes sense.
-    duration_nanos: i64,
+    pub(crate) duration_nanos: i64,
     // The kind of events between sampled ocurrences.
     // e.g [ "cpu","cycles" ] or [ "heap","bytes" ]
-    period_type: Option<value_type::ValueType>,
+    pub(crate) period_type: Option<value_type::ValueType>,
     // The number of events between sampled occurrences.
-    period: i64,
+    pub(crate) period: i64,
     // Freeform text associated to the profile.
-    comments: Vec<String>,
+    pub(crate) comments: Vec<String>,
     // Indices into string table.
     // Index into the string table of the type of the preferred sample
     // value. If unset, clients should default to the last sample value.
-    default_sample_type: String,
+    pub(crate) default_sample_type: String,
 
     // Index into string table.
+    #[cfg_attr(feature = "serde", serde(skip))]
     comment_index: Vec<i64>,
     // Index into string table.
+    #[cfg_attr(feature = "serde", serde(skip))]
     drop_frames_index: i64,
     // Index into string table.
+    #[cfg_attr(feature = "serde", serde(skip))]
     keep_frames_index: i64,
 
     // Index into string table.
+    #[cfg_attr(feature = "serde", serde(skip))]
     default_sample_type_index: i64,
 }
 
@@ -164,36 +208,117 @@ impl ToString for Profile {
     }
 }
 
+// decode_labels resolves the label indices of the sample into its string
+// and numeric labels.
+pub(crate) fn decode_labels(string_table: &[String], s: &mut sample::Sample) {
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    let mut num_labels: HashMap<String, Vec<i64>> = HashMap::new();
+    let mut num_units: HashMap<String, Vec<String>> = HashMap::new();
+
+    for label_index in s.label_index.iter() {
+        // key can't be empty
+        let key = string_table[label_index.key_index as usize].to_string();
+
+        if label_index.str_index != 0 {
+            let key_value = string_table[label_index.str_index as usize].to_string();
+            // using or_insert_with because: The function will always be called and potentially allocate an object acting as the default.
+            labels
+                .entry(key)
+                .and_modify(|e| e.push(key_value.clone()))
+                .or_insert_with(|| vec![key_value]);
+        } else if label_index.num_index != 0 {
+            if label_index.num_unit_index != 0 {
+                let unit = string_table[label_index.num_unit_index as usize].to_string();
+
+                let num_len = num_labels.get(&key).unwrap_or(&Vec::<i64>::new()).len();
+                let units_len = num_units.get(&key).unwrap_or(&Vec::<String>::new()).len();
+
+                if num_len > units_len {
+                    match num_units.entry(key.clone()) {
+                        Entry::Occupied(mut e) => {
+                            e.get_mut().resize(num_len, String::new());
+                        }
+                        Entry::Vacant(e) => {
+                            let mut v: Vec<String> = Vec::new();
+                            for _ in 0..num_len - units_len {
+                                v.push(String::new());
+                            }
+                            e.insert(v);
+                        }
+                    }
+                }
+
+                num_units
+                    .entry(key.clone())
+                    .and_modify(|e| e.push(unit.clone()))
+                    .or_insert_with(|| vec![unit]);
+            }
+
+            num_labels
+                .entry(key)
+                .and_modify(|e| e.push(label_index.num_index))
+                .or_insert_with(|| vec![label_index.num_index]);
+        }
+    }
+
+    if !labels.is_empty() {
+        s.label = labels;
+    }
+
+    if !num_labels.is_empty() {
+        s.num_label = num_labels.clone();
+
+        for (key, units) in num_units.iter_mut() {
+            if num_labels.get(key).is_some()
+                && !units.is_empty()
+                && units.len() > num_labels.get(key).unwrap().len()
+            {
+                for _ in 0..units.len() - num_labels.get(key).unwrap().len() {
+                    units.push(String::new());
+                }
+            }
+        }
+
+        s.num_unit_label = num_units;
+    }
+}
+
 impl Profile {
-    pub fn decode_profile_field(&mut self, buf: &mut Buffer, data: &mut Vec<u8>) {
+    pub fn decode_profile_field(
+        &mut self,
+        buf: &mut Buffer,
+        data: &mut Vec<u8>,
+    ) -> Result<(), RockError> {
         match buf.field {
             // repeated ValueType sample_type = 1
             1 => {
                 self.sample_type
-                    .push(value_type::ValueType::decode(buf, data));
+                    .push(value_type::ValueType::decode(buf, data)?);
             }
             // repeated Sample sample = 2
             2 => {
-                let a = sample::Sample::decode(buf, data);
+                let a = sample::Sample::decode(buf, data)?;
                 self.sample.push(a);
             }
             // repeated Mapping mapping = 3
             3 => {
-                self.mapping.push(mapping::Mapping::decode(buf, data));
+                self.mapping.push(mapping::Mapping::decode(buf, data)?);
             }
             // repeated Location location = 4
             4 => {
-                self.location.push(location::Location::decode(buf, data));
+                self.location.push(location::Location::decode(buf, data)?);
             }
             // repeated Function function = 5
             5 => {
-                self.function.push(function::Function::decode(buf, data));
+                self.function.push(function::Function::decode(buf, data)?);
             }
             // repeated string string_table = 6
             6 => {
-                self.string_table.push(decode_string(data.as_ref()));
+                self.string_table.push(decode_string(data.as_ref())?);
                 if !self.string_table[0].is_empty() {
-                    panic!("String table[0] should be empty");
+                    return Err(RockError::MalformedProfile {
+                        reason: String::from("string_table[0] must be empty"),
+                    });
                 }
             }
             // int64 drop_frames = 7
@@ -208,7 +333,9 @@ impl Profile {
             9 => {
                 //https://github.com/google/pprof/issues/273
                 if self.time_nanos != 0 {
-                    panic!("concatenated profiles detected")
+                    return Err(RockError::MalformedProfile {
+                        reason: String::from("concatenated profiles detected"),
+                    });
                 }
                 self.time_nanos = buf.u64 as i64;
             }
@@ -218,7 +345,7 @@ impl Profile {
             }
             // ValueType period_type = 11
             11 => {
-                self.period_type = Option::from(value_type::ValueType::decode(buf, data));
+                self.period_type = Option::from(value_type::ValueType::decode(buf, data)?);
             }
             // int64 period = 12
             12 => {
@@ -231,9 +358,7 @@ impl Profile {
                         let res = decode_varint(data);
                         match res {
                             Ok(varint) => self.comment_index.push(varint as i64),
-                            Err(err) => {
-                                panic!("{}", err);
-                            }
+                            Err(err) => return Err(err),
                         }
                     } else {
                         break;
@@ -247,9 +372,74 @@ impl Profile {
             }
             _ => {}
         }
+        Ok(())
     }
 
-    pub fn post_decode(&mut self) {
+    // check_references returns an error for the string table indexes out of
+    // range and the unknown function and location ids, which post_decode
+    // resolves unchecked.
+    fn check_references(&self) -> Result<(), RockError> {
+        let strings = self.string_table.len();
+        let string = |index: i64| -> Result<(), RockError> {
+            match usize::try_from(index) {
+                Ok(i) if i < strings => Ok(()),
+                _ => Err(RockError::MalformedProfile {
+                    reason: format!(
+                        "string table index {} out of range, table has {} strings",
+                        index, strings
+                    ),
+                }),
+            }
+        };
+        let unknown = |kind: &str, id: u64| RockError::MalformedProfile {
+            reason: format!("{} id {} not found", kind, id),
+        };
+
+        for m in self.mapping.iter() {
+            string(m.filename_index)?;
+            string(m.build_id_index)?;
+        }
+        for f in self.function.iter() {
+            string(f.name_index)?;
+            string(f.system_name_index)?;
+            string(f.filename_index)?;
+        }
+        let functions: HashSet<u64> = self.function.iter().map(|f| f.id).collect();
+        for l in self.location.iter() {
+            for line in l.line.iter() {
+                if line.function_index != 0 && !functions.contains(&line.function_index) {
+                    return Err(unknown("function", line.function_index));
+                }
+            }
+        }
+        let locations: HashSet<u64> = self.location.iter().map(|l| l.id).collect();
+        for s in self.sample.iter() {
+            for id in s.location_index.iter() {
+                if !locations.contains(id) {
+                    return Err(unknown("location", *id));
+                }
+            }
+            for l in s.label_index.iter() {
+                string(l.key_index)?;
+                string(l.str_index)?;
+                string(l.num_unit_index)?;
+            }
+        }
+        for vt in self.sample_type.iter().chain(self.period_type.iter()) {
+            string(vt.type_index)?;
+            string(vt.unit_index)?;
+        }
+        for index in self.comment_index.iter() {
+            string(*index)?;
+        }
+        string(self.drop_frames_index)?;
+        string(self.keep_frames_index)?;
+        string(self.default_sample_type_index)
+    }
+
+    pub fn post_decode(&mut self) -> Result<(), RockError> {
+        self.check_references()?;
+
         // MAPPING DECODE
         let mut mappings: HashMap<u64, mapping::Mapping> = HashMap::new();
         for m in self.mapping.iter_mut() {
@@ -268,8 +458,7 @@ impl Profile {
         }
 
         //LOCATION DECODE
-        let mut locations: HashMap<u64, location::Location> = HashMap::new();
-        for loc in self.location.iter_mut() {
+        let resolve_location = |loc: &mut location::Location| {
             if let Some(m) = mappings.get(loc.mapping_index.borrow()) {
                 loc.mapping = Option::from(m.clone());
             }
@@ -279,92 +468,27 @@ impl Profile {
                     line.function = functions.get(line.function_index.borrow()).unwrap().clone();
                 }
             }
+        };
+        for_each_mut(&mut self.location, resolve_location);
 
-            locations.insert(loc.id, loc.to_owned());
-        }
+        let locations: HashMap<u64, &location::Location> =
+            self.location.iter().map(|l| (l.id, l)).collect();
 
         for st in self.sample_type.iter_mut() {
             st.unit = self.string_table[st.unit_index as usize].to_string();
             st.r#type = self.string_table[st.type_index as usize].to_string();
         }
 
-        for s in self.sample.iter_mut() {
-            let mut labels: HashMap<String, Vec<String>> = HashMap::new();
-            let mut num_labels: HashMap<String, Vec<i64>> = HashMap::new();
-            let mut num_units: HashMap<String, Vec<String>> = HashMap::new();
-
-            for label_index in s.label_index.iter() {
-                // key can't be empty
-                let key = self.string_table[label_index.key_index as usize].to_string();
-
-                if label_index.str_index != 0 {
-                    let key_value = self.string_table[label_index.str_index as usize].to_string();
-                    // using or_insert_with because: The function will always be called and potentially allocate an object acting as the default.
-                    labels
-                        .entry(key)
-                        .and_modify(|e| e.push(key_value.clone()))
-                        .or_insert_with(|| vec![key_value]);
-                } else if label_index.num_index != 0 {
-                    if label_index.num_unit_index != 0 {
-                        let unit =
-                            self.string_table[label_index.num_unit_index as usize].to_string();
-
-                        let num_len = num_labels.get(&key).unwrap_or(&Vec::<i64>::new()).len();
-                        let units_len = num_units.get(&key).unwrap_or(&Vec::<String>::new()).len();
-
-                        if num_len > units_len {
-                            match num_units.entry(key.clone()) {
-                                Entry::Occupied(mut e) => {
-                                    e.get_mut().resize(num_len, String::new());
-                                }
-                                Entry::Vacant(e) => {
-                                    let mut v: Vec<String> = Vec::new();
-                                    for _ in 0..num_len - units_len {
-                                        v.push(String::new());
-                                    }
-                                    e.insert(v);
-                                }
-                            }
-                        }
-
-                        num_units
-                            .entry(key.clone())
-                            .and_modify(|e| e.push(unit.clone()))
-                            .or_insert_with(|| vec![unit]);
-                    }
-
-                    num_labels
-                        .entry(key)
-                        .and_modify(|e| e.push(label_index.num_index))
-                        .or_insert_with(|| vec![label_index.num_index]);
-                }
-            }
+        let string_table = &self.string_table;
+        let resolve_sample = |s: &mut sample::Sample| {
+            decode_labels(string_table, s);
 
-            if !labels.is_empty() {
-                s.label = labels;
+            for loc_index in s.location_index.iter() {
+                s.location
+                    .push((*locations.get(loc_index).unwrap()).clone())
             }
-
-            if !num_labels.is_empty() {
-                s.num_label = num_labels.clone();
-
-                for (key, units) in num_units.iter_mut() {
-                    if num_labels.get(key).is_some()
-                        && !units.is_empty()
-                        && units.len() > num_labels.get(key).unwrap().len()
-                    {
-                        for _ in 0..units.len() - num_labels.get(key).unwrap().len() {
-                            units.push(String::new());
-                        }
-                    }
-                }
-
-                s.num_unit_label = num_units;
-            }
-
-            for loc_index in &s.location_index.clone() {
-                s.location.push(locations.get(loc_index).unwrap().clone())
-            }
-        }
+        };
+        for_each_mut(&mut self.sample, resolve_sample);
 
         match self.period_type.as_mut() {
             None => {
@@ -386,24 +510,31 @@ impl Profile {
 
         self.default_sample_type =
             self.string_table[self.default_sample_type_index as usize].to_string();
+        Ok(())
     }
 
     #[inline]
     pub fn validate(&self) -> Result<(), RockError> {
         if self.sample_type.is_empty() && self.sample.is_empty() {
-            panic!("missing sample type information");
+            return Err(RockError::ValidationFailed {
+                reason: String::from("missing sample type information"),
+            });
         }
 
         for s in self.sample.iter() {
             if *s == sample::Sample::default() {
-                panic!("profile has default (uninitialized) sample")
+                return Err(RockError::ValidationFailed {
+                    reason: String::from("profile has default (uninitialized) sample"),
+                });
             }
             if s.value.len() != self.sample_type.len() {
-                panic!(
-                    "mismatch: sample has {} values vs. {} types",
-                    s.value.len(),
-                    self.sample_type.len()
-                );
+                return Err(RockError::ValidationFailed {
+                    reason: format!(
+                        "mismatch: sample has {} values vs. {} types",
+                        s.value.len(),
+                        self.sample_type.len()
+                    ),
+                });
             }
 
             for l in s.location.iter() {
@@ -578,14 +709,8 @@ impl Profile {
             let unit = num_label_units.get(&key);
             match unit {
                 None => {
-                    match key.as_ref() {
-                        "alignment" | "request" => {
-                            num_label_units.insert(key, String::from("bytes"));
-                        }
-                        _ => {
-                            num_label_units.insert(key.clone(), key);
-                        }
-                    };
+                    let unit = measurement::label_key_unit(&key);
+                    num_label_units.insert(key, unit);
                 }
                 Some(_) => {}
             }
@@ -608,6 +733,37 @@ impl Profile {
 
         Ok((num_label_units, units_ignored))
     }
+
+    // scale_n multiplies each sample values in a sample by a different amount
+    // and keeps only samples that have at least one non-zero value.
+    pub fn scale_n(&mut self, ratios: &[f64]) -> Result<(), RockError> {
+        if self.sample_type.len() != ratios.len() {
+            return Err(RockError::IncompatibleUnits {
+                reason: format!(
+                    "mismatched scale ratios, got {}, want {}",
+                    ratios.len(),
+                    self.sample_type.len()
+                ),
+            });
+        }
+
+        if ratios.iter().all(|r| *r == 1.0) {
+            return Ok(());
+        }
+
+        self.sample.retain_mut(|s| {
+            let mut keep_sample = false;
+            for (i, v) in s.value.iter_mut().enumerate() {
+                if ratios[i] != 1.0 {
+                    *v = (*v as f64 * ratios[i]).round() as i64;
+                    keep_sample = keep_sample || *v != 0;
+                }
+            }
+            keep_sample
+        });
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -803,4 +959,25 @@ mod tests {
     fn keys_match<T: Eq + Hash, U>(map1: &HashMap<T, U>, map2: &HashMap<T, U>) -> bool {
         map1.len() == map2.len() && map1.keys().all(|k| map2.contains_key(k))
     }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_post_decode() {
+        use crate::profile::buffer::{Buffer, Decoder};
+
+        let data = std::fs::read("tests/RR_CPU.pb.gz").unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let parallel = pool.install(|| Buffer::decode(&mut data.clone()).unwrap());
+        let serial = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| Buffer::decode(&mut data.clone()).unwrap());
+        assert_eq!(parallel, serial);
+        let golden = std::fs::read_to_string("tests/RR_CPU_golden.string").unwrap();
+        assert_eq!(parallel.to_string().trim_end(), golden);
+    }
 }
@@ -1,9 +1,15 @@
 use crate::profile::buffer::{decode_field, decode_varint, Buffer, WireTypes};
+use crate::profile::errors::RockError;
 use crate::profile::{label, location, Decoder};
 use std::collections::HashMap;
 use std::fmt::Write as _;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 // Each Sample records values encountered in some program
 // context. The program context is typically a stack trace, perhaps
 // augmented with auxiliary information like the thread-id, some
@@ -11,6 +17,7 @@ use std::fmt::Write as _;
 pub struct Sample {
     // The ids recorded here correspond to a Profile.location.id.
     // The leaf is at location_id[0].
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub location: Vec<location::Location>,
     // The type and unit of each value is defined by the corresponding
     // entry in Profile.sample_type. All samples must have the same
@@ -29,13 +36,15 @@ pub struct Sample {
     pub num_unit_label: HashMap<String, Vec<String>>, // label and unit measurement, key_index also is a key
 
     // These types are not present in the proto file
+    #[cfg_attr(feature = "serde", serde(rename = "location_id"))]
     pub location_index: Vec<u64>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub label_index: Vec<label::Label>,
 }
 
 impl Decoder<Sample> for Sample {
     #[inline]
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Sample {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<Sample, RockError> {
         let mut s = Sample::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -47,16 +56,16 @@ impl Decoder<Sample> for Sample {
                                 while !buf_data.is_empty() {
                                     match decode_varint(buf_data) {
                                         Ok(varint) => s.location_index.push(varint as u64),
-                                        Err(err) => {
-                                            panic!("{}", err);
-                                        }
+                                        Err(err) => return Err(err),
                                     }
                                 }
                             }
 
                             _ => {
                                 if buf.r#type != WireTypes::WireVarint {
-                                    panic!("value is not varint type");
+                                    return Err(RockError::DecodeFieldFailed {
+                                        reason: String::from("value is not varint type"),
+                                    });
                                 }
 
                                 s.location_index.push(buf.u64);
@@ -68,15 +77,15 @@ impl Decoder<Sample> for Sample {
                                 while !buf_data.is_empty() {
                                     match decode_varint(buf_data) {
                                         Ok(varint) => s.value.push(varint as i64),
-                                        Err(err) => {
-                                            panic!("{}", err);
-                                        }
+                                        Err(err) => return Err(err),
                                     }
                                 }
                             }
                             _ => {
                                 if buf.r#type != WireTypes::WireVarint {
-                                    panic!("value is not varint type");
+                                    return Err(RockError::DecodeFieldFailed {
+                                        reason: String::from("value is not varint type"),
+                                    });
                                 }
 
                                 s.value.push(buf.u64 as i64);
@@ -84,19 +93,19 @@ impl Decoder<Sample> for Sample {
                         },
                         //3
                         3 => {
-                            s.label_index.push(label::Label::decode(buf, buf_data));
+                            s.label_index.push(label::Label::decode(buf, buf_data)?);
                         }
                         _ => {
-                            panic!("Unknown sample type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown sample field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        s
+        Ok(s)
     }
 }
 
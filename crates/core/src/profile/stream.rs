@@ -0,0 +1,587 @@
+// Stream decodes a profile from a reader without holding it in memory: the
+// fields of the profile are read one at a time, gzip decompressed on the
+// fly, and the samples are yielded as soon as the strings, locations,
+// functions and mappings they reference have been read. The dictionaries
+// are kept until the end of the stream, the samples are not.
+//
+// The Go runtime writes the samples before the dictionaries, so they cannot
+// be resolved when they are read: they are kept until they can, in memory up
+// to max_pending bytes, then in a temporary file replayed at the end of the
+// stream. Legacy text profiles are not supported.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::read::GzDecoder;
+
+use crate::profile::buffer::{decode_fixed32, decode_fixed64, Buffer, WireTypes};
+use crate::profile::errors::RockError;
+use crate::profile::location::Location;
+use crate::profile::mapping::Mapping;
+use crate::profile::sample::Sample;
+use crate::profile::{decode_labels, Decoder, Profile};
+
+// MAX_PENDING is the default size of the samples waiting for their
+// dictionaries kept in memory.
+pub const MAX_PENDING: usize = 64 << 20;
+
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Stream<'r> {
+    reader: Box<dyn BufRead + 'r>,
+    gzip: bool,
+    eof: bool,
+    failed: bool,
+    // header and dictionaries of the profile, without samples
+    profile: Profile,
+    // positions of the ids in the tables of the profile
+    mappings: HashMap<u64, usize>,
+    functions: HashMap<u64, usize>,
+    locations: HashMap<u64, usize>,
+    resolved: HashMap<u64, Location>,
+    // samples waiting for their dictionaries, and their encoded size
+    pending: VecDeque<(Sample, usize)>,
+    pending_bytes: usize,
+    max_pending: usize,
+    spill: Option<Spill>,
+}
+
+impl<'r> Stream<'r> {
+    // new returns a stream decoding the profile read from r, gzipped or not.
+    pub fn new<R: Read + 'r>(r: R) -> Result<Self, RockError> {
+        let mut r = BufReader::new(r);
+        // https://tools.ietf.org/html/rfc1952#page-5
+        let gzip = r.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        let reader: Box<dyn BufRead + 'r> = if gzip {
+            Box::new(BufReader::new(GzDecoder::new(r)))
+        } else {
+            Box::new(r)
+        };
+        Ok(Stream {
+            reader,
+            gzip,
+            eof: false,
+            failed: false,
+            profile: Profile::default(),
+            mappings: HashMap::new(),
+            functions: HashMap::new(),
+            locations: HashMap::new(),
+            resolved: HashMap::new(),
+            pending: VecDeque::new(),
+            pending_bytes: 0,
+            max_pending: MAX_PENDING,
+            spill: None,
+        })
+    }
+
+    // max_pending sets the size of the samples kept in memory while waiting
+    // for their dictionaries, the following ones are written to a temporary
+    // file.
+    pub fn max_pending(mut self, bytes: usize) -> Self {
+        self.max_pending = bytes;
+        self
+    }
+
+    // next_sample returns the next sample of the profile, with its locations
+    // and labels resolved, or None at the end of the profile. The samples are
+    // returned in the order of the profile.
+    pub fn next_sample(&mut self) -> Result<Option<Sample>, RockError> {
+        loop {
+            if let Some(s) = self.pop_pending()? {
+                return Ok(Some(s));
+            }
+            if self.eof {
+                return self.replay();
+            }
+
+            let Some((mut buf, mut data)) = self.read_field()? else {
+                self.eof = true;
+                continue;
+            };
+            match buf.field {
+                // repeated Sample sample = 2
+                2 => {
+                    if let Some(s) = self.push_sample(&mut buf, data)? {
+                        return Ok(Some(s));
+                    }
+                }
+                field => {
+                    self.profile.decode_profile_field(&mut buf, &mut data)?;
+                    match field {
+                        3 => index(&mut self.mappings, &self.profile.mapping, |m| m.id),
+                        4 => index(&mut self.locations, &self.profile.location, |l| l.id),
+                        5 => index(&mut self.functions, &self.profile.function, |f| f.id),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // finish reads the rest of the stream, skipping the samples not returned
+    // yet, and returns the profile with its dictionaries and without samples.
+    pub fn finish(mut self) -> Result<Profile, RockError> {
+        while self.next_sample()?.is_some() {}
+        let mut p = std::mem::take(&mut self.profile);
+        p.post_decode()?;
+        Ok(p)
+    }
+
+    // push_sample returns the sample read if it can be resolved and no sample
+    // is waiting before it, and keeps it for later otherwise.
+    fn push_sample(
+        &mut self,
+        buf: &mut Buffer,
+        data: Vec<u8>,
+    ) -> Result<Option<Sample>, RockError> {
+        if let Some(spill) = self.spill.as_mut() {
+            spill.write(&data)?;
+            return Ok(None);
+        }
+        let size = data.len();
+        let mut s = Sample::decode(buf, &mut data.clone())?;
+        if self.pending.is_empty() {
+            match self.resolve(s)? {
+                Ok(s) => return Ok(Some(s)),
+                Err(unresolved) => s = unresolved,
+            }
+        }
+        if self.pending_bytes + size > self.max_pending {
+            let mut spill = Spill::create()?;
+            spill.write(&data)?;
+            self.spill = Some(spill);
+            return Ok(None);
+        }
+        self.pending_bytes += size;
+        self.pending.push_back((s, size));
+        Ok(None)
+    }
+
+    // pop_pending returns the first pending sample if it can be resolved.
+    fn pop_pending(&mut self) -> Result<Option<Sample>, RockError> {
+        let Some((s, size)) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        match self.resolve(s)? {
+            Ok(s) => {
+                self.pending_bytes -= size;
+                Ok(Some(s))
+            }
+            Err(s) => {
+                self.pending.push_front((s, size));
+                Ok(None)
+            }
+        }
+    }
+
+    // replay returns the samples written to the temporary file, once the
+    // whole profile has been read.
+    fn replay(&mut self) -> Result<Option<Sample>, RockError> {
+        let Some(spill) = self.spill.as_mut() else {
+            return Ok(None);
+        };
+        let Some(mut data) = spill.read()? else {
+            self.spill = None;
+            return Ok(None);
+        };
+        let mut buf = Buffer {
+            field: 2,
+            r#type: WireTypes::WireBytes,
+            u64: 0,
+        };
+        let s = Sample::decode(&mut buf, &mut data)?;
+        match self.resolve(s)? {
+            Ok(s) => Ok(Some(s)),
+            Err(_) => Err(unresolved()),
+        }
+    }
+
+    // resolve returns the sample with its locations and labels, or the
+    // sample unchanged if the dictionaries it references have not been read
+    // yet. At the end of the stream, missing entries are errors.
+    fn resolve(&mut self, mut s: Sample) -> Result<Result<Sample, Sample>, RockError> {
+        let strings = self.profile.string_table.len();
+        let mut labels_ready = true;
+        for l in s.label_index.iter() {
+            for index in [l.key_index, l.str_index, l.num_unit_index] {
+                match usize::try_from(index) {
+                    Ok(i) => labels_ready &= i < strings,
+                    Err(_) => {
+                        return Err(RockError::MalformedProfile {
+                            reason: format!("label string index {} out of range", index),
+                        })
+                    }
+                }
+            }
+        }
+        if !labels_ready {
+            return if self.eof {
+                Err(unresolved())
+            } else {
+                Ok(Err(s))
+            };
+        }
+
+        let mut locations = Vec::with_capacity(s.location_index.len());
+        for id in s.location_index.iter() {
+            match self.location(*id)? {
+                Some(l) => locations.push(l),
+                None if self.eof => return Err(unresolved()),
+                None => return Ok(Err(s)),
+            }
+        }
+        decode_labels(&self.profile.string_table, &mut s);
+        s.location = locations;
+        Ok(Ok(s))
+    }
+
+    // location returns the location with its mapping and functions, if they
+    // and their strings have been read.
+    fn location(&mut self, id: u64) -> Result<Option<Location>, RockError> {
+        if let Some(l) = self.resolved.get(&id) {
+            return Ok(Some(l.clone()));
+        }
+        let Some(i) = self.locations.get(&id) else {
+            return Ok(None);
+        };
+        let mut l = self.profile.location[*i].clone();
+        let strings = &self.profile.string_table;
+        let string = |i: i64| strings.get(i as usize).cloned();
+
+        if let Some(m) = self.mappings.get(&l.mapping_index) {
+            let m = &self.profile.mapping[*m];
+            let (Some(filename), Some(build_id)) =
+                (string(m.filename_index), string(m.build_id_index))
+            else {
+                return Ok(None);
+            };
+            l.mapping = Some(Mapping {
+                filename,
+                build_id,
+                ..m.clone()
+            });
+        } else if l.mapping_index != 0 && !self.eof {
+            // the mapping may follow, post_decode leaves unknown mappings unset
+            return Ok(None);
+        }
+
+        for line in l.line.iter_mut() {
+            if line.function_index == 0 {
+                continue;
+            }
+            let Some(f) = self.functions.get(&line.function_index) else {
+                return Ok(None);
+            };
+            let f = &self.profile.function[*f];
+            let (Some(name), Some(system_name), Some(filename)) = (
+                string(f.name_index),
+                string(f.system_name_index),
+                string(f.filename_index),
+            ) else {
+                return Ok(None);
+            };
+            line.function = f.clone();
+            line.function.name = name;
+            line.function.system_name = system_name;
+            line.function.filename = filename;
+        }
+
+        self.resolved.insert(id, l.clone());
+        Ok(Some(l))
+    }
+
+    // read_field returns the next field of the profile, or None at the end of
+    // the stream.
+    fn read_field(&mut self) -> Result<Option<(Buffer, Vec<u8>)>, RockError> {
+        let result = read_field(&mut self.reader);
+        if result.is_err() {
+            self.failed = true;
+        }
+        result.map_err(|err| match err {
+            RockError::IoFailed { reason } if self.gzip => {
+                RockError::ProfileUncompressFailed { reason }
+            }
+            err => err,
+        })
+    }
+}
+
+impl std::fmt::Debug for Stream<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stream")
+            .field("gzip", &self.gzip)
+            .field("eof", &self.eof)
+            .field("pending", &self.pending.len())
+            .field("spilled", &self.spill.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Iterator for Stream<'_> {
+    type Item = Result<Sample, RockError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_sample() {
+            Ok(s) => s.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// index records the position of the id of the last entry of the table.
+fn index<T>(ids: &mut HashMap<u64, usize>, table: &[T], id: impl Fn(&T) -> u64) {
+    if let Some(last) = table.last() {
+        ids.entry(id(last)).or_insert(table.len() - 1);
+    }
+}
+
+fn unresolved() -> RockError {
+    RockError::MalformedProfile {
+        reason: String::from("sample references missing locations, functions or strings"),
+    }
+}
+
+fn read_varint(r: &mut dyn BufRead) -> Result<Option<u64>, RockError> {
+    let mut u: u64 = 0;
+    let mut b = [0u8; 1];
+    for i in 0..10 {
+        if r.read(&mut b)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(RockError::DecodeFieldFailed {
+                reason: "bad varint".to_string(),
+            });
+        }
+        u |= ((b[0] & 0x7F) as u64) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            return Ok(Some(u));
+        }
+    }
+    Err(RockError::DecodeFieldFailed {
+        reason: "bad varint".to_string(),
+    })
+}
+
+// read_field reads the next field of a message, or None at the end of r.
+pub(crate) fn read_field(r: &mut dyn BufRead) -> Result<Option<(Buffer, Vec<u8>)>, RockError> {
+    let Some(key) = read_varint(r)? else {
+        return Ok(None);
+    };
+    let truncated = || RockError::DecodeFieldFailed {
+        reason: "truncated field".to_string(),
+    };
+    let mut buf = Buffer {
+        field: (key >> 3) as usize,
+        r#type: WireTypes::WireVarint,
+        u64: 0,
+    };
+    let mut data = vec![];
+    match key & 7 {
+        0 => buf.u64 = read_varint(r)?.ok_or_else(truncated)?,
+        1 => {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b).map_err(|_| truncated())?;
+            buf.r#type = WireTypes::WireFixed64;
+            buf.u64 = decode_fixed64(&b);
+        }
+        2 => {
+            let len = read_varint(r)?.ok_or_else(truncated)?;
+            buf.r#type = WireTypes::WireBytes;
+            // read through take so a corrupted length does not allocate it
+            r.take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(truncated());
+            }
+        }
+        5 => {
+            let mut b = [0u8; 4];
+            r.read_exact(&mut b).map_err(|_| truncated())?;
+            buf.r#type = WireTypes::WireFixed32;
+            buf.u64 = decode_fixed32(&b) as u64;
+        }
+        t => {
+            return Err(RockError::DecodeFieldFailed {
+                reason: format!("unknown wire type {}", t),
+            })
+        }
+    }
+    Ok(Some((buf, data)))
+}
+
+// Spill is a temporary file of length prefixed encoded samples, removed
+// when dropped.
+struct Spill {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    reader: Option<BufReader<File>>,
+}
+
+impl Spill {
+    fn create() -> Result<Self, RockError> {
+        let path = std::env::temp_dir().join(format!(
+            "rock-stream-{}-{}",
+            std::process::id(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Spill {
+            path,
+            writer: Some(BufWriter::new(file)),
+            reader: None,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), RockError> {
+        let Some(w) = self.writer.as_mut() else {
+            return Err(RockError::IoFailed {
+                reason: String::from("write to a replayed spill file"),
+            });
+        };
+        w.write_all(&(data.len() as u64).to_le_bytes())?;
+        w.write_all(data)?;
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Option<Vec<u8>>, RockError> {
+        if let Some(w) = self.writer.take() {
+            let mut file = w.into_inner().map_err(|err| err.into_error())?;
+            file.seek(SeekFrom::Start(0))?;
+            self.reader = Some(BufReader::new(file));
+        }
+        let Some(r) = self.reader.as_mut() else {
+            return Ok(None);
+        };
+        let mut len = [0u8; 8];
+        if r.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        r.read_exact(&mut len)?;
+        let mut data = vec![0u8; u64::from_le_bytes(len) as usize];
+        r.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::errors::RockError;
+    use crate::profile::sample::Sample;
+    use crate::profile::stream::{Stream, SPILLS};
+    use crate::profile::Profile;
+    use std::sync::atomic::Ordering;
+
+    fn samples(p: &Profile) -> Vec<String> {
+        p.sample.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_stream() {
+        for path in ["tests/CPU.pb.gz", "tests/HEAP.pb.gz", "tests/encoded"] {
+            let data = std::fs::read(path).unwrap();
+            let want = Buffer::decode(&mut data.clone()).unwrap();
+
+            let file = std::fs::File::open(path).unwrap();
+            let mut stream = Stream::new(file).unwrap();
+            let got: Vec<Sample> = stream.by_ref().map(|s| s.unwrap()).collect();
+            let mut p = stream.finish().unwrap();
+            assert!(p.sample.is_empty());
+            p.sample = got;
+            assert_eq!(samples(&p), samples(&want), "{}", path);
+            assert_eq!(p.to_string(), want.to_string(), "{}", path);
+
+            // the samples waiting for the dictionaries go to a temporary file
+            let spills = SPILLS.load(Ordering::Relaxed);
+            let mut stream = Stream::new(data.as_slice()).unwrap().max_pending(64);
+            let got: Vec<String> = stream.by_ref().map(|s| s.unwrap().to_string()).collect();
+            assert_eq!(got, samples(&want), "{}", path);
+            assert!(SPILLS.load(Ordering::Relaxed) > spills);
+            assert!(stream.spill.is_none());
+        }
+    }
+
+    #[test]
+    fn test_stream_dictionaries_first() {
+        // samples written after their dictionaries are returned as soon as
+        // they are read
+        let want = Buffer::decode(&mut std::fs::read("tests/CPU.pb.gz").unwrap()).unwrap();
+        let mut data = want.write_uncompressed();
+        let mut samples = vec![];
+        let mut rest = vec![];
+        let mut buf = Buffer::default();
+        while !data.is_empty() {
+            let start = data.len();
+            let mut copy = data.clone();
+            crate::profile::buffer::decode_field(&mut buf, &mut copy).unwrap();
+            let field = data[..start - copy.len()].to_vec();
+            data = copy;
+            if buf.field == 2 {
+                samples.extend(field);
+            } else {
+                rest.extend(field);
+            }
+        }
+        rest.extend(samples);
+
+        let mut stream = Stream::new(rest.as_slice()).unwrap().max_pending(0);
+        let first = stream.next_sample().unwrap().unwrap();
+        assert!(stream.pending.is_empty() && stream.spill.is_none());
+        assert_eq!(first.to_string(), want.sample[0].to_string());
+        assert_eq!(stream.count(), want.sample.len() - 1);
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let data = std::fs::read("tests/CPU.pb.gz").unwrap();
+        let mut stream = Stream::new(&data[..data.len() / 2]).unwrap();
+        assert!(stream.by_ref().any(|s| s.is_err()));
+        assert!(stream.next().is_none());
+
+        // a sample referencing a location that is not in the profile
+        let mut p = Buffer::decode(&mut data.clone()).unwrap();
+        p.location.pop();
+        let data = p.write_uncompressed();
+        assert!(Stream::new(data.as_slice()).unwrap().any(|s| s.is_err()));
+
+        let malformed: [&[u8]; 4] = [
+            // time_nanos twice
+            b"\x32\x00\x48\x01\x48\x02",
+            // string_table[0] is not empty
+            b"\x32\x01a",
+            // invalid UTF-8 string
+            b"\x32\x00\x32\x01\xff",
+            // a label with a negative key index
+            b"\x32\x00\x12\x0f\x10\x01\x1a\x0b\x08\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01",
+        ];
+        for data in malformed {
+            let mut stream = Stream::new(data).unwrap();
+            let err = stream.find_map(|s| s.err()).unwrap();
+            assert!(
+                matches!(err, RockError::MalformedProfile { .. }),
+                "{:?}: {}",
+                data,
+                err
+            );
+        }
+    }
+}
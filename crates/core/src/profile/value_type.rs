@@ -1,7 +1,13 @@
 use crate::profile::buffer::{decode_field, Buffer};
+use crate::profile::errors::RockError;
 use crate::profile::Decoder;
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 /// ValueType describes the semantics and measurement units of a value
 pub struct ValueType {
     // Type and uint do not present in proto file
@@ -12,13 +18,15 @@ pub struct ValueType {
     pub unit: String,
 
     // index in the string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub type_index: i64,
     // index in the string table
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub unit_index: i64,
 }
 
 impl Decoder<ValueType> for ValueType {
-    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> ValueType {
+    fn decode(buf: &mut Buffer, data: &mut Vec<u8>) -> Result<ValueType, RockError> {
         let mut vt = ValueType::default();
         while !data.is_empty() {
             match decode_field(buf, data) {
@@ -33,15 +41,15 @@ impl Decoder<ValueType> for ValueType {
                             vt.unit_index = buf.u64 as i64;
                         }
                         _ => {
-                            panic!("Unknown value_type type");
+                            return Err(RockError::DecodeFieldFailed {
+                                reason: format!("unknown value_type field {}", buf.field),
+                            });
                         }
                     }
                 }
-                Err(err) => {
-                    panic!("{}", err);
-                }
+                Err(err) => return Err(err),
             }
         }
-        vt
+        Ok(vt)
     }
 }
@@ -0,0 +1,213 @@
+// Encoding of a Profile into profile.proto, the inverse of Buffer::decode.
+// The string table is rebuilt from the strings of the profile, so the
+// *_index fields of the decoded messages are not used.
+
+use crate::profile::encoder::Encoder;
+use crate::profile::Profile;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io;
+
+// Strings collects the string table, "" is always at index 0.
+#[derive(Debug)]
+struct Strings {
+    table: Vec<String>,
+    index: HashMap<String, i64>,
+}
+
+impl Strings {
+    fn new() -> Self {
+        Strings {
+            table: vec![String::new()],
+            index: HashMap::from([(String::new(), 0)]),
+        }
+    }
+
+    fn get(&mut self, s: &str) -> i64 {
+        if let Some(i) = self.index.get(s) {
+            return *i;
+        }
+        self.table.push(s.to_string());
+        self.index
+            .insert(s.to_string(), self.table.len() as i64 - 1);
+        self.table.len() as i64 - 1
+    }
+}
+
+impl Profile {
+    // write writes the profile as gzip compressed profile.proto.
+    pub fn write<W: io::Write>(&self, w: W) -> io::Result<()> {
+        let mut gz = GzEncoder::new(w, Compression::default());
+        io::Write::write_all(&mut gz, &self.write_uncompressed())?;
+        gz.finish()?;
+        Ok(())
+    }
+
+    // write_uncompressed returns the profile encoded as profile.proto.
+    pub fn write_uncompressed(&self) -> Vec<u8> {
+        let mut s = Strings::new();
+        let mut e = Encoder::new();
+
+        // repeated ValueType sample_type = 1
+        for st in self.sample_type.iter() {
+            let (t, u) = (s.get(&st.r#type), s.get(&st.unit));
+            e.message(1, |e| {
+                e.int64(1, t);
+                e.int64(2, u);
+            });
+        }
+
+        // repeated Sample sample = 2
+        for sample in self.sample.iter() {
+            let mut labels = vec![];
+            let mut keys: Vec<&String> = sample.label.keys().collect();
+            keys.sort();
+            for key in keys {
+                for v in sample.label[key].iter() {
+                    labels.push((s.get(key), s.get(v), 0, 0));
+                }
+            }
+            let mut keys: Vec<&String> = sample.num_label.keys().collect();
+            keys.sort();
+            for key in keys {
+                let units = sample.num_unit_label.get(key);
+                for (i, v) in sample.num_label[key].iter().enumerate() {
+                    let unit = units.and_then(|u| u.get(i)).map_or(0, |u| s.get(u));
+                    labels.push((s.get(key), 0, *v, unit));
+                }
+            }
+
+            e.message(2, |e| {
+                e.packed_uint64(1, &sample.location_index);
+                e.packed_int64(2, &sample.value);
+                for (key, str, num, unit) in labels {
+                    e.message(3, |e| {
+                        e.int64(1, key);
+                        e.int64(2, str);
+                        e.int64(3, num);
+                        e.int64(4, unit);
+                    });
+                }
+            });
+        }
+
+        // repeated Mapping mapping = 3
+        for m in self.mapping.iter() {
+            let (filename, build_id) = (s.get(&m.filename), s.get(&m.build_id));
+            e.message(3, |e| {
+                e.uint64(1, m.id);
+                e.uint64(2, m.memory_start);
+                e.uint64(3, m.memory_limit);
+                e.uint64(4, m.memory_offset);
+                e.int64(5, filename);
+                e.int64(6, build_id);
+                e.bool(7, m.has_function);
+                e.bool(8, m.has_filenames);
+                e.bool(9, m.has_line_numbers);
+                e.bool(10, m.has_inline_frames);
+            });
+        }
+
+        // repeated Location location = 4
+        for l in self.location.iter() {
+            e.message(4, |e| {
+                e.uint64(1, l.id);
+                e.uint64(2, l.mapping_index);
+                e.uint64(3, l.address);
+                for line in l.line.iter() {
+                    e.message(4, |e| {
+                        e.uint64(1, line.function_index);
+                        e.int64(2, line.line);
+                    });
+                }
+                e.bool(5, l.is_folder);
+            });
+        }
+
+        // repeated Function function = 5
+        for f in self.function.iter() {
+            let (name, system_name, filename) =
+                (s.get(&f.name), s.get(&f.system_name), s.get(&f.filename));
+            e.message(5, |e| {
+                e.uint64(1, f.id);
+                e.int64(2, name);
+                e.int64(3, system_name);
+                e.int64(4, filename);
+                e.int64(5, f.start_line);
+            });
+        }
+
+        let drop_frames = s.get(&self.drop_frames);
+        let keep_frames = s.get(&self.keep_frames);
+        let period_type = self
+            .period_type
+            .as_ref()
+            .map(|pt| (s.get(&pt.r#type), s.get(&pt.unit)));
+        let comments: Vec<i64> = self.comments.iter().map(|c| s.get(c)).collect();
+        let default_sample_type = s.get(&self.default_sample_type);
+
+        // repeated string string_table = 6
+        e.repeated_string(6, &s.table);
+        e.int64(7, drop_frames);
+        e.int64(8, keep_frames);
+        e.int64(9, self.time_nanos);
+        e.int64(10, self.duration_nanos);
+        if let Some((t, u)) = period_type {
+            e.message(11, |e| {
+                e.int64(1, t);
+                e.int64(2, u);
+            });
+        }
+        e.int64(12, self.period);
+        e.packed_int64(13, &comments);
+        e.int64(14, default_sample_type);
+
+        e.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::buffer::{Buffer, Decoder};
+    use crate::profile::builder::ProfileBuilder;
+    use std::fs;
+
+    #[test]
+    fn test_write_round_trip() {
+        for file in ["tests/CPU.pb.gz", "tests/HEAP.pb.gz"] {
+            let mut data = fs::read(file).unwrap();
+            let p = Buffer::decode(&mut data).unwrap();
+
+            let mut out = vec![];
+            p.write(&mut out).unwrap();
+            let got = Buffer::decode(&mut out).unwrap();
+            assert_eq!(got.to_string(), p.to_string(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_write_labels() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("alloc_space", "bytes")
+            .period_type("space", "bytes", 512)
+            .add_comment("written by rock")
+            .default_sample_type("alloc_space");
+        let f = b.add_function("main", "main", "main.go", 1);
+        let l = b.add_location(0, 0x10, &[(f, 3)]);
+        let s = b.add_sample(vec![l], vec![1024]);
+        s.label
+            .insert(String::from("thread"), vec![String::from("main")]);
+        s.num_label.insert(String::from("bytes"), vec![1024]);
+        s.num_unit_label
+            .insert(String::from("bytes"), vec![String::from("bytes")]);
+        let p = b.build().unwrap();
+
+        let mut data = p.write_uncompressed();
+        let got = Buffer::decode(&mut data).unwrap();
+        assert_eq!(got.to_string(), p.to_string());
+        assert_eq!(got.sample[0].label, p.sample[0].label);
+        assert_eq!(got.sample[0].num_unit_label, p.sample[0].num_unit_label);
+        assert_eq!(got.comments, vec![String::from("written by rock")]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::measurement;
+use crate::report::Report;
+use std::io;
+
+impl<'a> Report<'a> {
+    // dot writes the call graph in the graphviz dot format, with the look of
+    // the graphs of pprof: the font size of a node grows with its flat value
+    // and its color goes from grey to red with its cum value.
+    pub fn dot<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let mut labels = vec![];
+        self.write_header(&mut labels)?;
+        let labels = String::from_utf8_lossy(&labels);
+        let title = labels.lines().next().unwrap_or_default();
+
+        writeln!(w, "digraph \"{}\" {{", escape(title))?;
+        writeln!(w, "node [style=filled fillcolor=\"#f8f8f8\"]")?;
+        writeln!(
+            w,
+            "subgraph cluster_L {{ \"{}\" [shape=box fontsize=16 label=\"{}\\l\" tooltip=\"{}\"] }}",
+            escape(title),
+            labels.lines().map(escape).collect::<Vec<_>>().join("\\l"),
+            escape(title)
+        )?;
+
+        let max_flat = self
+            .graph
+            .nodes
+            .iter()
+            .map(|n| n.flat.abs())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for (i, n) in self.graph.nodes.iter().enumerate() {
+            let name = n.info.printable_name();
+            let mut label = format!(
+                "{}\\n{} ({})",
+                escape(&name),
+                self.format_value(n.flat),
+                measurement::percentage(n.flat, self.total).trim_start()
+            );
+            if n.cum != n.flat {
+                label.push_str(&format!(
+                    "\\nof {} ({})",
+                    self.format_value(n.cum),
+                    measurement::percentage(n.cum, self.total).trim_start()
+                ));
+            }
+            let font_size = 8.0 + 42.0 * (n.flat.abs() as f64 / max_flat as f64).sqrt();
+            let score = ratio(n.cum, self.total);
+            writeln!(
+                w,
+                "N{} [label=\"{}\" id=\"node{}\" fontsize={} shape=box tooltip=\"{} ({})\" color=\"{}\" fillcolor=\"{}\"]",
+                i + 1,
+                label,
+                i + 1,
+                font_size as i64,
+                escape(&name),
+                self.format_value(n.cum),
+                color(score, false),
+                color(score, true)
+            )?;
+        }
+
+        let mut edges: Vec<_> = self.graph.edges.iter().collect();
+        edges.sort_by(|a, b| b.1.weight.abs().cmp(&a.1.weight.abs()).then(a.0.cmp(b.0)));
+        for ((caller, callee), e) in edges {
+            let score = ratio(e.weight, self.total);
+            let mut attrs = format!(
+                "label=\" {}{}\" weight={} penwidth={} color=\"{}\" tooltip=\"{} -> {} ({})\"",
+                self.format_value(e.weight),
+                if e.inline { "\\n (inline)" } else { "" },
+                1 + (score.abs() * 100.0) as i64,
+                1 + (score.abs() * 5.0) as i64,
+                color(score, false),
+                escape(&self.graph.nodes[*caller].info.printable_name()),
+                escape(&self.graph.nodes[*callee].info.printable_name()),
+                self.format_value(e.weight)
+            );
+            if e.residual {
+                attrs.push_str(" style=\"dotted\"");
+            }
+            writeln!(w, "N{} -> N{} [{}]", caller + 1, callee + 1, attrs)?;
+        }
+        writeln!(w, "}}")
+    }
+}
+
+fn ratio(v: i64, total: i64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    v as f64 / total as f64
+}
+
+// color returns the color of a node or edge for a score between -1 and 1,
+// shades of red for positive scores and green for negative ones (which
+// appear in diffs), the background colors being lighter.
+fn color(score: f64, background: bool) -> String {
+    const SHIFT: f64 = 0.7;
+    let (mut saturation, value) = if background { (0.1, 0.93) } else { (1.0, 0.7) };
+
+    let mut score = score.clamp(-1.0, 1.0);
+    if score.abs() < 0.2 {
+        saturation *= score.abs() / 0.2;
+    }
+    score = score.signum() * score.abs().powf(1.0 - SHIFT);
+
+    let (r, g) = if score < 0.0 {
+        (value * (1.0 + saturation * score), value)
+    } else {
+        (value, value * (1.0 - saturation * score))
+    };
+    let b = value * (1.0 - saturation);
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        (r * 255.0) as u8,
+        (g * 255.0) as u8,
+        (b * 255.0) as u8
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::dot::color;
+    use crate::report::tests::profile;
+    use crate::report::{Options, Report};
+
+    #[test]
+    fn test_dot() {
+        let p = profile();
+        let o = Options {
+            sample_index: 1,
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+        let mut out = vec![];
+        r.dot(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("digraph \"Type: cpu\" {\n"));
+        assert!(out.contains(
+            "N1 [label=\"sum\\n60ms (60.00%)\" id=\"node1\" fontsize=50 shape=box tooltip=\"sum (60ms)\""
+        ));
+        assert!(out.contains("N4 -> N1 [label=\" 60ms\\n (inline)\" weight=61 penwidth=4"));
+        assert!(out.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(color(0.0, false), "#b2b2b2");
+        assert_eq!(color(1.0, false), "#b20000");
+        assert_eq!(color(1.0, true), "#edd5d5");
+        assert_eq!(color(-1.0, false), "#00b200");
+    }
+}
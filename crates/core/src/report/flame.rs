@@ -0,0 +1,191 @@
+use crate::report::{frames, Report};
+use std::collections::BTreeMap;
+use std::io;
+
+// dimensions of the flame graph, in pixels
+const WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 18.0;
+// approximated width of a character of the 12px font
+const CHAR_WIDTH: f64 = 7.0;
+// frames narrower than this are not drawn
+const MIN_WIDTH: f64 = 0.5;
+
+// FlameNode is a frame of the flame graph, with the frames it calls.
+#[derive(Debug, Default)]
+struct FlameNode {
+    value: i64,
+    children: BTreeMap<String, FlameNode>,
+}
+
+impl<'a> Report<'a> {
+    // flame_svg writes the flame graph of the samples as a standalone SVG
+    // image (without scripts), the roots at the top. The title of a frame
+    // holds its name and value.
+    pub fn flame_svg<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let mut root = FlameNode::default();
+        let mut depth = 0;
+        for s in self.profile.sample.iter() {
+            let v = s.value.get(self.options.sample_index).copied().unwrap_or(0);
+            if v == 0 {
+                continue;
+            }
+            let stack = frames(&s.location, self.options.granularity);
+            depth = depth.max(stack.len());
+            root.value += v.abs();
+            let mut node = &mut root;
+            for f in stack.iter().rev() {
+                node = node.children.entry(f.info.printable_name()).or_default();
+                node.value += v.abs();
+            }
+        }
+
+        let height = (depth + 1) as f64 * FRAME_HEIGHT;
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="monospace" font-size="12">"#,
+            WIDTH, height, WIDTH, height
+        )?;
+        let total = format!("root ({})", self.format_value(root.value));
+        self.flame_frame(&mut w, "root", &total, 0.0, 0, WIDTH)?;
+        self.flame_children(&mut w, &root, 0.0, 1, WIDTH)?;
+        writeln!(w, "</svg>")
+    }
+
+    fn flame_children<W: io::Write>(
+        &self,
+        w: &mut W,
+        node: &FlameNode,
+        x: f64,
+        depth: usize,
+        width: f64,
+    ) -> io::Result<()> {
+        // the heaviest callees first
+        let mut children: Vec<(&String, &FlameNode)> = node.children.iter().collect();
+        children.sort_by(|a, b| b.1.value.cmp(&a.1.value).then(a.0.cmp(b.0)));
+
+        let mut x = x;
+        for (name, child) in children {
+            let child_width = width * child.value as f64 / node.value.max(1) as f64;
+            if child_width >= MIN_WIDTH {
+                let title = format!(
+                    "{} ({}, {:.2}%)",
+                    name,
+                    self.format_value(child.value),
+                    100.0 * child.value as f64 / self.total.max(1) as f64
+                );
+                self.flame_frame(w, name, &title, x, depth, child_width)?;
+                self.flame_children(w, child, x, depth + 1, child_width)?;
+            }
+            x += child_width;
+        }
+        Ok(())
+    }
+
+    fn flame_frame<W: io::Write>(
+        &self,
+        w: &mut W,
+        name: &str,
+        title: &str,
+        x: f64,
+        depth: usize,
+        width: f64,
+    ) -> io::Result<()> {
+        let y = depth as f64 * FRAME_HEIGHT;
+        write!(
+            w,
+            r#"<g><title>{}</title><rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" rx="2"/>"#,
+            escape(title),
+            x,
+            y,
+            width,
+            FRAME_HEIGHT - 1.0,
+            color(name)
+        )?;
+        let chars = ((width - 6.0) / CHAR_WIDTH) as usize;
+        if chars >= 3 {
+            let label: String = if name.chars().count() > chars {
+                let mut label: String = name.chars().take(chars - 2).collect();
+                label.push_str("..");
+                label
+            } else {
+                name.to_string()
+            };
+            write!(
+                w,
+                r#"<text x="{:.1}" y="{:.1}">{}</text>"#,
+                x + 3.0,
+                y + FRAME_HEIGHT - 5.0,
+                escape(&label)
+            )?;
+        }
+        writeln!(w, "</g>")
+    }
+}
+
+// color returns a warm color derived from the name, so that a function
+// keeps its color between graphs.
+fn color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(2166136261u32, |h, b| (h ^ b as u32).wrapping_mul(16777619));
+    let r = 205 + (hash % 50);
+    let g = 80 + ((hash >> 8) % 150);
+    let b = 40 + ((hash >> 16) % 50);
+    format!("rgb({},{},{})", r, g, b)
+}
+
+// escape escapes the text of a SVG or HTML document.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::flame::escape;
+    use crate::report::tests::profile;
+    use crate::report::{Options, Report};
+
+    #[test]
+    fn test_flame_svg() {
+        let p = profile();
+        let o = Options {
+            sample_index: 1,
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+        let mut out = vec![];
+        r.flame_svg(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out
+            .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1200\" height=\"72\""));
+        assert!(out.contains("<title>root (100ms)</title>"));
+        // main is the only root, work takes 90% of it
+        assert!(out.contains(
+            "<title>main (100ms, 100.00%)</title><rect x=\"0.0\" y=\"18.0\" width=\"1200.0\""
+        ));
+        assert!(out.contains(
+            "<title>work (90ms, 90.00%)</title><rect x=\"0.0\" y=\"36.0\" width=\"1080.0\""
+        ));
+        assert!(out.contains(
+            "<title>log (30ms, 30.00%)</title><rect x=\"720.0\" y=\"54.0\" width=\"360.0\""
+        ));
+        assert!(out.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b> & \"c\""), "a&lt;b&gt; &amp; &quot;c&quot;");
+    }
+}
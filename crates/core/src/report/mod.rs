@@ -0,0 +1,684 @@
+// Package report generates the reports of the pprof command line for a
+// profile, aggregated as a graph of nodes (functions, files, lines or
+// addresses depending on the granularity) and call edges:
+// text -- the top table, the call tree, the traces and the folded stacks
+// source -- the annotated source listing of the functions
+// dot -- the call graph in the graphviz format
+// flame -- the flame graph as a SVG image
+//
+// A report only reads one of the sample values, chosen with
+// Options::sample_index (see sample_index).
+
+pub mod dot;
+pub mod flame;
+pub mod source;
+pub mod text;
+
+use crate::measurement;
+use crate::profile::errors::RockError;
+use crate::profile::line::Line;
+use crate::profile::location::Location;
+use crate::profile::Profile;
+use chrono::DateTime;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+// label of the samples of the base profile added by -diff_base
+pub const BASE_LABEL: &str = "pprof::base";
+
+// Granularity is the level at which the frames of the samples are aggregated
+// into the nodes of a report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Granularity {
+    #[default]
+    Functions,
+    FileFunctions,
+    Files,
+    Lines,
+    Addresses,
+}
+
+// Options of a report, the defaults match the ones of pprof.
+#[derive(Clone, Debug)]
+pub struct Options {
+    // index of the sample value shown by the report
+    pub sample_index: usize,
+    pub granularity: Granularity,
+    // maximum number of nodes shown, all of them if None
+    pub node_count: Option<usize>,
+    // nodes with a cum value below node_fraction of the total are dropped
+    pub node_fraction: f64,
+    // edges with a weight below edge_fraction of the total are dropped
+    pub edge_fraction: f64,
+    // sort the nodes by their cum value instead of their flat value
+    pub cum: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            sample_index: 0,
+            granularity: Granularity::Functions,
+            node_count: None,
+            node_fraction: 0.005,
+            edge_fraction: 0.001,
+            cum: false,
+        }
+    }
+}
+
+// sample_index returns the index of the sample value selected by name, either
+// a sample type ("alloc_space") or an index ("1"). An empty name selects the
+// default sample type of the profile, or the last one if there is none.
+pub fn sample_index(p: &Profile, name: &str) -> Result<usize, RockError> {
+    if p.sample_type.is_empty() {
+        return Err(RockError::ValidationFailed {
+            reason: String::from("profile has no sample types"),
+        });
+    }
+
+    let name = if name.is_empty() {
+        p.default_sample_type.as_str()
+    } else {
+        name
+    };
+    if name.is_empty() {
+        return Ok(p.sample_type.len() - 1);
+    }
+    if let Some(i) = p.sample_type.iter().position(|st| st.r#type == name) {
+        return Ok(i);
+    }
+    match name.parse::<usize>() {
+        Ok(i) if i < p.sample_type.len() => Ok(i),
+        _ => Err(RockError::ValidationFailed {
+            reason: format!(
+                "sample_index {} is not a sample type or an index (sample types: {})",
+                name,
+                p.sample_type
+                    .iter()
+                    .map(|st| st.r#type.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }),
+    }
+}
+
+// NodeInfo contains the attributes identifying a node, the ones not relevant
+// for the granularity of the report are left empty.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NodeInfo {
+    pub name: String,
+    pub file: String,
+    pub line: i64,
+    pub address: u64,
+    pub objfile: String,
+}
+
+impl NodeInfo {
+    // new returns the info of a line of a location, or of the location itself
+    // if it is not symbolized.
+    fn new(l: &Location, line: Option<&Line>, g: Granularity) -> NodeInfo {
+        let objfile = l.mapping.as_ref().map_or("", |m| m.filename.as_str());
+        let line = match line {
+            Some(line) => line,
+            None => {
+                return NodeInfo {
+                    address: l.address,
+                    objfile: objfile.to_string(),
+                    ..Default::default()
+                }
+            }
+        };
+
+        let mut ni = NodeInfo {
+            name: line.function.name.clone(),
+            file: line.function.filename.clone(),
+            line: line.line,
+            address: l.address,
+            ..Default::default()
+        };
+        match g {
+            Granularity::Functions => {
+                ni.file.clear();
+                ni.line = 0;
+                ni.address = 0;
+            }
+            Granularity::FileFunctions => {
+                ni.line = 0;
+                ni.address = 0;
+            }
+            Granularity::Files => {
+                ni.name.clear();
+                ni.line = 0;
+                ni.address = 0;
+            }
+            Granularity::Lines => ni.address = 0,
+            Granularity::Addresses => {}
+        }
+        if ni.name.is_empty() && ni.file.is_empty() {
+            ni.objfile = objfile.to_string();
+        }
+        ni
+    }
+
+    // printable_name returns the name of the node shown in the reports.
+    pub fn printable_name(&self) -> String {
+        let mut name = vec![];
+        if self.address != 0 {
+            name.push(format!("{:016x}", self.address));
+        }
+        if !self.name.is_empty() {
+            name.push(self.name.clone());
+        }
+        if self.line != 0 {
+            name.push(format!("{}:{}", self.file, self.line));
+        } else if !self.file.is_empty() {
+            name.push(self.file.clone());
+        } else if self.name.is_empty() {
+            if !self.objfile.is_empty() {
+                name.push(format!("[{}]", basename(&self.objfile)));
+            } else if self.address == 0 {
+                name.push(String::from("<unknown>"));
+            }
+        }
+        name.join(" ")
+    }
+}
+
+// Frame is a node of the stack of a sample, inline is set when the frame was
+// inlined into its caller.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub info: NodeInfo,
+    pub inline: bool,
+}
+
+// frames returns the stack of a sample, the leaf first.
+pub fn frames(locations: &[Location], g: Granularity) -> Vec<Frame> {
+    let mut frames = vec![];
+    for l in locations {
+        if l.line.is_empty() {
+            frames.push(Frame {
+                info: NodeInfo::new(l, None, g),
+                inline: false,
+            });
+            continue;
+        }
+        for (i, line) in l.line.iter().enumerate() {
+            frames.push(Frame {
+                info: NodeInfo::new(l, Some(line), g),
+                inline: i != l.line.len() - 1,
+            });
+        }
+    }
+    frames
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    pub info: NodeInfo,
+    // value of the samples with this node as leaf
+    pub flat: i64,
+    // value of the samples with this node in their stack
+    pub cum: i64,
+}
+
+// Edge is a call from the caller node to the callee node.
+#[derive(Clone, Debug, Default)]
+pub struct Edge {
+    pub weight: i64,
+    // the call was inlined into the caller
+    pub inline: bool,
+    // the caller reaches the callee through nodes dropped from the report
+    pub residual: bool,
+}
+
+// Graph is the aggregation of the samples of a profile into nodes and edges.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    // (caller, callee) indexes of the nodes
+    pub edges: HashMap<(usize, usize), Edge>,
+}
+
+impl Graph {
+    // new aggregates the sample values at the given index, keeping only the
+    // nodes of kept (if any), the frames of the other nodes are skipped.
+    fn new(p: &Profile, o: &Options, kept: Option<&HashSet<NodeInfo>>) -> Graph {
+        let mut g = Graph::default();
+        let mut index: HashMap<NodeInfo, usize> = HashMap::new();
+
+        for s in p.sample.iter() {
+            let v = s.value.get(o.sample_index).copied().unwrap_or(0);
+            if v == 0 {
+                continue;
+            }
+
+            let mut seen_nodes = HashSet::new();
+            let mut seen_edges = HashSet::new();
+            // (node, inline) of the callee of the current frame
+            let mut callee: Option<(usize, bool)> = None;
+            let mut residual = false;
+            let mut leaf = true;
+            for f in frames(&s.location, o.granularity) {
+                if kept.is_some_and(|k| !k.contains(&f.info)) {
+                    residual = callee.is_some();
+                    leaf = false;
+                    continue;
+                }
+                let n = *index.entry(f.info.clone()).or_insert_with(|| {
+                    g.nodes.push(Node {
+                        info: f.info,
+                        ..Default::default()
+                    });
+                    g.nodes.len() - 1
+                });
+                if leaf {
+                    g.nodes[n].flat += v;
+                    leaf = false;
+                }
+                if seen_nodes.insert(n) {
+                    g.nodes[n].cum += v;
+                }
+                if let Some((c, inline)) = callee {
+                    if c != n && seen_edges.insert((n, c)) {
+                        let e = g.edges.entry((n, c)).or_insert(Edge {
+                            inline: true,
+                            ..Default::default()
+                        });
+                        e.weight += v;
+                        e.inline = e.inline && inline && !residual;
+                        e.residual = e.residual || residual;
+                    }
+                }
+                callee = Some((n, f.inline));
+                residual = false;
+            }
+        }
+        g
+    }
+
+    // in_edges returns the callers of the node, the heaviest first.
+    pub fn in_edges(&self, n: usize) -> Vec<(usize, &Edge)> {
+        let mut edges: Vec<(usize, &Edge)> = self
+            .edges
+            .iter()
+            .filter(|((_, callee), _)| *callee == n)
+            .map(|((caller, _), e)| (*caller, e))
+            .collect();
+        self.sort_edges(&mut edges);
+        edges
+    }
+
+    // out_edges returns the callees of the node, the heaviest first.
+    pub fn out_edges(&self, n: usize) -> Vec<(usize, &Edge)> {
+        let mut edges: Vec<(usize, &Edge)> = self
+            .edges
+            .iter()
+            .filter(|((caller, _), _)| *caller == n)
+            .map(|((_, callee), e)| (*callee, e))
+            .collect();
+        self.sort_edges(&mut edges);
+        edges
+    }
+
+    fn sort_edges(&self, edges: &mut [(usize, &Edge)]) {
+        edges.sort_by(|a, b| {
+            b.1.weight
+                .abs()
+                .cmp(&a.1.weight.abs())
+                .then_with(|| self.nodes[a.0].info.cmp(&self.nodes[b.0].info))
+        });
+    }
+}
+
+// Report is a profile aggregated for the reports.
+#[derive(Debug)]
+pub struct Report<'a> {
+    pub(crate) profile: &'a Profile,
+    pub(crate) options: Options,
+    // total of the sample values, the base samples excluded
+    pub(crate) total: i64,
+    // nodes sorted in the report order
+    pub(crate) graph: Graph,
+    // nodes dropped by node_fraction
+    pub(crate) dropped: usize,
+    // cum value under which the nodes were dropped
+    pub(crate) dropped_cum: i64,
+    // number of nodes before node_count was applied, if it dropped any
+    pub(crate) trimmed_from: Option<usize>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(p: &'a Profile, options: Options) -> Result<Report<'a>, RockError> {
+        if options.sample_index >= p.sample_type.len() {
+            return Err(RockError::ValidationFailed {
+                reason: format!(
+                    "sample_index {} out of range, the profile has {} sample types",
+                    options.sample_index,
+                    p.sample_type.len()
+                ),
+            });
+        }
+
+        let total = compute_total(p, options.sample_index);
+        let full = Graph::new(p, &options, None);
+
+        let dropped_cum = (total as f64 * options.node_fraction) as i64;
+        let mut nodes: Vec<&Node> = full
+            .nodes
+            .iter()
+            .filter(|n| n.cum.abs() > dropped_cum)
+            .collect();
+        let dropped = full.nodes.len() - nodes.len();
+        sort_nodes(&mut nodes, options.cum);
+        let mut trimmed_from = None;
+        if let Some(count) = options.node_count.filter(|c| *c < nodes.len()) {
+            trimmed_from = Some(nodes.len());
+            nodes.truncate(count);
+        }
+
+        let graph = if nodes.len() == full.nodes.len() {
+            full
+        } else {
+            let kept: HashSet<NodeInfo> = nodes.iter().map(|n| n.info.clone()).collect();
+            Graph::new(p, &options, Some(&kept))
+        };
+        let mut graph = graph;
+        graph.edges.retain(|_, e| {
+            e.weight != 0 && e.weight.abs() as f64 >= total as f64 * options.edge_fraction
+        });
+
+        // sort the nodes, keeping the edge indexes valid
+        let mut order: Vec<usize> = (0..graph.nodes.len()).collect();
+        order.sort_by(|a, b| compare_nodes(&graph.nodes[*a], &graph.nodes[*b], options.cum));
+        let mut position = vec![0; order.len()];
+        for (i, n) in order.iter().enumerate() {
+            position[*n] = i;
+        }
+        let nodes = order.iter().map(|n| graph.nodes[*n].clone()).collect();
+        let edges = graph
+            .edges
+            .into_iter()
+            .map(|((caller, callee), e)| ((position[caller], position[callee]), e))
+            .collect();
+
+        Ok(Report {
+            profile: p,
+            options,
+            total,
+            graph: Graph { nodes, edges },
+            dropped,
+            dropped_cum,
+            trimmed_from,
+        })
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn total(&self) -> i64 {
+        self.total
+    }
+
+    // unit of the reported sample value
+    pub fn unit(&self) -> &str {
+        &self.profile.sample_type[self.options.sample_index].unit
+    }
+
+    pub fn format_value(&self, v: i64) -> String {
+        measurement::label(v, self.unit())
+    }
+
+    // write_labels writes the description of the profile.
+    pub fn write_labels<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let p = self.profile;
+        if let Some(m) = p.mapping.first() {
+            if !m.filename.is_empty() {
+                writeln!(w, "File: {}", basename(&m.filename))?;
+            }
+            if !m.build_id.is_empty() {
+                writeln!(w, "Build ID: {}", m.build_id)?;
+            }
+        }
+        for c in p.comments.iter() {
+            writeln!(w, "Comment: {}", c)?;
+        }
+        writeln!(
+            w,
+            "Type: {}",
+            p.sample_type[self.options.sample_index].r#type
+        )?;
+        if p.time_nanos != 0 {
+            if let Some(t) = DateTime::from_timestamp(
+                p.time_nanos.div_euclid(1_000_000_000),
+                p.time_nanos.rem_euclid(1_000_000_000) as u32,
+            ) {
+                writeln!(w, "Time: {}", t.format("%Y-%m-%d %H:%M:%S UTC"))?;
+            }
+        }
+        if p.duration_nanos != 0 {
+            let duration = measurement::label(p.duration_nanos, "nanoseconds");
+            let (total, unit) = measurement::scale(self.total, self.unit(), "nanoseconds");
+            if unit == "ns" {
+                writeln!(
+                    w,
+                    "Duration: {}, Total samples = {} ({})",
+                    duration,
+                    self.format_value(self.total),
+                    measurement::percentage(total as i64, p.duration_nanos).trim_start()
+                )?;
+            } else {
+                writeln!(w, "Duration: {}", duration)?;
+            }
+        }
+        Ok(())
+    }
+
+    // write_header writes the description of the profile and of the nodes
+    // shown by the report.
+    pub fn write_header<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.write_labels(w)?;
+        let flat: i64 = self.graph.nodes.iter().map(|n| n.flat).sum();
+        writeln!(
+            w,
+            "Showing nodes accounting for {}, {} of {} total",
+            self.format_value(flat),
+            measurement::percentage(flat, self.total).trim_start(),
+            self.format_value(self.total)
+        )?;
+        if self.dropped > 0 {
+            writeln!(
+                w,
+                "Dropped {} nodes (cum <= {})",
+                self.dropped,
+                self.format_value(self.dropped_cum)
+            )?;
+        }
+        if let Some(from) = self.trimmed_from {
+            writeln!(
+                w,
+                "Showing top {} nodes out of {}",
+                self.graph.nodes.len(),
+                from
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// compute_total returns the total of the sample values, the samples of the
+// base profile of a diff are excluded unless they are the only ones.
+fn compute_total(p: &Profile, index: usize) -> i64 {
+    let (mut total, mut diff_total) = (0i64, 0i64);
+    for s in p.sample.iter() {
+        let v = s.value.get(index).copied().unwrap_or(0).abs();
+        if s.label.contains_key(BASE_LABEL) {
+            diff_total += v;
+        } else {
+            total += v;
+        }
+    }
+    if total == 0 {
+        return diff_total;
+    }
+    total
+}
+
+fn compare_nodes(a: &Node, b: &Node, cum: bool) -> Ordering {
+    let (flat, cum_order) = (
+        b.flat.abs().cmp(&a.flat.abs()),
+        b.cum.abs().cmp(&a.cum.abs()),
+    );
+    let order = if cum {
+        cum_order.then(flat)
+    } else {
+        flat.then(cum_order)
+    };
+    order.then_with(|| a.info.cmp(&b.info))
+}
+
+fn sort_nodes(nodes: &mut [&Node], cum: bool) {
+    nodes.sort_by(|a, b| compare_nodes(a, b, cum));
+}
+
+fn basename(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::Profile;
+    use crate::report::{sample_index, Granularity, Options, Report};
+
+    // main -> work -> {sum (inlined), log}
+    pub(crate) fn profile() -> Profile {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count")
+            .add_sample_type("cpu", "nanoseconds")
+            .period_type("cpu", "nanoseconds", 10_000_000)
+            .duration_nanos(1_000_000_000);
+        let main = b.add_function("main", "main", "main.go", 1);
+        let work = b.add_function("work", "work", "work.go", 5);
+        let sum = b.add_function("sum", "sum", "work.go", 20);
+        let log = b.add_function("log", "log", "log.go", 1);
+        let l_main = b.add_location(0, 0x1000, &[(main, 3)]);
+        let l_work = b.add_location(0, 0x2000, &[(sum, 21), (work, 7)]);
+        let l_log = b.add_location(0, 0x3000, &[(log, 2)]);
+        let l_work2 = b.add_location(0, 0x2010, &[(work, 8)]);
+        b.add_sample(vec![l_work, l_main], vec![6, 60_000_000]);
+        b.add_sample(vec![l_log, l_work2, l_main], vec![3, 30_000_000]);
+        b.add_sample(vec![l_main], vec![1, 10_000_000]);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn test_sample_index() {
+        let p = profile();
+        assert_eq!(sample_index(&p, "").unwrap(), 1);
+        assert_eq!(sample_index(&p, "samples").unwrap(), 0);
+        assert_eq!(sample_index(&p, "1").unwrap(), 1);
+        assert!(sample_index(&p, "alloc_space").is_err());
+    }
+
+    #[test]
+    fn test_report_graph() {
+        let p = profile();
+        let o = Options {
+            sample_index: 1,
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+        assert_eq!(r.total(), 100_000_000);
+
+        let g = r.graph();
+        let names: Vec<(String, i64, i64)> = g
+            .nodes
+            .iter()
+            .map(|n| (n.info.printable_name(), n.flat, n.cum))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (String::from("sum"), 60_000_000, 60_000_000),
+                (String::from("log"), 30_000_000, 30_000_000),
+                (String::from("main"), 10_000_000, 100_000_000),
+                (String::from("work"), 0, 90_000_000),
+            ]
+        );
+
+        // work calls sum inlined
+        let out = g.out_edges(3);
+        assert_eq!(out.len(), 2);
+        assert_eq!(
+            (out[0].0, out[0].1.weight, out[0].1.inline),
+            (0, 60_000_000, true)
+        );
+        assert_eq!((out[1].0, out[1].1.inline), (1, false));
+
+        // node_count keeps the heaviest nodes and links main to sum
+        // through the dropped work node
+        let o = Options {
+            sample_index: 1,
+            node_count: Some(3),
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+        assert_eq!((r.dropped, r.trimmed_from), (0, Some(4)));
+        let g = r.graph();
+        assert_eq!(g.nodes[0].info.name, "sum");
+        assert_eq!(g.nodes[2].info.name, "main");
+        assert!(g.edges[&(2, 0)].residual);
+        assert!(!g.edges[&(2, 0)].inline);
+    }
+
+    #[test]
+    fn test_granularity() {
+        let p = profile();
+        let names = |g: Granularity| -> Vec<String> {
+            let o = Options {
+                granularity: g,
+                ..Default::default()
+            };
+            let r = Report::new(&p, o).unwrap();
+            let mut names: Vec<String> = r
+                .graph()
+                .nodes
+                .iter()
+                .map(|n| n.info.printable_name())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(Granularity::Files),
+            vec!["log.go", "main.go", "work.go"]
+        );
+        assert_eq!(
+            names(Granularity::FileFunctions),
+            vec!["log log.go", "main main.go", "sum work.go", "work work.go"]
+        );
+        assert_eq!(
+            names(Granularity::Lines),
+            vec![
+                "log log.go:2",
+                "main main.go:3",
+                "sum work.go:21",
+                "work work.go:7",
+                "work work.go:8"
+            ]
+        );
+        assert_eq!(
+            names(Granularity::Addresses)[0],
+            "0000000000001000 main main.go:3"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::measurement;
+use crate::report::{frames, Granularity, Report};
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+
+// lines of source shown around the lines with samples
+const MARGIN: i64 = 5;
+
+// Listing contains the flat and cum values of a function and of its lines.
+#[derive(Debug, Default)]
+struct Listing {
+    flat: i64,
+    cum: i64,
+    // (flat, cum) by line number
+    lines: BTreeMap<i64, (i64, i64)>,
+}
+
+impl<'a> Report<'a> {
+    // list writes the source of the functions matching the regexp, annotated
+    // with the flat and cum values of their lines. The source files are read
+    // from their paths in the profile, the lines are shown without source if
+    // a file is not found.
+    pub fn list<W: io::Write>(&self, mut w: W, re: &Regex) -> io::Result<()> {
+        let mut functions: BTreeMap<(String, String), Listing> = BTreeMap::new();
+        for s in self.profile.sample.iter() {
+            let v = s.value.get(self.options.sample_index).copied().unwrap_or(0);
+            if v == 0 {
+                continue;
+            }
+            let (mut seen_functions, mut seen_lines) = (HashSet::new(), HashSet::new());
+            for (i, f) in frames(&s.location, Granularity::Lines)
+                .into_iter()
+                .enumerate()
+            {
+                if f.info.name.is_empty() || !re.is_match(&f.info.name) {
+                    continue;
+                }
+                let key = (f.info.name, f.info.file);
+                let listing = functions.entry(key.clone()).or_default();
+                let line = listing.lines.entry(f.info.line).or_default();
+                if i == 0 {
+                    line.0 += v;
+                    listing.flat += v;
+                }
+                if seen_lines.insert((key.clone(), f.info.line)) {
+                    line.1 += v;
+                }
+                if seen_functions.insert(key) {
+                    listing.cum += v;
+                }
+            }
+        }
+        if functions.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no matches found for regexp: {}", re.as_str()),
+            ));
+        }
+
+        writeln!(w, "Total: {}", self.format_value(self.total))?;
+        for ((name, file), listing) in functions.iter() {
+            self.write_function(&mut w, name, file, listing)?;
+        }
+        Ok(())
+    }
+
+    fn write_function<W: io::Write>(
+        &self,
+        w: &mut W,
+        name: &str,
+        file: &str,
+        listing: &Listing,
+    ) -> io::Result<()> {
+        let (flat, cum, lines) = (listing.flat, listing.cum, &listing.lines);
+        writeln!(w, "ROUTINE ======================== {} in {}", name, file)?;
+        writeln!(
+            w,
+            "{:>10} {:>10} (flat, cum) {} of Total",
+            self.format_value(flat),
+            self.format_value(cum),
+            measurement::percentage(cum, self.total).trim_start()
+        )?;
+
+        let source = fs::read_to_string(file).ok();
+        let source: Vec<&str> = source.as_deref().map_or(vec![], |s| s.lines().collect());
+        let numbers: Vec<i64> = if source.is_empty() {
+            lines.keys().copied().collect()
+        } else {
+            let first = lines.keys().next().copied().unwrap_or(1);
+            let last = lines.keys().last().copied().unwrap_or(1);
+            ((first - MARGIN).max(1)..=(last + MARGIN).min(source.len() as i64)).collect()
+        };
+
+        for n in numbers {
+            let text = usize::try_from(n - 1)
+                .ok()
+                .and_then(|i| source.get(i))
+                .copied()
+                .unwrap_or("");
+            let (flat, cum) = match lines.get(&n) {
+                Some((flat, cum)) => (self.list_value(*flat), self.list_value(*cum)),
+                None => (String::from("."), String::from(".")),
+            };
+            writeln!(w, "{:>10} {:>10} {:>6}:{}", flat, cum, n, text)?;
+        }
+        Ok(())
+    }
+
+    fn list_value(&self, v: i64) -> String {
+        if v == 0 {
+            return String::from(".");
+        }
+        self.format_value(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::tests::profile;
+    use crate::report::{Options, Report};
+    use regex::Regex;
+
+    #[test]
+    fn test_list() {
+        let p = profile();
+        let o = Options {
+            sample_index: 1,
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+
+        let mut out = vec![];
+        r.list(&mut out, &Regex::new("^work$").unwrap()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Total: 100ms
+ROUTINE ======================== work in work.go
+         0       90ms (flat, cum) 90.00% of Total
+         .       60ms      7:
+         .       30ms      8:
+"
+        );
+
+        let mut out = vec![];
+        assert!(r.list(&mut out, &Regex::new("missing").unwrap()).is_err());
+    }
+}
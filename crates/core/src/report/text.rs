@@ -0,0 +1,247 @@
+use crate::measurement;
+use crate::report::{frames, Granularity, Report};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::io;
+
+const TREE_SEPARATOR: &str =
+    "----------------------------------------------------------+-------------";
+const TRACES_SEPARATOR: &str =
+    "-----------+-------------------------------------------------------";
+
+impl<'a> Report<'a> {
+    // top writes the table of the nodes with their flat and cum values.
+    pub fn top<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        self.write_header(&mut w)?;
+        writeln!(w, "      flat  flat%   sum%        cum   cum%")?;
+        let mut sum = 0;
+        for n in self.graph.nodes.iter() {
+            sum += n.flat;
+            writeln!(
+                w,
+                "{:>10} {} {} {:>10} {}  {}",
+                self.format_value(n.flat),
+                measurement::percentage(n.flat, self.total),
+                measurement::percentage(sum, self.total),
+                self.format_value(n.cum),
+                measurement::percentage(n.cum, self.total),
+                n.info.printable_name()
+            )?;
+        }
+        Ok(())
+    }
+
+    // tree writes the callers and callees of every node.
+    pub fn tree<W: io::Write>(&self, w: W) -> io::Result<()> {
+        self.write_tree(w, None)
+    }
+
+    // peek writes the callers and callees of the nodes matching the regexp.
+    pub fn peek<W: io::Write>(&self, w: W, re: &Regex) -> io::Result<()> {
+        self.write_tree(w, Some(re))
+    }
+
+    fn write_tree<W: io::Write>(&self, mut w: W, re: Option<&Regex>) -> io::Result<()> {
+        self.write_header(&mut w)?;
+        writeln!(w, "{}", TREE_SEPARATOR)?;
+        writeln!(
+            w,
+            "      flat  flat%   sum%        cum   cum%   calls calls% + context"
+        )?;
+        writeln!(w, "{}", TREE_SEPARATOR)?;
+
+        let mut sum = 0;
+        for (i, n) in self.graph.nodes.iter().enumerate() {
+            sum += n.flat;
+            let name = n.info.printable_name();
+            if re.is_some_and(|re| !re.is_match(&name)) {
+                continue;
+            }
+
+            for (caller, e) in self.graph.in_edges(i) {
+                writeln!(
+                    w,
+                    "{:>50} {} |   {}",
+                    self.format_value(e.weight),
+                    measurement::percentage(e.weight, n.cum),
+                    self.graph.nodes[caller].info.printable_name()
+                )?;
+            }
+            writeln!(
+                w,
+                "{:>10} {} {} {:>10} {}                | {}",
+                self.format_value(n.flat),
+                measurement::percentage(n.flat, self.total),
+                measurement::percentage(sum, self.total),
+                self.format_value(n.cum),
+                measurement::percentage(n.cum, self.total),
+                name
+            )?;
+            for (callee, e) in self.graph.out_edges(i) {
+                let inline = if e.inline { " (inline)" } else { "" };
+                writeln!(
+                    w,
+                    "{:>50} {} |   {}{}",
+                    self.format_value(e.weight),
+                    measurement::percentage(e.weight, n.cum),
+                    self.graph.nodes[callee].info.printable_name(),
+                    inline
+                )?;
+            }
+            writeln!(w, "{}", TREE_SEPARATOR)?;
+        }
+        Ok(())
+    }
+
+    // traces writes the stack and the labels of every sample.
+    pub fn traces<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        self.write_labels(&mut w)?;
+        let (units, _) = self
+            .profile
+            .num_label_units()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        for s in self.profile.sample.iter() {
+            let stack = frames(&s.location, Granularity::Addresses);
+            if stack.is_empty() {
+                continue;
+            }
+            writeln!(w, "{}", TRACES_SEPARATOR)?;
+
+            let mut labels: Vec<String> = s
+                .label
+                .iter()
+                .map(|(k, vs)| format!("{:>10}:  {}", k, vs.join(" ")))
+                .collect();
+            labels.sort();
+            let mut num_labels: Vec<String> = s
+                .num_label
+                .iter()
+                .map(|(k, vs)| {
+                    let unit = units.get(k).map_or("", |u| u.as_str());
+                    let vs: Vec<String> = vs.iter().map(|v| measurement::label(*v, unit)).collect();
+                    format!("{:>10}:  {}", k, vs.join(" "))
+                })
+                .collect();
+            num_labels.sort();
+            for l in labels.iter().chain(num_labels.iter()) {
+                writeln!(w, "{}", l)?;
+            }
+
+            let v = s.value.get(self.options.sample_index).copied().unwrap_or(0);
+            for (i, f) in stack.iter().enumerate() {
+                let value = if i == 0 {
+                    self.format_value(v)
+                } else {
+                    String::new()
+                };
+                let inline = if f.inline { " (inline)" } else { "" };
+                writeln!(w, "{:>10}   {}{}", value, f.info.printable_name(), inline)?;
+            }
+        }
+        writeln!(w, "{}", TRACES_SEPARATOR)
+    }
+
+    // folded writes the stacks in the folded format of the flame graph tools
+    // (FlameGraph, inferno): the frames from the root separated by ';' and
+    // the value of the stack.
+    pub fn folded<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let mut stacks: BTreeMap<String, i64> = BTreeMap::new();
+        for s in self.profile.sample.iter() {
+            let v = s.value.get(self.options.sample_index).copied().unwrap_or(0);
+            if v == 0 {
+                continue;
+            }
+            let stack: Vec<String> = frames(&s.location, self.options.granularity)
+                .iter()
+                .rev()
+                .map(|f| f.info.printable_name().replace(';', ":"))
+                .collect();
+            *stacks.entry(stack.join(";")).or_default() += v;
+        }
+
+        for (stack, v) in stacks.iter() {
+            if *v != 0 {
+                writeln!(w, "{} {}", stack, v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::tests::profile;
+    use crate::report::{Options, Report};
+    use regex::Regex;
+
+    fn report(f: impl Fn(&Report, &mut Vec<u8>)) -> String {
+        let p = profile();
+        let o = Options {
+            sample_index: 1,
+            ..Default::default()
+        };
+        let r = Report::new(&p, o).unwrap();
+        let mut out = vec![];
+        f(&r, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_top() {
+        let out = report(|r, w| r.top(w).unwrap());
+        assert_eq!(
+            out,
+            "Type: cpu
+Duration: 1s, Total samples = 100ms (10.00%)
+Showing nodes accounting for 100ms, 100% of 100ms total
+      flat  flat%   sum%        cum   cum%
+      60ms 60.00% 60.00%       60ms 60.00%  sum
+      30ms 30.00% 90.00%       30ms 30.00%  log
+      10ms 10.00%   100%      100ms   100%  main
+         0     0%   100%       90ms 90.00%  work
+"
+        );
+    }
+
+    #[test]
+    fn test_peek() {
+        let out = report(|r, w| r.peek(w, &Regex::new("^work$").unwrap()).unwrap());
+        let body: Vec<&str> = out.lines().skip(3).collect();
+        assert_eq!(
+            body,
+            vec![
+                "----------------------------------------------------------+-------------",
+                "      flat  flat%   sum%        cum   cum%   calls calls% + context",
+                "----------------------------------------------------------+-------------",
+                "                                              90ms   100% |   main",
+                "         0     0%   100%       90ms 90.00%                | work",
+                "                                              60ms 66.67% |   sum (inline)",
+                "                                              30ms 33.33% |   log",
+                "----------------------------------------------------------+-------------",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_traces() {
+        let out = report(|r, w| r.traces(w).unwrap());
+        assert!(out.contains(
+            "-----------+-------------------------------------------------------
+      30ms   0000000000003000 log log.go:2
+             0000000000002010 work work.go:8
+             0000000000001000 main main.go:3
+"
+        ));
+        assert!(out.contains("      60ms   0000000000002000 sum work.go:21 (inline)\n"));
+    }
+
+    #[test]
+    fn test_folded() {
+        let out = report(|r, w| r.folded(w).unwrap());
+        assert_eq!(
+            out,
+            "main 10000000\nmain;work;log 30000000\nmain;work;sum 60000000\n"
+        );
+    }
+}
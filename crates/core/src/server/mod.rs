@@ -0,0 +1,591 @@
+// Package server implements the ingestion of profiles for continuous
+// profiling. Agents upload their profiles (pprof protobuf, gzipped or not,
+// or legacy text) with
+//
+//     POST /ingest?service=api&instance=host-1&type=cpu&region=eu
+//
+// and the decoded profiles are kept in a Store, the parameters other than
+// service, instance and type being labels of the profile. The merged
+// profiles of a period are served, gzipped, by
+//
+//     GET /query?service=api&type=cpu&from=1700000000&until=1700003600&region=eu
+//
+// with the times in seconds since the epoch, the profiles of all types,
+// instances and labels by default, and a resolution parameter (raw, minute
+// or hour) to read the rollups.
+// store -- the on-disk store of the profiles, indexed by service and time
+//
+// The profiles which cannot be decoded or are not valid are rejected with
+// 400 Bad Request and the reason in the body of the response.
+
+pub mod store;
+
+use crate::driver::webui::parse_query;
+use crate::profile::buffer::{Buffer, Decoder};
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use store::{Entry, Query, Retention, Store};
+use tiny_http::{Header, Method, Request, Server};
+
+// largest profile accepted, compressed
+pub const MAX_PROFILE_SIZE: usize = 64 << 20;
+// largest profile accepted once decompressed
+pub const MAX_UNCOMPRESSED_SIZE: usize = 512 << 20;
+// longest service, instance, profile type or label accepted
+const MAX_NAME_LEN: usize = 128;
+// time between the compactions of the store by the server
+const COMPACT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Metadata describes the origin of an uploaded profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    // name of the program profiled
+    pub service: String,
+    // instance of the service, like a host or a pod
+    pub instance: String,
+    // kind of the profile: cpu, heap, mutex...
+    pub profile_type: String,
+    // other properties of the instance: region, version...
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    // from_query reads the service, instance and type parameters of the
+    // query of an upload, the other parameters are labels.
+    pub fn from_query(query: &str) -> Result<Metadata, RockError> {
+        let mut meta = Metadata::default();
+        for (name, value) in parse_query(query) {
+            match name.as_str() {
+                "service" => meta.service = value,
+                "instance" => meta.instance = value,
+                "type" => meta.profile_type = value,
+                _ => {
+                    meta.labels.insert(name, value);
+                }
+            }
+        }
+        meta.validate()?;
+        Ok(meta)
+    }
+
+    // validate checks that the metadata names a profile of the store: the
+    // service, the type and the label names are limited to letters, digits,
+    // '-', '_' and '.', the first two being file names.
+    pub fn validate(&self) -> Result<(), RockError> {
+        let fields = [
+            ("service", &self.service),
+            ("instance", &self.instance),
+            ("type", &self.profile_type),
+        ];
+        for (name, value) in fields {
+            if value.is_empty() {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("missing {}", name),
+                });
+            }
+            if value.len() > MAX_NAME_LEN || value.chars().any(|c| c.is_control()) {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("invalid {} {:?}", name, value),
+                });
+            }
+        }
+        for (name, value) in [fields[0], fields[2]] {
+            if !is_file_name(value) {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("invalid {} {:?}", name, value),
+                });
+            }
+        }
+        for (name, value) in self.labels.iter() {
+            let valid = !name.is_empty()
+                && name.len() <= MAX_NAME_LEN
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+            if !valid || value.len() > MAX_NAME_LEN || value.chars().any(|c| c.is_control()) {
+                return Err(RockError::InvalidArgument {
+                    reason: format!("invalid label {:?}={:?}", name, value),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// decode decodes an uploaded profile, the profiles which fail to decode or
+// to validate are returned as MalformedProfile errors.
+pub fn decode(data: Vec<u8>) -> Result<Profile, RockError> {
+    decode_limited(data, MAX_UNCOMPRESSED_SIZE)
+}
+
+// decode_limited decodes a profile of at most limit bytes once decompressed,
+// the size of a gzipped upload is only known by decompressing it. A profile
+// gzipped twice is rejected, Buffer::decode would decompress it unbounded.
+fn decode_limited(mut data: Vec<u8>, limit: usize) -> Result<Profile, RockError> {
+    if data.is_empty() {
+        return Err(RockError::MalformedProfile {
+            reason: String::from("empty profile"),
+        });
+    }
+    // https://tools.ietf.org/html/rfc1952#page-5
+    let is_gzip = |data: &[u8]| data.starts_with(&[0x1f, 0x8b]);
+    if is_gzip(&data) {
+        let mut uncompressed = vec![];
+        GzDecoder::new(data.as_slice())
+            .take(limit as u64 + 1)
+            .read_to_end(&mut uncompressed)
+            .map_err(|err| RockError::ProfileUncompressFailed {
+                reason: err.to_string(),
+            })?;
+        if uncompressed.len() > limit {
+            return Err(RockError::MalformedProfile {
+                reason: format!("profile larger than {} bytes uncompressed", limit),
+            });
+        }
+        if is_gzip(&uncompressed) {
+            return Err(RockError::MalformedProfile {
+                reason: String::from("profile gzipped twice"),
+            });
+        }
+        data = uncompressed;
+    }
+    Buffer::decode(&mut data).map_err(|err| match err {
+        RockError::DecodeFieldFailed { reason } | RockError::ValidationFailed { reason } => {
+            RockError::MalformedProfile { reason }
+        }
+        err => err,
+    })
+}
+
+// Response is the answer to a request of the ingestion server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, body: String) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+}
+
+// Ingester stores the uploaded profiles.
+#[derive(Debug)]
+pub struct Ingester {
+    store: Store,
+}
+
+impl Ingester {
+    pub fn new(store: Store) -> Ingester {
+        Ingester { store }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    // handle answers a request, the body of POST requests is the profile.
+    pub fn handle(&self, method: &str, url: &str, body: Vec<u8>) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        match (path, method) {
+            ("/ingest", "POST") => self.ingest(query, body),
+            ("/ingest", _) => Response::new(405, String::from("profiles are uploaded with POST\n")),
+            ("/query", "GET") => self.query(query),
+            ("/query", _) => Response::new(405, String::from("profiles are queried with GET\n")),
+            _ => Response::new(404, String::from("not found\n")),
+        }
+    }
+
+    fn ingest(&self, query: &str, body: Vec<u8>) -> Response {
+        let meta = match Metadata::from_query(query) {
+            Ok(meta) => meta,
+            Err(e) => return Response::new(400, format!("{}\n", e)),
+        };
+        let p = match decode(body) {
+            Ok(p) => p,
+            Err(e) => return Response::new(400, format!("{}\n", e)),
+        };
+        match self.store.put(&meta, &p, now_nanos()) {
+            Ok(entry) => Response::new(200, format!("{}\n", describe(&entry))),
+            Err(e) => Response::new(500, format!("{}\n", e)),
+        }
+    }
+
+    fn query(&self, query: &str) -> Response {
+        let q = match parse_store_query(query) {
+            Ok(q) => q,
+            Err(e) => return Response::new(400, format!("{}\n", e)),
+        };
+        let p = match self.store.query(&q) {
+            Ok(Some(p)) => p,
+            Ok(None) => return Response::new(404, String::from("no profiles\n")),
+            Err(e) => return Response::new(500, format!("{}\n", e)),
+        };
+        let mut body = vec![];
+        match p.write(&mut body) {
+            Ok(()) => Response {
+                status: 200,
+                content_type: "application/octet-stream",
+                body,
+            },
+            Err(e) => Response::new(500, format!("{}\n", e)),
+        }
+    }
+}
+
+// parse_store_query reads the parameters of a query of the profiles.
+fn parse_store_query(query: &str) -> Result<Query, RockError> {
+    let mut q = Query::new("", 0, now_nanos());
+    for (name, value) in parse_query(query) {
+        let seconds = || -> Result<i64, RockError> {
+            value
+                .parse::<i64>()
+                .map(|s| s.saturating_mul(1_000_000_000))
+                .map_err(|_| RockError::InvalidArgument {
+                    reason: format!("invalid {} {:?}, want seconds since the epoch", name, value),
+                })
+        };
+        match name.as_str() {
+            "service" => q.service = value,
+            "type" => q.profile_type = Some(value),
+            "from" => q.start = seconds()?,
+            "until" => q.end = seconds()?,
+            "resolution" => q.resolution = value.parse()?,
+            _ => {
+                q.labels.insert(name, value);
+            }
+        }
+    }
+    if q.service.is_empty() {
+        return Err(RockError::InvalidArgument {
+            reason: String::from("missing service"),
+        });
+    }
+    // the service is a directory of the store
+    if q.service.len() > MAX_NAME_LEN || !is_file_name(&q.service) {
+        return Err(RockError::InvalidArgument {
+            reason: format!("invalid service {:?}", q.service),
+        });
+    }
+    Ok(q)
+}
+
+// is_file_name reports whether a service or a profile type can be used as a
+// file name of the store: letters, digits, '-', '_' and '.', not leading.
+fn is_file_name(value: &str) -> bool {
+    !value.starts_with('.')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn describe(entry: &Entry) -> String {
+    format!(
+        "stored {} profile of {}/{} taken at {} in {}",
+        entry.profile_type,
+        entry.service,
+        entry.instance,
+        entry.time_nanos,
+        entry.file.display()
+    )
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as i64)
+}
+
+// serve runs the ingestion server on the address (host:port) with a
+// thread per available CPU. The rollups of the store are written and the
+// retention applied every minute.
+pub fn serve(address: &str, store: Store, retention: Retention) -> Result<(), RockError> {
+    let server = Server::http(address).map_err(|e| RockError::IoFailed {
+        reason: format!("failed to listen on {}: {}", address, e),
+    })?;
+    println!(
+        "Ingesting profiles on http://{}/ingest into {}",
+        address,
+        store.root().display()
+    );
+    let ingester = Ingester::new(store);
+    thread::scope(|s| {
+        s.spawn(|| compact(ingester.store(), &retention));
+        run(&server, &ingester);
+    });
+    Ok(())
+}
+
+fn compact(store: &Store, retention: &Retention) {
+    loop {
+        thread::sleep(COMPACT_INTERVAL);
+        if let Err(e) = store.compact(retention, now_nanos()) {
+            eprintln!("failed to compact the store: {}", e);
+        }
+    }
+}
+
+fn run(server: &Server, ingester: &Ingester) {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                for request in server.incoming_requests() {
+                    respond(ingester, request);
+                }
+            });
+        }
+    });
+}
+
+fn respond(ingester: &Ingester, mut request: Request) {
+    let response = match read_body(&mut request) {
+        Ok(body) => ingester.handle(&request.method().to_string(), request.url(), body),
+        Err(response) => response,
+    };
+    if response.status >= 400 {
+        eprintln!(
+            "{} {}: {}",
+            request.method(),
+            request.url(),
+            String::from_utf8_lossy(&response.body).trim()
+        );
+    }
+    let header =
+        Header::from_bytes("Content-Type", response.content_type).expect("valid content type");
+    let length = response.body.len();
+    let r = tiny_http::Response::new(
+        response.status.into(),
+        vec![header],
+        Cursor::new(response.body),
+        Some(length),
+        None,
+    );
+    if let Err(e) = request.respond(r) {
+        eprintln!("failed to send the response: {}", e);
+    }
+}
+
+// read_body reads the profile of an upload, up to MAX_PROFILE_SIZE.
+fn read_body(request: &mut Request) -> Result<Vec<u8>, Response> {
+    if *request.method() != Method::Post {
+        return Ok(vec![]);
+    }
+    if request.body_length().is_some_and(|n| n > MAX_PROFILE_SIZE) {
+        return Err(Response::new(413, String::from("profile too large\n")));
+    }
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_PROFILE_SIZE as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| Response::new(400, format!("failed to read the profile: {}\n", e)))?;
+    if body.len() > MAX_PROFILE_SIZE {
+        return Err(Response::new(413, String::from("profile too large\n")));
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::errors::RockError;
+    use crate::server::store::{Resolution, Store};
+    use crate::server::{
+        decode, decode_limited, parse_store_query, run, Ingester, Metadata, Response,
+        MAX_UNCOMPRESSED_SIZE,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use tiny_http::Server;
+
+    fn ingester(name: &str) -> Ingester {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Ingester::new(Store::open(&dir).unwrap())
+    }
+
+    fn text(r: &Response) -> String {
+        String::from_utf8_lossy(&r.body).to_string()
+    }
+
+    #[test]
+    fn test_metadata() {
+        let meta = Metadata::from_query("service=api&instance=10.0.0.1%3A8080&type=cpu").unwrap();
+        assert_eq!(meta.service, "api");
+        assert_eq!(meta.instance, "10.0.0.1:8080");
+        assert_eq!(meta.profile_type, "cpu");
+        assert!(meta.labels.is_empty());
+        let meta = Metadata::from_query("service=api&instance=a&type=cpu&region=eu").unwrap();
+        assert_eq!(meta.labels["region"], "eu");
+
+        for query in [
+            "instance=a&type=cpu",
+            "service=api&type=cpu",
+            "service=api&instance=a",
+            "service=..&instance=a&type=cpu",
+            "service=a%2Fb&instance=a&type=cpu",
+            "service=api&instance=a&type=c%20pu",
+            "service=api&instance=a&type=cpu&re%20gion=eu",
+        ] {
+            assert!(Metadata::from_query(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        assert!(decode(fs::read("tests/HEAP.pb.gz").unwrap()).is_ok());
+        assert!(decode(vec![]).is_err());
+        // a truncated gzip stream
+        let mut data = fs::read("tests/CPU.pb.gz").unwrap();
+        data.truncate(100);
+        assert!(decode(data).is_err());
+        assert!(decode(b"\x0a\xff\xff\xff\x0f".to_vec()).is_err());
+
+        // the limit applies to the decompressed profile
+        let data = fs::read("tests/HEAP.pb.gz").unwrap();
+        assert!(decode_limited(data.clone(), data.len()).is_err());
+        assert!(decode_limited(data, MAX_UNCOMPRESSED_SIZE).is_ok());
+
+        // a gzip bomb nested in gzip is not decompressed a second time
+        let limit = 1 << 16;
+        let nested = gzip(&gzip(&vec![0; 4 * limit]));
+        assert!(nested.len() < limit);
+        let err = decode_limited(nested, limit).unwrap_err();
+        assert!(matches!(err, RockError::MalformedProfile { .. }), "{}", err);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(vec![], Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn test_ingest() {
+        let ingester = ingester("rock-ingest-test");
+        let data = fs::read("tests/CPU.pb.gz").unwrap();
+
+        let r = ingester.handle(
+            "POST",
+            "/ingest?service=api&instance=h1&type=cpu",
+            data.clone(),
+        );
+        assert_eq!(r.status, 200, "{}", text(&r));
+        assert!(text(&r).starts_with("stored cpu profile of api/h1 taken at 1579804633"));
+        let entries = ingester.store().entries("api", 0, i64::MAX).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let r = ingester.handle("POST", "/ingest?service=api&type=cpu", data.clone());
+        assert_eq!(
+            (r.status, text(&r).as_str()),
+            (400, "Invalid argument, reason: missing instance\n")
+        );
+        let r = ingester.handle(
+            "POST",
+            "/ingest?service=api&instance=h1&type=cpu",
+            vec![1, 2, 3],
+        );
+        assert_eq!(r.status, 400);
+        assert!(text(&r).starts_with("Malformed profile"));
+        let r = ingester.handle(
+            "POST",
+            "/ingest?service=api&instance=h1&type=cpu",
+            gzip(&data),
+        );
+        assert_eq!(r.status, 400);
+        assert_eq!(ingester.handle("GET", "/ingest", vec![]).status, 405);
+        assert_eq!(ingester.handle("POST", "/upload", data).status, 404);
+        assert_eq!(
+            ingester.store().entries("api", 0, i64::MAX).unwrap().len(),
+            1
+        );
+        let _ = fs::remove_dir_all(ingester.store().root());
+    }
+
+    #[test]
+    fn test_query() {
+        let ingester = ingester("rock-ingest-query-test");
+        let data = fs::read("tests/CPU.pb.gz").unwrap();
+        for instance in ["h1", "h2"] {
+            let url = format!("/ingest?service=api&instance={}&type=cpu", instance);
+            assert_eq!(ingester.handle("POST", &url, data.clone()).status, 200);
+        }
+
+        let r = ingester.handle("GET", "/query?service=api&type=cpu", vec![]);
+        assert_eq!(
+            (r.status, r.content_type),
+            (200, "application/octet-stream")
+        );
+        let merged = decode(r.body).unwrap();
+        let one = decode(data).unwrap();
+        let total =
+            |p: &crate::profile::Profile| -> i64 { p.sample.iter().map(|s| s.value[1]).sum() };
+        assert_eq!(total(&merged), 2 * total(&one));
+
+        let r = ingester.handle("GET", "/query?service=api&instance=h2", vec![]);
+        assert_eq!(total(&decode(r.body).unwrap()), total(&one));
+        let r = ingester.handle("GET", "/query?service=api&from=1579804634", vec![]);
+        assert_eq!((r.status, text(&r).as_str()), (404, "no profiles\n"));
+        assert_eq!(
+            ingester.handle("GET", "/query?type=cpu", vec![]).status,
+            400
+        );
+        assert_eq!(
+            ingester.handle("POST", "/query?service=api", vec![]).status,
+            405
+        );
+        let _ = fs::remove_dir_all(ingester.store().root());
+
+        let q = parse_store_query("service=api&from=1&until=2&resolution=hour&region=eu").unwrap();
+        assert_eq!((q.start, q.end), (1_000_000_000, 2_000_000_000));
+        assert_eq!(q.resolution, Resolution::Hour);
+        assert_eq!(q.labels["region"], "eu");
+        assert!(parse_store_query("service=api&from=yesterday").is_err());
+        // the service must not escape the root of the store
+        assert!(parse_store_query("service=..").is_err());
+        assert!(parse_store_query("service=..%2F..%2Fetc").is_err());
+        assert!(parse_store_query("service=%2Ftmp").is_err());
+    }
+
+    #[test]
+    fn test_server() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let ingester = ingester("rock-ingest-server-test");
+        let store = ingester.store().root().to_path_buf();
+        thread::spawn(move || run(&server, &ingester));
+
+        let data = fs::read("tests/HEAP.pb.gz").unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /ingest?service=web&instance=h1&type=heap HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            data.len()
+        )
+        .unwrap();
+        stream.write_all(&data).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("stored heap profile of web/h1"));
+
+        let store = Store::open(store).unwrap();
+        assert_eq!(store.entries("web", 0, i64::MAX).unwrap().len(), 1);
+        let _ = fs::remove_dir_all(store.root());
+    }
+}
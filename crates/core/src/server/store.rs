@@ -0,0 +1,728 @@
+use crate::driver::read_profile;
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use crate::server::Metadata;
+use chrono::DateTime;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MINUTE: i64 = 60_000_000_000;
+const HOUR: i64 = 60 * MINUTE;
+
+// Resolution of the profiles of the store: the profiles as uploaded, or
+// their rollups merged per minute and per hour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    #[default]
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub const ALL: [Resolution; 3] = [Resolution::Raw, Resolution::Minute, Resolution::Hour];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+
+    // period returns the time merged by the rollups, in nanoseconds.
+    pub fn period(&self) -> i64 {
+        match self {
+            Resolution::Raw => 1,
+            Resolution::Minute => MINUTE,
+            Resolution::Hour => HOUR,
+        }
+    }
+
+    fn index(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "index.jsonl",
+            Resolution::Minute => "index.minute.jsonl",
+            Resolution::Hour => "index.hour.jsonl",
+        }
+    }
+
+    // dir returns the directory of the profiles, relative to the directory
+    // of the service.
+    fn dir(&self) -> &'static str {
+        match self {
+            Resolution::Raw => "",
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = RockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Resolution::ALL
+            .iter()
+            .find(|r| r.name() == s)
+            .copied()
+            .ok_or_else(|| RockError::InvalidArgument {
+                reason: format!("unknown resolution {}, want raw, minute or hour", s),
+            })
+    }
+}
+
+// Entry is a profile of the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub service: String,
+    pub instance: String,
+    pub profile_type: String,
+    pub labels: BTreeMap<String, String>,
+    // time of the profile, the start of the period of the rollups
+    pub time_nanos: i64,
+    pub duration_nanos: i64,
+    pub resolution: Resolution,
+    // path of the profile, relative to the directory of the service
+    pub file: PathBuf,
+}
+
+impl Entry {
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            service: self.service.clone(),
+            instance: self.instance.clone(),
+            profile_type: self.profile_type.clone(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    // key identifies the series of the entry, merged by the rollups.
+    fn key(&self) -> String {
+        format!(
+            "{}\x00{}\x00{:?}",
+            self.instance, self.profile_type, self.labels
+        )
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "instance": self.instance,
+            "type": self.profile_type,
+            "labels": self.labels,
+            "time_nanos": self.time_nanos,
+            "duration_nanos": self.duration_nanos,
+            "file": self.file.to_string_lossy(),
+        })
+    }
+
+    fn from_json(service: &str, resolution: Resolution, v: &Value) -> Entry {
+        let labels = v["labels"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+            .collect();
+        Entry {
+            service: service.to_string(),
+            instance: v["instance"].as_str().unwrap_or_default().to_string(),
+            profile_type: v["type"].as_str().unwrap_or_default().to_string(),
+            labels,
+            time_nanos: v["time_nanos"].as_i64().unwrap_or_default(),
+            duration_nanos: v["duration_nanos"].as_i64().unwrap_or_default(),
+            resolution,
+            file: PathBuf::from(v["file"].as_str().unwrap_or_default()),
+        }
+    }
+}
+
+// Query selects the profiles of a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub service: String,
+    // type of the profiles, all of them if None
+    pub profile_type: Option<String>,
+    // the profiles taken in [start, end), in nanoseconds since the epoch;
+    // the rollups are selected by the start of their period
+    pub start: i64,
+    pub end: i64,
+    // labels the profiles must have, "instance" matches the instance
+    pub labels: BTreeMap<String, String>,
+    pub resolution: Resolution,
+}
+
+impl Query {
+    pub fn new(service: &str, start: i64, end: i64) -> Query {
+        Query {
+            service: service.to_string(),
+            profile_type: None,
+            start,
+            end,
+            labels: BTreeMap::new(),
+            resolution: Resolution::Raw,
+        }
+    }
+
+    pub fn matches(&self, e: &Entry) -> bool {
+        e.time_nanos >= self.start
+            && e.time_nanos < self.end
+            && self
+                .profile_type
+                .as_ref()
+                .is_none_or(|t| *t == e.profile_type)
+            && self.labels.iter().all(|(name, value)| {
+                if name == "instance" {
+                    e.instance == *value
+                } else {
+                    e.labels.get(name) == Some(value)
+                }
+            })
+    }
+}
+
+// Retention is how long the profiles of each resolution are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub raw: Duration,
+    pub minute: Duration,
+    pub hour: Duration,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            raw: Duration::from_secs(24 * 3600),
+            minute: Duration::from_secs(7 * 24 * 3600),
+            hour: Duration::from_secs(90 * 24 * 3600),
+        }
+    }
+}
+
+impl Retention {
+    fn of(&self, resolution: Resolution) -> Duration {
+        match resolution {
+            Resolution::Raw => self.raw,
+            Resolution::Minute => self.minute,
+            Resolution::Hour => self.hour,
+        }
+    }
+}
+
+// Store keeps the profiles in a directory, one directory per service:
+//
+//     <root>/<service>/index.jsonl
+//     <root>/<service>/<yyyy-mm-dd>/<time_nanos>.<type>.<n>.pb.gz
+//     <root>/<service>/index.minute.jsonl
+//     <root>/<service>/minute/<yyyy-mm-dd>/<time_nanos>.<type>.<n>.pb.gz
+//     <root>/<service>/index.hour.jsonl
+//     <root>/<service>/hour/<yyyy-mm-dd>/<time_nanos>.<type>.<n>.pb.gz
+//
+// The indexes have a JSON line per profile, appended when it is stored.
+// The rollups merge the profiles of a series (instance, type and labels)
+// of a minute, and the minutes of an hour.
+#[derive(Debug)]
+pub struct Store {
+    root: PathBuf,
+    // serializes the writes of the indexes
+    lock: Mutex<()>,
+}
+
+impl Store {
+    // open opens the store of the directory, creating it if needed.
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Store, RockError> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root).map_err(|e| RockError::IoFailed {
+            reason: format!("{}: {}", root.display(), e),
+        })?;
+        Ok(Store {
+            root,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // put stores the profile of the service. The profiles without time are
+    // stored at the time given, when they were received.
+    pub fn put(&self, meta: &Metadata, p: &Profile, now_nanos: i64) -> Result<Entry, RockError> {
+        meta.validate()?;
+        let time_nanos = if p.time_nanos != 0 {
+            p.time_nanos
+        } else {
+            now_nanos
+        };
+        self.write(Resolution::Raw, meta, p, time_nanos)
+    }
+
+    fn write(
+        &self,
+        resolution: Resolution,
+        meta: &Metadata,
+        p: &Profile,
+        time_nanos: i64,
+    ) -> Result<Entry, RockError> {
+        let day = DateTime::from_timestamp_nanos(time_nanos)
+            .format("%Y-%m-%d")
+            .to_string();
+        let service_dir = self.root.join(&meta.service);
+        let dir = Path::new(resolution.dir()).join(day);
+        fs::create_dir_all(service_dir.join(&dir))?;
+        let mut data = vec![];
+        p.write(&mut data)?;
+        let file = create_new(&service_dir, &dir, time_nanos, &meta.profile_type, &data)?;
+
+        let entry = Entry {
+            service: meta.service.clone(),
+            instance: meta.instance.clone(),
+            profile_type: meta.profile_type.clone(),
+            labels: meta.labels.clone(),
+            time_nanos,
+            duration_nanos: p.duration_nanos,
+            resolution,
+            file,
+        };
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(service_dir.join(resolution.index()))?;
+        writeln!(index, "{}", entry.to_json())?;
+        Ok(entry)
+    }
+
+    // services returns the names of the services of the store, sorted.
+    pub fn services(&self) -> Result<Vec<String>, RockError> {
+        let mut services = vec![];
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?;
+            if dir.path().join(Resolution::Raw.index()).is_file() {
+                services.push(dir.file_name().to_string_lossy().to_string());
+            }
+        }
+        services.sort();
+        Ok(services)
+    }
+
+    fn read_index(&self, service: &str, resolution: Resolution) -> Result<Vec<Entry>, RockError> {
+        let index = match fs::File::open(self.root.join(service).join(resolution.index())) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = vec![];
+        for line in BufReader::new(index).lines() {
+            let line = line?;
+            // a line cut by a crash of the server is skipped
+            if let Ok(v) = serde_json::from_str::<Value>(&line) {
+                entries.push(Entry::from_json(service, resolution, &v));
+            }
+        }
+        Ok(entries)
+    }
+
+    // entries returns the raw profiles of the service taken in [start, end),
+    // sorted by time.
+    pub fn entries(&self, service: &str, start: i64, end: i64) -> Result<Vec<Entry>, RockError> {
+        self.select(&Query::new(service, start, end))
+    }
+
+    // select returns the profiles matching the query, sorted by time.
+    pub fn select(&self, q: &Query) -> Result<Vec<Entry>, RockError> {
+        let mut entries: Vec<Entry> = self
+            .read_index(&q.service, q.resolution)?
+            .into_iter()
+            .filter(|e| q.matches(e))
+            .collect();
+        entries.sort_by_key(|e| e.time_nanos);
+        Ok(entries)
+    }
+
+    // query merges the profiles matching the query, None if there are none.
+    // The profiles of different types cannot be merged.
+    pub fn query(&self, q: &Query) -> Result<Option<Profile>, RockError> {
+        let entries = self.select(q)?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let profiles = entries
+            .iter()
+            .map(|e| self.get(e))
+            .collect::<Result<Vec<_>, _>>()?;
+        Profile::merge(&profiles).map(Some)
+    }
+
+    // get reads the profile of the entry.
+    pub fn get(&self, entry: &Entry) -> Result<Profile, RockError> {
+        let path = self.root.join(&entry.service).join(&entry.file);
+        read_profile(&path.to_string_lossy())
+    }
+
+    // rollup merges the raw profiles of the minutes, and the minute rollups
+    // of the hours, ended before now. The periods are merged once: the
+    // profiles stored after the rollup of their period are only kept raw.
+    pub fn rollup(&self, now_nanos: i64) -> Result<Vec<Entry>, RockError> {
+        let mut written = vec![];
+        for service in self.services()? {
+            for (source, target) in [
+                (Resolution::Raw, Resolution::Minute),
+                (Resolution::Minute, Resolution::Hour),
+            ] {
+                written.extend(self.rollup_service(&service, source, target, now_nanos)?);
+            }
+        }
+        Ok(written)
+    }
+
+    fn rollup_service(
+        &self,
+        service: &str,
+        source: Resolution,
+        target: Resolution,
+        now_nanos: i64,
+    ) -> Result<Vec<Entry>, RockError> {
+        let period = target.period();
+        let done: HashSet<(i64, String)> = self
+            .read_index(service, target)?
+            .iter()
+            .map(|e| (e.time_nanos, e.key()))
+            .collect();
+
+        let mut groups: BTreeMap<(i64, String), Vec<Entry>> = BTreeMap::new();
+        for e in self.read_index(service, source)? {
+            let start = e.time_nanos.div_euclid(period) * period;
+            // the periods still receiving profiles are left for later
+            if start + period > now_nanos {
+                continue;
+            }
+            let key = (start, e.key());
+            if !done.contains(&key) {
+                groups.entry(key).or_default().push(e);
+            }
+        }
+
+        let mut written = vec![];
+        for ((start, _), entries) in groups {
+            // a group which cannot be merged (profiles of different sample
+            // types in a series, a missing file) must not stop the others
+            let merged = entries
+                .iter()
+                .map(|e| self.get(e))
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|profiles| Profile::merge(&profiles));
+            let mut p = match merged {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!(
+                        "skipping the {} rollup of {}/{} {} at {}: {}",
+                        target.name(),
+                        service,
+                        entries[0].instance,
+                        entries[0].profile_type,
+                        start,
+                        e
+                    );
+                    continue;
+                }
+            };
+            p.time_nanos = start;
+            written.push(self.write(target, &entries[0].metadata(), &p, start)?);
+        }
+        Ok(written)
+    }
+
+    // retain removes the profiles older than the retention of their
+    // resolution and returns how many were removed.
+    pub fn retain(&self, retention: &Retention, now_nanos: i64) -> Result<usize, RockError> {
+        let mut removed = 0;
+        for service in self.services()? {
+            for resolution in Resolution::ALL {
+                let keep = retention.of(resolution).as_nanos().min(i64::MAX as u128) as i64;
+                let cutoff = now_nanos.saturating_sub(keep);
+                removed += self.remove_before(&service, resolution, cutoff)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn remove_before(
+        &self,
+        service: &str,
+        resolution: Resolution,
+        cutoff: i64,
+    ) -> Result<usize, RockError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (old, kept): (Vec<Entry>, Vec<Entry>) = self
+            .read_index(service, resolution)?
+            .into_iter()
+            .partition(|e| e.time_nanos < cutoff);
+        if old.is_empty() {
+            return Ok(0);
+        }
+
+        // the index is replaced first: a crash leaves files without entries
+        // rather than entries without files
+        let service_dir = self.root.join(service);
+        let index = service_dir.join(resolution.index());
+        let tmp = index.with_extension("jsonl.tmp");
+        let mut w = io::BufWriter::new(fs::File::create(&tmp)?);
+        for e in kept.iter() {
+            writeln!(w, "{}", e.to_json())?;
+        }
+        w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, &index)?;
+
+        for e in old.iter() {
+            let file = service_dir.join(&e.file);
+            match fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+            // the directory of the day goes with its last profile
+            if let Some(dir) = file.parent() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+        Ok(old.len())
+    }
+
+    // compact writes the rollups and applies the retention, as done
+    // periodically by the ingestion server.
+    pub fn compact(&self, retention: &Retention, now_nanos: i64) -> Result<(), RockError> {
+        // the retention applies even if the rollup fails, or the store
+        // would grow without bound
+        let rollup = self.rollup(now_nanos);
+        self.retain(retention, now_nanos)?;
+        rollup.map(|_| ())
+    }
+}
+
+// create_new writes the data to a new file of the directory (relative to
+// the service directory) and returns its path relative to the latter.
+fn create_new(
+    service_dir: &Path,
+    dir: &Path,
+    time_nanos: i64,
+    profile_type: &str,
+    data: &[u8],
+) -> Result<PathBuf, RockError> {
+    for n in 0.. {
+        let file = dir.join(format!("{}.{}.{}.pb.gz", time_nanos, profile_type, n));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(service_dir.join(&file))
+        {
+            Ok(mut f) => {
+                f.write_all(data)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::driver::read_profile;
+    use crate::profile::Profile;
+    use crate::server::store::{Query, Resolution, Retention, Store, HOUR, MINUTE};
+    use crate::server::Metadata;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn meta(service: &str, instance: &str) -> Metadata {
+        Metadata {
+            service: service.to_string(),
+            instance: instance.to_string(),
+            profile_type: String::from("cpu"),
+            ..Default::default()
+        }
+    }
+
+    fn store(name: &str) -> Store {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Store::open(&dir).unwrap()
+    }
+
+    fn total(p: &Profile) -> i64 {
+        p.sample.iter().map(|s| s.value[0]).sum()
+    }
+
+    #[test]
+    fn test_store() {
+        let store = store("rock-store-test");
+
+        let mut p = read_profile("tests/CPU.pb.gz").unwrap();
+        let t = p.time_nanos;
+        let first = store.put(&meta("api", "host-1"), &p, 0).unwrap();
+        assert_eq!(first.time_nanos, t);
+        assert_eq!(
+            first.file,
+            PathBuf::from(format!("2020-01-23/{}.cpu.0.pb.gz", t))
+        );
+        // the profiles received at the same time are kept
+        let second = store.put(&meta("api", "host-2"), &p, 0).unwrap();
+        assert_eq!(
+            second.file,
+            PathBuf::from(format!("2020-01-23/{}.cpu.1.pb.gz", t))
+        );
+
+        // without time, the time of reception
+        p.time_nanos = 0;
+        let third = store.put(&meta("api", "host-1"), &p, t - 1000).unwrap();
+        store.put(&meta("web", "host-1"), &p, t).unwrap();
+
+        assert_eq!(store.services().unwrap(), vec!["api", "web"]);
+        let entries = store.entries("api", 0, i64::MAX).unwrap();
+        assert_eq!(entries, vec![third.clone(), first.clone(), second]);
+        assert_eq!(store.entries("api", t - 1000, t).unwrap(), vec![third]);
+        assert!(store.entries("db", 0, i64::MAX).unwrap().is_empty());
+
+        let got = store.get(&first).unwrap();
+        assert_eq!(got.sample.len(), p.sample.len());
+        assert!(store.put(&meta("../api", "host-1"), &p, t).is_err());
+        let _ = fs::remove_dir_all(store.root());
+    }
+
+    #[test]
+    fn test_query() {
+        let store = store("rock-store-query-test");
+        let mut p = read_profile("tests/CPU.pb.gz").unwrap();
+        let t = p.time_nanos;
+        let one = total(&p);
+
+        let mut eu = meta("api", "host-1");
+        eu.labels.insert(String::from("region"), String::from("eu"));
+        let mut us = meta("api", "host-2");
+        us.labels.insert(String::from("region"), String::from("us"));
+        for i in 0..3 {
+            p.time_nanos = t + i * 10_000_000_000;
+            store.put(&eu, &p, 0).unwrap();
+            store.put(&us, &p, 0).unwrap();
+        }
+        let mut heap = read_profile("tests/HEAP.pb.gz").unwrap();
+        heap.time_nanos = t;
+        let mut m = meta("api", "host-1");
+        m.profile_type = String::from("heap");
+        store.put(&m, &heap, t).unwrap();
+
+        let mut q = Query::new("api", t, t + 30_000_000_000);
+        // the CPU and heap profiles cannot be merged
+        assert!(store.query(&q).is_err());
+        q.profile_type = Some(String::from("cpu"));
+        assert_eq!(total(&store.query(&q).unwrap().unwrap()), 6 * one);
+
+        q.labels = BTreeMap::from([(String::from("region"), String::from("eu"))]);
+        let eu_profile = store.query(&q).unwrap().unwrap();
+        assert_eq!(total(&eu_profile), 3 * one);
+        assert_eq!(eu_profile.time_nanos, t);
+        q.labels = BTreeMap::from([(String::from("instance"), String::from("host-2"))]);
+        assert_eq!(store.select(&q).unwrap().len(), 3);
+
+        q.end = t + 10_000_000_000;
+        assert_eq!(total(&store.query(&q).unwrap().unwrap()), one);
+        q.start = q.end + 1;
+        q.end = q.start + 1;
+        assert!(store.query(&q).unwrap().is_none());
+        let _ = fs::remove_dir_all(store.root());
+    }
+
+    #[test]
+    fn test_rollup_retention() {
+        let store = store("rock-store-rollup-test");
+        let mut p = read_profile("tests/CPU.pb.gz").unwrap();
+        // the start of an hour
+        let t = p.time_nanos.div_euclid(HOUR) * HOUR;
+        let one = total(&p);
+        // two profiles per minute for 3 minutes, then one in the next hour
+        for i in 0..6 {
+            p.time_nanos = t + i * 30_000_000_000;
+            store.put(&meta("api", "host-1"), &p, 0).unwrap();
+        }
+        p.time_nanos = t + HOUR;
+        store.put(&meta("api", "host-1"), &p, 0).unwrap();
+
+        // the minute in progress is not rolled up
+        let written = store.rollup(t + 2 * MINUTE + 1).unwrap();
+        assert_eq!(written.len(), 2);
+        assert!(written.iter().all(|e| e.resolution == Resolution::Minute));
+        assert!(written[1].file.starts_with("minute"));
+        assert!(store.rollup(t + 2 * MINUTE + 1).unwrap().is_empty());
+
+        let written = store.rollup(t + HOUR + MINUTE).unwrap();
+        let minutes: Vec<i64> = written.iter().map(|e| e.time_nanos - t).collect();
+        assert_eq!(minutes, vec![2 * MINUTE, HOUR, 0]);
+        assert_eq!(written[2].resolution, Resolution::Hour);
+
+        let mut q = Query::new("api", t, t + HOUR);
+        q.resolution = Resolution::Minute;
+        let entries = store.select(&q).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(total(&store.get(&entries[0]).unwrap()), 2 * one);
+        q.resolution = Resolution::Hour;
+        let hour = store.query(&q).unwrap().unwrap();
+        assert_eq!(total(&hour), 6 * one);
+        assert_eq!(hour.time_nanos, t);
+
+        // the raw profiles are kept an hour, the rollups longer
+        let retention = Retention {
+            raw: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        assert_eq!(store.retain(&retention, t + HOUR + 3 * MINUTE).unwrap(), 6);
+        assert_eq!(store.entries("api", 0, i64::MAX).unwrap().len(), 1);
+        assert_eq!(store.select(&q).unwrap().len(), 1);
+        let files = fs::read_dir(store.root().join("api").join(format!(
+            "{}",
+            chrono::DateTime::from_timestamp_nanos(t).format("%Y-%m-%d")
+        )))
+        .unwrap()
+        .count();
+        assert_eq!(files, 1);
+        let _ = fs::remove_dir_all(store.root());
+    }
+
+    #[test]
+    fn test_compact_incompatible() {
+        let store = store("rock-store-compact-test");
+        let mut cpu = read_profile("tests/CPU.pb.gz").unwrap();
+        let mut heap = read_profile("tests/HEAP.pb.gz").unwrap();
+        let t = cpu.time_nanos.div_euclid(HOUR) * HOUR;
+        // a series with profiles of different sample types cannot be merged
+        cpu.time_nanos = t;
+        heap.time_nanos = t + 1;
+        store.put(&meta("api", "host-1"), &cpu, 0).unwrap();
+        store.put(&meta("api", "host-1"), &heap, 0).unwrap();
+        store.put(&meta("web", "host-1"), &cpu, 0).unwrap();
+
+        let retention = Retention {
+            raw: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        store.compact(&retention, t + MINUTE).unwrap();
+        let mut q = Query::new("web", t, t + HOUR);
+        q.resolution = Resolution::Minute;
+        assert_eq!(store.select(&q).unwrap().len(), 1);
+        q.service = String::from("api");
+        assert!(store.select(&q).unwrap().is_empty());
+
+        // the retention still removes the profiles of the series
+        store.compact(&retention, t + 2 * HOUR).unwrap();
+        assert!(store.entries("api", 0, i64::MAX).unwrap().is_empty());
+        let _ = fs::remove_dir_all(store.root());
+    }
+}
@@ -0,0 +1,206 @@
+// Package speedscope exports profiles in the file format of the speedscope
+// viewer (https://www.speedscope.app/file-format-schema.json).
+// All profiles of the file share one table of frames, built from the lines
+// of the locations, and every sample type becomes a "sampled" profile.
+
+use crate::profile::location::Location;
+use crate::profile::Profile;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io;
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+const EXPORTER: &str = "rock";
+
+// units known to speedscope, the values of the other units are shown as numbers
+const UNITS: [&str; 6] = [
+    "nanoseconds",
+    "microseconds",
+    "milliseconds",
+    "seconds",
+    "bytes",
+    "none",
+];
+
+// frame key: function id, line number, and the address for unsymbolized locations
+type FrameKey = (u64, i64, u64);
+
+#[derive(Debug, Default)]
+struct Frames {
+    frames: Vec<Value>,
+    index: HashMap<FrameKey, usize>,
+}
+
+impl Frames {
+    // location_frames appends the frames of the location to the stack,
+    // the caller first, as speedscope expects stacks ordered from the root.
+    fn location_frames(&mut self, loc: &Location, stack: &mut Vec<usize>) {
+        if loc.line.is_empty() {
+            let key = (0, 0, loc.address);
+            let frame = self.index.get(&key).copied().unwrap_or_else(|| {
+                let mut f = Map::new();
+                f.insert(String::from("name"), json!(format!("{:#x}", loc.address)));
+                if let Some(m) = loc.mapping.as_ref().filter(|m| !m.filename.is_empty()) {
+                    f.insert(String::from("file"), json!(m.filename));
+                }
+                self.add(key, f)
+            });
+            stack.push(frame);
+            return;
+        }
+
+        // lines are ordered from the innermost inlined function
+        for line in loc.line.iter().rev() {
+            let key = (line.function.id, line.line, 0);
+            let frame = self.index.get(&key).copied().unwrap_or_else(|| {
+                let mut f = Map::new();
+                f.insert(String::from("name"), json!(line.function.name));
+                if !line.function.filename.is_empty() {
+                    f.insert(String::from("file"), json!(line.function.filename));
+                }
+                if line.line > 0 {
+                    f.insert(String::from("line"), json!(line.line));
+                }
+                self.add(key, f)
+            });
+            stack.push(frame);
+        }
+    }
+
+    fn add(&mut self, key: FrameKey, frame: Map<String, Value>) -> usize {
+        self.frames.push(Value::Object(frame));
+        self.index.insert(key, self.frames.len() - 1);
+        self.frames.len() - 1
+    }
+}
+
+// to_speedscope converts the profile into a speedscope file. Samples with
+// zero value for a sample type are left out of the profile of that type.
+pub fn to_speedscope(p: &Profile, name: &str) -> Value {
+    let mut frames = Frames::default();
+    let stacks: Vec<Vec<usize>> = p
+        .sample
+        .iter()
+        .map(|s| {
+            let mut stack = vec![];
+            for loc in s.location.iter().rev() {
+                frames.location_frames(loc, &mut stack);
+            }
+            stack
+        })
+        .collect();
+
+    let mut profiles = vec![];
+    for (i, st) in p.sample_type.iter().enumerate() {
+        let mut samples = vec![];
+        let mut weights = vec![];
+        let mut total: i64 = 0;
+        for (s, stack) in p.sample.iter().zip(stacks.iter()) {
+            let v = s.value.get(i).copied().unwrap_or(0);
+            if v == 0 {
+                continue;
+            }
+            samples.push(stack);
+            weights.push(v);
+            total += v;
+        }
+
+        let unit = if UNITS.contains(&st.unit.as_str()) {
+            st.unit.as_str()
+        } else {
+            "none"
+        };
+        profiles.push(json!({
+            "type": "sampled",
+            "name": format!("{} {}", name, st.r#type).trim(),
+            "unit": unit,
+            "startValue": 0,
+            "endValue": total,
+            "samples": samples,
+            "weights": weights,
+        }));
+    }
+
+    // the default sample type is shown first, pprof uses the last type by default
+    let active = p
+        .sample_type
+        .iter()
+        .position(|st| st.r#type == p.default_sample_type)
+        .unwrap_or(p.sample_type.len().saturating_sub(1));
+
+    json!({
+        "$schema": SCHEMA,
+        "shared": { "frames": frames.frames },
+        "profiles": profiles,
+        "name": name,
+        "activeProfileIndex": active,
+        "exporter": EXPORTER,
+    })
+}
+
+// write_speedscope writes the profile as a speedscope JSON file.
+pub fn write_speedscope<W: io::Write>(p: &Profile, name: &str, w: W) -> io::Result<()> {
+    serde_json::to_writer(w, &to_speedscope(p, name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::mapping::Mapping;
+    use crate::speedscope::{to_speedscope, write_speedscope};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_to_speedscope() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count")
+            .add_sample_type("cpu", "nanoseconds");
+        let m = b.add_mapping(Mapping {
+            memory_start: 0x1000,
+            memory_limit: 0x2000,
+            filename: String::from("/bin/app"),
+            ..Default::default()
+        });
+        let main = b.add_function("main", "main", "main.go", 1);
+        let work = b.add_function("work", "work", "work.go", 10);
+        let inlined = b.add_function("sum", "sum", "work.go", 30);
+        let l1 = b.add_location(m, 0x1010, &[(inlined, 31), (work, 12)]);
+        let l2 = b.add_location(m, 0x1020, &[(main, 3)]);
+        let l3 = b.add_location(m, 0x1030, &[]);
+        b.add_sample(vec![l1, l2], vec![2, 20]);
+        b.add_sample(vec![l3, l2], vec![1, 0]);
+        let p = b.build().unwrap();
+
+        let got = to_speedscope(&p, "app");
+        assert_eq!(
+            got["shared"]["frames"],
+            json!([
+                {"name": "main", "file": "main.go", "line": 3},
+                {"name": "work", "file": "work.go", "line": 12},
+                {"name": "sum", "file": "work.go", "line": 31},
+                {"name": "0x1030", "file": "/bin/app"},
+            ])
+        );
+        assert_eq!(
+            got["profiles"],
+            json!([
+                {
+                    "type": "sampled", "name": "app samples", "unit": "none",
+                    "startValue": 0, "endValue": 3,
+                    "samples": [[0, 1, 2], [0, 3]], "weights": [2, 1],
+                },
+                {
+                    "type": "sampled", "name": "app cpu", "unit": "nanoseconds",
+                    "startValue": 0, "endValue": 20,
+                    "samples": [[0, 1, 2]], "weights": [20],
+                },
+            ])
+        );
+        assert_eq!(got["activeProfileIndex"], 1);
+
+        let mut out = vec![];
+        write_speedscope(&p, "app", &mut out).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&out).unwrap(), got);
+    }
+}
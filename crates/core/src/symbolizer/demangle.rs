@@ -0,0 +1,250 @@
+// Demangling of the function system names into readable names, for Rust
+// (legacy "_ZN...17h<hash>E" and v0 "_R..." schemes), Itanium C++ ("_Z...")
+// and Swift ("$s...", "_$s...") symbols.
+//
+// Like the -symbolize=demangle option of pprof, the names are demangled
+//   none -- not at all
+//   full -- with the parameters, template arguments and Rust hashes
+//   simplified -- without parameters, template arguments and hashes (default)
+// Only the functions whose name is empty or equal to the system name are
+// demangled, the names set by the profiler are kept.
+//
+// Swift signatures are not decoded: both full and simplified give the
+// entity path ("main.Foo.bar"), and symbols using word substitutions are
+// left mangled.
+
+use crate::profile::errors::RockError;
+use crate::profile::Profile;
+use cpp_demangle::DemangleOptions;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Demangle {
+    None,
+    Full,
+    #[default]
+    Simplified,
+}
+
+impl FromStr for Demangle {
+    type Err = RockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Demangle::None),
+            "full" => Ok(Demangle::Full),
+            "" | "simplified" => Ok(Demangle::Simplified),
+            _ => Err(RockError::SymbolizationFailed {
+                reason: format!(
+                    "unknown demangle option {:?}, expected none, full or simplified",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+// demangle_profile sets the names of the functions of the profile to the
+// demangled system names.
+pub fn demangle_profile(p: &mut Profile, option: Demangle) {
+    if option == Demangle::None {
+        return;
+    }
+
+    let mut changed = false;
+    for f in p.function.iter_mut() {
+        if !f.name.is_empty() && f.name != f.system_name {
+            continue;
+        }
+        if let Some(name) = demangle(&f.system_name, option) {
+            f.name = name;
+            changed = true;
+        }
+    }
+    if changed {
+        p.link();
+    }
+}
+
+// demangle returns the demangled name, None if the name is not mangled
+// with a known scheme.
+pub fn demangle(name: &str, option: Demangle) -> Option<String> {
+    if option == Demangle::None {
+        return None;
+    }
+    let simplified = option == Demangle::Simplified;
+
+    // legacy Rust symbols are valid Itanium names too, the hash tells them apart
+    if let Ok(d) = rustc_demangle::try_demangle(name) {
+        return Some(if simplified {
+            format!("{:#}", d)
+        } else {
+            d.to_string()
+        });
+    }
+
+    if name.starts_with("_Z") {
+        let symbol = cpp_demangle::Symbol::new(name).ok()?;
+        let options = if simplified {
+            DemangleOptions::new().no_params().no_return_type()
+        } else {
+            DemangleOptions::new()
+        };
+        let demangled = symbol.demangle(&options).ok()?;
+        return Some(if simplified {
+            strip_templates(&demangled)
+        } else {
+            demangled
+        });
+    }
+
+    swift(name)
+}
+
+// strip_templates removes the template arguments, keeping the operators
+// which contain angle brackets ("operator<<", "operator->"...).
+fn strip_templates(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut depth = 0;
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        if depth == 0 && out.ends_with("operator") {
+            let op = rest
+                .find(|c: char| !"<>=-".contains(c))
+                .unwrap_or(rest.len());
+            if op > 0 {
+                out.push_str(&rest[..op]);
+                rest = &rest[op..];
+                continue;
+            }
+        }
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            _ if depth == 0 => out.push(c),
+            _ => {}
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+// swift demangles the entity path of a Swift symbol: the module, the
+// nominal types and the function or accessor.
+fn swift(name: &str) -> Option<String> {
+    let mut s = ["_$s", "$s", "_$S", "$S"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))?;
+
+    let mut path: Vec<String> = vec![];
+    loop {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        if digits == 0 {
+            // nominal type kinds: class, struct, enum, protocol
+            match s.chars().next() {
+                Some('C' | 'V' | 'O' | 'P') if !path.is_empty() => {
+                    s = &s[1..];
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        let n: usize = s[..digits].parse().ok()?;
+        // word substitutions are not supported
+        if n == 0 || s.len() < digits + n {
+            return None;
+        }
+        path.push(s[digits..digits + n].to_string());
+        s = &s[digits + n..];
+    }
+    if path.is_empty() {
+        return None;
+    }
+
+    let accessor = [
+        ("fC", "__allocating_init"),
+        ("fc", "init"),
+        ("fD", "__deallocating_deinit"),
+        ("fd", "deinit"),
+        ("vg", "getter"),
+        ("vs", "setter"),
+        ("vM", "modify"),
+    ]
+    .iter()
+    .find(|(suffix, _)| s.ends_with(suffix));
+    match accessor {
+        Some((_, accessor)) => path.push(accessor.to_string()),
+        None if s.ends_with('F') => {}
+        None => return None,
+    }
+    Some(path.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::symbolizer::demangle::{demangle, demangle_profile, Demangle};
+
+    #[test]
+    fn test_demangle() {
+        let tests = [
+            (
+                "_ZN4core3fmt5write17h4d7e2f0a1c3b5e6fE",
+                "core::fmt::write::h4d7e2f0a1c3b5e6f",
+                "core::fmt::write",
+            ),
+            (
+                "_RNvMNtCs1234_4rock7profileNtB2_7Profile8validate",
+                "<rock[3c1c0]::profile::Profile>::validate",
+                "<rock::profile::Profile>::validate",
+            ),
+            (
+                "_ZNSt6vectorIiSaIiEE9push_backERKi",
+                "std::vector<int, std::allocator<int> >::push_back(int const&)",
+                "std::vector::push_back",
+            ),
+            (
+                "_ZN3foolsERKS_",
+                "foo::operator<<(foo const&)",
+                "foo::operator<<",
+            ),
+            ("$s4main3FooV3baryyF", "main.Foo.bar", "main.Foo.bar"),
+            (
+                "_$s4main3FooC5countSivg",
+                "main.Foo.count.getter",
+                "main.Foo.count.getter",
+            ),
+        ];
+        for (mangled, full, simplified) in tests {
+            assert_eq!(demangle(mangled, Demangle::Full).unwrap(), full);
+            assert_eq!(demangle(mangled, Demangle::Simplified).unwrap(), simplified);
+            assert_eq!(demangle(mangled, Demangle::None), None);
+        }
+
+        for name in ["main", "runtime.mallocgc", "_Zinvalid", "$s0wordsF"] {
+            assert_eq!(demangle(name, Demangle::Full), None, "{}", name);
+        }
+
+        assert_eq!("full".parse::<Demangle>().unwrap(), Demangle::Full);
+        assert!("templates".parse::<Demangle>().is_err());
+    }
+
+    #[test]
+    fn test_demangle_profile() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count");
+        let f = b.add_function("", "_ZN4core3fmt5write17h4d7e2f0a1c3b5e6fE", "", 0);
+        let g = b.add_function("Named", "_ZN3foo3barEv", "", 0);
+        let l = b.add_location(0, 0x10, &[(f, 0), (g, 0)]);
+        b.add_sample(vec![l], vec![1]);
+        let mut p = b.build().unwrap();
+
+        demangle_profile(&mut p, Demangle::Simplified);
+        let names: Vec<&str> = p.sample[0].location[0]
+            .line
+            .iter()
+            .map(|l| l.function.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["core::fmt::write", "Named"]);
+    }
+}
@@ -0,0 +1,85 @@
+// DWARF debug information: the line table (.debug_line) gives the file and
+// line of an address, the inlined subroutines of .debug_info (with their
+// .debug_ranges/.debug_rnglists address ranges) give the inline call chain.
+
+use crate::profile::errors::RockError;
+use crate::symbolizer::Frame;
+use addr2line::Context;
+use gimli::{EndianArcSlice, RunTimeEndian, SectionId};
+use object::{Object, ObjectSection};
+use std::fmt;
+use std::sync::Arc;
+
+type Reader = EndianArcSlice<RunTimeEndian>;
+
+pub struct Dwarf {
+    context: Context<Reader>,
+}
+
+impl fmt::Debug for Dwarf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dwarf").finish_non_exhaustive()
+    }
+}
+
+impl Dwarf {
+    // load reads the DWARF sections of the binary, None if it has no debug information.
+    pub fn load(file: &object::File) -> Result<Option<Dwarf>, RockError> {
+        if file.section_by_name(".debug_info").is_none() {
+            return Ok(None);
+        }
+
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let section = |id: SectionId| -> Result<Reader, gimli::Error> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|s| s.data().ok())
+                .unwrap_or(&[]);
+            Ok(EndianArcSlice::new(Arc::from(data), endian))
+        };
+
+        let dwarf = gimli::Dwarf::load(section).map_err(dwarf_error)?;
+        let context = Context::from_dwarf(dwarf).map_err(dwarf_error)?;
+        Ok(Some(Dwarf { context }))
+    }
+
+    // frames returns the frames at the virtual address, the innermost inlined
+    // function first and the function holding the code last, like the lines
+    // of a Location. Frames without a function name are left out.
+    pub fn frames(&self, vaddr: u64) -> Result<Vec<Frame>, RockError> {
+        let mut frames = vec![];
+        let mut iter = self
+            .context
+            .find_frames(vaddr)
+            .skip_all_loads()
+            .map_err(dwarf_error)?;
+        while let Some(f) = iter.next().map_err(dwarf_error)? {
+            let name = match f.function.as_ref().map(|n| n.raw_name()) {
+                Some(Ok(name)) => name.to_string(),
+                _ => continue,
+            };
+            let (filename, line) = match f.location {
+                Some(l) => (l.file.unwrap_or_default().to_string(), l.line.unwrap_or(0)),
+                None => (String::new(), 0),
+            };
+            frames.push(Frame {
+                function: name.clone(),
+                system_name: name,
+                filename,
+                line: line as i64,
+                ..Default::default()
+            });
+        }
+        Ok(frames)
+    }
+}
+
+fn dwarf_error(err: gimli::Error) -> RockError {
+    RockError::SymbolizationFailed {
+        reason: format!("reading DWARF: {}", err),
+    }
+}
@@ -0,0 +1,249 @@
+// ELF binaries: the loadable segments used to translate the addresses of a
+// mapping into virtual addresses of the binary, the function symbols of the
+// .symtab and .dynsym sections and, when present, the DWARF debug information
+// or the .gopclntab table of Go binaries.
+
+use crate::profile::errors::RockError;
+use crate::profile::mapping::Mapping;
+use crate::symbolizer::dwarf::Dwarf;
+use crate::symbolizer::gopclntab::{GoTable, Memory};
+use crate::symbolizer::Frame;
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SectionKind, SymbolKind};
+use std::fs;
+use std::path::Path;
+
+// Segment is a PT_LOAD program header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    vaddr: u64,
+    offset: u64,
+    filesz: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    address: u64,
+    // 0 when the size is unknown, the symbol then ends at the next one
+    size: u64,
+    name: String,
+}
+
+// Elf is the symbol information read from an ELF binary.
+#[derive(Debug, Default)]
+pub struct Elf {
+    pub build_id: String,
+    segments: Vec<Segment>,
+    // function symbols sorted by address
+    symbols: Vec<Symbol>,
+    dwarf: Option<Dwarf>,
+    go: Option<GoTable>,
+}
+
+impl Elf {
+    pub fn open(path: &Path) -> Result<Elf, RockError> {
+        let data = fs::read(path).map_err(|err| RockError::SymbolizationFailed {
+            reason: format!("reading {}: {}", path.display(), err),
+        })?;
+        Elf::parse(&data).map_err(|err| RockError::SymbolizationFailed {
+            reason: format!("{}: {}", path.display(), err),
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Elf, RockError> {
+        let file = object::File::parse(data).map_err(|err| RockError::SymbolizationFailed {
+            reason: format!("parsing ELF: {}", err),
+        })?;
+        if file.format() != object::BinaryFormat::Elf {
+            return Err(RockError::SymbolizationFailed {
+                reason: format!("not an ELF binary: {:?}", file.format()),
+            });
+        }
+
+        let build_id = match file.build_id() {
+            Ok(Some(id)) => id.iter().map(|b| format!("{:02x}", b)).collect(),
+            _ => String::new(),
+        };
+
+        let segments = file
+            .segments()
+            .map(|s| {
+                let (offset, filesz) = s.file_range();
+                Segment {
+                    vaddr: s.address(),
+                    offset,
+                    filesz,
+                }
+            })
+            .collect();
+
+        // .symtab first, so its (possibly local) symbols win over the
+        // .dynsym copies at the same address
+        let mut symbols: Vec<Symbol> = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|s| s.kind() == SymbolKind::Text && s.address() != 0 && !s.is_undefined())
+            .filter_map(|s| {
+                Some(Symbol {
+                    address: s.address(),
+                    size: s.size(),
+                    name: s.name().ok()?.to_string(),
+                })
+            })
+            .filter(|s| !s.name.is_empty())
+            .collect();
+        symbols.sort_by_key(|s| s.address);
+        symbols.dedup_by_key(|s| s.address);
+
+        // broken debug information falls back to the symbol tables
+        let dwarf = Dwarf::load(&file).ok().flatten();
+
+        let go = go_table(&file);
+
+        Ok(Elf {
+            build_id,
+            segments,
+            symbols,
+            dwarf,
+            go,
+        })
+    }
+
+    // vaddr translates an address of the mapping into a virtual address of
+    // the binary: the mapping places the file offset memory_offset at
+    // memory_start, the PT_LOAD segment holding the file offset gives its
+    // virtual address. Mappings without a range (memory_limit == 0) already
+    // use virtual addresses.
+    pub fn vaddr(&self, m: &Mapping, address: u64) -> Option<u64> {
+        if m.memory_start == 0 && m.memory_limit == 0 {
+            return Some(address);
+        }
+        if address < m.memory_start || (m.memory_limit != 0 && address >= m.memory_limit) {
+            return None;
+        }
+
+        let offset = address - m.memory_start + m.memory_offset;
+        self.segments
+            .iter()
+            .find(|s| offset >= s.offset && offset < s.offset + s.filesz)
+            .map(|s| offset - s.offset + s.vaddr)
+    }
+
+    // frames returns the frames at the virtual address, the callee first:
+    // the inline call chain with file and line numbers from DWARF, or the
+    // function symbol holding the address.
+    pub fn frames(&self, vaddr: u64) -> Vec<Frame> {
+        if let Some(Ok(frames)) = self.dwarf.as_ref().map(|d| d.frames(vaddr)) {
+            if !frames.is_empty() {
+                return frames;
+            }
+        }
+        if let Some(frames) = self.go.as_ref().map(|t| t.frames(vaddr)) {
+            if !frames.is_empty() {
+                return frames;
+            }
+        }
+
+        let i = self.symbols.partition_point(|s| s.address <= vaddr);
+        if i == 0 {
+            return vec![];
+        }
+        let s = &self.symbols[i - 1];
+        if s.size != 0 && vaddr >= s.address + s.size {
+            return vec![];
+        }
+
+        vec![Frame {
+            function: s.name.clone(),
+            system_name: s.name.clone(),
+            ..Default::default()
+        }]
+    }
+}
+
+// go_table reads the .gopclntab section of a Go binary, with the data
+// sections its funcdata point to.
+fn go_table(file: &object::File) -> Option<GoTable> {
+    let data = file.section_by_name(".gopclntab")?.data().ok()?.to_vec();
+
+    let mut memory = Memory::default();
+    for s in file.sections() {
+        if s.address() != 0
+            && matches!(
+                s.kind(),
+                SectionKind::ReadOnlyData | SectionKind::ReadOnlyDataWithRel | SectionKind::Data
+            )
+        {
+            if let Ok(data) = s.data() {
+                memory.add(s.address(), data.to_vec());
+            }
+        }
+    }
+    // go.func.* until Go 1.19
+    let gofunc = file
+        .symbols()
+        .find(|s| matches!(s.name(), Ok("go:func.*" | "go.func.*")))
+        .map(|s| s.address());
+
+    GoTable::parse(data, memory, gofunc).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::mapping::Mapping;
+    use crate::symbolizer::elf::Elf;
+    use std::path::Path;
+
+    #[test]
+    fn test_elf_symbols() {
+        let elf = Elf::open(Path::new("tests/symbolizer/hello")).unwrap();
+        assert_eq!(elf.build_id, "48d7afe8c1555d5ac3b782f49f24be5404d3425b");
+        assert_eq!(elf.frames(0x1174)[0].function, "sum_squares");
+        assert_eq!(elf.frames(0x1050)[0].function, "main");
+        assert!(elf.frames(0x10).is_empty());
+
+        // square is inlined into sum_squares
+        let frames: Vec<(String, i64)> = elf
+            .frames(0x1184)
+            .into_iter()
+            .map(|f| {
+                assert!(f.filename.ends_with("tests/symbolizer/hello.c"));
+                (f.function, f.line)
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            vec![("square".to_string(), 7), ("sum_squares".to_string(), 13)]
+        );
+
+        // stripped, only the exported symbols of .dynsym are left
+        let lib = Elf::open(Path::new("tests/symbolizer/libhello.so")).unwrap();
+        assert_eq!(lib.frames(0x1104)[0].function, "sum_squares");
+
+        assert!(Elf::parse(b"not an elf").is_err());
+        assert!(Elf::open(Path::new("tests/symbolizer/missing")).is_err());
+    }
+
+    #[test]
+    fn test_elf_vaddr() {
+        let elf = Elf::open(Path::new("tests/symbolizer/hello")).unwrap();
+        // text segment of a PIE loaded at 0x555555555000
+        let m = Mapping {
+            memory_start: 0x555555555000,
+            memory_limit: 0x555555556000,
+            memory_offset: 0x1000,
+            ..Default::default()
+        };
+        assert_eq!(elf.vaddr(&m, 0x555555555174), Some(0x1174));
+        assert_eq!(elf.vaddr(&m, 0x555555556000), None);
+
+        // whole file mapped at 0x7f0000000000
+        let m = Mapping {
+            memory_start: 0x7f0000000000,
+            memory_limit: 0x7f0000004000,
+            ..Default::default()
+        };
+        assert_eq!(elf.vaddr(&m, 0x7f0000001050), Some(0x1050));
+
+        assert_eq!(elf.vaddr(&Mapping::default(), 0x1174), Some(0x1174));
+    }
+}
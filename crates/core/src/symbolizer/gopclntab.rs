@@ -0,0 +1,775 @@
+// Go binaries keep a table of their functions in the .gopclntab section, even
+// when they are built without DWARF (-ldflags=-w). For every function it has
+// the name and pc-value tables giving the file, the line and, through
+// PCDATA_InlTreeIndex and FUNCDATA_InlTree, the inlined calls of every pc.
+// The layouts are told apart by the magic number of the header:
+//   Go 1.2  -- 0xfffffffb, the _func layout of Go 1.10 to 1.15
+//   Go 1.16 -- 0xfffffffa, separate name, compilation unit, file and pc tables
+//   Go 1.18 -- 0xfffffff0, pcs relative to the start of the text
+//   Go 1.20 -- 0xfffffff1, start lines of the functions and a new inline tree entry
+// Since Go 1.18 the funcdata are offsets from the go:func.* symbol, so the
+// inline trees are only read from binaries that still have a symbol table.
+
+use crate::profile::errors::RockError;
+use crate::symbolizer::Frame;
+
+const GO12_MAGIC: u32 = 0xfffffffb;
+const GO116_MAGIC: u32 = 0xfffffffa;
+const GO118_MAGIC: u32 = 0xfffffff0;
+const GO120_MAGIC: u32 = 0xfffffff1;
+
+const PCDATA_INL_TREE_INDEX: u32 = 2;
+const FUNCDATA_INL_TREE: u32 = 3;
+
+// upper bound of the inline depth, against loops in a corrupted tree
+const MAX_INLINE_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Go12,
+    Go116,
+    Go118,
+    Go120,
+}
+
+// Memory holds the data of the sections of the binary by virtual address,
+// to read the funcdata the pclntab points to.
+#[derive(Debug, Default)]
+pub struct Memory {
+    sections: Vec<(u64, Vec<u8>)>,
+}
+
+impl Memory {
+    pub fn add(&mut self, address: u64, data: Vec<u8>) {
+        self.sections.push((address, data));
+    }
+
+    fn read(&self, address: u64, n: usize) -> Option<&[u8]> {
+        self.sections.iter().find_map(|(start, data)| {
+            let off = usize::try_from(address.checked_sub(*start)?).ok()?;
+            data.get(off..off.checked_add(n)?)
+        })
+    }
+}
+
+// GoTable is a parsed .gopclntab section, the offsets are relative to data.
+#[derive(Debug)]
+pub struct GoTable {
+    data: Vec<u8>,
+    version: Version,
+    big_endian: bool,
+    quantum: u64,
+    ptrsize: usize,
+    nfunc: usize,
+    text_start: u64,
+    funcnametab: usize,
+    cutab: usize,
+    filetab: usize,
+    pctab: usize,
+    functab: usize,
+    funcdata: usize,
+    // address of go:func.*, the base of the funcdata since Go 1.18
+    gofunc: Option<u64>,
+    memory: Memory,
+}
+
+// Func is an entry of the function table.
+#[derive(Debug, Clone, Copy)]
+struct Func {
+    entry: u64,
+    // offset of the _func structure
+    off: usize,
+}
+
+impl GoTable {
+    pub fn parse(data: Vec<u8>, memory: Memory, gofunc: Option<u64>) -> Result<GoTable, RockError> {
+        let malformed = |reason: &str| RockError::SymbolizationFailed {
+            reason: format!("gopclntab: {}", reason),
+        };
+        if data.len() < 8 || data[4] != 0 || data[5] != 0 {
+            return Err(malformed("truncated header"));
+        }
+
+        let le = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let be = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let magic = |m: u32| -> Option<Version> {
+            match m {
+                GO12_MAGIC => Some(Version::Go12),
+                GO116_MAGIC => Some(Version::Go116),
+                GO118_MAGIC => Some(Version::Go118),
+                GO120_MAGIC => Some(Version::Go120),
+                _ => None,
+            }
+        };
+        let (version, big_endian) = match (magic(le), magic(be)) {
+            (Some(v), _) => (v, false),
+            (None, Some(v)) => (v, true),
+            _ => return Err(malformed(&format!("unknown magic {:#x}", le))),
+        };
+
+        let ptrsize = data[7] as usize;
+        if ptrsize != 4 && ptrsize != 8 {
+            return Err(malformed(&format!("pointer size {}", ptrsize)));
+        }
+        let mut t = GoTable {
+            data,
+            version,
+            big_endian,
+            quantum: 0,
+            ptrsize,
+            nfunc: 0,
+            text_start: 0,
+            funcnametab: 0,
+            cutab: 0,
+            filetab: 0,
+            pctab: 0,
+            functab: 0,
+            funcdata: 0,
+            gofunc,
+            memory,
+        };
+        t.quantum = t.data[6] as u64;
+
+        let header = |t: &GoTable, i: usize| -> Result<usize, RockError> {
+            t.uintptr(8 + i * ptrsize)
+                .and_then(|v| usize::try_from(v).ok())
+                .ok_or_else(|| malformed("truncated header"))
+        };
+        t.nfunc = header(&t, 0)?;
+        match version {
+            Version::Go12 => {
+                t.functab = 8 + ptrsize;
+                let end = t
+                    .nfunc
+                    .checked_mul(2)
+                    .and_then(|n| n.checked_add(1))
+                    .and_then(|n| n.checked_mul(ptrsize))
+                    .and_then(|n| t.functab.checked_add(n))
+                    .ok_or_else(|| malformed("function table size overflows"))?;
+                t.filetab = t
+                    .u32(end)
+                    .ok_or_else(|| malformed("truncated function table"))?
+                    as usize;
+            }
+            Version::Go116 => {
+                t.funcnametab = header(&t, 2)?;
+                t.cutab = header(&t, 3)?;
+                t.filetab = header(&t, 4)?;
+                t.pctab = header(&t, 5)?;
+                t.functab = header(&t, 6)?;
+                t.funcdata = t.functab;
+            }
+            Version::Go118 | Version::Go120 => {
+                t.text_start = header(&t, 2)? as u64;
+                t.funcnametab = header(&t, 3)?;
+                t.cutab = header(&t, 4)?;
+                t.filetab = header(&t, 5)?;
+                t.pctab = header(&t, 6)?;
+                t.functab = header(&t, 7)?;
+                t.funcdata = t.functab;
+            }
+        }
+
+        let last = t
+            .nfunc
+            .checked_mul(2)
+            .ok_or_else(|| malformed("function table size overflows"))?;
+        if t.functab_field(last).is_none() {
+            return Err(malformed("truncated function table"));
+        }
+        Ok(t)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    fn bytes(&self, off: usize, n: usize) -> Option<&[u8]> {
+        self.data.get(off..off.checked_add(n)?)
+    }
+
+    fn decode_u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    fn decode_uintptr(&self, b: &[u8]) -> u64 {
+        if self.ptrsize == 4 {
+            return self.decode_u32(b) as u64;
+        }
+        let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+        if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        self.bytes(off, 4).map(|b| self.decode_u32(b))
+    }
+
+    fn uintptr(&self, off: usize) -> Option<u64> {
+        self.bytes(off, self.ptrsize)
+            .map(|b| self.decode_uintptr(b))
+    }
+
+    fn string(&self, off: usize) -> Option<String> {
+        let b = self.data.get(off..)?;
+        let end = b.iter().position(|c| *c == 0)?;
+        Some(String::from_utf8_lossy(&b[..end]).into_owned())
+    }
+
+    // functab_field returns the i-th field of the function table: the entry
+    // pcs and the offsets of the _func structures alternate, an entry pc
+    // closes the table.
+    fn functab_field(&self, i: usize) -> Option<u64> {
+        if self.version >= Version::Go118 {
+            self.u32(self.functab.checked_add(i.checked_mul(4)?)?)
+                .map(|v| v as u64)
+        } else {
+            self.uintptr(self.functab.checked_add(i.checked_mul(self.ptrsize)?)?)
+        }
+    }
+
+    fn functab_pc(&self, i: usize) -> Option<u64> {
+        let pc = self.functab_field(i.checked_mul(2)?)?;
+        if self.version >= Version::Go118 {
+            return self.text_start.checked_add(pc);
+        }
+        Some(pc)
+    }
+
+    // find_func returns the function holding the pc.
+    fn find_func(&self, pc: u64) -> Option<Func> {
+        if pc < self.functab_pc(0)? || pc >= self.functab_pc(self.nfunc)? {
+            return None;
+        }
+        // the last function whose entry is <= pc
+        let (mut lo, mut hi) = (0, self.nfunc);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.functab_pc(mid)? <= pc {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let off = usize::try_from(self.functab_field(2 * lo + 1)?).ok()?;
+        Some(Func {
+            entry: self.functab_pc(lo)?,
+            off: self.funcdata.checked_add(off)?,
+        })
+    }
+
+    // field returns the n-th 32-bit field of the _func structure, after the entry.
+    fn field(&self, f: Func, n: usize) -> Option<u32> {
+        self.u32(f.off + self.entry_size() + (n - 1) * 4)
+    }
+
+    fn entry_size(&self) -> usize {
+        if self.version >= Version::Go118 {
+            4
+        } else {
+            self.ptrsize
+        }
+    }
+
+    // fixed_size is the size of the _func structure before the pcdata array.
+    fn fixed_size(&self) -> usize {
+        let fields = match self.version {
+            Version::Go12 => 8,
+            Version::Go116 | Version::Go118 => 9,
+            Version::Go120 => 10,
+        };
+        self.entry_size() + fields * 4
+    }
+
+    fn name(&self, nameoff: u32) -> Option<String> {
+        self.string(self.funcnametab + nameoff as usize)
+    }
+
+    fn file(&self, f: Func, index: i64) -> Option<String> {
+        if index < 0 {
+            return None;
+        }
+        if self.version == Version::Go12 {
+            let off = self.u32(self.filetab.checked_add((index as usize).checked_mul(4)?)?)?;
+            if index == 0 || index as u32 >= self.u32(self.filetab)? {
+                return None;
+            }
+            return self.string(off as usize);
+        }
+
+        let cu = self.field(f, 8)? as usize;
+        let entry = cu.checked_add(index as usize)?.checked_mul(4)?;
+        let off = self.u32(self.cutab.checked_add(entry)?)?;
+        if off == u32::MAX {
+            return None;
+        }
+        self.string(self.filetab + off as usize)
+    }
+
+    // pcvalue returns the value of the pc-value table at the pc, -1 if the
+    // table is missing. The table is a sequence of (value delta, pc delta) varints.
+    fn pcvalue(&self, f: Func, table: u32, pc: u64) -> Option<i64> {
+        if table == 0 {
+            return Some(-1);
+        }
+        let mut p = self.data.get(self.pctab + table as usize..)?;
+        let mut value: i64 = -1;
+        let mut cur = f.entry;
+        let mut first = true;
+        loop {
+            let (uvdelta, n) = uvarint(p)?;
+            p = &p[n..];
+            if uvdelta == 0 && !first {
+                return None;
+            }
+            first = false;
+            // zigzag
+            value += if uvdelta & 1 != 0 {
+                !(uvdelta >> 1) as i64
+            } else {
+                (uvdelta >> 1) as i64
+            };
+            let (pcdelta, n) = uvarint(p)?;
+            p = &p[n..];
+            cur = cur.checked_add(pcdelta.checked_mul(self.quantum)?)?;
+            if pc < cur {
+                return Some(value);
+            }
+        }
+    }
+
+    fn pcdata(&self, f: Func, index: u32) -> Option<u32> {
+        if index >= self.field(f, 7)? {
+            return Some(0);
+        }
+        self.u32(f.off + self.fixed_size() + index as usize * 4)
+    }
+
+    // funcdata returns the address of the index-th funcdata.
+    fn funcdata(&self, f: Func, index: u32) -> Option<u64> {
+        // nfuncdata is the last byte of the fixed part
+        let nfuncdata = *self.data.get(f.off + self.fixed_size() - 1)? as u32;
+        if index >= nfuncdata {
+            return None;
+        }
+        let mut off = f.off + self.fixed_size() + self.field(f, 7)? as usize * 4;
+        if self.version >= Version::Go118 {
+            let v = self.u32(off + index as usize * 4)?;
+            if v == u32::MAX {
+                return None;
+            }
+            return Some(self.gofunc? + v as u64);
+        }
+
+        if self.ptrsize == 8 && !(off - f.off).is_multiple_of(8) {
+            off += 4;
+        }
+        match self.uintptr(off + index as usize * self.ptrsize)? {
+            0 => None,
+            address => Some(address),
+        }
+    }
+
+    // inlined_call returns the name, start line and parent pc offset of the
+    // index-th entry of the inline tree.
+    fn inlined_call(&self, tree: u64, index: i64) -> Option<(String, i64, u64)> {
+        let size = if self.version >= Version::Go120 {
+            16
+        } else {
+            20
+        };
+        let offset = u64::try_from(index).ok()?.checked_mul(size)?;
+        let b = self.memory.read(tree.checked_add(offset)?, size as usize)?;
+        let (nameoff, parent_pc, start_line) = if self.version >= Version::Go120 {
+            (
+                self.decode_u32(&b[4..]),
+                self.decode_u32(&b[8..]),
+                self.decode_u32(&b[12..]) as i32 as i64,
+            )
+        } else {
+            (self.decode_u32(&b[12..]), self.decode_u32(&b[16..]), 0)
+        };
+        Some((self.name(nameoff)?, start_line, parent_pc as u64))
+    }
+
+    // frames returns the frames at the pc, the innermost inlined call first.
+    pub fn frames(&self, pc: u64) -> Vec<Frame> {
+        self.try_frames(pc).unwrap_or_default()
+    }
+
+    fn try_frames(&self, mut pc: u64) -> Option<Vec<Frame>> {
+        let f = self.find_func(pc)?;
+        let pcfile = self.field(f, 5)?;
+        let pcln = self.field(f, 6)?;
+        let inl = self.pcdata(f, PCDATA_INL_TREE_INDEX)?;
+        let tree = self.funcdata(f, FUNCDATA_INL_TREE);
+
+        let mut frames = vec![];
+        loop {
+            let filename = self
+                .file(f, self.pcvalue(f, pcfile, pc)?)
+                .unwrap_or_default();
+            let line = self.pcvalue(f, pcln, pc)?.max(0);
+
+            let index = match tree {
+                Some(_) if frames.len() < MAX_INLINE_DEPTH => self.pcvalue(f, inl, pc)?,
+                _ => -1,
+            };
+            if index < 0 {
+                let name = self.name(self.field(f, 1)?)?;
+                let start_line = match self.version {
+                    Version::Go120 => self.field(f, 9)? as i32 as i64,
+                    _ => 0,
+                };
+                frames.push(frame(name, filename, line, start_line));
+                return Some(frames);
+            }
+
+            let (name, start_line, parent_pc) = self.inlined_call(tree?, index)?;
+            frames.push(frame(name, filename, line, start_line));
+            pc = f.entry + parent_pc;
+        }
+    }
+}
+
+fn frame(name: String, filename: String, line: i64, start_line: i64) -> Frame {
+    Frame {
+        function: name.clone(),
+        system_name: name,
+        filename,
+        line,
+        start_line,
+    }
+}
+
+fn uvarint(b: &[u8]) -> Option<(u64, usize)> {
+    let mut v = 0u64;
+    for (i, c) in b.iter().enumerate().take(10) {
+        v |= ((c & 0x7f) as u64) << (7 * i);
+        if c & 0x80 == 0 {
+            return Some((v, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbolizer::gopclntab::{GoTable, Memory, Version};
+
+    const WORK: u64 = 0x401000;
+    const MAIN: u64 = 0x401100;
+    const END: u64 = 0x401200;
+    const GOFUNC: u64 = 0x500000;
+    const INL_TREE: u64 = 0x500020;
+
+    fn uvarint(out: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    // pctab encodes a pc-value table of (value, pc count) pairs.
+    fn pctab(out: &mut Vec<u8>, entries: &[(i64, u64)]) -> u32 {
+        let off = out.len() as u32;
+        let mut prev = -1;
+        for (value, n) in entries {
+            let d = value - prev;
+            uvarint(out, ((d << 1) ^ (d >> 63)) as u64);
+            uvarint(out, *n);
+            prev = *value;
+        }
+        out.push(0);
+        off
+    }
+
+    fn put(data: &mut [u8], off: usize, v: u64, size: usize) {
+        data[off..off + size].copy_from_slice(&v.to_le_bytes()[..size]);
+    }
+
+    fn align(data: &mut Vec<u8>) {
+        while !data.len().is_multiple_of(8) {
+            data.push(0);
+        }
+    }
+
+    // pclntab builds the table of two functions of main.go: main.work
+    // inlines main.helper of helper.go between 0x401010 and 0x401020, the
+    // call is at 0x401008. The inline tree is at INL_TREE.
+    fn pclntab(version: Version) -> (Vec<u8>, Memory) {
+        let go118 = version >= Version::Go118;
+        let uintptr = |data: &mut Vec<u8>, v: u64| data.extend_from_slice(&v.to_le_bytes());
+        // the function table follows the header in the Go 1.2 layout
+        let header = match version {
+            Version::Go12 => 16 + 5 * 8 + 4,
+            Version::Go116 => 64,
+            _ => 72,
+        };
+        let mut data = vec![0u8; header];
+        align(&mut data);
+
+        // names, files, pc tables: a single region in the Go 1.2 layout
+        let funcnametab = data.len();
+        data.extend_from_slice(b"\0main.work\0main.main\0main.helper\0");
+        let (work_name, main_name, helper_name) = (1u64, 11u64, 21u64);
+        // names are relative to the whole table in the Go 1.2 layout
+        let names_base = if version == Version::Go12 {
+            funcnametab as u64
+        } else {
+            0
+        };
+        let (work_name, main_name, helper_name) = (
+            work_name + names_base,
+            main_name + names_base,
+            helper_name + names_base,
+        );
+
+        let filetab = data.len();
+        let cutab;
+        let (main_file, helper_file);
+        if version == Version::Go12 {
+            let strings = filetab + 12;
+            for v in [3, strings, strings + 13] {
+                data.extend_from_slice(&(v as u32).to_le_bytes());
+            }
+            data.extend_from_slice(b"/src/main.go\0/src/helper.go\0");
+            cutab = 0;
+            (main_file, helper_file) = (1, 2);
+        } else {
+            data.extend_from_slice(b"\0/src/main.go\0/src/helper.go\0");
+            cutab = data.len();
+            for v in [1u32, 14] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            (main_file, helper_file) = (0, 1);
+        }
+
+        let pctab_base = data.len();
+        let mut pc = vec![0u8];
+        let work_file = pctab(
+            &mut pc,
+            &[(main_file, 0x10), (helper_file, 0x10), (main_file, 0xe0)],
+        );
+        let work_line = pctab(&mut pc, &[(10, 0x10), (20, 0x10), (12, 0xe0)]);
+        let work_inl = pctab(&mut pc, &[(-1, 0x10), (0, 0x10), (-1, 0xe0)]);
+        let main_file_tab = pctab(&mut pc, &[(main_file, 0x100)]);
+        let main_line = pctab(&mut pc, &[(30, 0x100)]);
+        data.extend_from_slice(&pc);
+        // the pc tables are relative to the start of the table since Go 1.16
+        let pc_off = |off: u32| -> u64 {
+            if version == Version::Go12 {
+                (pctab_base + off as usize) as u64
+            } else {
+                off as u64
+            }
+        };
+        align(&mut data);
+
+        // the function table and the _func structures
+        let field_size = if go118 { 4 } else { 8 };
+        let functab = if version == Version::Go12 {
+            16
+        } else {
+            data.len()
+        };
+        if version != Version::Go12 {
+            data.resize(functab + 5 * field_size, 0);
+            align(&mut data);
+        }
+        let funcdata = if version == Version::Go12 { 0 } else { functab };
+        let text = if go118 { WORK } else { 0 };
+
+        let mut funcs = vec![];
+        for (entry, name, file, line, inl, start_line) in [
+            (WORK, work_name, work_file, work_line, Some(work_inl), 9u64),
+            (MAIN, main_name, main_file_tab, main_line, None, 29),
+        ] {
+            align(&mut data);
+            funcs.push((entry, data.len() - funcdata));
+            if go118 {
+                data.extend_from_slice(&((entry - text) as u32).to_le_bytes());
+            } else {
+                uintptr(&mut data, entry);
+            }
+            let npcdata = if inl.is_some() { 3 } else { 0 };
+            let mut fields = vec![name, 0, 0, 0, pc_off(file), pc_off(line), npcdata];
+            if version != Version::Go12 {
+                fields.push(0); // cuOffset
+            }
+            if version == Version::Go120 {
+                fields.push(start_line);
+            }
+            for v in fields {
+                data.extend_from_slice(&(v as u32).to_le_bytes());
+            }
+            // funcID, flag, padding, nfuncdata
+            let nfuncdata = if inl.is_some() { 4 } else { 0 };
+            data.extend_from_slice(&[0, 0, 0, nfuncdata]);
+            if let Some(inl) = inl {
+                for v in [0, 0, pc_off(inl)] {
+                    data.extend_from_slice(&(v as u32).to_le_bytes());
+                }
+                if go118 {
+                    for v in [u32::MAX, u32::MAX, u32::MAX, (INL_TREE - GOFUNC) as u32] {
+                        data.extend_from_slice(&v.to_le_bytes());
+                    }
+                } else {
+                    align(&mut data);
+                    for v in [0, 0, 0, INL_TREE] {
+                        uintptr(&mut data, v);
+                    }
+                }
+            }
+        }
+        for (i, v) in [
+            funcs[0].0 - text,
+            funcs[0].1 as u64,
+            funcs[1].0 - text,
+            funcs[1].1 as u64,
+            END - text,
+        ]
+        .iter()
+        .enumerate()
+        {
+            put(&mut data, functab + i * field_size, *v, field_size);
+        }
+
+        let magic = match version {
+            Version::Go12 => 0xfffffffb,
+            Version::Go116 => 0xfffffffa,
+            Version::Go118 => 0xfffffff0,
+            Version::Go120 => 0xfffffff1,
+        };
+        put(&mut data, 0, magic, 4);
+        data[6] = 1;
+        data[7] = 8;
+        put(&mut data, 8, 2, 8);
+        match version {
+            Version::Go12 => {
+                put(&mut data, functab + 5 * 8, filetab as u64, 4);
+            }
+            Version::Go116 => {
+                for (i, v) in [2, funcnametab, cutab, filetab, pctab_base, functab]
+                    .iter()
+                    .enumerate()
+                {
+                    put(&mut data, 16 + i * 8, *v as u64, 8);
+                }
+            }
+            _ => {
+                for (i, v) in [
+                    2,
+                    text as usize,
+                    funcnametab,
+                    cutab,
+                    filetab,
+                    pctab_base,
+                    functab,
+                ]
+                .iter()
+                .enumerate()
+                {
+                    put(&mut data, 16 + i * 8, *v as u64, 8);
+                }
+            }
+        }
+
+        // the inline tree entry of main.helper, called at offset 8 of main.work
+        let mut tree = vec![];
+        if version == Version::Go120 {
+            for v in [0, helper_name as u32, 8, 19] {
+                tree.extend_from_slice(&v.to_le_bytes());
+            }
+        } else {
+            // parent, funcID, file, line, func, parentPc
+            tree.extend_from_slice(&[0xff, 0xff, 0, 0]);
+            for v in [0u32, 0, helper_name as u32, 8] {
+                tree.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let mut memory = Memory::default();
+        let mut section = vec![0u8; (INL_TREE - GOFUNC) as usize];
+        section.extend(tree);
+        memory.add(GOFUNC, section);
+        (data, memory)
+    }
+
+    #[test]
+    fn test_gopclntab_versions() {
+        for version in [
+            Version::Go12,
+            Version::Go116,
+            Version::Go118,
+            Version::Go120,
+        ] {
+            let (data, memory) = pclntab(version);
+            let t = GoTable::parse(data, memory, Some(GOFUNC)).unwrap();
+            assert_eq!(t.version(), version);
+
+            let frames = |pc: u64| -> Vec<(String, String, i64, i64)> {
+                t.frames(pc)
+                    .into_iter()
+                    .map(|f| (f.function, f.filename, f.line, f.start_line))
+                    .collect()
+            };
+            let go120 = |line: i64| if version == Version::Go120 { line } else { 0 };
+            let frame = |name: &str, file: &str, line: i64, start_line: i64| {
+                (name.to_string(), file.to_string(), line, go120(start_line))
+            };
+
+            assert_eq!(
+                frames(WORK + 4),
+                vec![frame("main.work", "/src/main.go", 10, 9)],
+                "{:?}",
+                version
+            );
+            assert_eq!(
+                frames(WORK + 0x14),
+                vec![
+                    frame("main.helper", "/src/helper.go", 20, 19),
+                    frame("main.work", "/src/main.go", 10, 9),
+                ],
+                "{:?}",
+                version
+            );
+            assert_eq!(
+                frames(MAIN + 0x50),
+                vec![frame("main.main", "/src/main.go", 30, 29)],
+                "{:?}",
+                version
+            );
+            assert!(frames(END).is_empty());
+            assert!(frames(WORK - 1).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_gopclntab_errors() {
+        assert!(GoTable::parse(vec![], Memory::default(), None).is_err());
+        assert!(GoTable::parse(vec![1, 2, 3, 4, 0, 0, 1, 8], Memory::default(), None).is_err());
+
+        let (mut data, memory) = pclntab(Version::Go118);
+        data.truncate(80);
+        assert!(GoTable::parse(data, memory, None).is_err());
+
+        // the function count of a corrupt header overflows the table size
+        let mut data = vec![0xfb, 0xff, 0xff, 0xff, 0, 0, 1, 8];
+        data.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+        assert!(GoTable::parse(data, Memory::default(), None).is_err());
+
+        // without go:func.* the inline tree of Go 1.18+ is not found
+        let (data, memory) = pclntab(Version::Go120);
+        let t = GoTable::parse(data, memory, None).unwrap();
+        assert_eq!(t.frames(WORK + 0x14).len(), 1);
+    }
+}
@@ -0,0 +1,227 @@
+// Package symbolizer fills in the functions and lines of the locations of
+// unsymbolized mappings (Mapping::has_function == false) from the binaries
+// found on the local file system.
+// elf -- function symbols of the .symtab and .dynsym sections of ELF binaries
+// dwarf -- files, line numbers and inlined functions from the DWARF debug information
+// gopclntab -- functions, files, lines and inlined calls of Go binaries without DWARF
+// demangle -- readable names for the Rust, C++ and Swift system names
+// store -- lookup of the binaries by build id in local symbol directories
+//
+// Mappings without a binary, like "[vdso]" or the kernel without debug
+// information in the symbol store, are left as they are.
+
+pub mod demangle;
+pub mod dwarf;
+pub mod elf;
+pub mod gopclntab;
+pub mod store;
+
+use crate::profile::errors::RockError;
+use crate::profile::function::Function;
+use crate::profile::line::Line;
+use crate::profile::Profile;
+use demangle::{demangle_profile, Demangle};
+use std::collections::HashMap;
+use store::SymbolStore;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // symbol directories searched before the mapping filename
+    pub store: SymbolStore,
+    // demangling of the system names of the resolved functions
+    pub demangle: Demangle,
+}
+
+// Frame is a function call at an address of a binary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub function: String,
+    pub system_name: String,
+    pub filename: String,
+    pub line: i64,
+    pub start_line: i64,
+}
+
+// symbolize resolves the addresses of the locations without lines, for every
+// mapping which is not symbolized yet and whose binary is available.
+// The mapping flags are updated: has_function is set when every location of
+// the mapping has lines. The function names are demangled as set by opts.demangle.
+pub fn symbolize(p: &mut Profile, opts: &Options) -> Result<(), RockError> {
+    let mut functions: HashMap<(String, String, String, i64), u64> = p
+        .function
+        .iter()
+        .map(|f| {
+            (
+                (
+                    f.name.clone(),
+                    f.system_name.clone(),
+                    f.filename.clone(),
+                    f.start_line,
+                ),
+                f.id,
+            )
+        })
+        .collect();
+    let mut next_function = p.function.iter().map(|f| f.id).max().unwrap_or(0) + 1;
+
+    for mi in 0..p.mapping.len() {
+        if p.mapping[mi].has_function {
+            continue;
+        }
+        let elf = match opts.store.open(&p.mapping[mi]) {
+            Some(elf) => elf,
+            None => continue,
+        };
+
+        let m = &p.mapping[mi];
+        let mut frames = vec![];
+        for loc in p.location.iter_mut() {
+            if loc.mapping_index != m.id || !loc.line.is_empty() {
+                continue;
+            }
+            let vaddr = match elf.vaddr(m, loc.address) {
+                Some(vaddr) => vaddr,
+                None => continue,
+            };
+            for frame in elf.frames(vaddr) {
+                let key = (
+                    frame.function.clone(),
+                    frame.system_name.clone(),
+                    frame.filename.clone(),
+                    frame.start_line,
+                );
+                let id = *functions.entry(key).or_insert_with(|| {
+                    p.function.push(Function {
+                        id: next_function,
+                        name: frame.function.clone(),
+                        system_name: frame.system_name.clone(),
+                        filename: frame.filename.clone(),
+                        start_line: frame.start_line,
+                        ..Default::default()
+                    });
+                    next_function += 1;
+                    next_function - 1
+                });
+                loc.line.push(Line {
+                    line: frame.line,
+                    function_index: id,
+                    ..Default::default()
+                });
+                frames.push(frame);
+            }
+        }
+
+        let id = m.id;
+        let locations = p.location.iter().filter(|l| l.mapping_index == id);
+        let mut lines = locations.clone().flat_map(|l| l.line.iter());
+        let has_function = locations.clone().all(|l| !l.line.is_empty());
+        let has_inline_frames = locations.clone().any(|l| l.line.len() > 1);
+        let has_line_numbers = has_function && lines.all(|l| l.line > 0);
+        let has_filenames = has_function && frames.iter().all(|f| !f.filename.is_empty());
+
+        let m = &mut p.mapping[mi];
+        m.has_function = has_function;
+        m.has_filenames = has_filenames;
+        m.has_line_numbers = has_line_numbers;
+        m.has_inline_frames = has_inline_frames;
+    }
+
+    p.link();
+    demangle_profile(p, opts.demangle);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::builder::ProfileBuilder;
+    use crate::profile::mapping::Mapping;
+    use crate::symbolizer::store::SymbolStore;
+    use crate::symbolizer::{symbolize, Options};
+
+    #[test]
+    fn test_symbolize() {
+        let mut b = ProfileBuilder::new();
+        b.add_sample_type("samples", "count");
+        let exe = b.add_mapping(Mapping {
+            memory_start: 0x555555555000,
+            memory_limit: 0x555555556000,
+            memory_offset: 0x1000,
+            filename: String::from("/nonexistent/hello"),
+            build_id: String::from("48d7afe8c1555d5ac3b782f49f24be5404d3425b"),
+            ..Default::default()
+        });
+        let lib = b.add_mapping(Mapping {
+            memory_start: 0x7f0000000000,
+            memory_limit: 0x7f0000004000,
+            filename: String::from("tests/symbolizer/libhello.so"),
+            ..Default::default()
+        });
+        let vdso = b.add_mapping(Mapping {
+            memory_start: 0x7fff00000000,
+            memory_limit: 0x7fff00002000,
+            filename: String::from("[vdso]"),
+            ..Default::default()
+        });
+        let main = b.add_location(exe, 0x555555555060, &[]);
+        let sum = b.add_location(exe, 0x555555555174, &[]);
+        let inlined = b.add_location(exe, 0x555555555184, &[]);
+        let lib_sum = b.add_location(lib, 0x7f0000001104, &[]);
+        let v = b.add_location(vdso, 0x7fff00000100, &[]);
+        b.add_sample(vec![sum, main], vec![3]);
+        b.add_sample(vec![inlined, main], vec![2]);
+        b.add_sample(vec![lib_sum, v], vec![1]);
+        let mut p = b.build().unwrap();
+
+        // the executable is found by build id only
+        let dir = std::env::temp_dir().join(format!("rock-symbolizer-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let id = dir.join("48d7afe8c1555d5ac3b782f49f24be5404d3425b");
+        std::fs::create_dir_all(&id).unwrap();
+        std::fs::copy("tests/symbolizer/hello", id.join("hello")).unwrap();
+        let opts = Options {
+            store: SymbolStore::new(vec![dir.clone()]),
+            ..Default::default()
+        };
+        symbolize(&mut p, &opts).unwrap();
+
+        let names: Vec<Vec<&str>> = p
+            .sample
+            .iter()
+            .map(|s| {
+                s.location
+                    .iter()
+                    .flat_map(|l| l.line.iter().map(|l| l.function.name.as_str()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                vec!["sum_squares", "main"],
+                vec!["square", "sum_squares", "main"],
+                vec!["sum_squares"]
+            ]
+        );
+        // lines of the executable come from DWARF, the stripped library only has symbols
+        let lines: Vec<i64> = p.sample[1].location[0]
+            .line
+            .iter()
+            .map(|l| l.line)
+            .collect();
+        assert_eq!(lines, vec![7, 13]);
+
+        let m = &p.mapping[0];
+        assert!(m.has_function && m.has_filenames && m.has_line_numbers && m.has_inline_frames);
+        let m = &p.mapping[1];
+        assert!(m.has_function && !m.has_filenames && !m.has_line_numbers && !m.has_inline_frames);
+        assert!(!p.mapping[2].has_function);
+        assert!(
+            p.sample[1].location[0]
+                .mapping
+                .as_ref()
+                .unwrap()
+                .has_function
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
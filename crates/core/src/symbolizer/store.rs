@@ -0,0 +1,248 @@
+// SymbolStore finds the binary of a mapping in local symbol directories,
+// following the search order of pprof: for every directory
+//   <dir>/<build id>/<file basename>
+//   <dir>/<build id>/*
+//   <dir>/<file>/<build id> (perf buildid-cache)
+//   <dir>/.build-id/<first 2 chars of build id>/<rest of build id>.debug (debuginfod, gdb)
+//   <dir>/<file basename>
+//   <dir>/<file> (perf --symfs)
+// and finally the mapping filename itself. A file is used only if it is an
+// ELF binary with the build id of the mapping (when both have one).
+
+use crate::profile::mapping::Mapping;
+use crate::symbolizer::elf::Elf;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// environment variable holding the symbol directories, separated like PATH
+pub const BINARY_PATH_ENV: &str = "PPROF_BINARY_PATH";
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolStore {
+    pub dirs: Vec<PathBuf>,
+}
+
+impl SymbolStore {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        SymbolStore { dirs }
+    }
+
+    // from_env returns the store of the directories of PPROF_BINARY_PATH,
+    // $HOME/pprof/binaries if it is not set.
+    pub fn from_env() -> Self {
+        match env::var_os(BINARY_PATH_ENV) {
+            Some(path) => SymbolStore::from_search_path(&path),
+            None => SymbolStore::new(
+                env::var_os("HOME")
+                    .map(|home| vec![Path::new(&home).join("pprof").join("binaries")])
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    pub fn from_search_path(path: &OsStr) -> Self {
+        SymbolStore::new(
+            env::split_paths(path)
+                .filter(|p| !p.as_os_str().is_empty())
+                .collect(),
+        )
+    }
+
+    // candidates returns the paths where the binary of the mapping is looked for, in order.
+    pub fn candidates(&self, m: &Mapping) -> Vec<PathBuf> {
+        // virtual mappings ([vdso], [kernel.kallsyms]...) are found by build id only
+        let file = (!m.filename.is_empty() && !m.filename.starts_with('[')).then_some(&m.filename);
+        let base = file.and_then(|f| Path::new(f).file_name());
+        // joining an absolute path would replace the directory
+        let relative = file.map(|f| f.trim_start_matches('/'));
+        // the build id comes from the profile, anything but hex digits could
+        // escape the directories (../, /etc) and is ignored
+        let build_id = (!m.build_id.is_empty()
+            && m.build_id.chars().all(|c| c.is_ascii_hexdigit()))
+        .then_some(&m.build_id);
+
+        let mut paths = vec![];
+        for dir in self.dirs.iter() {
+            if let Some(build_id) = build_id {
+                let id = dir.join(build_id);
+                if let Some(base) = base {
+                    paths.push(id.join(base));
+                }
+                if let Ok(entries) = fs::read_dir(&id) {
+                    let mut entries: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+                    entries.sort();
+                    paths.extend(entries);
+                }
+                if let Some(relative) = relative {
+                    paths.push(dir.join(relative).join(build_id));
+                }
+                if build_id.len() > 2 {
+                    paths.push(
+                        dir.join(".build-id")
+                            .join(&build_id[..2])
+                            .join(format!("{}.debug", &build_id[2..])),
+                    );
+                }
+            }
+            if let (Some(base), Some(relative)) = (base, relative) {
+                paths.push(dir.join(base));
+                paths.push(dir.join(relative));
+            }
+        }
+        if let Some(file) = file {
+            paths.push(PathBuf::from(file));
+        }
+        paths
+    }
+
+    // open returns the first candidate binary of the mapping with a matching
+    // build id. A binary without build id is only used when it is the mapping
+    // file itself, never when it is found in the store directories.
+    pub fn open(&self, m: &Mapping) -> Option<Elf> {
+        let file = Path::new(&m.filename);
+        self.candidates(m)
+            .iter()
+            .filter(|path| path.is_file())
+            .filter_map(|path| Elf::open(path).ok().map(|elf| (path, elf)))
+            .find(|(path, elf)| {
+                m.build_id.is_empty()
+                    || elf.build_id.eq_ignore_ascii_case(&m.build_id)
+                    || elf.build_id.is_empty() && path.as_path() == file
+            })
+            .map(|(_, elf)| elf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::mapping::Mapping;
+    use crate::symbolizer::store::SymbolStore;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const BUILD_ID: &str = "48d7afe8c1555d5ac3b782f49f24be5404d3425b";
+
+    #[test]
+    fn test_store_candidates() {
+        let store = SymbolStore::from_search_path(OsStr::new("/a:/b"));
+        assert_eq!(store.dirs, vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+
+        let m = Mapping {
+            filename: String::from("/usr/bin/app"),
+            build_id: String::from("abcdef"),
+            ..Default::default()
+        };
+        let got: Vec<String> = SymbolStore::new(vec![PathBuf::from("/s")])
+            .candidates(&m)
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                "/s/abcdef/app",
+                "/s/usr/bin/app/abcdef",
+                "/s/.build-id/ab/cdef.debug",
+                "/s/app",
+                "/s/usr/bin/app",
+                "/usr/bin/app",
+            ]
+        );
+
+        let vdso = Mapping {
+            filename: String::from("[vdso]"),
+            ..Default::default()
+        };
+        assert!(store.candidates(&vdso).is_empty());
+
+        // invalid build ids are ignored, they must not select other paths
+        for build_id in ["éabc", "../..", "/etc", "ab/../cd"] {
+            let m = Mapping {
+                build_id: String::from(build_id),
+                ..m.clone()
+            };
+            let got: Vec<String> = SymbolStore::new(vec![PathBuf::from("/s")])
+                .candidates(&m)
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            assert_eq!(got, vec!["/s/app", "/s/usr/bin/app", "/usr/bin/app"]);
+            assert!(store.open(&m).is_none());
+        }
+    }
+
+    #[test]
+    fn test_store_open() {
+        let dir =
+            std::env::temp_dir().join(format!("rock-symbol-store-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let debug = dir.join(".build-id").join(&BUILD_ID[..2]);
+        fs::create_dir_all(&debug).unwrap();
+        fs::copy(
+            "tests/symbolizer/hello",
+            debug.join(format!("{}.debug", &BUILD_ID[2..])),
+        )
+        .unwrap();
+        let store = SymbolStore::new(vec![dir.clone()]);
+
+        let m = Mapping {
+            filename: String::from("/nonexistent/hello"),
+            build_id: String::from(BUILD_ID),
+            ..Default::default()
+        };
+        assert_eq!(store.open(&m).unwrap().build_id, BUILD_ID);
+
+        // the mapping filename exists, but it is another binary
+        let m = Mapping {
+            filename: String::from("tests/symbolizer/libhello.so"),
+            build_id: String::from("0123456789"),
+            ..Default::default()
+        };
+        assert!(store.open(&m).is_none());
+
+        // without a build id the filename is trusted
+        let m = Mapping {
+            filename: String::from("tests/symbolizer/libhello.so"),
+            ..Default::default()
+        };
+        assert!(store.open(&m).is_some());
+
+        // a binary without build id found in the store is rejected, the
+        // mapping file itself is used
+        let stripped = dir.join("stripped");
+        fs::create_dir_all(&stripped).unwrap();
+        fs::write(
+            stripped.join("hello"),
+            without_build_id("tests/symbolizer/hello"),
+        )
+        .unwrap();
+        let m = Mapping {
+            filename: String::from("/nonexistent/hello"),
+            build_id: String::from("0123456789"),
+            ..Default::default()
+        };
+        let store = SymbolStore::new(vec![stripped.clone()]);
+        assert!(store.open(&m).is_none());
+        let m = Mapping {
+            filename: stripped.join("hello").display().to_string(),
+            build_id: String::from("0123456789"),
+            ..Default::default()
+        };
+        assert!(store.open(&m).unwrap().build_id.is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // without_build_id returns the binary with its NT_GNU_BUILD_ID note
+    // turned into a note of an unknown type.
+    fn without_build_id(path: &str) -> Vec<u8> {
+        let mut data = fs::read(path).unwrap();
+        let note = [4, 0, 0, 0, 20, 0, 0, 0, 3, 0, 0, 0, b'G', b'N', b'U', 0];
+        let i = data.windows(note.len()).position(|w| w == note).unwrap();
+        data[i + 8] = 0x7f;
+        data
+    }
+}
@@ -0,0 +1,23 @@
+// Source of the symbolizer test binaries, built with
+//   gcc -g -O2 -Wl,--build-id=sha1 -o hello hello.c
+//   gcc -O2 -shared -fPIC -DLIBRARY -Wl,--build-id=sha1 -o libhello.so hello.c && strip libhello.so
+#include <stdio.h>
+
+static inline __attribute__((always_inline)) long square(long x) {
+    return x * x;
+}
+
+__attribute__((noinline)) long sum_squares(long n) {
+    long s = 0;
+    for (long i = 0; i < n; i++) {
+        s += square(i);
+    }
+    return s;
+}
+
+#ifndef LIBRARY
+int main(int argc, char **argv) {
+    printf("%ld\n", sum_squares(argc * 1000));
+    return 0;
+}
+#endif